@@ -2,5 +2,6 @@ fn main() {
     capnpc::CompilerCommand::new()
         .src_prefix("schema")
         .file("schema/bui.capnp")
+        .file("schema/bytecode.capnp")
         .run().expect("schema compiler command");
 }
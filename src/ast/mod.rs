@@ -1,10 +1,14 @@
 pub mod resolve;
 
+use std::iter::Peekable;
+
 use crate::ast::resolve::{lower_ast, LoweringContext, ResolveCollectVisitor, ResolvePathVisitor};
-use crate::error::dump_errors;
+use crate::error::{dump_errors, report_error, Error};
 use crate::hil::Hil;
+use crate::op::{Assoc, BinOp, UnOp};
+use crate::s_expr::node_find_attr;
 
-use lexpr::{Cons, Value};
+use lexpr::{sexp, Cons, Value};
 
 pub type Ast = Value;
 pub type Node = Cons;
@@ -13,24 +17,68 @@ pub trait AstVisitor {
     fn visit(&mut self, ast: &Ast) {
         walk_ast(self, ast)
     }
+    fn visit_def(&mut self, _: &Node) {}
     fn visit_item(&mut self, _: &Node) {}
     fn visit_expr(&mut self, _: &Node) {}
+    fn visit_post_expr(&mut self, _: &Node) {}
     fn visit_type(&mut self, _: &Node) {}
+    fn visit_item_ref(&mut self, _: &Node) {}
+    fn visit_include(&mut self, _: &Node) {}
     fn visit_param(&mut self, _: &Node) {}
+    fn visit_local(&mut self, _: &Node) {}
     fn visit_pre_namespace(&mut self, _: &Node) {}
     fn visit_post_namespace(&mut self, _: &Node) {}
+    fn visit_pre_scope(&mut self, _: &Node) {}
+    fn visit_post_scope(&mut self, _: &Node) {}
+}
+
+// Nodes which are assigned a `DefId`, numbered in pre-order.
+pub fn is_def(sym: &str) -> bool {
+    sym == "Item" || sym == "Decl" || sym == "Binding" || sym == "Variant" || sym == "FieldDef"
+}
+
+// Items, bindings and field definitions are private to their namespace,
+// module or class unless marked `#:vis #:pub`.
+pub fn is_public(node: &Node) -> bool {
+    node_find_attr(node, "vis").and_then(|x| x.as_keyword()) == Some("pub")
+}
+
+// Defns marked `#:inline #:always` are inlined by other units whatever
+// their size.
+pub fn is_inline(node: &Node) -> bool {
+    node_find_attr(node, "inline").and_then(|x| x.as_keyword()) == Some("always")
+}
+
+// Nodes naming a trait, interface or module by path.
+pub fn is_item_ref(sym: &str) -> bool {
+    sym == "TraitRef" || sym == "IfaceRef" || sym == "ModRef"
+}
+
+// Nodes which open a new scope for local variables.
+fn is_scope(sym: &str) -> bool {
+    sym == "Item" || sym == "Binding" || sym == "Decl" || sym == "Block"
 }
 
 fn walk_ast<T: ?Sized + AstVisitor>(v: &mut T, ast: &Ast) {
     match ast {
         Value::Cons(c) => {
             if let Some(sym) = c.car().as_symbol() {
+                if is_scope(sym) {
+                    v.visit_pre_scope(c);
+                }
+                if is_def(sym) {
+                    v.visit_def(c);
+                }
                 if sym == "Item" {
                     v.visit_item(c);
                 } else if sym == "Expr" {
                     v.visit_expr(c);
                 } else if sym == "Ty" {
                     v.visit_type(c);
+                } else if is_item_ref(sym) {
+                    v.visit_item_ref(c);
+                } else if sym == "Include" {
+                    v.visit_include(c);
                 } else if sym == "Param" {
                     v.visit_param(c);
                 } else if sym == "Namespace" {
@@ -41,6 +89,15 @@ fn walk_ast<T: ?Sized + AstVisitor>(v: &mut T, ast: &Ast) {
             if let Some(sym) = c.car().as_symbol() {
                 if sym == "Namespace" {
                     v.visit_post_namespace(c);
+                } else if sym == "Expr" {
+                    v.visit_post_expr(c);
+                } else if sym == "Local" {
+                    // Visited after its initializer, which cannot see the
+                    // binding it introduces.
+                    v.visit_local(c);
+                }
+                if is_scope(sym) {
+                    v.visit_post_scope(c);
                 }
             }
         }
@@ -64,11 +121,63 @@ impl NodeId {
     }
 }
 
+pub fn make_unary(op: UnOp, operand: Ast) -> Ast {
+    let op = Value::keyword(op.keyword());
+    sexp!((Expr #:kind #:unary #:op ,op ,operand))
+}
+
+pub fn make_binary(op: BinOp, lhs: Ast, rhs: Ast) -> Ast {
+    let op = Value::keyword(op.keyword());
+    sexp!((Expr #:kind #:binary #:op ,op ,lhs ,rhs))
+}
+
+fn fold_infix_from<I: Iterator<Item = (BinOp, Ast)>>(
+    mut lhs: Ast,
+    rest: &mut Peekable<I>,
+    min_prec: u8,
+) -> Ast {
+    while let Some(op) = rest.peek().map(|x| x.0) {
+        if op.precedence() < min_prec {
+            break;
+        }
+        let (_, mut rhs) = rest.next().unwrap();
+        while let Some(next) = rest.peek().map(|x| x.0) {
+            if next.precedence() <= op.precedence() {
+                break;
+            }
+            rhs = fold_infix_from(rhs, rest, op.precedence() + 1);
+        }
+        if let Some(next) = rest.peek().map(|x| x.0) {
+            if op.assoc() == Assoc::None && next.precedence() == op.precedence() {
+                report_error(Error::ChainedOperators(
+                    op.symbol().to_string(),
+                    next.symbol().to_string(),
+                ));
+            }
+        }
+        lhs = make_binary(op, lhs, rhs);
+    }
+    lhs
+}
+
+// Builds the `binary` expression tree for the infix sequence
+// `operands[0] ops[0] operands[1] ... operands[n]`. Every AST producer should
+// go through this so that operators group according to `BinOp::precedence`
+// and `BinOp::assoc`.
+pub fn fold_infix(operands: Vec<Ast>, ops: &[BinOp]) -> Ast {
+    assert_eq!(operands.len(), ops.len() + 1);
+    let mut operands = operands.into_iter();
+    let lhs = operands.next().unwrap();
+    let mut rest = ops.iter().copied().zip(operands).peekable();
+    fold_infix_from(lhs, &mut rest, 0)
+}
+
 pub fn to_hil(ast: &Ast) -> Hil {
     let mut collector = ResolveCollectVisitor::new();
     collector.visit(ast);
     let mut resolver = ResolvePathVisitor::new(collector.collect());
     resolver.visit(ast);
     dump_errors();
-    lower_ast(ast, &mut LoweringContext::new(resolver.resolve()))
+    let (resolutions, captures) = resolver.resolve();
+    lower_ast(ast, &mut LoweringContext::new(resolutions, captures))
 }
@@ -2,15 +2,47 @@ use std::collections::HashMap;
 
 use lexpr::{sexp, Cons, Value};
 
-use crate::ast::{Ast, AstVisitor, Node, NodeId};
+use crate::ast::{is_def, is_item_ref, is_public, Ast, AstVisitor, Node, NodeId};
 use crate::def::{DefId, DefLocalId};
 use crate::error::{report_error, Error};
 use crate::hil::{Hil, HilId};
-use crate::s_expr::{node_add_attr, node_get_attr, node_get_field};
+use crate::s_expr::{node_add_attr, node_find_attr, node_get_attr, node_get_field, node_get_fields};
 
+#[derive(Clone, Copy)]
+pub enum PrimTy {
+    Bool,
+    Int,
+}
+
+impl PrimTy {
+    fn from_ident(ident: &str) -> Option<Self> {
+        match ident {
+            "bool" => Some(PrimTy::Bool),
+            "int" => Some(PrimTy::Int),
+            _ => None,
+        }
+    }
+
+    fn keyword(self) -> &'static str {
+        match self {
+            PrimTy::Bool => "bool",
+            PrimTy::Int => "int",
+        }
+    }
+}
+
+#[derive(Clone)]
 pub enum Resolution<Id> {
     Def(DefId),
     Local(Id),
+    Prim(PrimTy),
+    // Generic parameter of the enclosing defn, by index.
+    Param(usize),
+    // Member of a functor parameter, seen through the parameter's
+    // interface.
+    Member(DefId, DefId),
+    // `Self` inside of a trait.
+    SelfTy,
 }
 
 #[derive(Copy, Clone)]
@@ -51,6 +83,12 @@ impl Namespace {
 pub struct NamespaceContext {
     namespaces: Vec<Namespace>,
     current_ns: NamespaceId,
+    fields: HashMap<DefId, HashMap<String, DefId>>,
+    // Members of interfaces, modules and functors.
+    members: HashMap<DefId, HashMap<String, DefId>>,
+    // Private members of modules and functors, with the `DefId` and name of
+    // the item they belong to.
+    private: HashMap<DefId, (DefId, String)>,
 }
 
 impl NamespaceContext {
@@ -69,6 +107,9 @@ impl NamespaceContext {
         let mut ret = NamespaceContext {
             namespaces: vec![],
             current_ns: NamespaceId::new(0),
+            fields: HashMap::new(),
+            members: HashMap::new(),
+            private: HashMap::new(),
         };
         ret.make_namespace(String::new());
         ret
@@ -97,6 +138,18 @@ trait NamespaceManager {
 pub struct ResolveCollectVisitor {
     ns_ctx: NamespaceContext,
     def_local_id: DefLocalId,
+    class: Option<DefId>,
+    container: Option<DefId>,
+    item_name: String,
+    // Interfaces included by each interface, expanded once every interface
+    // of the unit is collected.
+    includes: Vec<(DefId, NamespaceId, String)>,
+}
+
+impl Default for ResolveCollectVisitor {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ResolveCollectVisitor {
@@ -104,11 +157,55 @@ impl ResolveCollectVisitor {
         ResolveCollectVisitor {
             ns_ctx: NamespaceContext::new(),
             def_local_id: DefLocalId::new(),
+            class: None,
+            container: None,
+            item_name: String::new(),
+            includes: vec![],
+        }
+    }
+
+    pub fn collect(mut self) -> NamespaceContext {
+        let mut containers = vec![];
+        let mut includes = HashMap::<_, Vec<_>>::new();
+        for (container, namespace, ident) in std::mem::take(&mut self.includes) {
+            let included = self.ns_ctx.namespaces[namespace.private].lookup(&ident);
+            if let Some(included) = included.filter(|x| self.ns_ctx.members.contains_key(x)) {
+                if !containers.contains(&container) {
+                    containers.push(container.clone());
+                }
+                includes.entry(container).or_default().push(included);
+            }
+        }
+        for container in containers {
+            self.include_members(&container, &mut includes);
+        }
+        self.ns_ctx
+    }
+
+    // The members of an included interface become members of the including
+    // one, sharing their definitions. Included interfaces get the members of
+    // their own includes first, whatever their order in the unit.
+    fn include_members(&mut self, container: &DefId, includes: &mut HashMap<DefId, Vec<DefId>>) {
+        for included in includes.remove(container).unwrap_or_default() {
+            self.include_members(&included, includes);
+            let mut members = self.ns_ctx.members[&included]
+                .clone()
+                .into_iter()
+                .collect::<Vec<_>>();
+            members.sort_by_key(|(_, def_id)| def_id.local());
+            let container_members = self.ns_ctx.members.get_mut(container).unwrap();
+            for (name, def_id) in members {
+                if container_members.insert(name.clone(), def_id).is_some() {
+                    report_error(Error::DuplicateMember(name));
+                }
+            }
         }
     }
 
-    pub fn collect(self) -> NamespaceContext {
+    fn def_private(&mut self, def_id: DefId, owner: DefId) {
         self.ns_ctx
+            .private
+            .insert(def_id, (owner, self.item_name.clone()));
     }
 }
 
@@ -119,13 +216,73 @@ impl NamespaceManager for ResolveCollectVisitor {
 }
 
 impl AstVisitor for ResolveCollectVisitor {
-    fn visit_item(&mut self, node: &Node) {
-        let ident = node_get_attr(node, "ident");
+    // Must number definitions exactly like `AstLowering` does.
+    fn visit_def(&mut self, node: &Node) {
         let id = self.def_local_id;
         self.def_local_id = id.next();
-        self.get_namespace_context()
-            .get_namespace()
-            .def(ident.to_string(), id.to_def_id());
+        let head = node.car().as_symbol().unwrap();
+        if head == "Item" {
+            // Impls are anonymous.
+            if let Some(ident) = node_find_attr(node, "ident") {
+                self.get_namespace_context()
+                    .get_namespace()
+                    .def(ident.to_string(), id.to_def_id());
+            }
+            self.item_name = node_find_attr(node, "ident")
+                .map(|x| x.to_string())
+                .unwrap_or_default();
+            let kind = node_get_attr(node, "kind").as_keyword();
+            self.class = if kind == Some("class") {
+                self.ns_ctx.fields.insert(id.to_def_id(), HashMap::new());
+                Some(id.to_def_id())
+            } else {
+                None
+            };
+            self.container = match kind {
+                Some("interface") | Some("module") | Some("functor") => {
+                    self.ns_ctx.members.insert(id.to_def_id(), HashMap::new());
+                    Some(id.to_def_id())
+                }
+                _ => None,
+            };
+        } else if head == "Decl" || head == "Binding" {
+            if let Some(container) = &self.container {
+                let ident = node_get_attr(node, "ident").as_symbol().unwrap();
+                let members = self.ns_ctx.members.get_mut(container).unwrap();
+                if members.insert(ident.to_string(), id.to_def_id()).is_some() {
+                    report_error(Error::DuplicateMember(ident.to_string()));
+                }
+            }
+            // Declarations of interfaces are as visible as the interface.
+            // Private methods of classes are left out of the BUI, and private
+            // fields are checked against other units by type checking.
+            if let Some(container) = &self.container {
+                if head == "Binding" && !is_public(node) {
+                    self.def_private(id.to_def_id(), container.clone());
+                }
+            }
+        } else if head == "FieldDef" {
+            let ident = node_get_field(node, 0).as_symbol().unwrap();
+            let class = self.class.as_ref().unwrap();
+            self.ns_ctx
+                .fields
+                .get_mut(class)
+                .unwrap()
+                .insert(ident.to_string(), id.to_def_id());
+        }
+    }
+
+    // The included interface may come later in the unit, it is looked up by
+    // `collect`.
+    fn visit_include(&mut self, node: &Node) {
+        let iface = node_get_field(node, 0).as_cons().unwrap();
+        let segments = node_get_field(iface, 0).as_slice().unwrap();
+        let ident = segments[0].as_symbol().unwrap();
+        if let Some(container) = &self.container {
+            let namespace = self.ns_ctx.current_ns;
+            self.includes
+                .push((container.clone(), namespace, ident.to_string()));
+        }
     }
 
     fn visit_pre_namespace(&mut self, node: &Node) {
@@ -138,23 +295,226 @@ impl AstVisitor for ResolveCollectVisitor {
     }
 }
 
+struct ClosureScope {
+    node_id: NodeId,
+    // Index of the closure's own scope in `locals`, locals found below it
+    // are captured.
+    depth: usize,
+    // Loop depth outside of the closure, `break` cannot cross closures.
+    loop_depth: usize,
+}
+
 pub struct ResolvePathVisitor {
     ns_ctx: NamespaceContext,
-    locals: HashMap<String, NodeId>,
+    def_local_id: DefLocalId,
+    // Item being resolved, which can access its own private members.
+    item: Option<DefId>,
+    // Interface, module or functor whose members are in scope.
+    container: Option<DefId>,
+    generics: Vec<String>,
+    // What `Self` stands for in the current item.
+    self_ty: Option<Resolution<NodeId>>,
+    // Type of an untyped `self` receiver of the defn being entered.
+    receiver: Option<Resolution<NodeId>>,
+    // Parameters of the enclosing functor and their interfaces.
+    functor_params: HashMap<String, DefId>,
+    locals: Vec<HashMap<String, NodeId>>,
+    loop_depth: usize,
+    closures: Vec<ClosureScope>,
     resolutions: HashMap<NodeId, Resolution<NodeId>>,
+    captures: HashMap<NodeId, Vec<NodeId>>,
 }
 
 impl ResolvePathVisitor {
     pub fn new(ns_ctx: NamespaceContext) -> Self {
         ResolvePathVisitor {
             ns_ctx,
-            locals: HashMap::new(),
+            def_local_id: DefLocalId::new(),
+            item: None,
+            container: None,
+            generics: vec![],
+            self_ty: None,
+            receiver: None,
+            functor_params: HashMap::new(),
+            locals: vec![HashMap::new()],
+            loop_depth: 0,
+            closures: vec![],
             resolutions: HashMap::new(),
+            captures: HashMap::new(),
+        }
+    }
+
+    fn enter_closure(&mut self, node: &Node) {
+        self.locals.push(HashMap::new());
+        self.closures.push(ClosureScope {
+            node_id: NodeId::new(node),
+            depth: self.locals.len() - 1,
+            loop_depth: self.loop_depth,
+        });
+        self.loop_depth = 0;
+    }
+
+    fn exit_closure(&mut self) {
+        let closure = self.closures.pop().unwrap();
+        self.loop_depth = closure.loop_depth;
+        self.locals.pop();
+    }
+
+    // Records `local` as captured by every closure between its definition
+    // and the current scope.
+    fn capture(&mut self, depth: usize, local: NodeId) {
+        for closure in self.closures.iter().rev().take_while(|c| c.depth > depth) {
+            let captures = self.captures.entry(closure.node_id).or_default();
+            if !captures.contains(&local) {
+                captures.push(local);
+            }
         }
     }
 
-    pub fn resolve(self) -> HashMap<NodeId, Resolution<NodeId>> {
+    fn def_local(&mut self, node: &Node) {
+        let ident = node_get_field(node, 0);
+        self.locals
+            .last_mut()
+            .unwrap()
+            .insert(ident.as_symbol().unwrap().to_string(), NodeId::new(node));
+    }
+
+    fn resolve_struct(&mut self, node: &Node) {
+        let fields = node_get_fields(node);
+        let segments = fields[0].as_slice().unwrap();
+        if segments.len() > 1 {
+            panic!("qualified path is not yet implemented.")
+        }
+        let ident = segments[0].as_symbol().unwrap();
+        let def_id = match self.get_namespace_context().get_namespace().lookup(ident) {
+            Some(def_id) => def_id,
+            None => {
+                report_error(Error::UndefinedName(ident.to_string()));
+                return;
+            }
+        };
+        let class_fields = match self.ns_ctx.fields.get(&def_id) {
+            Some(class_fields) => class_fields,
+            None => {
+                report_error(Error::NotAClass(ident.to_string()));
+                return;
+            }
+        };
+        for field in fields.iter().skip(1) {
+            let field = field.as_cons().unwrap();
+            let name = node_get_field(field, 0).as_symbol().unwrap();
+            match class_fields.get(name) {
+                Some(field_def_id) => {
+                    self.resolutions
+                        .insert(NodeId::new(field), Resolution::Def(field_def_id.clone()));
+                }
+                None => report_error(Error::UnknownField(name.to_string(), ident.to_string())),
+            }
+        }
         self.resolutions
+            .insert(NodeId::new(node), Resolution::Def(def_id));
+    }
+
+    // Private members can only be used inside of the item defining them.
+    fn check_access(&self, def_id: &DefId, name: &str) {
+        if let Some((owner, owner_name)) = self.ns_ctx.private.get(def_id) {
+            if self.item.as_ref() != Some(owner) {
+                report_error(Error::PrivateMember(name.to_string(), owner_name.clone()));
+            }
+        }
+    }
+
+    // Members of the enclosing container shadow the items of the namespace.
+    fn lookup_def(&mut self, ident: &str) -> Option<DefId> {
+        let member = self
+            .container
+            .as_ref()
+            .and_then(|container| self.ns_ctx.members.get(container))
+            .and_then(|members| members.get(ident))
+            .cloned();
+        member.or_else(|| self.get_namespace_context().get_namespace().lookup(ident))
+    }
+
+    fn resolve_ty_ident(&mut self, ident: &str) -> Option<Resolution<NodeId>> {
+        if let Some(index) = self.generics.iter().position(|x| x == ident) {
+            return Some(Resolution::Param(index));
+        }
+        if ident == "Self" {
+            return self.self_ty.clone();
+        }
+        if let Some(def_id) = self.lookup_def(ident) {
+            return Some(Resolution::Def(def_id));
+        }
+        PrimTy::from_ident(ident).map(Resolution::Prim)
+    }
+
+    fn resolve_item_ref(&mut self, node: &Node) {
+        let segments = node_get_field(node, 0).as_slice().unwrap();
+        if segments.len() > 1 {
+            panic!("qualified path is not yet implemented.")
+        }
+        let ident = segments[0].as_symbol().unwrap();
+        match self.get_namespace_context().get_namespace().lookup(ident) {
+            Some(def_id) => {
+                self.resolutions
+                    .insert(NodeId::new(node), Resolution::Def(def_id));
+            }
+            None => report_error(Error::UndefinedName(ident.to_string())),
+        }
+    }
+
+    // `A::m` names the member `m` of the interface, module or functor `A`,
+    // or of the interface of the functor parameter `A`.
+    fn resolve_member(&mut self, segments: &[Value]) -> Option<Resolution<NodeId>> {
+        if segments.len() > 2 {
+            panic!("qualified path is not yet implemented.")
+        }
+        let outer = segments[0].as_symbol().unwrap();
+        let member = segments[1].as_symbol().unwrap();
+        let param = self.functor_params.get(outer).cloned();
+        let container = match &param {
+            Some(def_id) => def_id.clone(),
+            None => self.get_namespace_context().get_namespace().lookup(outer)?,
+        };
+        let def_id = self
+            .ns_ctx
+            .members
+            .get(&container)
+            .and_then(|members| members.get(member))
+            .cloned()?;
+        self.check_access(&def_id, member);
+        Some(match param {
+            Some(iface) => Resolution::Member(iface, def_id),
+            None => Resolution::Def(def_id),
+        })
+    }
+
+    fn resolve_qualified(&mut self, node: &Node, segments: &[Value]) {
+        match self.resolve_member(segments) {
+            Some(res) => {
+                self.resolutions.insert(NodeId::new(node), res);
+            }
+            None => {
+                let path = segments
+                    .iter()
+                    .map(|x| x.as_symbol().unwrap())
+                    .collect::<Vec<_>>();
+                report_error(Error::UndefinedName(path.join("::")));
+            }
+        }
+    }
+
+    // Returns the local together with the index of its scope.
+    fn lookup_local(&self, ident: &str) -> Option<(usize, NodeId)> {
+        self.locals
+            .iter()
+            .enumerate()
+            .rev()
+            .find_map(|(depth, scope)| scope.get(ident).map(|x| (depth, *x)))
+    }
+
+    pub fn resolve(self) -> (HashMap<NodeId, Resolution<NodeId>>, HashMap<NodeId, Vec<NodeId>>) {
+        (self.resolutions, self.captures)
     }
 }
 
@@ -165,29 +525,124 @@ impl NamespaceManager for ResolvePathVisitor {
 }
 
 impl AstVisitor for ResolvePathVisitor {
+    // Generic parameters are visible in the signature and body of their
+    // defn, `Self` in the whole trait or impl.
+    fn visit_def(&mut self, node: &Node) {
+        let id = self.def_local_id;
+        self.def_local_id = id.next();
+        let head = node.car().as_symbol().unwrap();
+        if head == "Item" {
+            self.item = Some(id.to_def_id());
+            self.container = Some(id.to_def_id()).filter(|x| self.ns_ctx.members.contains_key(x));
+            self.functor_params.clear();
+            if node_get_attr(node, "kind").as_keyword() == Some("functor") {
+                for param in node_get_field(node, 0).as_slice().unwrap() {
+                    let param = param.as_cons().unwrap();
+                    let iface = node_get_field(param, 1).as_cons().unwrap();
+                    let segments = node_get_field(iface, 0).as_slice().unwrap();
+                    let ident = segments[0].as_symbol().unwrap();
+                    if let Some(def_id) = self.get_namespace_context().get_namespace().lookup(ident)
+                    {
+                        let name = node_get_field(param, 0).as_symbol().unwrap();
+                        self.functor_params.insert(name.to_string(), def_id);
+                    }
+                }
+            }
+            self.self_ty = match node_get_attr(node, "kind").as_keyword() {
+                Some("trait") => Some(Resolution::SelfTy),
+                Some("class") => {
+                    self.resolve_ty_ident(node_get_attr(node, "ident").as_symbol().unwrap())
+                }
+                Some("impl") => {
+                    let ty = node_get_field(node, 1).as_cons().unwrap();
+                    let segments = node_get_field(ty, 0).as_slice().unwrap();
+                    self.resolve_ty_ident(segments[0].as_symbol().unwrap())
+                }
+                _ => None,
+            };
+        }
+        if head == "Item" || head == "Binding" || head == "Decl" {
+            // Methods of a class, impl or trait may omit the type of `self`.
+            self.receiver = match head {
+                "Binding" | "Decl" => self.self_ty.clone(),
+                _ => None,
+            };
+            self.generics = if node_get_attr(node, "kind").as_keyword() == Some("defn") {
+                node_get_fields(node_get_field(node, 0).as_cons().unwrap())
+                    .iter()
+                    .map(|x| node_get_field(x.as_cons().unwrap(), 0).to_string())
+                    .collect()
+            } else {
+                vec![]
+            };
+        }
+    }
+
+    fn visit_item_ref(&mut self, node: &Node) {
+        self.resolve_item_ref(node);
+    }
+
     fn visit_param(&mut self, node: &Node) {
-        let ident = node_get_field(node, 0);
-        self.locals
-            .insert(ident.as_symbol().unwrap().to_string(), NodeId::new(node));
+        if let Some(res) = self.receiver.take() {
+            let is_self = node_get_field(node, 0).as_symbol() == Some("self");
+            if is_self && node_get_fields(node).len() == 1 {
+                self.resolutions.insert(NodeId::new(node), res);
+            }
+        }
+        self.def_local(node);
+    }
+
+    fn visit_local(&mut self, node: &Node) {
+        self.def_local(node);
+    }
+
+    fn visit_pre_scope(&mut self, _: &Node) {
+        self.locals.push(HashMap::new());
+    }
+
+    fn visit_post_scope(&mut self, _: &Node) {
+        self.locals.pop();
+    }
+
+    fn visit_post_expr(&mut self, node: &Node) {
+        let kind = node_get_attr(node, "kind").as_keyword().unwrap();
+        if kind == "loop" || kind == "while" {
+            self.loop_depth -= 1;
+        } else if kind == "closure" {
+            self.exit_closure();
+        }
     }
 
     fn visit_expr(&mut self, node: &Node) {
-        let kind = node_get_attr(node, "kind");
-        if kind.as_keyword().unwrap() != "path" {
+        let kind = node_get_attr(node, "kind").as_keyword().unwrap();
+        if kind == "loop" || kind == "while" {
+            self.loop_depth += 1;
+        } else if kind == "closure" {
+            self.enter_closure(node);
+        } else if (kind == "break" || kind == "continue") && self.loop_depth == 0 {
+            report_error(Error::OutsideOfLoop(kind.to_string()));
+        }
+        if kind == "struct" {
+            self.resolve_struct(node);
+            return;
+        }
+        if kind != "path" {
             return;
         }
 
         let segments = node_get_field(node, 0).as_slice().unwrap();
         if segments.len() > 1 {
-            panic!("qualified path is not yet implemented.")
+            self.resolve_qualified(node, segments);
+            return;
         }
         let ident = segments[0].as_symbol().unwrap();
-        if let Some(node_id) = self.locals.get(ident) {
+        if let Some((depth, node_id)) = self.lookup_local(ident) {
+            self.capture(depth, node_id);
             self.resolutions
-                .insert(NodeId::new(node), Resolution::Local(*node_id));
+                .insert(NodeId::new(node), Resolution::Local(node_id));
             return;
         }
-        if let Some(def_id) = self.get_namespace_context().get_namespace().lookup(ident) {
+        if let Some(def_id) = self.lookup_def(ident) {
             self.resolutions
                 .insert(NodeId::new(node), Resolution::Def(def_id));
         } else {
@@ -203,14 +658,15 @@ impl AstVisitor for ResolvePathVisitor {
 
         let segments = node_get_field(node, 0).as_slice().unwrap();
         if segments.len() > 1 {
-            panic!("qualified path is not yet implemented.")
+            self.resolve_qualified(node, segments);
+            return;
         }
         let ident = segments[0].as_symbol().unwrap();
-        if let Some(def_id) = self.get_namespace_context().get_namespace().lookup(ident) {
-            self.resolutions
-                .insert(NodeId::new(node), Resolution::Def(def_id));
-        } else {
-            report_error(Error::UndefinedName(ident.to_string()));
+        match self.resolve_ty_ident(ident) {
+            Some(res) => {
+                self.resolutions.insert(NodeId::new(node), res);
+            }
+            None => report_error(Error::UndefinedName(ident.to_string())),
         }
     }
 
@@ -226,6 +682,7 @@ impl AstVisitor for ResolvePathVisitor {
 
 pub struct LoweringContext {
     resolutions: HashMap<NodeId, Resolution<NodeId>>,
+    captures: HashMap<NodeId, Vec<NodeId>>,
     node_to_hil: HashMap<NodeId, HilId>,
 }
 
@@ -234,16 +691,46 @@ impl LoweringContext {
         *self
             .node_to_hil
             .entry(node_id)
-            .or_insert_with(|| HilId::new())
+            .or_default()
     }
-    pub fn new(resolutions: HashMap<NodeId, Resolution<NodeId>>) -> Self {
+    pub fn new(
+        resolutions: HashMap<NodeId, Resolution<NodeId>>,
+        captures: HashMap<NodeId, Vec<NodeId>>,
+    ) -> Self {
         LoweringContext {
             resolutions,
+            captures,
             node_to_hil: HashMap::new(),
         }
     }
 }
 
+// Attributes of a resolved path, `name` being its last segment.
+fn lower_resolution(res: &Resolution<NodeId>, name: &Value, ctx: &mut LoweringContext) -> Value {
+    let resolved = |k, r| sexp!((#:kind #:qpath (Path #:kind ,k ,r)));
+    match res {
+        Resolution::Def(def_id) => resolved(Value::keyword("def"), def_id.to_s_expr()),
+        Resolution::Member(iface, def_id) => {
+            let def_id = def_id.to_s_expr();
+            let iface = iface.to_s_expr();
+            sexp!((#:kind #:qpath (Path #:kind #:def ,def_id #:via ,iface)))
+        }
+        Resolution::Local(node_id) => {
+            resolved(Value::keyword("local"), ctx.hil_id(*node_id).to_s_expr())
+        }
+        Resolution::Prim(prim) => {
+            let kind = Value::keyword(prim.keyword());
+            sexp!((#:kind ,kind))
+        }
+        Resolution::Param(index) => {
+            let index = u32::try_from(*index).unwrap();
+            let name = name.clone();
+            sexp!((#:kind #:param #:index ,index ,name))
+        }
+        Resolution::SelfTy => sexp!((#:kind #:self)),
+    }
+}
+
 struct AstLowering {
     def_local_id: DefLocalId,
 }
@@ -255,12 +742,7 @@ impl AstLowering {
                 let (car, cdr) = c.as_pair();
 
                 let head = car.as_symbol().unwrap().to_string();
-                let def_local_id = if head == "Item"
-                    || head == "Decl"
-                    || head == "Binding"
-                    || head == "Variant"
-                    || head == "FieldDef"
-                {
+                let def_local_id = if is_def(&head) {
                     let d = Some(self.def_local_id);
                     self.def_local_id = self.def_local_id.next();
                     d
@@ -270,16 +752,51 @@ impl AstLowering {
                 let mut hil = if (head == "Expr" || head == "Ty")
                     && node_get_attr(c, "kind").as_keyword() == Some("path")
                 {
-                    let resolved =
-                        |k, r| Cons::new(car.clone(), sexp!((#:kind #:qpath (Path #:kind ,k ,r))));
-                    match ctx.resolutions.get(&NodeId::new(c)).unwrap() {
-                        Resolution::Def(def_id) => {
-                            resolved(Value::keyword("def"), def_id.to_s_expr())
-                        }
-                        Resolution::Local(node_id) => {
-                            resolved(Value::keyword("local"), ctx.hil_id(*node_id).to_s_expr())
+                    let res = ctx.resolutions.get(&NodeId::new(c)).unwrap().clone();
+                    let name = &node_get_field(c, 0).as_slice().unwrap()[0];
+                    Cons::new(car.clone(), lower_resolution(&res, name, ctx))
+                } else if head == "Param" && ctx.resolutions.contains_key(&NodeId::new(c)) {
+                    // The receiver of a method gets the type of `Self`.
+                    let res = ctx.resolutions.get(&NodeId::new(c)).unwrap().clone();
+                    let ty = Cons::new(
+                        Value::symbol("Ty"),
+                        lower_resolution(&res, &Value::symbol("Self"), ctx),
+                    );
+                    let ty = node_add_attr(ty, "hil_id", HilId::new().to_s_expr());
+                    Cons::new(
+                        car.clone(),
+                        Value::list(
+                            cdr.list_iter()
+                                .unwrap()
+                                .map(|x| self.run(x, ctx))
+                                .chain(std::iter::once(Value::Cons(ty)))
+                                .collect::<Vec<Hil>>(),
+                        ),
+                    )
+                } else if is_item_ref(&head)
+                    || (head == "Expr" && node_get_attr(c, "kind").as_keyword() == Some("struct"))
+                {
+                    // Paths which did not resolve were reported, they are
+                    // left as they are.
+                    let path = match ctx.resolutions.get(&NodeId::new(c)) {
+                        Some(Resolution::Def(def_id)) => {
+                            let def_id = def_id.to_s_expr();
+                            Some(sexp!((Path #:kind #:def ,def_id)))
                         }
-                    }
+                        _ => None,
+                    };
+                    Cons::new(
+                        car.clone(),
+                        Value::list(
+                            cdr.list_iter()
+                                .unwrap()
+                                .map(|x| match (x, &path) {
+                                    (Value::Vector(_), Some(path)) => path.clone(),
+                                    (x, _) => self.run(x, ctx),
+                                })
+                                .collect::<Vec<Hil>>(),
+                        ),
+                    )
                 } else {
                     Cons::new(
                         self.run(car, ctx),
@@ -292,8 +809,24 @@ impl AstLowering {
                     )
                 };
 
-                if def_local_id.is_some() {
-                    hil = node_add_attr(hil, "def_id", def_local_id.unwrap().to_s_expr());
+                if head == "Expr" && node_get_attr(c, "kind").as_keyword() == Some("closure") {
+                    let captures = ctx
+                        .captures
+                        .get(&NodeId::new(c))
+                        .cloned()
+                        .unwrap_or_default()
+                        .into_iter()
+                        .map(|x| ctx.hil_id(x).to_s_expr())
+                        .collect::<Vec<_>>();
+                    hil = node_add_attr(hil, "captures", Value::vector(captures));
+                }
+                if head == "Field" {
+                    if let Some(Resolution::Def(def_id)) = ctx.resolutions.get(&NodeId::new(c)) {
+                        hil = node_add_attr(hil, "field", def_id.to_s_expr());
+                    }
+                }
+                if let Some(def_local_id) = def_local_id {
+                    hil = node_add_attr(hil, "def_id", def_local_id.to_s_expr());
                 }
                 if head != "Item" && head != "Unit" {
                     hil = node_add_attr(hil, "hil_id", ctx.hil_id(NodeId::new(c)).to_s_expr());
@@ -1,19 +1,34 @@
+use std::collections::HashMap;
+
 use capnp::message::{ReaderOptions, TypedBuilder, TypedReader};
 use capnp::serialize::{read_message, write_message_to_words, OwnedSegments};
 
 use crate::bui_capnp;
+use crate::def::DefId;
 
 pub struct BuiMessage {
     buffer: Vec<u8>,
+    // Numbers the BUI refers to the items of the unit by.
+    numbers: HashMap<DefId, u32>,
 }
 
 impl BuiMessage {
-    pub fn new(builder: &TypedBuilder<bui_capnp::unit::Owned>) -> Self {
+    pub fn new(
+        builder: &TypedBuilder<bui_capnp::unit::Owned>,
+        numbers: HashMap<DefId, u32>,
+    ) -> Self {
         BuiMessage {
             buffer: write_message_to_words(builder.borrow_inner()),
+            numbers,
         }
     }
 
+    // Lets the TIL of the unit be renumbered like its BUI, see
+    // `til::import::renumber_til`.
+    pub fn numbers(&self) -> &HashMap<DefId, u32> {
+        &self.numbers
+    }
+
     pub fn deserialize(&self) -> Bui {
         let reader = read_message(self.buffer.as_slice(), ReaderOptions::new()).unwrap();
         Bui {
@@ -28,10 +43,8 @@ pub struct BuiKind<'a> {
 
 impl<'a> BuiKind<'a> {
     pub fn is_ty(&self) -> bool {
-        if let Ok(ok) = self.reader.which() {
-            if let bui_capnp::kind::Ty(_) = ok {
-                return true;
-            }
+        if let Ok(bui_capnp::kind::Ty(_)) = self.reader.which() {
+            return true;
         }
         false
     }
@@ -42,11 +55,9 @@ pub struct BuiTyDecl<'a> {
 }
 
 impl<'a> BuiTyDecl<'a> {
-    pub fn transparent(&self) -> Option<BuiTy> {
-        if let Ok(ok) = self.reader.which() {
-            if let bui_capnp::ty_decl::Transparent(t) = ok {
-                return Some(BuiTy { reader: t.unwrap() });
-            }
+    pub fn transparent(&self) -> Option<BuiTy<'_>> {
+        if let Ok(bui_capnp::ty_decl::Transparent(t)) = self.reader.which() {
+            return Some(BuiTy { reader: t.unwrap() });
         }
         None
     }
@@ -57,7 +68,7 @@ pub struct BuiInterface<'a> {
 }
 
 impl<'a> BuiInterface<'a> {
-    pub fn types(&self) -> Vec<(&str, BuiTyDecl)> {
+    pub fn types(&self) -> Vec<(&str, BuiTyDecl<'_>)> {
         self.reader
             .get_types()
             .unwrap()
@@ -73,7 +84,7 @@ impl<'a> BuiInterface<'a> {
             .collect::<Vec<_>>()
     }
 
-    pub fn defns(&self) -> Vec<(&str, BuiDefn)> {
+    pub fn defns(&self) -> Vec<(&str, BuiDefn<'_>)> {
         self.reader
             .get_defns()
             .unwrap()
@@ -88,6 +99,24 @@ impl<'a> BuiInterface<'a> {
             })
             .collect::<Vec<_>>()
     }
+
+    pub fn type_defs(&self) -> Vec<BuiItemRef<'_>> {
+        self.reader
+            .get_type_defs()
+            .unwrap()
+            .iter()
+            .map(|x| BuiItemRef { reader: x })
+            .collect::<Vec<_>>()
+    }
+
+    pub fn defn_defs(&self) -> Vec<BuiItemRef<'_>> {
+        self.reader
+            .get_defn_defs()
+            .unwrap()
+            .iter()
+            .map(|x| BuiItemRef { reader: x })
+            .collect::<Vec<_>>()
+    }
 }
 
 pub struct BuiFieldDef<'a> {
@@ -98,6 +127,20 @@ impl<'a> BuiFieldDef<'a> {
     pub fn name(&self) -> &str {
         self.reader.get_name().unwrap()
     }
+
+    pub fn ty(&self) -> BuiTy<'_> {
+        BuiTy {
+            reader: self.reader.get_type().unwrap(),
+        }
+    }
+
+    pub fn is_public(&self) -> bool {
+        self.reader.get_public()
+    }
+
+    pub fn def(&self) -> u32 {
+        self.reader.get_def()
+    }
 }
 
 pub struct BuiClass<'a> {
@@ -105,7 +148,7 @@ pub struct BuiClass<'a> {
 }
 
 impl<'a> BuiClass<'a> {
-    pub fn fields(&self) -> Vec<BuiFieldDef> {
+    pub fn fields(&self) -> Vec<BuiFieldDef<'_>> {
         self.reader
             .get_fields()
             .unwrap()
@@ -113,6 +156,26 @@ impl<'a> BuiClass<'a> {
             .map(|x| BuiFieldDef { reader: x })
             .collect::<Vec<_>>()
     }
+
+    pub fn methods(&self) -> Vec<(&str, BuiDefn<'_>)> {
+        self.reader
+            .get_methods()
+            .unwrap()
+            .iter()
+            .map(|x| {
+                (
+                    x.get_key().unwrap(),
+                    BuiDefn {
+                        reader: x.get_value().unwrap(),
+                    },
+                )
+            })
+            .collect::<Vec<_>>()
+    }
+
+    pub fn method_defs(&self) -> Vec<u32> {
+        self.reader.get_method_defs().unwrap().iter().collect()
+    }
 }
 
 pub struct BuiItemRef<'a> {
@@ -135,33 +198,48 @@ pub struct BuiTy<'a> {
 
 impl<'a> BuiTy<'a> {
     pub fn is_bool(&self) -> bool {
-        if let Ok(ok) = self.reader.which() {
-            if let bui_capnp::ty::Bool(_) = ok {
-                return true;
-            }
+        if let Ok(bui_capnp::ty::Bool(_)) = self.reader.which() {
+            return true;
         }
         false
     }
 
-    pub fn adt(&self) -> Option<BuiItemRef> {
-        if let Ok(ok) = self.reader.which() {
-            if let bui_capnp::ty::Adt(a) = ok {
-                return Some(BuiItemRef { reader: a.unwrap() });
-            }
+    pub fn is_int(&self) -> bool {
+        if let Ok(bui_capnp::ty::Int(_)) = self.reader.which() {
+            return true;
+        }
+        false
+    }
+
+    pub fn adt(&self) -> Option<BuiItemRef<'_>> {
+        if let Ok(bui_capnp::ty::Adt(a)) = self.reader.which() {
+            return Some(BuiItemRef { reader: a.unwrap() });
+        }
+        None
+    }
+
+    pub fn fn_decl(&self) -> Option<BuiFnDecl<'_>> {
+        if let Ok(bui_capnp::ty::Fn(d)) = self.reader.which() {
+            return Some(BuiFnDecl { reader: d.unwrap() });
+        }
+        None
+    }
+
+    pub fn param(&self) -> Option<u32> {
+        if let Ok(bui_capnp::ty::Param(p)) = self.reader.which() {
+            return Some(p);
         }
         None
     }
 
-    pub fn tuple(&self) -> Option<Vec<BuiTy>> {
-        if let Ok(ok) = self.reader.which() {
-            if let bui_capnp::ty::Tuple(t) = ok {
-                return Some(
-                    t.unwrap()
-                        .iter()
-                        .map(|x| BuiTy { reader: x })
-                        .collect::<Vec<_>>(),
-                );
-            }
+    pub fn tuple(&self) -> Option<Vec<BuiTy<'_>>> {
+        if let Ok(bui_capnp::ty::Tuple(t)) = self.reader.which() {
+            return Some(
+                t.unwrap()
+                    .iter()
+                    .map(|x| BuiTy { reader: x })
+                    .collect::<Vec<_>>(),
+            );
         }
         None
     }
@@ -172,7 +250,7 @@ pub struct BuiFnDecl<'a> {
 }
 
 impl<'a> BuiFnDecl<'a> {
-    pub fn inputs(&self) -> Vec<BuiTy> {
+    pub fn inputs(&self) -> Vec<BuiTy<'_>> {
         self.reader
             .get_inputs()
             .unwrap()
@@ -180,6 +258,12 @@ impl<'a> BuiFnDecl<'a> {
             .map(|x| BuiTy { reader: x })
             .collect::<Vec<_>>()
     }
+
+    pub fn output(&self) -> BuiTy<'_> {
+        BuiTy {
+            reader: self.reader.get_output().unwrap(),
+        }
+    }
 }
 
 pub struct BuiFnSig<'a> {
@@ -187,7 +271,7 @@ pub struct BuiFnSig<'a> {
 }
 
 impl<'a> BuiFnSig<'a> {
-    pub fn decl(&self) -> BuiFnDecl {
+    pub fn decl(&self) -> BuiFnDecl<'_> {
         BuiFnDecl {
             reader: self.reader.get_decl().unwrap(),
         }
@@ -199,11 +283,107 @@ pub struct BuiDefn<'a> {
 }
 
 impl<'a> BuiDefn<'a> {
-    pub fn fn_sig(&self) -> BuiFnSig {
+    pub fn fn_sig(&self) -> BuiFnSig<'_> {
         BuiFnSig {
             reader: self.reader.get_fn_sig().unwrap(),
         }
     }
+
+    pub fn generics(&self) -> Vec<BuiGenericParam<'_>> {
+        self.reader
+            .get_generics()
+            .unwrap()
+            .iter()
+            .map(|x| BuiGenericParam { reader: x })
+            .collect::<Vec<_>>()
+    }
+
+    // Read by `til::import`, which knows the layout of the TIL.
+    pub fn inline_body(&self) -> Option<bui_capnp::til_body::Reader<'_>> {
+        if !self.reader.has_inline() {
+            return None;
+        }
+        Some(self.reader.get_inline().unwrap())
+    }
+}
+
+pub struct BuiGenericParam<'a> {
+    reader: bui_capnp::generic_param::Reader<'a>,
+}
+
+impl<'a> BuiGenericParam<'a> {
+    pub fn name(&self) -> &str {
+        self.reader.get_name().unwrap()
+    }
+
+    pub fn bounds(&self) -> Vec<BuiItemRef<'_>> {
+        self.reader
+            .get_bounds()
+            .unwrap()
+            .iter()
+            .map(|x| BuiItemRef { reader: x })
+            .collect::<Vec<_>>()
+    }
+}
+
+pub struct BuiTrait<'a> {
+    reader: bui_capnp::trait_def::Reader<'a>,
+}
+
+impl<'a> BuiTrait<'a> {
+    pub fn methods(&self) -> Vec<(&str, BuiDefn<'_>)> {
+        self.reader
+            .get_methods()
+            .unwrap()
+            .iter()
+            .map(|x| {
+                (
+                    x.get_key().unwrap(),
+                    BuiDefn {
+                        reader: x.get_value().unwrap(),
+                    },
+                )
+            })
+            .collect::<Vec<_>>()
+    }
+
+    pub fn method_defs(&self) -> Vec<u32> {
+        self.reader.get_method_defs().unwrap().iter().collect()
+    }
+}
+
+pub struct BuiImpl<'a> {
+    reader: bui_capnp::impl_def::Reader<'a>,
+}
+
+impl<'a> BuiImpl<'a> {
+    pub fn trait_ref(&self) -> BuiItemRef<'_> {
+        BuiItemRef {
+            reader: self.reader.get_trait().unwrap(),
+        }
+    }
+
+    pub fn self_ty(&self) -> BuiTy<'_> {
+        BuiTy {
+            reader: self.reader.get_self_ty().unwrap(),
+        }
+    }
+
+    pub fn methods(&self) -> Vec<(&str, BuiItemRef<'_>)> {
+        self.reader
+            .get_methods()
+            .unwrap()
+            .iter()
+            .map(|x| {
+                (
+                    x.get_key().unwrap(),
+                    BuiItemRef {
+                        reader: x.get_value().unwrap(),
+                    },
+                )
+            })
+            .collect::<Vec<_>>()
+    }
 }
 
 pub struct BuiModule<'a> {
@@ -211,7 +391,7 @@ pub struct BuiModule<'a> {
 }
 
 impl<'a> BuiModule<'a> {
-    pub fn types(&self) -> Vec<(&str, BuiTy)> {
+    pub fn types(&self) -> Vec<(&str, BuiTy<'_>)> {
         self.reader
             .get_types()
             .unwrap()
@@ -227,7 +407,7 @@ impl<'a> BuiModule<'a> {
             .collect::<Vec<_>>()
     }
 
-    pub fn defns(&self) -> Vec<(&str, BuiDefn)> {
+    pub fn defns(&self) -> Vec<(&str, BuiDefn<'_>)> {
         self.reader
             .get_defns()
             .unwrap()
@@ -244,6 +424,103 @@ impl<'a> BuiModule<'a> {
     }
 }
 
+pub struct BuiFunctor<'a> {
+    reader: bui_capnp::functor::Reader<'a>,
+}
+
+impl<'a> BuiFunctor<'a> {
+    pub fn params(&self) -> Vec<(&str, BuiItemRef<'_>)> {
+        self.reader
+            .get_params()
+            .unwrap()
+            .iter()
+            .map(|x| {
+                (
+                    x.get_key().unwrap(),
+                    BuiItemRef {
+                        reader: x.get_value().unwrap(),
+                    },
+                )
+            })
+            .collect::<Vec<_>>()
+    }
+
+    pub fn body(&self) -> BuiModule<'_> {
+        BuiModule {
+            reader: self.reader.get_body().unwrap(),
+        }
+    }
+}
+
+pub struct BuiConstValue<'a> {
+    reader: bui_capnp::const_value::Reader<'a>,
+}
+
+impl<'a> BuiConstValue<'a> {
+    pub fn bool(&self) -> Option<bool> {
+        if let Ok(bui_capnp::const_value::Bool(b)) = self.reader.which() {
+            return Some(b);
+        }
+        None
+    }
+
+    pub fn int(&self) -> Option<i64> {
+        if let Ok(bui_capnp::const_value::Int(i)) = self.reader.which() {
+            return Some(i);
+        }
+        None
+    }
+
+    pub fn tuple(&self) -> Option<Vec<BuiConstValue<'_>>> {
+        if let Ok(bui_capnp::const_value::Tuple(t)) = self.reader.which() {
+            return Some(
+                t.unwrap()
+                    .iter()
+                    .map(|x| BuiConstValue { reader: x })
+                    .collect::<Vec<_>>(),
+            );
+        }
+        None
+    }
+
+    pub fn adt(&self) -> Option<(BuiItemRef<'_>, Vec<BuiConstValue<'_>>)> {
+        if let Ok(bui_capnp::const_value::Adt(a)) = self.reader.which() {
+            let a = a.unwrap();
+            let fields = a
+                .get_fields()
+                .unwrap()
+                .iter()
+                .map(|x| BuiConstValue { reader: x })
+                .collect::<Vec<_>>();
+            return Some((BuiItemRef { reader: a.get_adt().unwrap() }, fields));
+        }
+        None
+    }
+}
+
+pub struct BuiConst<'a> {
+    reader: bui_capnp::const_::Reader<'a>,
+}
+
+impl<'a> BuiConst<'a> {
+    pub fn ty(&self) -> BuiTy<'_> {
+        BuiTy {
+            reader: self.reader.get_ty().unwrap(),
+        }
+    }
+
+    pub fn value(&self) -> BuiConstValue<'_> {
+        BuiConstValue {
+            reader: self.reader.get_value().unwrap(),
+        }
+    }
+
+    // Consts whose initializer failed to evaluate have no value.
+    pub fn has_value(&self) -> bool {
+        self.reader.has_value()
+    }
+}
+
 pub struct BuiItem<'a> {
     reader: bui_capnp::item::Reader<'a>,
 }
@@ -252,6 +529,9 @@ impl<'a> BuiItem<'a> {
     pub fn ident(&self) -> &str {
         self.reader.get_ident().unwrap()
     }
+    pub fn def(&self) -> u32 {
+        self.reader.get_def()
+    }
     pub fn namespace(&self) -> Vec<&str> {
         self.reader
             .get_namespace()
@@ -260,35 +540,59 @@ impl<'a> BuiItem<'a> {
             .map(|x| x.unwrap())
             .collect::<Vec<_>>()
     }
-    pub fn interface(&self) -> Option<BuiInterface> {
-        if let Ok(ok) = self.reader.get_kind().which() {
-            if let bui_capnp::item::kind::Interface(i) = ok {
-                return Some(BuiInterface { reader: i.unwrap() });
-            }
+    pub fn interface(&self) -> Option<BuiInterface<'_>> {
+        if let Ok(bui_capnp::item::kind::Interface(i)) = self.reader.get_kind().which() {
+            return Some(BuiInterface { reader: i.unwrap() });
+        }
+        None
+    }
+    pub fn class(&self) -> Option<BuiClass<'_>> {
+        if let Ok(bui_capnp::item::kind::Class(c)) = self.reader.get_kind().which() {
+            return Some(BuiClass { reader: c.unwrap() });
+        }
+        None
+    }
+    pub fn defn(&self) -> Option<BuiDefn<'_>> {
+        if let Ok(bui_capnp::item::kind::Defn(d)) = self.reader.get_kind().which() {
+            return Some(BuiDefn { reader: d.unwrap() });
         }
         None
     }
-    pub fn class(&self) -> Option<BuiClass> {
-        if let Ok(ok) = self.reader.get_kind().which() {
-            if let bui_capnp::item::kind::Class(c) = ok {
-                return Some(BuiClass { reader: c.unwrap() });
-            }
+    pub fn module(&self) -> Option<BuiModule<'_>> {
+        if let Ok(bui_capnp::item::kind::Module(m)) = self.reader.get_kind().which() {
+            return Some(BuiModule { reader: m.unwrap() });
         }
         None
     }
-    pub fn defn(&self) -> Option<BuiDefn> {
-        if let Ok(ok) = self.reader.get_kind().which() {
-            if let bui_capnp::item::kind::Defn(d) = ok {
-                return Some(BuiDefn { reader: d.unwrap() });
-            }
+    pub fn functor(&self) -> Option<BuiFunctor<'_>> {
+        if let Ok(bui_capnp::item::kind::Functor(f)) = self.reader.get_kind().which() {
+            return Some(BuiFunctor { reader: f.unwrap() });
         }
         None
     }
-    pub fn module(&self) -> Option<BuiModule> {
-        if let Ok(ok) = self.reader.get_kind().which() {
-            if let bui_capnp::item::kind::Module(m) = ok {
-                return Some(BuiModule { reader: m.unwrap() });
-            }
+    pub fn trait_def(&self) -> Option<BuiTrait<'_>> {
+        if let Ok(bui_capnp::item::kind::Trait(t)) = self.reader.get_kind().which() {
+            return Some(BuiTrait { reader: t.unwrap() });
+        }
+        None
+    }
+    pub fn impl_def(&self) -> Option<BuiImpl<'_>> {
+        if let Ok(bui_capnp::item::kind::Impl(i)) = self.reader.get_kind().which() {
+            return Some(BuiImpl { reader: i.unwrap() });
+        }
+        None
+    }
+    pub fn const_def(&self) -> Option<BuiConst<'_>> {
+        if let Ok(bui_capnp::item::kind::Const(c)) = self.reader.get_kind().which() {
+            return Some(BuiConst { reader: c.unwrap() });
+        }
+        None
+    }
+    pub fn static_ty(&self) -> Option<BuiTy<'_>> {
+        if let Ok(bui_capnp::item::kind::Static(s)) = self.reader.get_kind().which() {
+            return Some(BuiTy {
+                reader: s.unwrap().get_ty().unwrap(),
+            });
         }
         None
     }
@@ -303,7 +607,13 @@ impl Bui {
         Bui { reader: None }
     }
 
-    pub fn items(&self) -> Vec<BuiItem> {
+    // The hash of the interface of the unit, none for an empty BUI.
+    pub fn hash(&self) -> Option<&[u8]> {
+        let reader = self.reader.as_ref()?;
+        Some(reader.get().unwrap().get_hash().unwrap())
+    }
+
+    pub fn items(&self) -> Vec<BuiItem<'_>> {
         let items = self
             .reader
             .as_ref()
@@ -0,0 +1,768 @@
+pub mod vm;
+
+use std::collections::HashMap;
+use std::fmt;
+
+use capnp::message::{Builder, ReaderOptions};
+use capnp::serialize::{read_message, write_message_to_words};
+
+use crate::bytecode_capnp;
+use crate::context::GlobalContext;
+use crate::def::DefId;
+use crate::op::{BinOp, UnOp};
+use crate::t::const_eval::ConstValue;
+use crate::til::{Body, BodyTy, Local, Operand, Place, Rvalue, Statement, Terminator, Til};
+
+// Binary operators in the order of their opcodes.
+const BINARY_OPS: [BinOp; 13] = [
+    BinOp::Add,
+    BinOp::Sub,
+    BinOp::Mul,
+    BinOp::Div,
+    BinOp::Rem,
+    BinOp::Eq,
+    BinOp::Ne,
+    BinOp::Lt,
+    BinOp::Le,
+    BinOp::Gt,
+    BinOp::Ge,
+    BinOp::And,
+    BinOp::Or,
+];
+
+// Instructions of a stack machine. Locals are numbered like in TIL, and
+// jump targets are offsets in the encoded instructions of the function.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Instr {
+    // Pushes a constant of the unit.
+    Const(u32),
+    Load(u32),
+    Store(u32),
+    Dup,
+    // Replaces an aggregate by one of its fields.
+    Field(u32),
+    // Pops a value and an aggregate, and pushes the aggregate with the
+    // field replaced by the value.
+    Insert(u32),
+    Tuple(u32),
+    // Instance of a class of the unit from the given number of fields.
+    Adt(u32, u32),
+    // Pushes a function of the unit.
+    Fn(u32),
+    // Closure of a function of the unit from the given number of captures.
+    Closure(u32, u32),
+    Neg,
+    Not,
+    Binary(BinOp),
+    Jump(u32),
+    JumpIfNot(u32),
+    // Calls a function value below the given number of arguments, pushing
+    // the returned value.
+    Call(u32),
+    Return,
+    Unreachable,
+}
+
+const OP_CONST: u8 = 0;
+const OP_LOAD: u8 = 1;
+const OP_STORE: u8 = 2;
+const OP_DUP: u8 = 3;
+const OP_FIELD: u8 = 4;
+const OP_INSERT: u8 = 5;
+const OP_TUPLE: u8 = 6;
+const OP_ADT: u8 = 7;
+const OP_FN: u8 = 8;
+const OP_CLOSURE: u8 = 9;
+const OP_NEG: u8 = 10;
+const OP_NOT: u8 = 11;
+const OP_BINARY: u8 = 12;
+const OP_JUMP: u8 = OP_BINARY + BINARY_OPS.len() as u8;
+const OP_JUMP_IF_NOT: u8 = OP_JUMP + 1;
+const OP_CALL: u8 = OP_JUMP + 2;
+const OP_RETURN: u8 = OP_JUMP + 3;
+const OP_UNREACHABLE: u8 = OP_JUMP + 4;
+
+impl Instr {
+    // An opcode byte followed by the operands in little endian.
+    pub fn encode(self, code: &mut Vec<u8>) {
+        let (opcode, operands): (u8, &[u32]) = match self {
+            Instr::Const(i) => (OP_CONST, &[i]),
+            Instr::Load(local) => (OP_LOAD, &[local]),
+            Instr::Store(local) => (OP_STORE, &[local]),
+            Instr::Dup => (OP_DUP, &[]),
+            Instr::Field(i) => (OP_FIELD, &[i]),
+            Instr::Insert(i) => (OP_INSERT, &[i]),
+            Instr::Tuple(len) => (OP_TUPLE, &[len]),
+            Instr::Adt(class, len) => (OP_ADT, &[class, len]),
+            Instr::Fn(func) => (OP_FN, &[func]),
+            Instr::Closure(func, len) => (OP_CLOSURE, &[func, len]),
+            Instr::Neg => (OP_NEG, &[]),
+            Instr::Not => (OP_NOT, &[]),
+            Instr::Binary(op) => {
+                let i = BINARY_OPS.iter().position(|x| *x == op).unwrap();
+                (OP_BINARY + u8::try_from(i).unwrap(), &[])
+            }
+            Instr::Jump(target) => (OP_JUMP, &[target]),
+            Instr::JumpIfNot(target) => (OP_JUMP_IF_NOT, &[target]),
+            Instr::Call(argc) => (OP_CALL, &[argc]),
+            Instr::Return => (OP_RETURN, &[]),
+            Instr::Unreachable => (OP_UNREACHABLE, &[]),
+        };
+        code.push(opcode);
+        for operand in operands {
+            code.extend_from_slice(&operand.to_le_bytes());
+        }
+    }
+
+    // Decodes the instruction at `*pc` and advances `*pc` past it.
+    pub fn decode(code: &[u8], pc: &mut usize) -> Result<Instr, String> {
+        let opcode = *code
+            .get(*pc)
+            .ok_or_else(|| format!("Instruction offset {} is out of bounds", pc))?;
+        let start = *pc;
+        *pc += 1;
+        let mut operand = || {
+            let bytes = code
+                .get(*pc..*pc + 4)
+                .ok_or_else(|| format!("Truncated instruction at {}", start))?;
+            *pc += 4;
+            Ok::<_, String>(u32::from_le_bytes(bytes.try_into().unwrap()))
+        };
+        Ok(match opcode {
+            OP_CONST => Instr::Const(operand()?),
+            OP_LOAD => Instr::Load(operand()?),
+            OP_STORE => Instr::Store(operand()?),
+            OP_DUP => Instr::Dup,
+            OP_FIELD => Instr::Field(operand()?),
+            OP_INSERT => Instr::Insert(operand()?),
+            OP_TUPLE => Instr::Tuple(operand()?),
+            OP_ADT => Instr::Adt(operand()?, operand()?),
+            OP_FN => Instr::Fn(operand()?),
+            OP_CLOSURE => Instr::Closure(operand()?, operand()?),
+            OP_NEG => Instr::Neg,
+            OP_NOT => Instr::Not,
+            OP_JUMP => Instr::Jump(operand()?),
+            OP_JUMP_IF_NOT => Instr::JumpIfNot(operand()?),
+            OP_CALL => Instr::Call(operand()?),
+            OP_RETURN => Instr::Return,
+            OP_UNREACHABLE => Instr::Unreachable,
+            _ if (OP_BINARY..OP_JUMP).contains(&opcode) => {
+                Instr::Binary(BINARY_OPS[usize::from(opcode - OP_BINARY)])
+            }
+            _ => return Err(format!("Unknown opcode {} at {}", opcode, start)),
+        })
+    }
+}
+
+impl fmt::Display for Instr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Instr::Const(i) => write!(f, "const {}", i),
+            Instr::Load(local) => write!(f, "load _{}", local),
+            Instr::Store(local) => write!(f, "store _{}", local),
+            Instr::Dup => write!(f, "dup"),
+            Instr::Field(i) => write!(f, "field {}", i),
+            Instr::Insert(i) => write!(f, "insert {}", i),
+            Instr::Tuple(len) => write!(f, "tuple {}", len),
+            Instr::Adt(class, len) => write!(f, "adt {} {}", class, len),
+            Instr::Fn(func) => write!(f, "fn {}", func),
+            Instr::Closure(func, len) => write!(f, "closure {} {}", func, len),
+            Instr::Neg => write!(f, "neg"),
+            Instr::Not => write!(f, "not"),
+            Instr::Binary(op) => write!(f, "{}", op.keyword()),
+            Instr::Jump(target) => write!(f, "jump {}", target),
+            Instr::JumpIfNot(target) => write!(f, "jump_if_not {}", target),
+            Instr::Call(argc) => write!(f, "call {}", argc),
+            Instr::Return => write!(f, "return"),
+            Instr::Unreachable => write!(f, "unreachable"),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq)]
+pub enum Constant {
+    Bool(bool),
+    Int(i64),
+    // Tuples have no class.
+    Aggregate(Option<u32>, Vec<Constant>),
+}
+
+#[derive(Clone, PartialEq)]
+pub struct Class {
+    pub name: String,
+    pub def_id: DefId,
+}
+
+#[derive(Clone, PartialEq)]
+pub enum FunctionKind {
+    Code {
+        arg_count: u32,
+        local_count: u32,
+        instructions: Vec<u8>,
+    },
+    Extern,
+    Missing,
+}
+
+#[derive(Clone, PartialEq)]
+pub struct Function {
+    pub name: String,
+    pub public: bool,
+    // Defn lowered to the function, closures have none.
+    pub def_id: Option<DefId>,
+    pub kind: FunctionKind,
+}
+
+impl Function {
+    // One instruction per line, prefixed by its offset.
+    pub fn disassemble(&self) -> String {
+        let FunctionKind::Code { instructions, .. } = &self.kind else {
+            return String::new();
+        };
+        let mut out = String::new();
+        let mut pc = 0;
+        while pc < instructions.len() {
+            let offset = pc;
+            let instr = Instr::decode(instructions, &mut pc).unwrap();
+            out.push_str(&format!("{:4} {}\n", offset, instr));
+        }
+        out
+    }
+}
+
+// The bytecode of a unit. Function `i` is the body `i` of its TIL, followed
+// by the functions of other units which it refers to.
+#[derive(Clone, PartialEq)]
+pub struct Unit {
+    pub path: Vec<String>,
+    pub constants: Vec<Constant>,
+    pub classes: Vec<Class>,
+    pub functions: Vec<Function>,
+}
+
+impl Unit {
+    pub fn function(&self, def_id: &DefId) -> Option<u32> {
+        self.functions
+            .iter()
+            .position(|x| x.def_id.as_ref() == Some(def_id))
+            .map(|i| u32::try_from(i).unwrap())
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut message = Builder::new_default();
+        let mut builder = message.init_root::<bytecode_capnp::compiled_unit::Builder>();
+        let mut path = builder.reborrow().init_path(len(self.path.len()));
+        for (i, segment) in self.path.iter().enumerate() {
+            path.set(len(i), segment);
+        }
+        let mut constants = builder.reborrow().init_constants(len(self.constants.len()));
+        for (i, constant) in self.constants.iter().enumerate() {
+            set_constant(constants.reborrow().get(len(i)), constant);
+        }
+        let mut classes = builder.reborrow().init_classes(len(self.classes.len()));
+        for (i, class) in self.classes.iter().enumerate() {
+            let mut class_builder = classes.reborrow().get(len(i));
+            class_builder.set_name(&class.name);
+            set_def_ref(class_builder.init_def(), &class.def_id);
+        }
+        let mut functions = builder.init_functions(len(self.functions.len()));
+        for (i, function) in self.functions.iter().enumerate() {
+            let mut function_builder = functions.reborrow().get(len(i));
+            function_builder.set_name(&function.name);
+            function_builder.set_public(function.public);
+            let mut origin = function_builder.reborrow().init_origin();
+            match &function.def_id {
+                Some(def_id) => set_def_ref(origin.init_defn(), def_id),
+                None => origin.set_closure(()),
+            }
+            let mut kind = function_builder.init_kind();
+            match &function.kind {
+                FunctionKind::Code {
+                    arg_count,
+                    local_count,
+                    instructions,
+                } => {
+                    let mut code = kind.init_code();
+                    code.set_arg_count(*arg_count);
+                    code.set_local_count(*local_count);
+                    code.set_instructions(instructions);
+                }
+                FunctionKind::Extern => kind.set_extern(()),
+                FunctionKind::Missing => kind.set_missing(()),
+            }
+        }
+        write_message_to_words(&message)
+    }
+
+    pub fn deserialize(buffer: &[u8]) -> capnp::Result<Unit> {
+        let message = read_message(buffer, ReaderOptions::new())?;
+        let reader = message.get_root::<bytecode_capnp::compiled_unit::Reader>()?;
+        let path = reader
+            .get_path()?
+            .iter()
+            .map(|x| Ok(x?.to_string()))
+            .collect::<capnp::Result<Vec<_>>>()?;
+        let constants = reader
+            .get_constants()?
+            .iter()
+            .map(get_constant)
+            .collect::<capnp::Result<Vec<_>>>()?;
+        let classes = reader
+            .get_classes()?
+            .iter()
+            .map(|x| {
+                Ok(Class {
+                    name: x.get_name()?.to_string(),
+                    def_id: get_def_ref(x.get_def()?),
+                })
+            })
+            .collect::<capnp::Result<Vec<_>>>()?;
+        let functions = reader
+            .get_functions()?
+            .iter()
+            .map(|x| {
+                use bytecode_capnp::function::{kind, origin};
+                let def_id = match x.get_origin().which()? {
+                    origin::Defn(def) => Some(get_def_ref(def?)),
+                    origin::Closure(()) => None,
+                };
+                let kind = match x.get_kind().which()? {
+                    kind::Code(code) => {
+                        let code = code?;
+                        FunctionKind::Code {
+                            arg_count: code.get_arg_count(),
+                            local_count: code.get_local_count(),
+                            instructions: code.get_instructions()?.to_vec(),
+                        }
+                    }
+                    kind::Extern(()) => FunctionKind::Extern,
+                    kind::Missing(()) => FunctionKind::Missing,
+                };
+                Ok(Function {
+                    name: x.get_name()?.to_string(),
+                    public: x.get_public(),
+                    def_id,
+                    kind,
+                })
+            })
+            .collect::<capnp::Result<Vec<_>>>()?;
+        let unit = Unit {
+            path,
+            constants,
+            classes,
+            functions,
+        };
+        unit.check().map_err(capnp::Error::failed)?;
+        Ok(unit)
+    }
+
+    // Checks that the indices in the constants and instructions refer to
+    // entries of the tables of the unit and the jumps to instructions of
+    // their function, so that the VM can run a deserialized unit. Field
+    // indices depend on the aggregate and are checked by the VM.
+    fn check(&self) -> Result<(), String> {
+        for constant in &self.constants {
+            self.check_constant(constant)?;
+        }
+        for function in &self.functions {
+            if let FunctionKind::Code {
+                arg_count,
+                local_count,
+                instructions,
+            } = &function.kind
+            {
+                self.check_code(*arg_count, *local_count, instructions)
+                    .map_err(|message| format!("{} in {}", message, function.name))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn check_constant(&self, constant: &Constant) -> Result<(), String> {
+        if let Constant::Aggregate(class, fields) = constant {
+            if let Some(class) = class {
+                check_index("Class", *class, self.classes.len())?;
+            }
+            for field in fields {
+                self.check_constant(field)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn check_code(&self, arg_count: u32, local_count: u32, code: &[u8]) -> Result<(), String> {
+        // Local 0 holds the result, the arguments follow it.
+        if arg_count >= local_count {
+            return Err(format!("{} arguments exceed {} locals", arg_count, local_count));
+        }
+        let local_count = usize::try_from(local_count).unwrap();
+        let mut starts = vec![];
+        let mut targets = vec![];
+        let mut pc = 0;
+        while pc < code.len() {
+            starts.push(pc);
+            match Instr::decode(code, &mut pc)? {
+                Instr::Const(i) => check_index("Constant", i, self.constants.len())?,
+                Instr::Load(local) | Instr::Store(local) => {
+                    check_index("Local", local, local_count)?
+                }
+                Instr::Adt(class, _) => check_index("Class", class, self.classes.len())?,
+                Instr::Fn(func) | Instr::Closure(func, _) => {
+                    check_index("Function", func, self.functions.len())?
+                }
+                Instr::Jump(target) | Instr::JumpIfNot(target) => targets.push(target),
+                _ => {}
+            }
+        }
+        for target in targets {
+            if starts.binary_search(&(target as usize)).is_err() {
+                return Err(format!("Jump target {} is not an instruction", target));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn check_index(what: &str, index: u32, len: usize) -> Result<(), String> {
+    if index as usize >= len {
+        return Err(format!("{} {} is out of range", what, index));
+    }
+    Ok(())
+}
+
+fn len(n: usize) -> u32 {
+    u32::try_from(n).unwrap()
+}
+
+fn set_def_ref(mut builder: bytecode_capnp::def_ref::Builder, def_id: &DefId) {
+    let (unit, def) = def_id.to_raw();
+    builder.set_unit(unit);
+    builder.set_def(def);
+}
+
+fn get_def_ref(reader: bytecode_capnp::def_ref::Reader) -> DefId {
+    DefId::from_raw(reader.get_unit(), reader.get_def())
+}
+
+fn set_constant(mut builder: bytecode_capnp::constant::Builder, constant: &Constant) {
+    match constant {
+        Constant::Bool(b) => builder.set_bool(*b),
+        Constant::Int(i) => builder.set_int(*i),
+        Constant::Aggregate(class, fields) => {
+            let mut aggregate = builder.init_aggregate();
+            match class {
+                Some(class) => aggregate.reborrow().init_class().set_adt(*class),
+                None => aggregate.reborrow().init_class().set_tuple(()),
+            }
+            let mut fields_builder = aggregate.init_fields(len(fields.len()));
+            for (i, field) in fields.iter().enumerate() {
+                set_constant(fields_builder.reborrow().get(len(i)), field);
+            }
+        }
+    }
+}
+
+fn get_constant(reader: bytecode_capnp::constant::Reader) -> capnp::Result<Constant> {
+    use bytecode_capnp::{aggregate, constant};
+    Ok(match reader.which()? {
+        constant::Bool(b) => Constant::Bool(b),
+        constant::Int(i) => Constant::Int(i),
+        constant::Aggregate(aggregate) => {
+            let aggregate = aggregate?;
+            let class = match aggregate.get_class().which()? {
+                aggregate::class::Tuple(()) => None,
+                aggregate::class::Adt(class) => Some(class),
+            };
+            let fields = aggregate
+                .get_fields()?
+                .iter()
+                .map(get_constant)
+                .collect::<capnp::Result<Vec<_>>>()?;
+            Constant::Aggregate(class, fields)
+        }
+    })
+}
+
+// Compiles the bodies of `til`. Values are dynamically typed, so generic
+// defns need no monomorphization, and calls of interface or trait members
+// are externs failing at run time. Statics are inlined as constants.
+pub fn to_bytecode<'gcx>(til: &Til<'gcx>, ctx: &'gcx GlobalContext<'gcx>) -> Unit {
+    let mut compiler = Compiler {
+        til,
+        ctx,
+        unit: Unit {
+            path: til.unit_path.clone(),
+            constants: vec![],
+            classes: vec![],
+            functions: vec![],
+        },
+        externs: HashMap::new(),
+    };
+    // Functions of the bodies come first, externs being appended while the
+    // bodies are compiled.
+    for body in &til.bodies {
+        let def_id = match &body.ty {
+            BodyTy::Fn(def_id) => Some(def_id.clone()),
+            BodyTy::Closure { .. } => None,
+        };
+        compiler.unit.functions.push(Function {
+            name: body.name.clone(),
+            public: body.public,
+            def_id,
+            kind: FunctionKind::Missing,
+        });
+    }
+    for (i, body) in til.bodies.iter().enumerate() {
+        let kind = compiler.compile_body(body);
+        compiler.unit.functions[i].kind = kind;
+    }
+    compiler.unit
+}
+
+struct Compiler<'a, 'gcx> {
+    til: &'a Til<'gcx>,
+    ctx: &'gcx GlobalContext<'gcx>,
+    unit: Unit,
+    externs: HashMap<DefId, u32>,
+}
+
+// Code of one body, with the jumps to patch once the offsets of all blocks
+// are known.
+struct CodeBuilder {
+    code: Vec<u8>,
+    block_offsets: Vec<u32>,
+    jumps: Vec<(usize, usize)>,
+}
+
+impl CodeBuilder {
+    fn push(&mut self, instr: Instr) {
+        instr.encode(&mut self.code);
+    }
+
+    fn jump(&mut self, instr: fn(u32) -> Instr, block: usize) {
+        self.push(instr(0));
+        self.jumps.push((self.code.len() - 4, block));
+    }
+}
+
+impl<'a, 'gcx> Compiler<'a, 'gcx> {
+    fn compile_body(&mut self, body: &Body<'gcx>) -> FunctionKind {
+        let mut code = CodeBuilder {
+            code: vec![],
+            block_offsets: vec![],
+            jumps: vec![],
+        };
+        for (i, block) in body.blocks.iter().enumerate() {
+            code.block_offsets.push(len(code.code.len()));
+            for statement in &block.statements {
+                let Statement::Assign(place, rvalue) = statement;
+                self.store(place, &mut code, |this, code| this.rvalue(rvalue, code));
+            }
+            self.terminator(&block.terminator, i, &mut code);
+        }
+        for (at, block) in code.jumps {
+            let offset = code.block_offsets[block].to_le_bytes();
+            code.code[at..at + 4].copy_from_slice(&offset);
+        }
+        FunctionKind::Code {
+            arg_count: len(body.arg_count),
+            local_count: len(body.locals.len()),
+            instructions: code.code,
+        }
+    }
+
+    fn constant(&mut self, value: &ConstValue) -> u32 {
+        let constant = self.lower_constant(value);
+        let i = match self.unit.constants.iter().position(|x| *x == constant) {
+            Some(i) => i,
+            None => {
+                self.unit.constants.push(constant);
+                self.unit.constants.len() - 1
+            }
+        };
+        len(i)
+    }
+
+    fn lower_constant(&mut self, value: &ConstValue) -> Constant {
+        match value {
+            ConstValue::Bool(b) => Constant::Bool(*b),
+            ConstValue::Int(i) => Constant::Int(*i),
+            ConstValue::Tuple(values) => {
+                Constant::Aggregate(None, values.iter().map(|x| self.lower_constant(x)).collect())
+            }
+            ConstValue::Adt(def_id, values) => {
+                let class = self.class(def_id);
+                let values = values.iter().map(|x| self.lower_constant(x)).collect();
+                Constant::Aggregate(Some(class), values)
+            }
+        }
+    }
+
+    fn class(&mut self, def_id: &DefId) -> u32 {
+        if let Some(i) = self.unit.classes.iter().position(|x| x.def_id == *def_id) {
+            return len(i);
+        }
+        let name = match self.til.item_paths.get(def_id) {
+            Some(path) => path.join("::"),
+            None => def_id.to_string(),
+        };
+        self.unit.classes.push(Class {
+            name,
+            def_id: def_id.clone(),
+        });
+        len(self.unit.classes.len() - 1)
+    }
+
+    // Defns of other units, and the ones without a body, are appended to
+    // the functions as externs.
+    fn function(&mut self, def_id: &DefId) -> u32 {
+        if let Some(body_id) = self.til.fn_body(def_id) {
+            return len(body_id.index());
+        }
+        if let Some(i) = self.externs.get(def_id) {
+            return *i;
+        }
+        let name = match self.til.item_paths.get(def_id) {
+            Some(path) => path.join("::"),
+            None => def_id.to_string(),
+        };
+        self.unit.functions.push(Function {
+            name,
+            public: false,
+            def_id: Some(def_id.clone()),
+            kind: FunctionKind::Extern,
+        });
+        let i = len(self.unit.functions.len() - 1);
+        self.externs.insert(def_id.clone(), i);
+        i
+    }
+
+    fn load(&mut self, place: &Place, code: &mut CodeBuilder) {
+        code.push(Instr::Load(len(place.local.index())));
+        for i in &place.projection {
+            code.push(Instr::Field(len(*i)));
+        }
+    }
+
+    // Stores the value pushed by `value`. Fields are replaced by copying the
+    // enclosing aggregates.
+    fn store(
+        &mut self,
+        place: &Place,
+        code: &mut CodeBuilder,
+        value: impl FnOnce(&mut Self, &mut CodeBuilder),
+    ) {
+        let local = len(place.local.index());
+        if let Some((last, prefix)) = place.projection.split_last() {
+            code.push(Instr::Load(local));
+            for i in prefix {
+                code.push(Instr::Dup);
+                code.push(Instr::Field(len(*i)));
+            }
+            value(self, code);
+            code.push(Instr::Insert(len(*last)));
+            for i in prefix.iter().rev() {
+                code.push(Instr::Insert(len(*i)));
+            }
+        } else {
+            value(self, code);
+        }
+        code.push(Instr::Store(local));
+    }
+
+    fn operand(&mut self, operand: &Operand, code: &mut CodeBuilder) {
+        match operand {
+            Operand::Copy(place) => self.load(place, code),
+            Operand::Const(value) => {
+                let i = self.constant(value);
+                code.push(Instr::Const(i));
+            }
+            Operand::Fn(def_id) => {
+                let func = self.function(def_id);
+                code.push(Instr::Fn(func));
+            }
+            Operand::Static(def_id) => {
+                let i = self.constant(&self.ctx.const_value(def_id).unwrap());
+                code.push(Instr::Const(i));
+            }
+        }
+    }
+
+    fn operands(&mut self, operands: &[Operand], code: &mut CodeBuilder) -> u32 {
+        for operand in operands {
+            self.operand(operand, code);
+        }
+        len(operands.len())
+    }
+
+    fn rvalue(&mut self, rvalue: &Rvalue, code: &mut CodeBuilder) {
+        match rvalue {
+            Rvalue::Use(operand) => self.operand(operand, code),
+            Rvalue::Unary(op, operand) => {
+                self.operand(operand, code);
+                code.push(match op {
+                    UnOp::Neg => Instr::Neg,
+                    UnOp::Not => Instr::Not,
+                });
+            }
+            Rvalue::Binary(op, lhs, rhs) => {
+                self.operand(lhs, code);
+                self.operand(rhs, code);
+                code.push(Instr::Binary(*op));
+            }
+            Rvalue::Tuple(elems) => {
+                let n = self.operands(elems, code);
+                code.push(Instr::Tuple(n));
+            }
+            Rvalue::Adt(def_id, fields) => {
+                let n = self.operands(fields, code);
+                let class = self.class(def_id);
+                code.push(Instr::Adt(class, n));
+            }
+            Rvalue::Closure(body_id, captures) => {
+                let n = self.operands(captures, code);
+                code.push(Instr::Closure(len(body_id.index()), n));
+            }
+        }
+    }
+
+    fn terminator(&mut self, terminator: &Terminator, block: usize, code: &mut CodeBuilder) {
+        let next = block + 1;
+        match terminator {
+            Terminator::Goto(target) if target.index() == next => (),
+            Terminator::Goto(target) => code.jump(Instr::Jump, target.index()),
+            Terminator::Branch {
+                cond,
+                then_block,
+                else_block,
+            } => {
+                self.operand(cond, code);
+                code.jump(Instr::JumpIfNot, else_block.index());
+                if then_block.index() != next {
+                    code.jump(Instr::Jump, then_block.index());
+                }
+            }
+            Terminator::Return => {
+                code.push(Instr::Load(len(Local::RETURN.index())));
+                code.push(Instr::Return);
+            }
+            Terminator::Call {
+                func,
+                args,
+                dest,
+                target,
+            } => {
+                // The returned value is pushed once the callee returns.
+                self.store(dest, code, |this, code| {
+                    this.operand(func, code);
+                    let n = this.operands(args, code);
+                    code.push(Instr::Call(n));
+                });
+                if target.index() != next {
+                    code.jump(Instr::Jump, target.index());
+                }
+            }
+            Terminator::Unreachable => code.push(Instr::Unreachable),
+        }
+    }
+}
@@ -0,0 +1,411 @@
+use std::fmt;
+use std::rc::Rc;
+
+use crate::bytecode::{Constant, FunctionKind, Instr, Unit};
+use crate::def::DefId;
+use crate::op::BinOp;
+use crate::til::interp::{RuntimeError, DEFAULT_FRAME_LIMIT, DEFAULT_STEP_LIMIT};
+
+// Values fit in two words: aggregates and closures are shared, and copied
+// when a field is replaced while they have other owners.
+#[derive(Clone, PartialEq)]
+pub enum Value {
+    // Locals which were not assigned yet.
+    Uninit,
+    Bool(bool),
+    Int(i64),
+    Aggregate(Rc<Aggregate>),
+    Fn(u32),
+    Closure(Rc<Closure>),
+}
+
+#[derive(Clone, PartialEq)]
+pub struct Aggregate {
+    // Index in the classes of the unit, tuples have none.
+    pub class: Option<u32>,
+    pub fields: Vec<Value>,
+}
+
+#[derive(Clone, PartialEq)]
+pub struct Closure {
+    pub function: u32,
+    pub captures: Vec<Value>,
+}
+
+impl Value {
+    pub fn unit() -> Self {
+        Value::Aggregate(Rc::new(Aggregate {
+            class: None,
+            fields: vec![],
+        }))
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_int(&self) -> Option<i64> {
+        match self {
+            Value::Int(i) => Some(*i),
+            _ => None,
+        }
+    }
+
+    fn from_constant(constant: &Constant) -> Self {
+        match constant {
+            Constant::Bool(b) => Value::Bool(*b),
+            Constant::Int(i) => Value::Int(*i),
+            Constant::Aggregate(class, fields) => Value::Aggregate(Rc::new(Aggregate {
+                class: *class,
+                fields: fields.iter().map(Value::from_constant).collect(),
+            })),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let write_list = |f: &mut fmt::Formatter, values: &[Value]| {
+            for (i, value) in values.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{}", value)?;
+            }
+            Ok(())
+        };
+        match self {
+            Value::Uninit => write!(f, "uninit"),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Int(i) => write!(f, "{}", i),
+            Value::Aggregate(aggregate) => match aggregate.class {
+                Some(class) => {
+                    write!(f, "class{} {{ ", class)?;
+                    write_list(f, &aggregate.fields)?;
+                    write!(f, " }}")
+                }
+                None => {
+                    write!(f, "(")?;
+                    write_list(f, &aggregate.fields)?;
+                    if aggregate.fields.len() == 1 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, ")")
+                }
+            },
+            Value::Fn(function) => write!(f, "fn{}", function),
+            Value::Closure(closure) => write!(f, "closure fn{}", closure.function),
+        }
+    }
+}
+
+struct Frame {
+    function: u32,
+    pc: usize,
+    // Locals of the frame start at `base` on the stack, followed by its
+    // operands from `operands`.
+    base: usize,
+    operands: usize,
+}
+
+// Executes the bytecode of a unit with a single stack for the locals and
+// operands of all frames. Units are only checked for indices and jump
+// targets when deserialized, so operands of the wrong kind and stack
+// underflows are runtime errors.
+pub struct Vm<'u> {
+    unit: &'u Unit,
+    constants: Vec<Value>,
+    stack: Vec<Value>,
+    frames: Vec<Frame>,
+    steps: u64,
+    step_limit: u64,
+    frame_limit: usize,
+}
+
+impl<'u> Vm<'u> {
+    pub fn new(unit: &'u Unit) -> Self {
+        Vm {
+            unit,
+            constants: unit.constants.iter().map(Value::from_constant).collect(),
+            stack: vec![],
+            frames: vec![],
+            steps: 0,
+            step_limit: DEFAULT_STEP_LIMIT,
+            frame_limit: DEFAULT_FRAME_LIMIT,
+        }
+    }
+
+    // Number of instructions one call may execute.
+    pub fn set_step_limit(&mut self, step_limit: u64) {
+        self.step_limit = step_limit;
+    }
+
+    pub fn set_frame_limit(&mut self, frame_limit: usize) {
+        self.frame_limit = frame_limit;
+    }
+
+    pub fn call(&mut self, def_id: &DefId, args: Vec<Value>) -> Result<Value, RuntimeError> {
+        self.steps = 0;
+        self.frames.clear();
+        self.stack.clear();
+        let function = match self.unit.function(def_id) {
+            Some(function) => function,
+            None => return Err(self.error(format!("{} has no body", def_id))),
+        };
+        self.stack.push(Value::Fn(function));
+        let argc = u32::try_from(args.len()).unwrap();
+        self.stack.extend(args);
+        self.call_value(argc)?;
+        self.run()
+    }
+
+    fn error(&self, message: impl Into<String>) -> RuntimeError {
+        let backtrace = self
+            .frames
+            .iter()
+            .rev()
+            .map(|frame| self.unit.functions[frame.function as usize].name.clone())
+            .collect();
+        RuntimeError {
+            message: message.into(),
+            backtrace,
+        }
+    }
+
+    fn type_error(&self, expected: &str, value: &Value) -> RuntimeError {
+        self.error(format!("expected {}, found {}", expected, value))
+    }
+
+    // Operands of the current frame, which cannot be popped past.
+    fn operand_count(&self) -> usize {
+        let start = self.frames.last().map_or(0, |frame| frame.operands);
+        self.stack.len() - start
+    }
+
+    fn pop(&mut self) -> Result<Value, RuntimeError> {
+        Ok(self.pop_n(1)?.pop().unwrap())
+    }
+
+    fn pop_n(&mut self, n: u32) -> Result<Vec<Value>, RuntimeError> {
+        if self.operand_count() < n as usize {
+            return Err(self.error("operand stack underflow"));
+        }
+        let start = self.stack.len() - n as usize;
+        Ok(self.stack.split_off(start))
+    }
+
+    fn pop_aggregate(&mut self) -> Result<Rc<Aggregate>, RuntimeError> {
+        match self.pop()? {
+            Value::Aggregate(aggregate) => Ok(aggregate),
+            value => Err(self.type_error("an aggregate", &value)),
+        }
+    }
+
+    fn pop_bool(&mut self) -> Result<bool, RuntimeError> {
+        match self.pop()? {
+            Value::Bool(b) => Ok(b),
+            value => Err(self.type_error("a bool", &value)),
+        }
+    }
+
+    fn run(&mut self) -> Result<Value, RuntimeError> {
+        loop {
+            self.steps += 1;
+            if self.steps > self.step_limit {
+                return Err(self.error(format!("step limit of {} exceeded", self.step_limit)));
+            }
+            let frame = self.frames.last_mut().unwrap();
+            let FunctionKind::Code { instructions, .. } =
+                &self.unit.functions[frame.function as usize].kind
+            else {
+                unreachable!()
+            };
+            let instr = Instr::decode(instructions, &mut frame.pc);
+            let base = frame.base;
+            let instr = instr.map_err(|message| self.error(message))?;
+            match instr {
+                Instr::Const(i) => self.stack.push(self.constants[i as usize].clone()),
+                Instr::Load(local) => {
+                    let value = self.stack[base + local as usize].clone();
+                    if value == Value::Uninit {
+                        let message = format!("use of uninitialized local _{}", local);
+                        return Err(self.error(message));
+                    }
+                    self.stack.push(value);
+                }
+                Instr::Store(local) => {
+                    let value = self.pop()?;
+                    self.stack[base + local as usize] = value;
+                }
+                Instr::Dup => {
+                    let value = self.pop()?;
+                    self.stack.push(value.clone());
+                    self.stack.push(value);
+                }
+                Instr::Field(i) => {
+                    let aggregate = self.pop_aggregate()?;
+                    match aggregate.fields.get(i as usize) {
+                        Some(value) => self.stack.push(value.clone()),
+                        None => return Err(self.error(format!("field {} is out of range", i))),
+                    }
+                }
+                Instr::Insert(i) => {
+                    let value = self.pop()?;
+                    let mut aggregate = self.pop_aggregate()?;
+                    match Rc::make_mut(&mut aggregate).fields.get_mut(i as usize) {
+                        Some(field) => *field = value,
+                        None => return Err(self.error(format!("field {} is out of range", i))),
+                    }
+                    self.stack.push(Value::Aggregate(aggregate));
+                }
+                Instr::Tuple(n) => {
+                    let fields = self.pop_n(n)?;
+                    let aggregate = Aggregate {
+                        class: None,
+                        fields,
+                    };
+                    self.stack.push(Value::Aggregate(Rc::new(aggregate)));
+                }
+                Instr::Adt(class, n) => {
+                    let fields = self.pop_n(n)?;
+                    let aggregate = Aggregate {
+                        class: Some(class),
+                        fields,
+                    };
+                    self.stack.push(Value::Aggregate(Rc::new(aggregate)));
+                }
+                Instr::Fn(function) => self.stack.push(Value::Fn(function)),
+                Instr::Closure(function, n) => {
+                    let captures = self.pop_n(n)?;
+                    let closure = Closure { function, captures };
+                    self.stack.push(Value::Closure(Rc::new(closure)));
+                }
+                Instr::Neg => {
+                    let value = match self.pop()? {
+                        Value::Int(i) => i.checked_neg().map(Value::Int),
+                        value => return Err(self.type_error("an int", &value)),
+                    };
+                    match value {
+                        Some(value) => self.stack.push(value),
+                        None => return Err(self.error("attempt to negate with overflow")),
+                    }
+                }
+                Instr::Not => {
+                    let b = self.pop_bool()?;
+                    self.stack.push(Value::Bool(!b));
+                }
+                Instr::Binary(op) => {
+                    let rhs = self.pop()?;
+                    let lhs = self.pop()?;
+                    let value = self.eval_binary(op, lhs, rhs)?;
+                    self.stack.push(value);
+                }
+                Instr::Jump(target) => self.frames.last_mut().unwrap().pc = target as usize,
+                Instr::JumpIfNot(target) => {
+                    if !self.pop_bool()? {
+                        self.frames.last_mut().unwrap().pc = target as usize;
+                    }
+                }
+                Instr::Call(argc) => self.call_value(argc)?,
+                Instr::Return => {
+                    let value = self.pop()?;
+                    let frame = self.frames.pop().unwrap();
+                    self.stack.truncate(frame.base);
+                    if self.frames.is_empty() {
+                        return Ok(value);
+                    }
+                    self.stack.push(value);
+                }
+                Instr::Unreachable => return Err(self.error("entered unreachable code")),
+            }
+        }
+    }
+
+    // Calls the function value below the `argc` arguments on the stack.
+    fn call_value(&mut self, argc: u32) -> Result<(), RuntimeError> {
+        let args = self.pop_n(argc)?;
+        let (function, args) = match self.pop()? {
+            Value::Fn(function) => (function, args),
+            Value::Closure(closure) => {
+                let mut captures = closure.captures.clone();
+                captures.extend(args);
+                (closure.function, captures)
+            }
+            value => return Err(self.type_error("a function", &value)),
+        };
+        let callee = &self.unit.functions[function as usize];
+        let FunctionKind::Code {
+            arg_count,
+            local_count,
+            ..
+        } = callee.kind
+        else {
+            return Err(self.error(format!("{} has no body", callee.name)));
+        };
+        if args.len() != arg_count as usize {
+            let message = format!(
+                "{} takes {} arguments but {} were given",
+                callee.name,
+                arg_count,
+                args.len()
+            );
+            return Err(self.error(message));
+        }
+        if self.frames.len() >= self.frame_limit {
+            return Err(self.error(format!("frame limit of {} exceeded", self.frame_limit)));
+        }
+        let base = self.stack.len();
+        self.stack.push(Value::Uninit);
+        self.stack.extend(args);
+        for _ in arg_count + 1..local_count {
+            self.stack.push(Value::Uninit);
+        }
+        self.frames.push(Frame {
+            function,
+            pc: 0,
+            base,
+            operands: self.stack.len(),
+        });
+        Ok(())
+    }
+
+    // Arithmetic is checked like in the interpreter.
+    fn eval_binary(&self, op: BinOp, lhs: Value, rhs: Value) -> Result<Value, RuntimeError> {
+        if op == BinOp::Eq || op == BinOp::Ne {
+            return Ok(Value::Bool((lhs == rhs) == (op == BinOp::Eq)));
+        }
+        let (a, b) = match (lhs, rhs) {
+            (Value::Bool(a), Value::Bool(b)) if op == BinOp::And => return Ok(Value::Bool(a && b)),
+            (Value::Bool(a), Value::Bool(b)) if op == BinOp::Or => return Ok(Value::Bool(a || b)),
+            (Value::Int(a), Value::Int(b)) if op != BinOp::And && op != BinOp::Or => (a, b),
+            (lhs, rhs) => {
+                let message = format!("invalid operands {} and {} of `{}`", lhs, rhs, op.symbol());
+                return Err(self.error(message));
+            }
+        };
+        let (value, reason) = match op {
+            BinOp::Add => (a.checked_add(b), "attempt to add with overflow"),
+            BinOp::Sub => (a.checked_sub(b), "attempt to subtract with overflow"),
+            BinOp::Mul => (a.checked_mul(b), "attempt to multiply with overflow"),
+            BinOp::Div if b == 0 => (None, "attempt to divide by zero"),
+            BinOp::Div => (a.checked_div(b), "attempt to divide with overflow"),
+            BinOp::Rem if b == 0 => (
+                None,
+                "attempt to calculate the remainder with a divisor of zero",
+            ),
+            BinOp::Rem => (
+                a.checked_rem(b),
+                "attempt to calculate the remainder with overflow",
+            ),
+            BinOp::Lt => return Ok(Value::Bool(a < b)),
+            BinOp::Le => return Ok(Value::Bool(a <= b)),
+            BinOp::Gt => return Ok(Value::Bool(a > b)),
+            BinOp::Ge => return Ok(Value::Bool(a >= b)),
+            BinOp::And | BinOp::Or | BinOp::Eq | BinOp::Ne => unreachable!(),
+        };
+        value.map(Value::Int).ok_or_else(|| self.error(reason))
+    }
+}
@@ -0,0 +1,615 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+
+use crate::codegen::{body_symbol, foreign_callee, foreign_class, is_supported, item_symbol, Import};
+use crate::context::GlobalContext;
+use crate::def::DefId;
+use crate::op::{BinOp, UnOp};
+use crate::t::const_eval::ConstValue;
+use crate::t::{Type, TypeKind, TypeRef};
+use crate::til::{
+    Body, BodyId, BodyTy, Operand, Place, Rvalue, Statement, Terminator, Til, const_ty, field_ty,
+};
+
+// Checked arithmetic panics like the interpreter, with exit status 101.
+const PRELUDE: &str = r#"#include <inttypes.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+
+typedef uint8_t ct_unit;
+typedef void (*ct_code)(void);
+typedef struct {
+    ct_code code;
+    void *env;
+} ct_fn;
+
+static inline void ct_panic(const char *msg) {
+    fprintf(stderr, "panic: %s\n", msg);
+    exit(101);
+}
+
+static inline int64_t ct_add(int64_t a, int64_t b) {
+    if ((b > 0 && a > INT64_MAX - b) || (b < 0 && a < INT64_MIN - b))
+        ct_panic("attempt to add with overflow");
+    return a + b;
+}
+
+static inline int64_t ct_sub(int64_t a, int64_t b) {
+    if ((b < 0 && a > INT64_MAX + b) || (b > 0 && a < INT64_MIN + b))
+        ct_panic("attempt to subtract with overflow");
+    return a - b;
+}
+
+static inline int64_t ct_mul(int64_t a, int64_t b) {
+    bool overflow;
+    if (a > 0)
+        overflow = b > 0 ? a > INT64_MAX / b : b < INT64_MIN / a;
+    else
+        overflow = b > 0 ? a < INT64_MIN / b : a != 0 && b < INT64_MAX / a;
+    if (overflow)
+        ct_panic("attempt to multiply with overflow");
+    return a * b;
+}
+
+static inline int64_t ct_div(int64_t a, int64_t b) {
+    if (b == 0)
+        ct_panic("attempt to divide by zero");
+    if (a == INT64_MIN && b == -1)
+        ct_panic("attempt to divide with overflow");
+    return a / b;
+}
+
+static inline int64_t ct_rem(int64_t a, int64_t b) {
+    if (b == 0)
+        ct_panic("attempt to calculate the remainder with a divisor of zero");
+    if (a == INT64_MIN && b == -1)
+        ct_panic("attempt to calculate the remainder with overflow");
+    return a % b;
+}
+
+static inline int64_t ct_neg(int64_t a) {
+    if (a == INT64_MIN)
+        ct_panic("attempt to negate with overflow");
+    return -a;
+}
+"#;
+
+// Generates a C99 translation unit from `til`. Public defns get external
+// linkage, and `entry`, a defn without parameters, becomes `main`, printing
+// its result. Closures and defns used as values are `ct_fn`s, whose code
+// takes an environment before the parameters. Environments of closures are
+// never freed. Defns of other units are declared from the signatures in the
+// BUIs of `imports`.
+pub fn to_c<'gcx>(
+    til: &Til<'gcx>,
+    ctx: &'gcx GlobalContext<'gcx>,
+    imports: &[Import],
+    entry: Option<&DefId>,
+) -> String {
+    let mut gen = CGen::new(til, ctx, imports);
+    let supported = (0..til.bodies.len())
+        .map(|i| is_supported(til, BodyId(u32::try_from(i).unwrap()), "C"))
+        .collect::<Vec<_>>();
+    let mut protos = String::new();
+    let mut fns = String::new();
+    for (i, supported) in supported.iter().enumerate() {
+        if *supported {
+            let body_id = BodyId(u32::try_from(i).unwrap());
+            writeln!(protos, "{};", gen.signature(body_id)).unwrap();
+            gen.emit_body(body_id, &mut fns);
+        }
+    }
+    let mut out = format!("/* Generated from unit {}. */\n", til.unit_path.join("::"));
+    out.push_str(PRELUDE);
+    for section in [&gen.type_defs, &gen.eq_defs, &gen.externs, &protos, &gen.helpers, &fns] {
+        if !section.is_empty() {
+            out.push('\n');
+            out.push_str(section);
+        }
+    }
+    if let Some(entry) = entry {
+        out.push('\n');
+        gen.emit_main(entry, &mut out);
+    }
+    out
+}
+
+struct CGen<'a, 'gcx> {
+    til: &'a Til<'gcx>,
+    ctx: &'gcx GlobalContext<'gcx>,
+    imports: &'a [Import<'a>],
+    // C names of the struct types defined so far.
+    type_names: HashMap<*const Type<'gcx>, String>,
+    type_defs: String,
+    foreign_structs: HashSet<String>,
+    eq_names: HashMap<*const Type<'gcx>, String>,
+    eq_defs: String,
+    externs: String,
+    // Symbols and C signatures of the defns of other units.
+    extern_sigs: HashMap<DefId, (String, Vec<String>, String)>,
+    // Thunks and closure constructors.
+    helpers: String,
+    thunk_names: HashMap<DefId, String>,
+}
+
+impl<'a, 'gcx> CGen<'a, 'gcx> {
+    fn new(til: &'a Til<'gcx>, ctx: &'gcx GlobalContext<'gcx>, imports: &'a [Import]) -> Self {
+        CGen {
+            til,
+            ctx,
+            imports,
+            type_names: HashMap::new(),
+            type_defs: String::new(),
+            foreign_structs: HashSet::new(),
+            eq_names: HashMap::new(),
+            eq_defs: String::new(),
+            externs: String::new(),
+            extern_sigs: HashMap::new(),
+            helpers: String::new(),
+            thunk_names: HashMap::new(),
+        }
+    }
+
+    fn c_type(&mut self, t: TypeRef<'gcx>) -> String {
+        match t.kind() {
+            TypeKind::Bool => "bool".to_string(),
+            TypeKind::Int => "int64_t".to_string(),
+            TypeKind::Tuple([]) => "ct_unit".to_string(),
+            TypeKind::Never => "ct_unit".to_string(),
+            TypeKind::Fn(..) => "ct_fn".to_string(),
+            TypeKind::Tuple(_) | TypeKind::Adt(..) => self.struct_type(t),
+            TypeKind::Error | TypeKind::Infer(_) | TypeKind::Param(_) => {
+                unreachable!("Type {} has no C representation", t)
+            }
+        }
+    }
+
+    // Defines the struct of a tuple or class after the ones of its fields.
+    fn struct_type(&mut self, t: TypeRef<'gcx>) -> String {
+        if let Some(name) = self.type_names.get(&(t as *const _)) {
+            return name.clone();
+        }
+        let (name, fields) = match t.kind() {
+            TypeKind::Tuple(elems) => {
+                let name = format!("ct_tuple{}", self.type_names.len());
+                (name, elems.iter().map(|t| (*t, None)).collect::<Vec<_>>())
+            }
+            TypeKind::Adt(adt_def, _) => {
+                if let Some(name) = self.foreign_struct(&adt_def.def_id) {
+                    self.type_names.insert(t as *const _, name.clone());
+                    return name;
+                }
+                let name = item_symbol(self.til, &adt_def.def_id);
+                let fields = adt_def
+                    .fields()
+                    .iter()
+                    .enumerate()
+                    .map(|(i, x)| (field_ty(self.ctx, t, i), Some(x.name.clone())))
+                    .collect();
+                (name, fields)
+            }
+            _ => unreachable!(),
+        };
+        let fields = fields
+            .into_iter()
+            .map(|(t, name)| (self.c_type(t), name))
+            .collect::<Vec<_>>();
+        self.define_struct(&name, &fields);
+        self.type_names.insert(t as *const _, name.clone());
+        name
+    }
+
+    fn define_struct(&mut self, name: &str, fields: &[(String, Option<String>)]) {
+        let mut def = "typedef struct {\n".to_string();
+        // Empty structs are not valid C.
+        if fields.is_empty() {
+            def.push_str("    ct_unit unused;\n");
+        }
+        for (i, (ty, name)) in fields.iter().enumerate() {
+            match name {
+                Some(name) => writeln!(def, "    {} f{}; /* {} */", ty, i, name).unwrap(),
+                None => writeln!(def, "    {} f{};", ty, i).unwrap(),
+            }
+        }
+        writeln!(def, "}} {};", name).unwrap();
+        self.type_defs.push_str(&def);
+    }
+
+    // Struct of a class of an imported unit, laid out from its BUI.
+    fn foreign_struct(&mut self, def_id: &DefId) -> Option<String> {
+        let (name, fields) = foreign_class(self.ctx, self.imports, def_id)?;
+        if !self.foreign_structs.contains(&name) {
+            let fields = fields
+                .into_iter()
+                .map(|(field, t)| (self.c_type(t), Some(field)))
+                .collect::<Vec<_>>();
+            self.define_struct(&name, &fields);
+            self.foreign_structs.insert(name.clone());
+        }
+        Some(name)
+    }
+
+    fn eq_expr(&mut self, t: TypeRef<'gcx>, a: &str, b: &str) -> String {
+        match t.kind() {
+            TypeKind::Fn(..) => format!("({a}.code == {b}.code && {a}.env == {b}.env)"),
+            TypeKind::Tuple(elems) if !elems.is_empty() => {
+                format!("{}({}, {})", self.eq_fn(t), a, b)
+            }
+            TypeKind::Adt(..) => format!("{}({}, {})", self.eq_fn(t), a, b),
+            _ => format!("({} == {})", a, b),
+        }
+    }
+
+    fn eq_fn(&mut self, t: TypeRef<'gcx>) -> String {
+        if let Some(name) = self.eq_names.get(&(t as *const _)) {
+            return name.clone();
+        }
+        let ty = self.c_type(t);
+        let name = format!("ct_eq_{}", ty);
+        self.eq_names.insert(t as *const _, name.clone());
+        let count = match t.kind() {
+            TypeKind::Tuple(elems) => elems.len(),
+            TypeKind::Adt(adt_def, _) => adt_def.fields().len(),
+            _ => unreachable!(),
+        };
+        let mut conds = (0..count)
+            .map(|i| {
+                let field = field_ty(self.ctx, t, i);
+                self.eq_expr(field, &format!("a.f{}", i), &format!("b.f{}", i))
+            })
+            .collect::<Vec<_>>();
+        if conds.is_empty() {
+            conds.push("true".to_string());
+        }
+        writeln!(
+            self.eq_defs,
+            "static inline bool {}({} a, {} b) {{\n    return {};\n}}",
+            name,
+            ty,
+            ty,
+            conds.join(" && ")
+        )
+        .unwrap();
+        name
+    }
+
+    fn fn_sig_types(&mut self, t: TypeRef<'gcx>) -> (Vec<String>, String) {
+        match t.kind() {
+            TypeKind::Fn(inputs, output) => (
+                inputs.iter().map(|t| self.c_type(t)).collect(),
+                self.c_type(output),
+            ),
+            _ => unreachable!(),
+        }
+    }
+
+    // Name to call the defn `def_id` by and its C signature, declaring it
+    // when it is defined in another unit.
+    fn callee(&mut self, def_id: &DefId) -> (String, Vec<String>, String) {
+        if def_id.is_local() {
+            let (inputs, output) = self.fn_sig_types(self.ctx.type_of(def_id).unwrap());
+            return (item_symbol(self.til, def_id), inputs, output);
+        }
+        if let Some(callee) = self.extern_sigs.get(def_id) {
+            return callee.clone();
+        }
+        let (name, t) = foreign_callee(self.til, self.ctx, self.imports, def_id, "C");
+        let (inputs, output) = self.fn_sig_types(t);
+        writeln!(self.externs, "extern {} {}({});", output, name, params_list(&inputs)).unwrap();
+        let callee = (name, inputs, output);
+        self.extern_sigs.insert(def_id.clone(), callee.clone());
+        callee
+    }
+
+    // `ct_fn` of the defn `def_id`, a static function dropping the `env`
+    // it is called with.
+    fn thunk(&mut self, def_id: &DefId) -> String {
+        if let Some(name) = self.thunk_names.get(def_id) {
+            return name.clone();
+        }
+        let (callee, inputs, output) = self.callee(def_id);
+        let name = format!("{}_thunk", callee);
+        let mut params = vec!["void *env".to_string()];
+        params.extend(inputs.iter().enumerate().map(|(i, t)| format!("{} a{}", t, i)));
+        let args = (0..inputs.len()).map(|i| format!("a{}", i)).collect::<Vec<_>>();
+        writeln!(
+            self.helpers,
+            "static {} {}({}) {{\n    (void)env;\n    return {}({});\n}}",
+            output,
+            name,
+            params.join(", "),
+            callee,
+            args.join(", ")
+        )
+        .unwrap();
+        self.thunk_names.insert(def_id.clone(), name.clone());
+        name
+    }
+
+    fn signature(&mut self, body_id: BodyId) -> String {
+        let body = self.til.body(body_id);
+        let mut params = vec![];
+        let first_param = match body.ty {
+            BodyTy::Fn(_) => 1,
+            BodyTy::Closure { captures, .. } => {
+                params.push("void *env_".to_string());
+                captures + 1
+            }
+        };
+        for local in body.args().skip(first_param - 1) {
+            params.push(format!("{} {}", self.c_type(body.local_decl(local).ty), local));
+        }
+        if params.is_empty() {
+            params.push("void".to_string());
+        }
+        let linkage = if body.public { "" } else { "static " };
+        format!(
+            "{}{} {}({})",
+            linkage,
+            self.c_type(body.return_ty()),
+            body_symbol(self.til, body_id),
+            params.join(", ")
+        )
+    }
+
+    fn emit_body(&mut self, body_id: BodyId, out: &mut String) {
+        let body = self.til.body(body_id);
+        let signature = self.signature(body_id);
+        writeln!(out, "{} {{", signature).unwrap();
+        let captures = match body.ty {
+            BodyTy::Fn(_) => 0,
+            BodyTy::Closure { captures, .. } => captures,
+        };
+        if captures > 0 {
+            let mut env = "typedef struct {\n".to_string();
+            for (i, ty) in self.capture_types(body_id).iter().enumerate() {
+                writeln!(env, "    {} c{};", ty, i).unwrap();
+            }
+            writeln!(env, "}} ct_env{};", body_id.index()).unwrap();
+            self.type_defs.push_str(&env);
+            writeln!(out, "    ct_env{} *env = env_;", body_id.index()).unwrap();
+        }
+        for (i, decl) in body.locals.iter().enumerate() {
+            let is_param = i > captures && i <= body.arg_count;
+            if is_param {
+                continue;
+            }
+            let ty = self.c_type(decl.ty);
+            if i > 0 && i <= captures {
+                writeln!(out, "    {} _{} = env->c{};", ty, i, i - 1).unwrap();
+            } else {
+                writeln!(out, "    {} _{};", ty, i).unwrap();
+            }
+        }
+        // Blocks are emitted in order, leaving out the unreachable ones, and
+        // only the targets of jumps are labeled.
+        let mut reachable = vec![false; body.blocks.len()];
+        let mut targeted = vec![false; body.blocks.len()];
+        let mut stack = vec![0];
+        reachable[0] = true;
+        while let Some(i) = stack.pop() {
+            for succ in body.blocks[i].terminator.successors() {
+                targeted[succ.index()] = true;
+                if !reachable[succ.index()] {
+                    reachable[succ.index()] = true;
+                    stack.push(succ.index());
+                }
+            }
+        }
+        for (i, block) in body.blocks.iter().enumerate() {
+            if !reachable[i] {
+                continue;
+            }
+            if targeted[i] {
+                writeln!(out, "bb{}:", i).unwrap();
+            }
+            for statement in &block.statements {
+                let Statement::Assign(place, rvalue) = statement;
+                let t = body.place_ty(self.ctx, place);
+                let rvalue = self.rvalue(body, t, rvalue);
+                writeln!(out, "    {} = {};", place_expr(place), rvalue).unwrap();
+            }
+            let terminator = self.terminator(body, &block.terminator);
+            writeln!(out, "    {}", terminator).unwrap();
+        }
+        writeln!(out, "}}").unwrap();
+        out.push('\n');
+    }
+
+    fn operand(&mut self, operand: &Operand) -> String {
+        match operand {
+            Operand::Copy(place) => place_expr(place),
+            Operand::Const(value) => self.const_value(value),
+            Operand::Fn(def_id) => format!("((ct_fn){{(ct_code){}, NULL}})", self.thunk(def_id)),
+            Operand::Static(def_id) => {
+                let value = self.ctx.const_value(def_id).unwrap();
+                self.const_value(&value)
+            }
+        }
+    }
+
+    fn operands(&mut self, operands: &[Operand]) -> Vec<String> {
+        operands.iter().map(|x| self.operand(x)).collect()
+    }
+
+    fn const_value(&mut self, value: &ConstValue) -> String {
+        match value {
+            ConstValue::Bool(b) => b.to_string(),
+            ConstValue::Int(i64::MIN) => "INT64_MIN".to_string(),
+            ConstValue::Int(i) => format!("INT64_C({})", i),
+            ConstValue::Tuple(values) if values.is_empty() => "0".to_string(),
+            ConstValue::Tuple(values) | ConstValue::Adt(_, values) => {
+                let ty = self.c_type(const_ty(self.ctx, value));
+                let values = values.iter().map(|x| self.const_value(x)).collect::<Vec<_>>();
+                format!("(({}){{{}}})", ty, values.join(", "))
+            }
+        }
+    }
+
+    fn rvalue(&mut self, body: &Body<'gcx>, t: TypeRef<'gcx>, rvalue: &Rvalue) -> String {
+        match rvalue {
+            Rvalue::Use(operand) => self.operand(operand),
+            Rvalue::Unary(UnOp::Neg, operand) => format!("ct_neg({})", self.operand(operand)),
+            Rvalue::Unary(UnOp::Not, operand) => format!("!{}", self.operand(operand)),
+            Rvalue::Binary(op, lhs, rhs) => {
+                let t = body.operand_ty(self.ctx, lhs);
+                let (lhs, rhs) = (self.operand(lhs), self.operand(rhs));
+                match op {
+                    BinOp::Add | BinOp::Sub | BinOp::Mul | BinOp::Div | BinOp::Rem => {
+                        format!("ct_{}({}, {})", op.keyword(), lhs, rhs)
+                    }
+                    BinOp::Eq => self.eq_expr(t, &lhs, &rhs),
+                    BinOp::Ne => format!("!{}", self.eq_expr(t, &lhs, &rhs)),
+                    _ => format!("({} {} {})", lhs, op.symbol(), rhs),
+                }
+            }
+            Rvalue::Tuple(elems) if elems.is_empty() => "0".to_string(),
+            Rvalue::Tuple(elems) => {
+                let ty = self.c_type(t);
+                format!("(({}){{{}}})", ty, self.operands(elems).join(", "))
+            }
+            Rvalue::Adt(_, fields) => {
+                let ty = self.c_type(t);
+                let fields = self.operands(fields);
+                if fields.is_empty() {
+                    format!("(({}){{0}})", ty)
+                } else {
+                    format!("(({}){{{}}})", ty, fields.join(", "))
+                }
+            }
+            Rvalue::Closure(body_id, captures) => {
+                let maker = self.closure_maker(*body_id);
+                format!("{}({})", maker, self.operands(captures).join(", "))
+            }
+        }
+    }
+
+    // Defines the function allocating the environment of the closure
+    // `body_id`, which is defined along with its body.
+    fn closure_maker(&mut self, body_id: BodyId) -> String {
+        let name = format!("ct_make_closure{}", body_id.index());
+        if self.helpers.contains(&format!("ct_fn {}(", name)) {
+            return name;
+        }
+        let tys = self.capture_types(body_id);
+        let code = body_symbol(self.til, body_id);
+        if tys.is_empty() {
+            writeln!(
+                self.helpers,
+                "static ct_fn {}(void) {{\n    return (ct_fn){{(ct_code){}, NULL}};\n}}",
+                name, code
+            )
+            .unwrap();
+            return name;
+        }
+        let params = tys
+            .iter()
+            .enumerate()
+            .map(|(i, ty)| format!("{} c{}", ty, i))
+            .collect::<Vec<_>>();
+        let def = &mut self.helpers;
+        writeln!(def, "static ct_fn {}({}) {{", name, params.join(", ")).unwrap();
+        writeln!(def, "    ct_env{} *env = malloc(sizeof *env);", body_id.index()).unwrap();
+        writeln!(def, "    if (env == NULL)\n        ct_panic(\"out of memory\");").unwrap();
+        for i in 0..tys.len() {
+            writeln!(def, "    env->c{} = c{};", i, i).unwrap();
+        }
+        writeln!(def, "    return (ct_fn){{(ct_code){}, env}};\n}}", code).unwrap();
+        name
+    }
+
+    fn capture_types(&mut self, body_id: BodyId) -> Vec<String> {
+        let body = self.til.body(body_id);
+        let captures = match body.ty {
+            BodyTy::Closure { captures, .. } => captures,
+            BodyTy::Fn(_) => 0,
+        };
+        body.args()
+            .take(captures)
+            .map(|local| self.c_type(body.local_decl(local).ty))
+            .collect()
+    }
+
+    fn terminator(&mut self, body: &Body<'gcx>, terminator: &Terminator) -> String {
+        match terminator {
+            Terminator::Goto(target) => format!("goto {};", target),
+            Terminator::Branch {
+                cond,
+                then_block,
+                else_block,
+            } => format!(
+                "if ({}) goto {}; else goto {};",
+                self.operand(cond),
+                then_block,
+                else_block
+            ),
+            Terminator::Return => "return _0;".to_string(),
+            Terminator::Call {
+                func,
+                args,
+                dest,
+                target,
+            } => {
+                let args = self.operands(args);
+                let call = match func {
+                    Operand::Fn(def_id) => {
+                        let (callee, ..) = self.callee(def_id);
+                        format!("{}({})", callee, args.join(", "))
+                    }
+                    _ => {
+                        let t = body.operand_ty(self.ctx, func);
+                        let (inputs, output) = self.fn_sig_types(t);
+                        let mut param_tys = vec!["void *".to_string()];
+                        param_tys.extend(inputs);
+                        let f = self.operand(func);
+                        let mut call_args = vec![format!("{}.env", f)];
+                        call_args.extend(args);
+                        format!(
+                            "(({} (*)({})){}.code)({})",
+                            output,
+                            param_tys.join(", "),
+                            f,
+                            call_args.join(", ")
+                        )
+                    }
+                };
+                format!("{} = {};\n    goto {};", place_expr(dest), call, target)
+            }
+            Terminator::Unreachable => "ct_panic(\"entered unreachable code\");".to_string(),
+        }
+    }
+
+    fn emit_main(&mut self, entry: &DefId, out: &mut String) {
+        let (callee, ..) = self.callee(entry);
+        let output = match self.ctx.type_of(entry).unwrap().kind() {
+            TypeKind::Fn(_, output) => *output,
+            _ => unreachable!(),
+        };
+        writeln!(out, "int main(void) {{").unwrap();
+        match output.kind() {
+            TypeKind::Int => writeln!(out, "    printf(\"%\" PRId64 \"\\n\", {}());", callee),
+            TypeKind::Bool => writeln!(out, "    puts({}() ? \"true\" : \"false\");", callee),
+            _ => writeln!(out, "    {}();", callee),
+        }
+        .unwrap();
+        writeln!(out, "    return 0;\n}}").unwrap();
+    }
+}
+
+fn params_list(tys: &[String]) -> String {
+    if tys.is_empty() {
+        "void".to_string()
+    } else {
+        tys.join(", ")
+    }
+}
+
+fn place_expr(place: &Place) -> String {
+    let mut expr = place.local.to_string();
+    for index in &place.projection {
+        write!(expr, ".f{}", index).unwrap();
+    }
+    expr
+}
@@ -0,0 +1,756 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+
+use crate::codegen::{body_symbol, foreign_callee, foreign_class, is_supported, item_symbol, Import};
+use crate::context::GlobalContext;
+use crate::def::DefId;
+use crate::op::{BinOp, UnOp};
+use crate::t::const_eval::ConstValue;
+use crate::t::{Type, TypeKind, TypeRef};
+use crate::til::dom::Dominators;
+use crate::til::ssa::{promotable_locals, to_ssa};
+use crate::til::{
+    Body, BodyId, BodyTy, Local, Operand, Place, Rvalue, Statement, Terminator, Til, const_ty,
+    field_ty,
+};
+
+const FN_TYPE: &str = "{ ptr, ptr }";
+
+// Messages of the checks of the runtime, like the ones of the interpreter.
+const CHECKS: &[(&str, &str)] = &[
+    ("add", "attempt to add with overflow"),
+    ("sub", "attempt to subtract with overflow"),
+    ("mul", "attempt to multiply with overflow"),
+    ("div_zero", "attempt to divide by zero"),
+    ("div_overflow", "attempt to divide with overflow"),
+    ("rem_zero", "attempt to calculate the remainder with a divisor of zero"),
+    ("rem_overflow", "attempt to calculate the remainder with overflow"),
+    ("neg", "attempt to negate with overflow"),
+    ("unreachable", "entered unreachable code"),
+];
+
+// Generates textual LLVM IR from `til`, using opaque pointers. Bodies are
+// put in SSA form by `to_ssa`, whose locals become SSA values and whose phis
+// become `phi` instructions. Only the locals which cannot be promoted live
+// in allocas. Tuples and class instances are first-class aggregates.
+// Function values pair code taking an environment before the parameters
+// with the environment, which is allocated by `malloc` for closures and
+// never freed. Defns of other units are declared from the signatures in the
+// BUIs of `imports`, and `entry` becomes `main`, printing its result like
+// the C backend.
+pub fn to_llvm<'gcx>(
+    til: &Til<'gcx>,
+    ctx: &'gcx GlobalContext<'gcx>,
+    imports: &[Import],
+    entry: Option<&DefId>,
+) -> String {
+    let mut gen = LlvmGen::new(til, ctx, imports);
+    let mut funcs = String::new();
+    for i in 0..til.bodies.len() {
+        let body_id = BodyId(u32::try_from(i).unwrap());
+        if is_supported(til, body_id, "LLVM") {
+            gen.emit_body(body_id, &mut funcs);
+        }
+    }
+    if let Some(entry) = entry {
+        gen.emit_main(entry, &mut funcs);
+    }
+    let mut out = format!("; Generated from unit {}.\n", til.unit_path.join("::"));
+    for section in [&gen.type_defs, &runtime_globals(), &gen.decls, &runtime(), &gen.helpers] {
+        if !section.is_empty() {
+            out.push('\n');
+            out.push_str(section.trim_end());
+            out.push('\n');
+        }
+    }
+    out.push_str(&funcs);
+    out
+}
+
+fn string_constant(name: &str, text: &str) -> String {
+    let mut escaped = String::new();
+    for b in text.bytes() {
+        if b.is_ascii_graphic() && b != b'"' && b != b'\\' || b == b' ' {
+            escaped.push(char::from(b));
+        } else {
+            write!(escaped, "\\{:02X}", b).unwrap();
+        }
+    }
+    format!(
+        "@{} = private unnamed_addr constant [{} x i8] c\"{}\"\n",
+        name,
+        text.len(),
+        escaped
+    )
+}
+
+fn runtime_globals() -> String {
+    let mut out = String::new();
+    for (name, message) in CHECKS {
+        out.push_str(&string_constant(&format!("ct.msg.{}", name), &format!("{}\n", message)));
+    }
+    out.push_str(&string_constant("ct.panic", "panic: "));
+    out.push_str(&string_constant("ct.fmt.int", "%lld\n\0"));
+    out.push_str(&string_constant("ct.true", "true\0"));
+    out.push_str(&string_constant("ct.false", "false\0"));
+    out.push_str("\ndeclare i64 @write(i32, ptr, i64)\n");
+    out.push_str("declare void @exit(i32)\n");
+    out.push_str("declare ptr @malloc(i64)\n");
+    out.push_str("declare i32 @printf(ptr, ...)\n");
+    out.push_str("declare i32 @puts(ptr)\n");
+    for op in ["sadd", "ssub", "smul"] {
+        writeln!(out, "declare {{ i64, i1 }} @llvm.{}.with.overflow.i64(i64, i64)", op).unwrap();
+    }
+    out
+}
+
+fn panic_call(check: &str) -> String {
+    let (_, message) = CHECKS.iter().find(|(name, _)| *name == check).unwrap();
+    format!(
+        "call void @ct_panic(ptr @ct.msg.{}, i64 {})\n  unreachable",
+        check,
+        message.len() + 1
+    )
+}
+
+// Checked arithmetic, panicking with exit status 101.
+fn runtime() -> String {
+    let mut out = String::new();
+    out.push_str(
+        "define internal void @ct_panic(ptr %msg, i64 %len) {\n  \
+         %1 = call i64 @write(i32 2, ptr @ct.panic, i64 7)\n  \
+         %2 = call i64 @write(i32 2, ptr %msg, i64 %len)\n  \
+         call void @exit(i32 101)\n  \
+         unreachable\n}\n\n",
+    );
+    for (name, op) in [("add", "sadd"), ("sub", "ssub"), ("mul", "smul")] {
+        writeln!(
+            out,
+            "define internal i64 @ct_{name}(i64 %a, i64 %b) {{\n  \
+             %r = call {{ i64, i1 }} @llvm.{op}.with.overflow.i64(i64 %a, i64 %b)\n  \
+             %o = extractvalue {{ i64, i1 }} %r, 1\n  \
+             br i1 %o, label %panic, label %ok\n\
+             ok:\n  \
+             %v = extractvalue {{ i64, i1 }} %r, 0\n  \
+             ret i64 %v\n\
+             panic:\n  \
+             {}\n}}\n",
+            panic_call(name)
+        )
+        .unwrap();
+    }
+    for (name, op) in [("div", "sdiv"), ("rem", "srem")] {
+        writeln!(
+            out,
+            "define internal i64 @ct_{name}(i64 %a, i64 %b) {{\n  \
+             %z = icmp eq i64 %b, 0\n  \
+             br i1 %z, label %zero, label %nonzero\n\
+             nonzero:\n  \
+             %m = icmp eq i64 %a, -9223372036854775808\n  \
+             %n = icmp eq i64 %b, -1\n  \
+             %o = and i1 %m, %n\n  \
+             br i1 %o, label %overflow, label %ok\n\
+             ok:\n  \
+             %v = {op} i64 %a, %b\n  \
+             ret i64 %v\n\
+             zero:\n  \
+             {}\n\
+             overflow:\n  \
+             {}\n}}\n",
+            panic_call(&format!("{}_zero", name)),
+            panic_call(&format!("{}_overflow", name))
+        )
+        .unwrap();
+    }
+    writeln!(
+        out,
+        "define internal i64 @ct_neg(i64 %a) {{\n  \
+         %r = call {{ i64, i1 }} @llvm.ssub.with.overflow.i64(i64 0, i64 %a)\n  \
+         %o = extractvalue {{ i64, i1 }} %r, 1\n  \
+         br i1 %o, label %panic, label %ok\n\
+         ok:\n  \
+         %v = extractvalue {{ i64, i1 }} %r, 0\n  \
+         ret i64 %v\n\
+         panic:\n  \
+         {}\n}}",
+        panic_call("neg")
+    )
+    .unwrap();
+    out
+}
+
+struct LlvmGen<'a, 'gcx> {
+    til: &'a Til<'gcx>,
+    ctx: &'gcx GlobalContext<'gcx>,
+    imports: &'a [Import<'a>],
+    // Named struct types of classes.
+    type_defs: String,
+    struct_names: HashMap<*const Type<'gcx>, String>,
+    foreign_structs: HashSet<String>,
+    decls: String,
+    declared: HashSet<String>,
+    // Thunks and equality functions.
+    helpers: String,
+    eq_names: HashMap<String, String>,
+}
+
+// Instructions of the function being generated.
+struct FnCode {
+    text: String,
+    next_temp: usize,
+    // Value of each local promoted to SSA, the others live in allocas.
+    values: Vec<Option<String>>,
+}
+
+impl FnCode {
+    fn temp(&mut self) -> String {
+        self.next_temp += 1;
+        format!("%t{}", self.next_temp)
+    }
+
+    fn push(&mut self, instr: &str) {
+        writeln!(self.text, "  {}", instr).unwrap();
+    }
+
+    // Pushes an instruction producing a value and returns the value.
+    fn assign(&mut self, instr: &str) -> String {
+        let temp = self.temp();
+        self.push(&format!("{} = {}", temp, instr));
+        temp
+    }
+}
+
+impl<'a, 'gcx> LlvmGen<'a, 'gcx> {
+    fn new(til: &'a Til<'gcx>, ctx: &'gcx GlobalContext<'gcx>, imports: &'a [Import]) -> Self {
+        LlvmGen {
+            til,
+            ctx,
+            imports,
+            type_defs: String::new(),
+            struct_names: HashMap::new(),
+            foreign_structs: HashSet::new(),
+            decls: String::new(),
+            declared: HashSet::new(),
+            helpers: String::new(),
+            eq_names: HashMap::new(),
+        }
+    }
+
+    fn llvm_type(&mut self, t: TypeRef<'gcx>) -> String {
+        match t.kind() {
+            TypeKind::Bool => "i1".to_string(),
+            TypeKind::Int => "i64".to_string(),
+            TypeKind::Tuple([]) | TypeKind::Never => "{}".to_string(),
+            TypeKind::Fn(..) => FN_TYPE.to_string(),
+            TypeKind::Tuple(elems) => {
+                let elems = elems.iter().map(|t| self.llvm_type(t)).collect::<Vec<_>>();
+                format!("{{ {} }}", elems.join(", "))
+            }
+            TypeKind::Adt(adt_def, _) => {
+                if let Some(name) = self.struct_names.get(&(t as *const _)) {
+                    return name.clone();
+                }
+                if let Some(name) = self.foreign_struct(&adt_def.def_id) {
+                    return name;
+                }
+                let name = format!("%{}", item_symbol(self.til, &adt_def.def_id));
+                self.struct_names.insert(t as *const _, name.clone());
+                let fields = (0..adt_def.fields().len())
+                    .map(|i| self.llvm_type(field_ty(self.ctx, t, i)))
+                    .collect::<Vec<_>>();
+                writeln!(self.type_defs, "{} = type {{ {} }}", name, fields.join(", ")).unwrap();
+                name
+            }
+            TypeKind::Error | TypeKind::Infer(_) | TypeKind::Param(_) => {
+                unreachable!("Type {} has no LLVM representation", t)
+            }
+        }
+    }
+
+    // Struct of a class of an imported unit, laid out from its BUI.
+    fn foreign_struct(&mut self, def_id: &DefId) -> Option<String> {
+        let (symbol, fields) = foreign_class(self.ctx, self.imports, def_id)?;
+        let name = format!("%{}", symbol);
+        if self.foreign_structs.insert(name.clone()) {
+            let fields = fields
+                .into_iter()
+                .map(|(_, t)| self.llvm_type(t))
+                .collect::<Vec<_>>();
+            writeln!(self.type_defs, "{} = type {{ {} }}", name, fields.join(", ")).unwrap();
+        }
+        Some(name)
+    }
+
+    // Symbol and signature of the defn `def_id`, declaring it when it is
+    // defined in another unit.
+    fn callee(&mut self, def_id: &DefId) -> (String, Vec<String>, String) {
+        if def_id.is_local() {
+            let (inputs, output) = self.fn_sig(self.ctx.type_of(def_id).unwrap());
+            return (format!("@{}", item_symbol(self.til, def_id)), inputs, output);
+        }
+        let (symbol, t) = foreign_callee(self.til, self.ctx, self.imports, def_id, "LLVM");
+        let (inputs, output) = self.fn_sig(t);
+        if self.declared.insert(symbol.clone()) {
+            writeln!(self.decls, "declare {} @{}({})", output, symbol, inputs.join(", ")).unwrap();
+        }
+        (format!("@{}", symbol), inputs, output)
+    }
+
+    fn fn_sig(&mut self, t: TypeRef<'gcx>) -> (Vec<String>, String) {
+        match t.kind() {
+            TypeKind::Fn(inputs, output) => (
+                inputs.iter().map(|t| self.llvm_type(t)).collect(),
+                self.llvm_type(output),
+            ),
+            _ => unreachable!(),
+        }
+    }
+
+    // Code of the function value of the defn `def_id`, an internal function
+    // dropping the `%env` it is called with.
+    fn thunk(&mut self, def_id: &DefId) -> String {
+        let (callee, inputs, output) = self.callee(def_id);
+        let thunk = format!("{}.thunk", callee);
+        if !self.declared.insert(thunk.clone()) {
+            return thunk;
+        }
+        let params = inputs
+            .iter()
+            .enumerate()
+            .map(|(i, t)| format!("{} %a{}", t, i))
+            .collect::<Vec<_>>();
+        write!(self.helpers, "define internal {} {}(ptr %env", output, thunk).unwrap();
+        for param in &params {
+            write!(self.helpers, ", {}", param).unwrap();
+        }
+        writeln!(self.helpers, ") {{").unwrap();
+        writeln!(self.helpers, "  %r = call {} {}({})", output, callee, params.join(", ")).unwrap();
+        writeln!(self.helpers, "  ret {} %r\n}}\n", output).unwrap();
+        thunk
+    }
+
+    // Structural equality of tuples, class instances and function values.
+    fn eq_fn(&mut self, t: TypeRef<'gcx>) -> String {
+        let ty = self.llvm_type(t);
+        if let Some(name) = self.eq_names.get(&ty) {
+            return name.clone();
+        }
+        let name = format!("@ct.eq.{}", self.eq_names.len());
+        self.eq_names.insert(ty.clone(), name.clone());
+        let fields = match t.kind() {
+            TypeKind::Fn(..) => vec![None, None],
+            _ => {
+                let count = match t.kind() {
+                    TypeKind::Tuple(elems) => elems.len(),
+                    TypeKind::Adt(adt_def, _) => adt_def.fields().len(),
+                    _ => unreachable!(),
+                };
+                (0..count).map(|i| Some(field_ty(self.ctx, t, i))).collect()
+            }
+        };
+        let mut code = FnCode {
+            text: String::new(),
+            next_temp: 0,
+            values: vec![],
+        };
+        let mut result = "true".to_string();
+        for (i, field) in fields.into_iter().enumerate() {
+            let a = code.assign(&format!("extractvalue {} %a, {}", ty, i));
+            let b = code.assign(&format!("extractvalue {} %b, {}", ty, i));
+            let eq = match field {
+                Some(field) => self.eq_value(field, &a, &b, &mut code),
+                None => code.assign(&format!("icmp eq ptr {}, {}", a, b)),
+            };
+            result = code.assign(&format!("and i1 {}, {}", result, eq));
+        }
+        writeln!(self.helpers, "define internal i1 {}({} %a, {} %b) {{", name, ty, ty).unwrap();
+        self.helpers.push_str(&code.text);
+        writeln!(self.helpers, "  ret i1 {}\n}}\n", result).unwrap();
+        name
+    }
+
+    fn eq_value(&mut self, t: TypeRef<'gcx>, a: &str, b: &str, code: &mut FnCode) -> String {
+        match t.kind() {
+            TypeKind::Bool | TypeKind::Int => {
+                let ty = self.llvm_type(t);
+                code.assign(&format!("icmp eq {} {}, {}", ty, a, b))
+            }
+            TypeKind::Tuple([]) | TypeKind::Never => "true".to_string(),
+            _ => {
+                let ty = self.llvm_type(t);
+                let eq = self.eq_fn(t);
+                code.assign(&format!("call i1 {}({} {}, {} {})", eq, ty, a, ty, b))
+            }
+        }
+    }
+
+    fn emit_body(&mut self, body_id: BodyId, out: &mut String) {
+        let mut body = self.til.body(body_id).clone();
+        to_ssa(&mut body);
+        let body = &body;
+        let captures = match body.ty {
+            BodyTy::Fn(_) => 0,
+            BodyTy::Closure { captures, .. } => captures,
+        };
+        let mut params = vec![];
+        if let BodyTy::Closure { .. } = body.ty {
+            params.push("ptr %env".to_string());
+        }
+        for local in body.args().skip(captures) {
+            let ty = self.llvm_type(body.local_decl(local).ty);
+            params.push(format!("{} %a{}", ty, local.index()));
+        }
+        let linkage = if body.public { "" } else { "internal " };
+        let return_ty = self.llvm_type(body.return_ty());
+        writeln!(
+            out,
+            "\ndefine {}{} @{}({}) {{",
+            linkage,
+            return_ty,
+            body_symbol(self.til, body_id),
+            params.join(", ")
+        )
+        .unwrap();
+        let values = promotable_locals(body)
+            .into_iter()
+            .map(|x| x.then(|| "undef".to_string()))
+            .collect();
+        let mut code = FnCode {
+            text: String::new(),
+            next_temp: 0,
+            values,
+        };
+        // The entry block can have no predecessors, unlike `bb0`.
+        code.text.push_str("start:\n");
+        for (i, decl) in body.locals.iter().enumerate() {
+            if code.values[i].is_none() {
+                let ty = self.llvm_type(decl.ty);
+                code.push(&format!("%_{} = alloca {}", i, ty));
+            }
+        }
+        let env_ty = self.env_type(body_id);
+        for local in body.args() {
+            let i = local.index();
+            let ty = self.llvm_type(body.local_decl(local).ty);
+            let value = if i <= captures {
+                let ptr = code.assign(&format!(
+                    "getelementptr inbounds {}, ptr %env, i32 0, i32 {}",
+                    env_ty,
+                    i - 1
+                ));
+                code.assign(&format!("load {}, ptr {}", ty, ptr))
+            } else {
+                format!("%a{}", i)
+            };
+            self.store(body, &Place::local(local), &(ty, value), &mut code);
+        }
+        code.push("br label %bb0");
+        // Blocks are emitted in reverse postorder so that definitions come
+        // before their uses, except for phi arguments which are filled in
+        // once all blocks are done.
+        let dominators = Dominators::new(body);
+        let mut phi_positions = vec![];
+        for block_id in dominators.reverse_postorder() {
+            let block = body.block(*block_id);
+            writeln!(code.text, "{}:", block_id).unwrap();
+            phi_positions.push((*block_id, code.text.len()));
+            for phi in &block.phis {
+                code.values[phi.dest.index()] = Some(format!("%{}", phi.dest));
+            }
+            for statement in &block.statements {
+                let Statement::Assign(place, rvalue) = statement;
+                let t = body.place_ty(self.ctx, place);
+                let value = self.rvalue(body, t, rvalue, &mut code);
+                self.store(body, place, &value, &mut code);
+            }
+            self.terminator(body, &block.terminator, &mut code);
+        }
+        for (block_id, position) in phi_positions.into_iter().rev() {
+            let mut phis = String::new();
+            for phi in &body.block(block_id).phis {
+                let ty = self.llvm_type(body.local_decl(phi.dest).ty);
+                let args = phi
+                    .args
+                    .iter()
+                    .filter(|(pred, _)| dominators.is_reachable(*pred))
+                    .map(|(pred, arg)| {
+                        let (_, value) = self.operand(body, arg, &mut code);
+                        format!("[ {}, %{} ]", value, pred)
+                    })
+                    .collect::<Vec<_>>();
+                writeln!(phis, "  %{} = phi {} {}", phi.dest, ty, args.join(", ")).unwrap();
+            }
+            code.text.insert_str(position, &phis);
+        }
+        out.push_str(&code.text);
+        out.push_str("}\n");
+    }
+
+    // Struct of the captures of the closure `body_id`.
+    fn env_type(&mut self, body_id: BodyId) -> String {
+        let body = self.til.body(body_id);
+        let captures = match body.ty {
+            BodyTy::Closure { captures, .. } => captures,
+            BodyTy::Fn(_) => 0,
+        };
+        let tys = (1..=captures)
+            .map(|i| self.llvm_type(body.locals[i].ty))
+            .collect::<Vec<_>>();
+        format!("{{ {} }}", tys.join(", "))
+    }
+
+    fn load(&mut self, body: &Body<'gcx>, place: &Place, code: &mut FnCode) -> (String, String) {
+        let local_ty = self.llvm_type(body.local_decl(place.local).ty);
+        let value = match &code.values[place.local.index()] {
+            Some(value) => value.clone(),
+            None => code.assign(&format!("load {}, ptr %{}", local_ty, place.local)),
+        };
+        if place.projection.is_empty() {
+            return (local_ty, value);
+        }
+        let indices = place.projection.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+        let value = code.assign(&format!(
+            "extractvalue {} {}, {}",
+            local_ty,
+            value,
+            indices.join(", ")
+        ));
+        (self.llvm_type(body.place_ty(self.ctx, place)), value)
+    }
+
+    fn store(
+        &mut self,
+        body: &Body<'gcx>,
+        place: &Place,
+        value: &(String, String),
+        code: &mut FnCode,
+    ) {
+        let local_ty = self.llvm_type(body.local_decl(place.local).ty);
+        let (ty, value) = value;
+        if code.values[place.local.index()].is_some() {
+            code.values[place.local.index()] = Some(value.clone());
+            return;
+        }
+        if place.projection.is_empty() {
+            code.push(&format!("store {} {}, ptr %{}", ty, value, place.local));
+            return;
+        }
+        let old = code.assign(&format!("load {}, ptr %{}", local_ty, place.local));
+        let indices = place.projection.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+        let new = code.assign(&format!(
+            "insertvalue {} {}, {} {}, {}",
+            local_ty,
+            old,
+            ty,
+            value,
+            indices.join(", ")
+        ));
+        code.push(&format!("store {} {}, ptr %{}", local_ty, new, place.local));
+    }
+
+    fn const_value(&mut self, value: &ConstValue) -> (String, String) {
+        let ty = self.llvm_type(const_ty(self.ctx, value));
+        let value = match value {
+            ConstValue::Bool(b) => b.to_string(),
+            ConstValue::Int(i) => i.to_string(),
+            ConstValue::Tuple(values) | ConstValue::Adt(_, values) if values.is_empty() => {
+                "zeroinitializer".to_string()
+            }
+            ConstValue::Tuple(values) | ConstValue::Adt(_, values) => {
+                let values = values
+                    .iter()
+                    .map(|x| {
+                        let (ty, value) = self.const_value(x);
+                        format!("{} {}", ty, value)
+                    })
+                    .collect::<Vec<_>>();
+                format!("{{ {} }}", values.join(", "))
+            }
+        };
+        (ty, value)
+    }
+
+    fn operand(
+        &mut self,
+        body: &Body<'gcx>,
+        operand: &Operand,
+        code: &mut FnCode,
+    ) -> (String, String) {
+        match operand {
+            Operand::Copy(place) => self.load(body, place, code),
+            Operand::Const(value) => self.const_value(value),
+            Operand::Fn(def_id) => {
+                let thunk = self.thunk(def_id);
+                (FN_TYPE.to_string(), format!("{{ ptr {}, ptr null }}", thunk))
+            }
+            Operand::Static(def_id) => self.const_value(&self.ctx.const_value(def_id).unwrap()),
+        }
+    }
+
+    fn rvalue(
+        &mut self,
+        body: &Body<'gcx>,
+        t: TypeRef<'gcx>,
+        rvalue: &Rvalue,
+        code: &mut FnCode,
+    ) -> (String, String) {
+        let ty = self.llvm_type(t);
+        let value = match rvalue {
+            Rvalue::Use(operand) => return self.operand(body, operand, code),
+            Rvalue::Unary(UnOp::Neg, operand) => {
+                let (_, value) = self.operand(body, operand, code);
+                code.assign(&format!("call i64 @ct_neg(i64 {})", value))
+            }
+            Rvalue::Unary(UnOp::Not, operand) => {
+                let (_, value) = self.operand(body, operand, code);
+                code.assign(&format!("xor i1 {}, true", value))
+            }
+            Rvalue::Binary(op, lhs, rhs) => {
+                let operand_ty = body.operand_ty(self.ctx, lhs);
+                let (lhs_ty, lhs) = self.operand(body, lhs, code);
+                let (_, rhs) = self.operand(body, rhs, code);
+                let signed = if lhs_ty == "i64" { "s" } else { "u" };
+                match op {
+                    BinOp::Add | BinOp::Sub | BinOp::Mul | BinOp::Div | BinOp::Rem => {
+                        let callee = op.keyword();
+                        code.assign(&format!("call i64 @ct_{}(i64 {}, i64 {})", callee, lhs, rhs))
+                    }
+                    BinOp::Eq => self.eq_value(operand_ty, &lhs, &rhs, code),
+                    BinOp::Ne => {
+                        let eq = self.eq_value(operand_ty, &lhs, &rhs, code);
+                        code.assign(&format!("xor i1 {}, true", eq))
+                    }
+                    BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge => code.assign(&format!(
+                        "icmp {}{} {} {}, {}",
+                        signed,
+                        op.keyword(),
+                        lhs_ty,
+                        lhs,
+                        rhs
+                    )),
+                    BinOp::And | BinOp::Or => {
+                        code.assign(&format!("{} i1 {}, {}", op.keyword(), lhs, rhs))
+                    }
+                }
+            }
+            Rvalue::Tuple(fields) | Rvalue::Adt(_, fields) if fields.is_empty() => {
+                "zeroinitializer".to_string()
+            }
+            Rvalue::Tuple(fields) | Rvalue::Adt(_, fields) => {
+                let mut value = "undef".to_string();
+                for (i, field) in fields.iter().enumerate() {
+                    let (field_ty, field) = self.operand(body, field, code);
+                    value = code.assign(&format!(
+                        "insertvalue {} {}, {} {}, {}",
+                        ty, value, field_ty, field, i
+                    ));
+                }
+                value
+            }
+            Rvalue::Closure(body_id, captures) => {
+                let closure = format!("@{}", body_symbol(self.til, *body_id));
+                if captures.is_empty() {
+                    return (ty, format!("{{ ptr {}, ptr null }}", closure));
+                }
+                let env_ty = self.env_type(*body_id);
+                let size = code.assign(&format!("getelementptr {}, ptr null, i32 1", env_ty));
+                let size = code.assign(&format!("ptrtoint ptr {} to i64", size));
+                let env = code.assign(&format!("call ptr @malloc(i64 {})", size));
+                for (i, capture) in captures.iter().enumerate() {
+                    let (capture_ty, capture) = self.operand(body, capture, code);
+                    let ptr = code.assign(&format!(
+                        "getelementptr inbounds {}, ptr {}, i32 0, i32 {}",
+                        env_ty, env, i
+                    ));
+                    code.push(&format!("store {} {}, ptr {}", capture_ty, capture, ptr));
+                }
+                code.assign(&format!(
+                    "insertvalue {} {{ ptr {}, ptr null }}, ptr {}, 1",
+                    FN_TYPE, closure, env
+                ))
+            }
+        };
+        (ty, value)
+    }
+
+    fn terminator(&mut self, body: &Body<'gcx>, terminator: &Terminator, code: &mut FnCode) {
+        match terminator {
+            Terminator::Goto(target) => code.push(&format!("br label %{}", target)),
+            Terminator::Branch {
+                cond,
+                then_block,
+                else_block,
+            } => {
+                let (_, cond) = self.operand(body, cond, code);
+                if then_block == else_block {
+                    code.push(&format!("br label %{}", then_block));
+                    return;
+                }
+                code.push(&format!(
+                    "br i1 {}, label %{}, label %{}",
+                    cond, then_block, else_block
+                ));
+            }
+            Terminator::Return => {
+                let (ty, value) = self.load(body, &Place::local(Local(0)), code);
+                code.push(&format!("ret {} {}", ty, value));
+            }
+            Terminator::Call {
+                func,
+                args,
+                dest,
+                target,
+            } => {
+                let args = args
+                    .iter()
+                    .map(|x| {
+                        let (ty, value) = self.operand(body, x, code);
+                        format!("{} {}", ty, value)
+                    })
+                    .collect::<Vec<_>>();
+                let (output, value) = match func {
+                    Operand::Fn(def_id) => {
+                        let (callee, _, output) = self.callee(def_id);
+                        let call = format!("call {} {}({})", output, callee, args.join(", "));
+                        (output.clone(), code.assign(&call))
+                    }
+                    _ => {
+                        let (_, output) = self.fn_sig(body.operand_ty(self.ctx, func));
+                        let (_, func) = self.operand(body, func, code);
+                        let code_ptr =
+                            code.assign(&format!("extractvalue {} {}, 0", FN_TYPE, func));
+                        let env = code.assign(&format!("extractvalue {} {}, 1", FN_TYPE, func));
+                        let mut call_args = vec![format!("ptr {}", env)];
+                        call_args.extend(args);
+                        let call =
+                            format!("call {} {}({})", output, code_ptr, call_args.join(", "));
+                        (output, code.assign(&call))
+                    }
+                };
+                self.store(body, dest, &(output, value), code);
+                code.push(&format!("br label %{}", target));
+            }
+            Terminator::Unreachable => {
+                for line in panic_call("unreachable").lines() {
+                    code.push(line.trim());
+                }
+            }
+        }
+    }
+
+    fn emit_main(&mut self, entry: &DefId, out: &mut String) {
+        let (callee, _, output) = self.callee(entry);
+        writeln!(out, "\ndefine i32 @main() {{").unwrap();
+        writeln!(out, "  %r = call {} {}()", output, callee).unwrap();
+        match output.as_str() {
+            "i64" => writeln!(out, "  %p = call i32 (ptr, ...) @printf(ptr @ct.fmt.int, i64 %r)"),
+            "i1" => writeln!(
+                out,
+                "  %s = select i1 %r, ptr @ct.true, ptr @ct.false\n  %p = call i32 @puts(ptr %s)"
+            ),
+            _ => Ok(()),
+        }
+        .unwrap();
+        writeln!(out, "  ret i32 0\n}}").unwrap();
+    }
+}
@@ -0,0 +1,215 @@
+pub mod c;
+pub mod llvm;
+pub mod wat;
+
+use crate::bui::{Bui, BuiItem, BuiTy};
+use crate::context::GlobalContext;
+use crate::def::DefId;
+use crate::error::{report_error, Error};
+use crate::t::context::UnitPath;
+use crate::t::{TypeKind, TypeRef};
+use crate::til::{BodyId, BodyTy, Operand, Terminator, Til};
+
+// Mangles `segments` like `ctN4test3mdl1fE`, each segment being prefixed by
+// its length so that distinct paths never collide.
+pub fn mangle(segments: &[String]) -> String {
+    let mut symbol = "ctN".to_string();
+    for segment in segments {
+        symbol.push_str(&format!("{}{}", segment.len(), segment));
+    }
+    symbol.push('E');
+    symbol
+}
+
+// Symbol of an item of `til`. Items of other units are only known by their
+// `DefId`.
+pub fn item_symbol(til: &Til, def_id: &DefId) -> String {
+    match til.item_paths.get(def_id) {
+        Some(path) if def_id.is_local() => {
+            let mut segments = til.unit_path.clone();
+            segments.extend(path.iter().cloned());
+            mangle(&segments)
+        }
+        _ => format!("ctD{}_{}", def_id.unit_num(), def_id.local()),
+    }
+}
+
+// Interface of a unit whose items are referred to by `DefId`s of unit
+// `unit_num`.
+pub struct Import<'b> {
+    pub unit_num: u16,
+    pub path: &'b UnitPath,
+    pub bui: &'b Bui,
+}
+
+// Item of the BUI of one of `imports` which `def_id` refers to.
+pub fn find_import<'b>(
+    imports: &'b [Import<'b>],
+    def_id: &DefId,
+) -> Option<(&'b Import<'b>, BuiItem<'b>)> {
+    if def_id.is_local() {
+        return None;
+    }
+    let import = imports.iter().find(|x| x.unit_num == def_id.unit_num())?;
+    let offset = def_id.local().to_string().parse::<u32>().unwrap();
+    let item = import.bui.items().into_iter().find(|x| x.def() == offset)?;
+    Some((import, item))
+}
+
+// Symbol of an item of an imported unit, which is the one the unit defines
+// it with.
+pub fn foreign_symbol(import: &Import, item: &BuiItem) -> String {
+    let mut segments = import.path.segments().to_vec();
+    segments.extend(item.namespace().iter().map(|x| x.to_string()));
+    segments.push(item.ident().to_string());
+    mangle(&segments)
+}
+
+// Symbol and fields of the class `def_id` of one of `imports`, laid out from
+// its BUI.
+pub fn foreign_class<'gcx>(
+    ctx: &'gcx GlobalContext<'gcx>,
+    imports: &[Import],
+    def_id: &DefId,
+) -> Option<(String, Vec<(String, TypeRef<'gcx>)>)> {
+    let (import, item) = find_import(imports, def_id)?;
+    let fields = item
+        .class()?
+        .fields()
+        .iter()
+        .map(|x| Some((x.name().to_string(), bui_type(ctx, import, &x.ty())?)))
+        .collect::<Option<Vec<_>>>()?;
+    Some((foreign_symbol(import, &item), fields))
+}
+
+// Symbol and type of the defn `def_id` of another unit. Defns of `imports`
+// are typed by their BUI, the others by `ctx`. Unknown signatures are
+// reported as unsupported by `backend`, and taken to be `fn() -> ()`.
+pub fn foreign_callee<'gcx>(
+    til: &Til<'gcx>,
+    ctx: &'gcx GlobalContext<'gcx>,
+    imports: &[Import],
+    def_id: &DefId,
+    backend: &str,
+) -> (String, TypeRef<'gcx>) {
+    let (symbol, t) = match find_import(imports, def_id) {
+        Some((import, item)) => (foreign_symbol(import, &item), bui_fn_type(ctx, import, &item)),
+        None => (item_symbol(til, def_id), ctx.type_of(def_id)),
+    };
+    match t {
+        Some(t) => (symbol, t),
+        None => {
+            report_error(Error::UnsupportedByBackend(
+                format!("Signature of `{}`", symbol),
+                backend.to_string(),
+            ));
+            (symbol, ctx.fn_type(&[], ctx.unit_type()))
+        }
+    }
+}
+
+// Type `t` of the BUI of `import`. BUIs refer to other units by their own
+// numbering, of which only the unit itself is known.
+fn bui_type<'gcx>(
+    ctx: &'gcx GlobalContext<'gcx>,
+    import: &Import,
+    t: &BuiTy,
+) -> Option<TypeRef<'gcx>> {
+    if t.is_bool() {
+        Some(ctx.bool_type())
+    } else if t.is_int() {
+        Some(ctx.int_type())
+    } else if let Some(elems) = t.tuple() {
+        let elems = elems
+            .iter()
+            .map(|t| bui_type(ctx, import, t))
+            .collect::<Option<Vec<_>>>()?;
+        Some(ctx.tuple_type(&elems))
+    } else if let Some(decl) = t.fn_decl() {
+        let inputs = decl
+            .inputs()
+            .iter()
+            .map(|t| bui_type(ctx, import, t))
+            .collect::<Option<Vec<_>>>()?;
+        Some(ctx.fn_type(&inputs, bui_type(ctx, import, &decl.output())?))
+    } else if let Some(adt) = t.adt().filter(|x| x.unit() == 0) {
+        let def_id = DefId::from_raw(import.unit_num, adt.def());
+        Some(ctx.adt_type(ctx.adt_def(def_id)))
+    } else {
+        None
+    }
+}
+
+// Type of the defn `item` of the BUI of `import`.
+fn bui_fn_type<'gcx>(
+    ctx: &'gcx GlobalContext<'gcx>,
+    import: &Import,
+    item: &BuiItem,
+) -> Option<TypeRef<'gcx>> {
+    let defn = item.defn()?;
+    let sig = defn.fn_sig();
+    let decl = sig.decl();
+    let inputs = decl
+        .inputs()
+        .iter()
+        .map(|t| bui_type(ctx, import, t))
+        .collect::<Option<Vec<_>>>()?;
+    Some(ctx.fn_type(&inputs, bui_type(ctx, import, &decl.output())?))
+}
+
+pub fn body_symbol(til: &Til, body_id: BodyId) -> String {
+    match &til.body(body_id).ty {
+        BodyTy::Fn(def_id) => item_symbol(til, def_id),
+        BodyTy::Closure { owner, .. } => {
+            format!("{}_closure{}", item_symbol(til, owner), body_id.index())
+        }
+    }
+}
+
+// Generic bodies and calls which are only resolved at run time need
+// monomorphization, which TIL does not provide. Reports the body `body_id`
+// if `backend` cannot generate it.
+pub fn is_supported(til: &Til, body_id: BodyId, backend: &str) -> bool {
+    let body = til.body(body_id);
+    let unsupported = |what: String| {
+        report_error(Error::UnsupportedByBackend(what, backend.to_string()));
+        false
+    };
+    if body.locals.iter().any(|x| has_param(x.ty)) {
+        return unsupported(format!("Generic defn `{}`", body.name));
+    }
+    if body.locals.iter().any(|x| !is_concrete(x.ty)) {
+        return unsupported(format!("Defn `{}` with types which were not inferred", body.name));
+    }
+    let calls_member = body.blocks.iter().any(|block| match &block.terminator {
+        Terminator::Call {
+            func: Operand::Fn(def_id),
+            ..
+        } => def_id.is_local() && til.fn_body(def_id).is_none(),
+        _ => false,
+    });
+    if calls_member {
+        return unsupported(format!("Call of an interface or trait member in `{}`", body.name));
+    }
+    true
+}
+
+fn has_param(t: TypeRef) -> bool {
+    match t.kind() {
+        TypeKind::Param(_) => true,
+        TypeKind::Tuple(elems) => elems.iter().any(|t| has_param(t)),
+        TypeKind::Fn(inputs, output) => inputs.iter().any(|t| has_param(t)) || has_param(output),
+        _ => false,
+    }
+}
+
+fn is_concrete(t: TypeRef) -> bool {
+    match t.kind() {
+        TypeKind::Error | TypeKind::Infer(_) => false,
+        TypeKind::Tuple(elems) => elems.iter().all(|t| is_concrete(t)),
+        TypeKind::Fn(inputs, output) => {
+            inputs.iter().all(|t| is_concrete(t)) && is_concrete(output)
+        }
+        _ => true,
+    }
+}
@@ -0,0 +1,698 @@
+pub mod verify;
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::codegen::{body_symbol, foreign_callee, is_supported, item_symbol, Import};
+use crate::context::GlobalContext;
+use crate::def::DefId;
+use crate::op::{BinOp, UnOp};
+use crate::t::const_eval::ConstValue;
+use crate::t::{Type, TypeKind, TypeRef};
+use crate::til::{
+    BlockId, Body, BodyId, BodyTy, Operand, Place, Rvalue, Statement, Terminator, Til, field_ty,
+};
+
+// Every field of a tuple or class takes a slot of this many bytes.
+const SLOT_SIZE: u32 = 8;
+// Address 0 is never allocated.
+const DATA_START: u32 = 8;
+const PAGE_SIZE: u32 = 65536;
+
+// Checked arithmetic traps where the interpreter reports an error.
+// Division traps natively.
+const RUNTIME: &str = r#"  (func $ct_alloc (param $size i32) (result i32) (local $ptr i32)
+    global.get $ct_heap
+    local.set $ptr
+    global.get $ct_heap
+    local.get $size
+    i32.add
+    global.set $ct_heap
+    block $done
+      global.get $ct_heap
+      memory.size
+      i32.const 16
+      i32.shl
+      i32.le_u
+      br_if $done
+      global.get $ct_heap
+      memory.size
+      i32.const 16
+      i32.shl
+      i32.sub
+      i32.const 65535
+      i32.add
+      i32.const 16
+      i32.shr_u
+      memory.grow
+      i32.const -1
+      i32.ne
+      br_if $done
+      unreachable
+    end
+    local.get $ptr)
+  (func $ct_clone (param $src i32) (param $size i32) (result i32) (local $dst i32) (local $i i32)
+    local.get $size
+    call $ct_alloc
+    local.set $dst
+    block $done
+      loop $copy
+        local.get $i
+        local.get $size
+        i32.ge_u
+        br_if $done
+        local.get $dst
+        local.get $i
+        i32.add
+        local.get $src
+        local.get $i
+        i32.add
+        i64.load
+        i64.store
+        local.get $i
+        i32.const 8
+        i32.add
+        local.set $i
+        br $copy
+      end
+    end
+    local.get $dst)
+  (func $ct_add (param $a i64) (param $b i64) (result i64) (local $r i64)
+    local.get $a
+    local.get $b
+    i64.add
+    local.set $r
+    local.get $a
+    local.get $r
+    i64.xor
+    local.get $b
+    local.get $r
+    i64.xor
+    i64.and
+    i64.const 0
+    i64.lt_s
+    if
+      unreachable
+    end
+    local.get $r)
+  (func $ct_sub (param $a i64) (param $b i64) (result i64) (local $r i64)
+    local.get $a
+    local.get $b
+    i64.sub
+    local.set $r
+    local.get $a
+    local.get $b
+    i64.xor
+    local.get $a
+    local.get $r
+    i64.xor
+    i64.and
+    i64.const 0
+    i64.lt_s
+    if
+      unreachable
+    end
+    local.get $r)
+  (func $ct_mul (param $a i64) (param $b i64) (result i64) (local $r i64)
+    local.get $a
+    i64.const -1
+    i64.eq
+    local.get $b
+    i64.const -9223372036854775808
+    i64.eq
+    i32.and
+    if
+      unreachable
+    end
+    local.get $a
+    local.get $b
+    i64.mul
+    local.set $r
+    local.get $a
+    i64.const 0
+    i64.ne
+    if
+      local.get $r
+      local.get $a
+      i64.div_s
+      local.get $b
+      i64.ne
+      if
+        unreachable
+      end
+    end
+    local.get $r)
+  (func $ct_rem (param $a i64) (param $b i64) (result i64)
+    local.get $a
+    i64.const -9223372036854775808
+    i64.eq
+    local.get $b
+    i64.const -1
+    i64.eq
+    i32.and
+    if
+      unreachable
+    end
+    local.get $a
+    local.get $b
+    i64.rem_s)
+  (func $ct_neg (param $a i64) (result i64)
+    local.get $a
+    i64.const -9223372036854775808
+    i64.eq
+    if
+      unreachable
+    end
+    i64.const 0
+    local.get $a
+    i64.sub)
+"#;
+
+// Generates a WebAssembly text module from `til`. `int` is `i64`, while
+// `bool`, `()` and pointers are `i32`. Tuples, class instances and function
+// values live in linear memory, are allocated by bumping a pointer which is
+// never reset, and are copied before a field is assigned, so that they can
+// be shared. Function values point to the index of their code in the table,
+// followed by the captures of closures, and are passed to the code before
+// the parameters. Public defns are exported, and the memory as "memory".
+// Defns of other units are imported from the module "ct" with the
+// signatures in the BUIs of `imports`.
+pub fn to_wat<'gcx>(
+    til: &Til<'gcx>,
+    ctx: &'gcx GlobalContext<'gcx>,
+    imports: &[Import],
+) -> String {
+    let mut gen = WatGen::new(til, ctx, imports);
+    let supported = (0..til.bodies.len())
+        .map(|i| is_supported(til, BodyId(u32::try_from(i).unwrap()), "WebAssembly"))
+        .collect::<Vec<_>>();
+    let mut funcs = String::new();
+    for (i, supported) in supported.iter().enumerate() {
+        if *supported {
+            gen.emit_body(BodyId(u32::try_from(i).unwrap()), &mut funcs);
+        }
+    }
+    let mut out = format!(";; Generated from unit {}.\n(module\n", til.unit_path.join("::"));
+    out.push_str(&gen.types);
+    out.push_str(&gen.import_decls);
+    let heap_start = align(gen.data_end);
+    let pages = heap_start.div_ceil(PAGE_SIZE).max(1);
+    writeln!(out, "  (memory (export \"memory\") {})", pages).unwrap();
+    writeln!(out, "  (global $ct_heap (mut i32) (i32.const {}))", heap_start).unwrap();
+    if !gen.table.is_empty() {
+        writeln!(out, "  (table {} funcref)", gen.table.len()).unwrap();
+        writeln!(out, "  (elem (i32.const 0) {})", gen.table.join(" ")).unwrap();
+    }
+    for (addr, bytes) in &gen.data {
+        let escaped = bytes.iter().map(|b| format!("\\{:02x}", b)).collect::<String>();
+        writeln!(out, "  (data (i32.const {}) \"{}\")", addr, escaped).unwrap();
+    }
+    out.push_str(RUNTIME);
+    out.push_str(&gen.helpers);
+    out.push_str(&funcs);
+    out.push_str(")\n");
+    out
+}
+
+fn align(addr: u32) -> u32 {
+    addr.div_ceil(SLOT_SIZE) * SLOT_SIZE
+}
+
+fn wasm_ty(t: TypeRef) -> &'static str {
+    match t.kind() {
+        TypeKind::Int => "i64",
+        _ => "i32",
+    }
+}
+
+fn field_count(t: TypeRef) -> u32 {
+    let count = match t.kind() {
+        TypeKind::Tuple(elems) => elems.len(),
+        TypeKind::Adt(adt_def, _) => adt_def.fields().len(),
+        _ => unreachable!(),
+    };
+    u32::try_from(count).unwrap()
+}
+
+fn is_aggregate(t: TypeRef) -> bool {
+    match t.kind() {
+        TypeKind::Tuple(elems) => !elems.is_empty(),
+        TypeKind::Adt(..) => true,
+        _ => false,
+    }
+}
+
+fn offset(index: usize) -> u32 {
+    u32::try_from(index).unwrap() * SLOT_SIZE
+}
+
+struct WatGen<'a, 'gcx> {
+    til: &'a Til<'gcx>,
+    ctx: &'gcx GlobalContext<'gcx>,
+    imports: &'a [Import<'a>],
+    types: String,
+    type_names: HashMap<String, String>,
+    import_decls: String,
+    import_names: HashMap<DefId, String>,
+    // Functions called through function values.
+    table: Vec<String>,
+    data: Vec<(u32, Vec<u8>)>,
+    data_end: u32,
+    // Addresses of the function values of defns.
+    fn_values: HashMap<DefId, u32>,
+    // Thunks and equality functions.
+    helpers: String,
+    eq_names: HashMap<*const Type<'gcx>, String>,
+}
+
+impl<'a, 'gcx> WatGen<'a, 'gcx> {
+    fn new(til: &'a Til<'gcx>, ctx: &'gcx GlobalContext<'gcx>, imports: &'a [Import]) -> Self {
+        WatGen {
+            til,
+            ctx,
+            imports,
+            types: String::new(),
+            type_names: HashMap::new(),
+            import_decls: String::new(),
+            import_names: HashMap::new(),
+            table: vec![],
+            data: vec![],
+            data_end: DATA_START,
+            fn_values: HashMap::new(),
+            helpers: String::new(),
+            eq_names: HashMap::new(),
+        }
+    }
+
+    fn fn_sig(&self, t: TypeRef<'gcx>) -> (Vec<&'static str>, &'static str) {
+        match t.kind() {
+            TypeKind::Fn(inputs, output) => {
+                (inputs.iter().map(|t| wasm_ty(t)).collect(), wasm_ty(output))
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    // Type of the code of function values of type `t`.
+    fn code_type(&mut self, t: TypeRef<'gcx>) -> String {
+        let (inputs, output) = self.fn_sig(t);
+        let mut sig = "(func (param i32".to_string();
+        for input in inputs {
+            write!(sig, " {}", input).unwrap();
+        }
+        write!(sig, ") (result {}))", output).unwrap();
+        if let Some(name) = self.type_names.get(&sig) {
+            return name.clone();
+        }
+        let name = format!("$ct_code{}", self.type_names.len());
+        writeln!(self.types, "  (type {} {})", name, sig).unwrap();
+        self.type_names.insert(sig, name.clone());
+        name
+    }
+
+    // Name to call the defn `def_id` by, importing it when it is defined in
+    // another unit.
+    fn callee(&mut self, def_id: &DefId) -> String {
+        if def_id.is_local() {
+            return format!("${}", item_symbol(self.til, def_id));
+        }
+        if let Some(name) = self.import_names.get(def_id) {
+            return name.clone();
+        }
+        let (symbol, t) = foreign_callee(self.til, self.ctx, self.imports, def_id, "WebAssembly");
+        let (inputs, output) = self.fn_sig(t);
+        let name = format!("${}", symbol);
+        let mut func = format!("(func {}", name);
+        for input in inputs {
+            write!(func, " (param {})", input).unwrap();
+        }
+        write!(func, " (result {}))", output).unwrap();
+        writeln!(self.import_decls, "  (import \"ct\" \"{}\" {})", symbol, func).unwrap();
+        self.import_names.insert(def_id.clone(), name.clone());
+        name
+    }
+
+    fn push_data(&mut self, bytes: Vec<u8>) -> u32 {
+        let addr = self.data_end;
+        self.data_end = align(addr + u32::try_from(bytes.len()).unwrap());
+        self.data.push((addr, bytes));
+        addr
+    }
+
+    // Value of `value` as a constant, placing aggregates in the data
+    // segment.
+    fn const_value(&mut self, value: &ConstValue) -> (&'static str, i64) {
+        match value {
+            ConstValue::Bool(b) => ("i32", i64::from(*b)),
+            ConstValue::Int(i) => ("i64", *i),
+            ConstValue::Tuple(values) if values.is_empty() => ("i32", 0),
+            ConstValue::Tuple(values) | ConstValue::Adt(_, values) => {
+                let mut bytes = vec![];
+                for value in values {
+                    let (_, x) = self.const_value(value);
+                    bytes.extend_from_slice(&x.to_le_bytes());
+                }
+                ("i32", i64::from(self.push_data(bytes)))
+            }
+        }
+    }
+
+    // Defns used as values are called through a thunk ignoring the
+    // environment.
+    fn fn_value(&mut self, def_id: &DefId) -> u32 {
+        if let Some(addr) = self.fn_values.get(def_id) {
+            return *addr;
+        }
+        let callee = self.callee(def_id);
+        let thunk = format!("{}_thunk", callee);
+        let (inputs, output) = self.fn_sig(self.ctx.type_of(def_id).unwrap());
+        write!(self.helpers, "  (func {} (param $env i32)", thunk).unwrap();
+        for (i, input) in inputs.iter().enumerate() {
+            write!(self.helpers, " (param $a{} {})", i, input).unwrap();
+        }
+        writeln!(self.helpers, " (result {})", output).unwrap();
+        for i in 0..inputs.len() {
+            writeln!(self.helpers, "    local.get $a{}", i).unwrap();
+        }
+        writeln!(self.helpers, "    call {})", callee).unwrap();
+        let index = i64::try_from(self.table.len()).unwrap();
+        self.table.push(thunk);
+        let addr = self.push_data(index.to_le_bytes().to_vec());
+        self.fn_values.insert(def_id.clone(), addr);
+        addr
+    }
+
+    // Structural equality of tuples and class instances.
+    fn eq_fn(&mut self, t: TypeRef<'gcx>) -> String {
+        if let Some(name) = self.eq_names.get(&(t as *const _)) {
+            return name.clone();
+        }
+        let name = format!("$ct_eq{}", self.eq_names.len());
+        self.eq_names.insert(t as *const _, name.clone());
+        let mut def = format!("  (func {} (param $a i32) (param $b i32) (result i32)\n", name);
+        writeln!(def, "    i32.const 1").unwrap();
+        for i in 0..field_count(t) {
+            let field = field_ty(self.ctx, t, i as usize);
+            let load = format!("{}.load offset={}", wasm_ty(field), i * SLOT_SIZE);
+            writeln!(def, "    local.get $a\n    {}", load).unwrap();
+            writeln!(def, "    local.get $b\n    {}", load).unwrap();
+            writeln!(def, "    {}\n    i32.and", self.eq_instr(field)).unwrap();
+        }
+        def.pop();
+        def.push_str(")\n");
+        self.helpers.push_str(&def);
+        name
+    }
+
+    fn eq_instr(&mut self, t: TypeRef<'gcx>) -> String {
+        if is_aggregate(t) {
+            format!("call {}", self.eq_fn(t))
+        } else {
+            format!("{}.eq", wasm_ty(t))
+        }
+    }
+
+    // Closures are only called through function values.
+    fn closure_index(&mut self, body_id: BodyId) -> usize {
+        let name = format!("${}", body_symbol(self.til, body_id));
+        match self.table.iter().position(|x| *x == name) {
+            Some(index) => index,
+            None => {
+                self.table.push(name);
+                self.table.len() - 1
+            }
+        }
+    }
+
+    fn emit_body(&mut self, body_id: BodyId, out: &mut String) {
+        let body = self.til.body(body_id);
+        let captures = match body.ty {
+            BodyTy::Fn(_) => 0,
+            BodyTy::Closure { captures, .. } => {
+                self.closure_index(body_id);
+                captures
+            }
+        };
+        write!(out, "  (func ${}", body_symbol(self.til, body_id)).unwrap();
+        match &body.ty {
+            BodyTy::Fn(def_id) if body.public => {
+                write!(out, " (export \"{}\")", item_symbol(self.til, def_id)).unwrap()
+            }
+            BodyTy::Fn(_) => {}
+            BodyTy::Closure { .. } => write!(out, " (param $env i32)").unwrap(),
+        }
+        for local in body.args().skip(captures) {
+            write!(out, " (param ${} {})", local, wasm_ty(body.local_decl(local).ty)).unwrap();
+        }
+        writeln!(out, " (result {})", wasm_ty(body.return_ty())).unwrap();
+        for (i, decl) in body.locals.iter().enumerate() {
+            if i <= captures || i > body.arg_count {
+                writeln!(out, "    (local $_{} {})", i, wasm_ty(decl.ty)).unwrap();
+            }
+        }
+        // The current block and scratch pointers.
+        writeln!(out, "    (local $bb i32) (local $p i32) (local $q i32) (local $r i32)").unwrap();
+        let mut code = Code {
+            text: String::new(),
+            indent: 2,
+        };
+        for i in 0..captures {
+            let t = body.local_decl(body.args().nth(i).unwrap()).ty;
+            code.push("local.get $env");
+            code.push(&format!("{}.load offset={}", wasm_ty(t), offset(i + 1)));
+            code.push(&format!("local.set $_{}", i + 1));
+        }
+        let dispatch = body.blocks.len() > 1 || !body.blocks[0].terminator.successors().is_empty();
+        if dispatch {
+            // Jumps set the current block and branch to the loop, which
+            // branches out of the nested block ending right before it.
+            code.push("loop $dispatch");
+            code.indent += 1;
+            for i in (0..body.blocks.len()).rev() {
+                code.push(&format!("block $bb{}", i));
+                code.indent += 1;
+            }
+            code.push("local.get $bb");
+            let labels = (0..body.blocks.len()).map(|i| format!("$bb{}", i)).collect::<Vec<_>>();
+            code.push(&format!("br_table {}", labels.join(" ")));
+        }
+        for block in &body.blocks {
+            if dispatch {
+                code.indent -= 1;
+                code.push("end");
+            }
+            for statement in &block.statements {
+                let Statement::Assign(place, rvalue) = statement;
+                self.store_prefix(body, place, &mut code);
+                self.rvalue(body, body.place_ty(self.ctx, place), rvalue, &mut code);
+                self.store_suffix(body, place, &mut code);
+            }
+            self.terminator(body, &block.terminator, &mut code);
+        }
+        if dispatch {
+            code.indent -= 1;
+            code.push("end");
+            code.push("unreachable");
+        }
+        code.text.pop();
+        out.push_str(&code.text);
+        out.push_str(")\n");
+    }
+
+    // Types of the places along the projection of `place`.
+    fn place_tys(&self, body: &Body<'gcx>, place: &Place) -> Vec<TypeRef<'gcx>> {
+        let mut tys = vec![body.local_decl(place.local).ty];
+        for index in &place.projection {
+            tys.push(field_ty(self.ctx, tys.last().unwrap(), *index));
+        }
+        tys
+    }
+
+    fn load_place(&mut self, body: &Body<'gcx>, place: &Place, code: &mut Code) {
+        code.push(&format!("local.get ${}", place.local));
+        let tys = self.place_tys(body, place);
+        for (index, t) in place.projection.iter().zip(&tys[1..]) {
+            code.push(&format!("{}.load offset={}", wasm_ty(t), offset(*index)));
+        }
+    }
+
+    // Pushes the address to store a field to, after copying the aggregates
+    // along the projection.
+    fn store_prefix(&mut self, body: &Body<'gcx>, place: &Place, code: &mut Code) {
+        let Some((_, path)) = place.projection.split_last() else {
+            return;
+        };
+        let tys = self.place_tys(body, place);
+        code.push(&format!("local.get ${}", place.local));
+        code.push(&format!("i32.const {}", field_count(tys[0]) * SLOT_SIZE));
+        code.push("call $ct_clone");
+        code.push(&format!("local.tee ${}", place.local));
+        code.push("local.set $p");
+        for (index, t) in path.iter().zip(&tys[1..]) {
+            code.push("local.get $p");
+            code.push(&format!("i32.load offset={}", offset(*index)));
+            code.push(&format!("i32.const {}", field_count(t) * SLOT_SIZE));
+            code.push("call $ct_clone");
+            code.push("local.set $q");
+            code.push("local.get $p");
+            code.push("local.get $q");
+            code.push(&format!("i32.store offset={}", offset(*index)));
+            code.push("local.get $q");
+            code.push("local.set $p");
+        }
+        code.push("local.get $p");
+    }
+
+    fn store_suffix(&mut self, body: &Body<'gcx>, place: &Place, code: &mut Code) {
+        match place.projection.last() {
+            Some(index) => {
+                let t = *self.place_tys(body, place).last().unwrap();
+                code.push(&format!("{}.store offset={}", wasm_ty(t), offset(*index)));
+            }
+            None => code.push(&format!("local.set ${}", place.local)),
+        }
+    }
+
+    fn operand(&mut self, body: &Body<'gcx>, operand: &Operand, code: &mut Code) {
+        let (ty, value) = match operand {
+            Operand::Copy(place) => return self.load_place(body, place, code),
+            Operand::Const(value) => self.const_value(value),
+            Operand::Fn(def_id) => ("i32", i64::from(self.fn_value(def_id))),
+            Operand::Static(def_id) => self.const_value(&self.ctx.const_value(def_id).unwrap()),
+        };
+        code.push(&format!("{}.const {}", ty, value));
+    }
+
+    fn rvalue(&mut self, body: &Body<'gcx>, t: TypeRef<'gcx>, rvalue: &Rvalue, code: &mut Code) {
+        match rvalue {
+            Rvalue::Use(operand) => self.operand(body, operand, code),
+            Rvalue::Unary(op, operand) => {
+                self.operand(body, operand, code);
+                code.push(match op {
+                    UnOp::Neg => "call $ct_neg",
+                    UnOp::Not => "i32.eqz",
+                });
+            }
+            Rvalue::Binary(op, lhs, rhs) => {
+                let operand_ty = body.operand_ty(self.ctx, lhs);
+                self.operand(body, lhs, code);
+                self.operand(body, rhs, code);
+                let ty = wasm_ty(operand_ty);
+                let signed = if ty == "i64" { "s" } else { "u" };
+                match op {
+                    BinOp::Add | BinOp::Sub | BinOp::Mul | BinOp::Rem => {
+                        code.push(&format!("call $ct_{}", op.keyword()))
+                    }
+                    BinOp::Div => code.push("i64.div_s"),
+                    BinOp::Eq => code.push(&self.eq_instr(operand_ty)),
+                    BinOp::Ne => {
+                        code.push(&self.eq_instr(operand_ty));
+                        code.push("i32.eqz");
+                    }
+                    BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge => {
+                        code.push(&format!("{}.{}_{}", ty, op.keyword(), signed))
+                    }
+                    BinOp::And | BinOp::Or => code.push(&format!("i32.{}", op.keyword())),
+                }
+            }
+            Rvalue::Tuple(elems) if elems.is_empty() => code.push("i32.const 0"),
+            Rvalue::Tuple(fields) | Rvalue::Adt(_, fields) => {
+                code.push(&format!("i32.const {}", field_count(t) * SLOT_SIZE));
+                code.push("call $ct_alloc");
+                code.push("local.set $r");
+                for (i, field) in fields.iter().enumerate() {
+                    code.push("local.get $r");
+                    self.operand(body, field, code);
+                    let ty = wasm_ty(field_ty(self.ctx, t, i));
+                    code.push(&format!("{}.store offset={}", ty, offset(i)));
+                }
+                code.push("local.get $r");
+            }
+            Rvalue::Closure(body_id, captures) => {
+                let index = self.closure_index(*body_id);
+                code.push(&format!("i32.const {}", offset(captures.len() + 1)));
+                code.push("call $ct_alloc");
+                code.push("local.set $r");
+                code.push("local.get $r");
+                code.push(&format!("i32.const {}", index));
+                code.push("i32.store");
+                for (i, capture) in captures.iter().enumerate() {
+                    code.push("local.get $r");
+                    self.operand(body, capture, code);
+                    let ty = wasm_ty(body.operand_ty(self.ctx, capture));
+                    code.push(&format!("{}.store offset={}", ty, offset(i + 1)));
+                }
+                code.push("local.get $r");
+            }
+        }
+    }
+
+    fn terminator(&mut self, body: &Body<'gcx>, terminator: &Terminator, code: &mut Code) {
+        match terminator {
+            Terminator::Goto(target) => jump(code, *target),
+            Terminator::Branch {
+                cond,
+                then_block,
+                else_block,
+            } => {
+                code.push(&format!("i32.const {}", then_block.index()));
+                code.push(&format!("i32.const {}", else_block.index()));
+                self.operand(body, cond, code);
+                code.push("select");
+                code.push("local.set $bb");
+                code.push("br $dispatch");
+            }
+            Terminator::Return => {
+                code.push("local.get $_0");
+                code.push("return");
+            }
+            Terminator::Call {
+                func,
+                args,
+                dest,
+                target,
+            } => {
+                self.store_prefix(body, dest, code);
+                match func {
+                    Operand::Fn(def_id) => {
+                        for arg in args {
+                            self.operand(body, arg, code);
+                        }
+                        let callee = self.callee(def_id);
+                        code.push(&format!("call {}", callee));
+                    }
+                    _ => {
+                        self.operand(body, func, code);
+                        for arg in args {
+                            self.operand(body, arg, code);
+                        }
+                        self.operand(body, func, code);
+                        code.push("i32.load");
+                        let ty = self.code_type(body.operand_ty(self.ctx, func));
+                        code.push(&format!("call_indirect (type {})", ty));
+                    }
+                }
+                self.store_suffix(body, dest, code);
+                jump(code, *target);
+            }
+            Terminator::Unreachable => code.push("unreachable"),
+        }
+    }
+}
+
+fn jump(code: &mut Code, target: BlockId) {
+    code.push(&format!("i32.const {}", target.index()));
+    code.push("local.set $bb");
+    code.push("br $dispatch");
+}
+
+struct Code {
+    text: String,
+    indent: usize,
+}
+
+impl Code {
+    fn push(&mut self, instr: &str) {
+        writeln!(self.text, "{}{}", "  ".repeat(self.indent), instr).unwrap();
+    }
+}
@@ -0,0 +1,703 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+// Checks modules in the subset of the WebAssembly text format produced by
+// `to_wat`: module fields refer to declared items, and the flat
+// instructions of every function are type checked with the validation
+// algorithm of the specification.
+pub fn verify(text: &str) -> Result<(), String> {
+    let sexps = parse(text)?;
+    let [Sexp::List(module)] = sexps.as_slice() else {
+        return Err("Expected a single module".to_string());
+    };
+    if module.first().and_then(Sexp::as_atom) != Some("module") {
+        return Err("Expected a module".to_string());
+    }
+    let module = Module::collect(&module[1..])?;
+    for field in module.fields {
+        if field_kind(field) == Some("func") {
+            module.check_func(field)?;
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+enum Sexp {
+    Atom(String),
+    Str(Vec<u8>),
+    List(Vec<Sexp>),
+}
+
+impl Sexp {
+    fn as_atom(&self) -> Option<&str> {
+        match self {
+            Sexp::Atom(atom) => Some(atom),
+            _ => None,
+        }
+    }
+
+    fn as_list(&self) -> Option<&[Sexp]> {
+        match self {
+            Sexp::List(list) => Some(list),
+            _ => None,
+        }
+    }
+}
+
+fn parse(text: &str) -> Result<Vec<Sexp>, String> {
+    let mut stack = vec![vec![]];
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '(' => stack.push(vec![]),
+            ')' => {
+                let list = stack.pop().unwrap();
+                match stack.last_mut() {
+                    Some(parent) => parent.push(Sexp::List(list)),
+                    None => return Err("Unbalanced `)`".to_string()),
+                }
+            }
+            ';' if chars.peek() == Some(&';') => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            '"' => {
+                let mut bytes = vec![];
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => {
+                            let hex = chars.by_ref().take(2).collect::<String>();
+                            let byte = u8::from_str_radix(&hex, 16)
+                                .map_err(|_| format!("Invalid escape `\\{}`", hex))?;
+                            bytes.push(byte);
+                        }
+                        Some(c) => {
+                            let mut buf = [0; 4];
+                            bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                        }
+                        None => return Err("Unterminated string".to_string()),
+                    }
+                }
+                stack.last_mut().unwrap().push(Sexp::Str(bytes));
+            }
+            c if c.is_whitespace() => {}
+            c => {
+                let mut atom = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || c == '(' || c == ')' || c == '"' {
+                        break;
+                    }
+                    atom.push(c);
+                    chars.next();
+                }
+                stack.last_mut().unwrap().push(Sexp::Atom(atom));
+            }
+        }
+    }
+    if stack.len() != 1 {
+        return Err("Unbalanced `(`".to_string());
+    }
+    Ok(stack.pop().unwrap())
+}
+
+fn field_kind(field: &Sexp) -> Option<&str> {
+    field.as_list()?.first()?.as_atom()
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum ValType {
+    I32,
+    I64,
+    // Popped from the stack of unreachable code.
+    Any,
+}
+
+impl ValType {
+    fn parse(atom: Option<&str>) -> Result<ValType, String> {
+        match atom {
+            Some("i32") => Ok(ValType::I32),
+            Some("i64") => Ok(ValType::I64),
+            _ => Err(format!("Unsupported value type {:?}", atom)),
+        }
+    }
+
+    fn matches(self, other: ValType) -> bool {
+        self == other || self == ValType::Any || other == ValType::Any
+    }
+}
+
+impl fmt::Display for ValType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ValType::I32 => write!(f, "i32"),
+            ValType::I64 => write!(f, "i64"),
+            ValType::Any => write!(f, "any"),
+        }
+    }
+}
+
+#[derive(Clone, Default, PartialEq, Debug)]
+struct FuncType {
+    params: Vec<ValType>,
+    results: Vec<ValType>,
+}
+
+// Parses `(param ...)` and `(result ...)` entries, and `(local ...)` ones
+// if `locals` is given, returning the names of the parameters and locals.
+fn parse_func_type(
+    items: &[Sexp],
+    ty: &mut FuncType,
+    mut locals: Option<&mut Vec<ValType>>,
+) -> Result<HashMap<String, u32>, String> {
+    let mut names = HashMap::new();
+    let mut index = 0;
+    for item in items {
+        let Some(entry) = item.as_list() else {
+            continue;
+        };
+        let kind = entry.first().and_then(Sexp::as_atom);
+        if !matches!(kind, Some("param" | "result" | "local")) {
+            continue;
+        }
+        let rest = &entry[1..];
+        let types = match rest.first().and_then(Sexp::as_atom) {
+            Some(name) if name.starts_with('$') && kind != Some("result") => {
+                if rest.len() != 2 {
+                    return Err(format!("Named {:?} must have a single type", kind));
+                }
+                if names.insert(name.to_string(), index).is_some() {
+                    return Err(format!("Duplicate local {}", name));
+                }
+                &rest[1..]
+            }
+            _ => rest,
+        };
+        let types = types
+            .iter()
+            .map(|x| ValType::parse(x.as_atom()))
+            .collect::<Result<Vec<_>, _>>()?;
+        match (kind, &mut locals) {
+            (Some("param"), _) => ty.params.extend(&types),
+            (Some("result"), _) => {
+                ty.results.extend(&types);
+                continue;
+            }
+            (Some("local"), Some(locals)) => locals.extend(&types),
+            _ => return Err("Locals in a function type".to_string()),
+        }
+        index += u32::try_from(types.len()).unwrap();
+    }
+    Ok(names)
+}
+
+struct Module<'s> {
+    fields: &'s [Sexp],
+    types: HashMap<String, FuncType>,
+    funcs: HashMap<String, FuncType>,
+    globals: HashMap<String, (ValType, bool)>,
+    memory_pages: Option<u32>,
+    table_size: Option<u32>,
+}
+
+impl<'s> Module<'s> {
+    fn collect(fields: &'s [Sexp]) -> Result<Self, String> {
+        let mut module = Module {
+            fields,
+            types: HashMap::new(),
+            funcs: HashMap::new(),
+            globals: HashMap::new(),
+            memory_pages: None,
+            table_size: None,
+        };
+        let mut exports = HashSet::new();
+        let mut defined = false;
+        for field in fields {
+            let items = field.as_list().unwrap_or_default();
+            let name = items.get(1).and_then(Sexp::as_atom).filter(|x| x.starts_with('$'));
+            for item in items {
+                let Some([Sexp::Atom(kind), Sexp::Str(export)]) = item.as_list() else {
+                    continue;
+                };
+                if kind == "export" && !exports.insert(export.clone()) {
+                    return Err(format!("Duplicate export {:?}", String::from_utf8_lossy(export)));
+                }
+            }
+            match field_kind(field) {
+                Some("type") => {
+                    let name = name.ok_or("Unnamed type")?;
+                    let func = items.get(2).and_then(Sexp::as_list).unwrap_or_default();
+                    if func.first().and_then(Sexp::as_atom) != Some("func") {
+                        return Err(format!("Type {} is not a function type", name));
+                    }
+                    let mut ty = FuncType::default();
+                    parse_func_type(&func[1..], &mut ty, None)?;
+                    if module.types.insert(name.to_string(), ty).is_some() {
+                        return Err(format!("Duplicate type {}", name));
+                    }
+                }
+                Some("import") => {
+                    if defined {
+                        return Err("Imports must precede definitions".to_string());
+                    }
+                    let func = items.get(3).and_then(Sexp::as_list).unwrap_or_default();
+                    let valid = matches!(items.get(1), Some(Sexp::Str(_)))
+                        && matches!(items.get(2), Some(Sexp::Str(_)))
+                        && func.first().and_then(Sexp::as_atom) == Some("func");
+                    let name = func.get(1).and_then(Sexp::as_atom).filter(|x| x.starts_with('$'));
+                    let (true, Some(name)) = (valid, name) else {
+                        return Err("Only named function imports are supported".to_string());
+                    };
+                    module.add_func(name, &func[2..])?;
+                }
+                Some("func") => {
+                    defined = true;
+                    module.add_func(name.ok_or("Unnamed function")?, &items[2..])?;
+                }
+                Some("memory") => {
+                    defined = true;
+                    let pages = items.last().and_then(Sexp::as_atom).and_then(|x| x.parse().ok());
+                    if module.memory_pages.replace(pages.ok_or("Invalid memory")?).is_some() {
+                        return Err("Multiple memories".to_string());
+                    }
+                }
+                Some("table") => {
+                    defined = true;
+                    let size = items.get(1).and_then(Sexp::as_atom).and_then(|x| x.parse().ok());
+                    if items.get(2).and_then(Sexp::as_atom) != Some("funcref") {
+                        return Err("Only tables of funcref are supported".to_string());
+                    }
+                    if module.table_size.replace(size.ok_or("Invalid table")?).is_some() {
+                        return Err("Multiple tables".to_string());
+                    }
+                }
+                Some("global") => {
+                    defined = true;
+                    let name = name.ok_or("Unnamed global")?;
+                    let (ty, mutable) = match items.get(2) {
+                        Some(Sexp::List(ty)) => match ty.as_slice() {
+                            [Sexp::Atom(kind), Sexp::Atom(ty)] if kind == "mut" => {
+                                (ValType::parse(Some(ty))?, true)
+                            }
+                            _ => return Err(format!("Invalid type of {}", name)),
+                        },
+                        ty => (ValType::parse(ty.and_then(Sexp::as_atom))?, false),
+                    };
+                    if const_expr(items.get(3))?.0 != ty {
+                        return Err(format!("Initializer of {} has the wrong type", name));
+                    }
+                    if module.globals.insert(name.to_string(), (ty, mutable)).is_some() {
+                        return Err(format!("Duplicate global {}", name));
+                    }
+                }
+                Some("elem") | Some("data") => {}
+                _ => return Err(format!("Unsupported module field {:?}", field)),
+            }
+        }
+        for field in fields {
+            let items = field.as_list().unwrap_or_default();
+            match field_kind(field) {
+                Some("elem") => {
+                    let size = module.table_size.ok_or("Elements without a table")?;
+                    let (ty, offset) = const_expr(items.get(1))?;
+                    let funcs = &items[2..];
+                    if ty != ValType::I32 || offset + funcs.len() as i64 > i64::from(size) {
+                        return Err("Elements out of the bounds of the table".to_string());
+                    }
+                    for func in funcs {
+                        let name = func.as_atom().unwrap_or_default();
+                        if !module.funcs.contains_key(name) {
+                            return Err(format!("Unknown function {} in elements", name));
+                        }
+                    }
+                }
+                Some("data") => {
+                    let pages = module.memory_pages.ok_or("Data without a memory")?;
+                    let (ty, offset) = const_expr(items.get(1))?;
+                    let Some(Sexp::Str(bytes)) = items.get(2) else {
+                        return Err("Invalid data".to_string());
+                    };
+                    let end = offset + bytes.len() as i64;
+                    if ty != ValType::I32 || offset < 0 || end > i64::from(pages) * 65536 {
+                        return Err("Data out of the bounds of the memory".to_string());
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(module)
+    }
+
+    fn add_func(&mut self, name: &str, items: &[Sexp]) -> Result<(), String> {
+        let mut ty = FuncType::default();
+        parse_func_type(items, &mut ty, Some(&mut vec![]))?;
+        if self.funcs.insert(name.to_string(), ty).is_some() {
+            return Err(format!("Duplicate function {}", name));
+        }
+        Ok(())
+    }
+
+    fn check_func(&self, field: &Sexp) -> Result<(), String> {
+        let items = field.as_list().unwrap();
+        let name = items[1].as_atom().unwrap();
+        let mut ty = FuncType::default();
+        let mut locals = vec![];
+        let names = parse_func_type(&items[2..], &mut ty, Some(&mut locals))?;
+        let body = items[2..]
+            .iter()
+            .skip_while(|x| x.as_list().is_some_and(|x| !x.is_empty() && !is_instr(&x[0])))
+            .collect::<Vec<_>>();
+        let mut checker = FuncChecker {
+            module: self,
+            locals: ty.params.iter().chain(&locals).copied().collect(),
+            names,
+            results: ty.results.clone(),
+            stack: vec![],
+            frames: vec![Frame {
+                kind: FrameKind::Func,
+                label: None,
+                height: 0,
+                unreachable: false,
+            }],
+        };
+        checker
+            .check(&body)
+            .map_err(|message| format!("In function {}: {}", name, message))
+    }
+}
+
+// Function headers are made of lists, while instructions are atoms apart
+// from the type of `call_indirect`.
+fn is_instr(head: &Sexp) -> bool {
+    !matches!(head.as_atom(), Some("export" | "param" | "result" | "local"))
+}
+
+fn const_expr(expr: Option<&Sexp>) -> Result<(ValType, i64), String> {
+    match expr.and_then(Sexp::as_list) {
+        Some([Sexp::Atom(op), Sexp::Atom(value)]) if op == "i32.const" || op == "i64.const" => {
+            let ty = ValType::parse(op.split('.').next())?;
+            Ok((ty, parse_int(value, ty)?))
+        }
+        _ => Err(format!("Unsupported constant expression {:?}", expr)),
+    }
+}
+
+fn parse_int(value: &str, ty: ValType) -> Result<i64, String> {
+    let parsed = match ty {
+        ValType::I32 => value.parse::<i32>().map(i64::from).ok(),
+        _ => value.parse::<i64>().ok(),
+    };
+    parsed.ok_or_else(|| format!("Invalid {} constant `{}`", ty, value))
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum FrameKind {
+    Func,
+    Block,
+    Loop,
+    If,
+    Else,
+}
+
+struct Frame {
+    kind: FrameKind,
+    label: Option<String>,
+    height: usize,
+    unreachable: bool,
+}
+
+struct FuncChecker<'m, 's> {
+    module: &'m Module<'s>,
+    locals: Vec<ValType>,
+    names: HashMap<String, u32>,
+    results: Vec<ValType>,
+    stack: Vec<ValType>,
+    frames: Vec<Frame>,
+}
+
+impl FuncChecker<'_, '_> {
+    fn push(&mut self, ty: ValType) {
+        self.stack.push(ty);
+    }
+
+    fn pop(&mut self, expected: ValType) -> Result<ValType, String> {
+        let frame = self.frames.last().unwrap();
+        if self.stack.len() == frame.height {
+            return if frame.unreachable {
+                Ok(expected)
+            } else {
+                Err(format!("Expected {} but the stack is empty", expected))
+            };
+        }
+        let actual = self.stack.pop().unwrap();
+        if !actual.matches(expected) {
+            return Err(format!("Expected {}, found {}", expected, actual));
+        }
+        Ok(if actual == ValType::Any { expected } else { actual })
+    }
+
+    fn pop_all(&mut self, types: &[ValType]) -> Result<(), String> {
+        for ty in types.iter().rev() {
+            self.pop(*ty)?;
+        }
+        Ok(())
+    }
+
+    fn set_unreachable(&mut self) {
+        let frame = self.frames.last_mut().unwrap();
+        self.stack.truncate(frame.height);
+        frame.unreachable = true;
+    }
+
+    // Types which branches to the label `label` carry.
+    fn label_types(&self, label: &str) -> Result<Vec<ValType>, String> {
+        let frame = match label.parse::<usize>() {
+            Ok(depth) => self.frames.iter().rev().nth(depth),
+            Err(_) => self.frames.iter().rev().find(|x| x.label.as_deref() == Some(label)),
+        };
+        match frame.map(|x| x.kind) {
+            Some(FrameKind::Loop) => Ok(vec![]),
+            Some(FrameKind::Func) => Ok(self.results.clone()),
+            Some(_) => Ok(vec![]),
+            None => Err(format!("Unknown label {}", label)),
+        }
+    }
+
+    fn local(&self, name: &str) -> Result<ValType, String> {
+        let index = match name.parse::<u32>() {
+            Ok(index) => Some(index),
+            Err(_) => self.names.get(name).copied(),
+        };
+        index
+            .and_then(|i| self.locals.get(i as usize).copied())
+            .ok_or_else(|| format!("Unknown local {}", name))
+    }
+
+    fn check(&mut self, body: &[&Sexp]) -> Result<(), String> {
+        let mut i = 0;
+        while i < body.len() {
+            let op = body[i]
+                .as_atom()
+                .ok_or_else(|| format!("Folded instructions are not supported: {:?}", body[i]))?;
+            i += 1;
+            self.check_instr(op, body, &mut i)
+                .map_err(|message| format!("`{}`: {}", op, message))?;
+        }
+        if self.frames.len() != 1 {
+            return Err("Unterminated block".to_string());
+        }
+        let results = self.results.clone();
+        self.pop_all(&results)?;
+        if self.stack.is_empty() {
+            Ok(())
+        } else {
+            Err("Values left on the stack".to_string())
+        }
+    }
+
+    fn check_instr(&mut self, op: &str, body: &[&Sexp], i: &mut usize) -> Result<(), String> {
+        use ValType::{I32, I64};
+        let mut immediate = || {
+            let atom = body.get(*i).and_then(|x| x.as_atom());
+            *i += 1;
+            atom.ok_or("Missing immediate")
+        };
+        let (ty, name) = op.split_once('.').unwrap_or(("", op));
+        match (ty, name) {
+            ("i32" | "i64", "const") => {
+                let ty = ValType::parse(Some(ty))?;
+                parse_int(immediate()?, ty)?;
+                self.push(ty);
+            }
+            ("i32" | "i64", "load" | "store") => {
+                self.module.memory_pages.ok_or("No memory")?;
+                while let Some(arg) = body.get(*i).and_then(|x| x.as_atom()) {
+                    let Some((key, value)) = arg.split_once('=') else {
+                        break;
+                    };
+                    if !matches!(key, "offset" | "align") || value.parse::<u32>().is_err() {
+                        return Err(format!("Invalid memory argument `{}`", arg));
+                    }
+                    *i += 1;
+                }
+                let ty = ValType::parse(Some(ty))?;
+                if name == "load" {
+                    self.pop(I32)?;
+                    self.push(ty);
+                } else {
+                    self.pop(ty)?;
+                    self.pop(I32)?;
+                }
+            }
+            ("i32" | "i64", _) => {
+                let ty = ValType::parse(Some(ty))?;
+                let (params, result) = match name {
+                    "eqz" => (vec![ty], I32),
+                    "eq" | "ne" | "lt_s" | "lt_u" | "gt_s" | "gt_u" | "le_s" | "le_u" | "ge_s"
+                    | "ge_u" => (vec![ty, ty], I32),
+                    "add" | "sub" | "mul" | "div_s" | "div_u" | "rem_s" | "rem_u" | "and" | "or"
+                    | "xor" | "shl" | "shr_s" | "shr_u" => (vec![ty, ty], ty),
+                    "wrap_i64" if ty == I32 => (vec![I64], I32),
+                    "extend_i32_s" | "extend_i32_u" if ty == I64 => (vec![I32], I64),
+                    _ => return Err("Unknown instruction".to_string()),
+                };
+                self.pop_all(&params)?;
+                self.push(result);
+            }
+            ("memory", "size" | "grow") => {
+                self.module.memory_pages.ok_or("No memory")?;
+                if name == "grow" {
+                    self.pop(I32)?;
+                }
+                self.push(I32);
+            }
+            ("local", _) => {
+                let ty = self.local(immediate()?)?;
+                match name {
+                    "get" => self.push(ty),
+                    "set" => {
+                        self.pop(ty)?;
+                    }
+                    "tee" => {
+                        self.pop(ty)?;
+                        self.push(ty);
+                    }
+                    _ => return Err("Unknown instruction".to_string()),
+                }
+            }
+            ("global", "get" | "set") => {
+                let global = immediate()?;
+                let (ty, mutable) = *self
+                    .module
+                    .globals
+                    .get(global)
+                    .ok_or_else(|| format!("Unknown global {}", global))?;
+                if name == "get" {
+                    self.push(ty);
+                } else if mutable {
+                    self.pop(ty)?;
+                } else {
+                    return Err(format!("Global {} is immutable", global));
+                }
+            }
+            (_, "call") => {
+                let func = immediate()?;
+                let ty = self
+                    .module
+                    .funcs
+                    .get(func)
+                    .ok_or_else(|| format!("Unknown function {}", func))?;
+                self.pop_all(&ty.params)?;
+                self.stack.extend(&ty.results);
+            }
+            (_, "call_indirect") => {
+                self.module.table_size.ok_or("No table")?;
+                let ty = match body.get(*i).and_then(|x| x.as_list()) {
+                    Some([Sexp::Atom(kind), Sexp::Atom(name)]) if kind == "type" => {
+                        *i += 1;
+                        self.module
+                            .types
+                            .get(name)
+                            .ok_or_else(|| format!("Unknown type {}", name))?
+                    }
+                    _ => return Err("Missing type".to_string()),
+                };
+                self.pop(I32)?;
+                self.pop_all(&ty.params)?;
+                self.stack.extend(&ty.results);
+            }
+            (_, "block" | "loop" | "if") => {
+                if name == "if" {
+                    self.pop(I32)?;
+                }
+                let label = match body.get(*i).and_then(|x| x.as_atom()) {
+                    Some(label) if label.starts_with('$') => {
+                        *i += 1;
+                        Some(label.to_string())
+                    }
+                    _ => None,
+                };
+                let kind = match name {
+                    "block" => FrameKind::Block,
+                    "loop" => FrameKind::Loop,
+                    _ => FrameKind::If,
+                };
+                self.frames.push(Frame {
+                    kind,
+                    label,
+                    height: self.stack.len(),
+                    unreachable: false,
+                });
+            }
+            (_, "else" | "end") => {
+                let frame = self.frames.last().unwrap();
+                if frame.kind == FrameKind::Func || (name == "else" && frame.kind != FrameKind::If)
+                {
+                    return Err("Unmatched instruction".to_string());
+                }
+                if self.stack.len() != frame.height {
+                    return Err("Values left on the stack".to_string());
+                }
+                let frame = self.frames.pop().unwrap();
+                if name == "else" {
+                    self.frames.push(Frame {
+                        kind: FrameKind::Else,
+                        height: self.stack.len(),
+                        unreachable: false,
+                        ..frame
+                    });
+                }
+            }
+            (_, "br" | "br_if") => {
+                let types = self.label_types(immediate()?)?;
+                if name == "br_if" {
+                    self.pop(I32)?;
+                    self.pop_all(&types)?;
+                    self.stack.extend(&types);
+                } else {
+                    self.pop_all(&types)?;
+                    self.set_unreachable();
+                }
+            }
+            (_, "br_table") => {
+                let mut labels = vec![];
+                while let Some(label) = body.get(*i).and_then(|x| x.as_atom()) {
+                    if !label.starts_with('$') && label.parse::<u32>().is_err() {
+                        break;
+                    }
+                    labels.push(self.label_types(label)?);
+                    *i += 1;
+                }
+                let default = labels.pop().ok_or("Missing default label")?;
+                if labels.iter().any(|x| x.len() != default.len()) {
+                    return Err("Labels have different arities".to_string());
+                }
+                self.pop(I32)?;
+                self.pop_all(&default)?;
+                self.set_unreachable();
+            }
+            (_, "return") => {
+                let results = self.results.clone();
+                self.pop_all(&results)?;
+                self.set_unreachable();
+            }
+            (_, "unreachable") => self.set_unreachable(),
+            (_, "drop") => {
+                self.pop(ValType::Any)?;
+            }
+            (_, "select") => {
+                self.pop(I32)?;
+                let ty = self.pop(ValType::Any)?;
+                self.pop(ty)?;
+                self.push(ty);
+            }
+            (_, "nop") => {}
+            _ => return Err("Unknown instruction".to_string()),
+        }
+        Ok(())
+    }
+}
@@ -1,14 +1,22 @@
 use std::collections::HashMap;
 use std::cell::RefCell;
+use std::rc::Rc;
 
 use typed_arena::Arena;
 
 use crate::bui::Bui;
 use crate::def::DefId;
 use crate::hil::Node;
-use crate::s_expr::{node_get_attr, node_get_field};
-use crate::t::{Type, AdtDef};
+use crate::s_expr::{node_find_attr, node_get_attr, node_get_field, node_get_fields};
+use crate::t::{
+    AdtDef, GenericParamDef, ImplDef, ModuleInstance, ModuleSig, ParamTy, TraitDef, Type, TypeKind,
+    TypeRef,
+};
+use crate::t::const_eval::ConstValue;
 use crate::t::context::UnitPath;
+use crate::t::import::import_items;
+use crate::til::import::renumber_til;
+use crate::til::{BodyId, BodyTy, Til};
 
 struct UnitCache {
     units: Vec<Bui>,
@@ -23,39 +31,156 @@ impl UnitCache {
         }
     }
 
-    fn load(&mut self, unit_path: &UnitPath) {
+    // Units are numbered from 1 in the order they are loaded, 0 being the
+    // unit being compiled.
+    fn load(&mut self, unit_path: &UnitPath, bui: Bui) -> (u16, bool) {
+        if let Some(index) = self.index.get(unit_path) {
+            return (unit_num(*index), false);
+        }
         self.index.insert(unit_path.clone(), self.units.len());
-        self.units.push(Bui::empty());
+        self.units.push(bui);
+        (unit_num(self.units.len() - 1), true)
     }
+}
 
+fn unit_num(index: usize) -> u16 {
+    u16::try_from(index + 1).unwrap()
 }
 
 struct TypeCache<'gcx> {
     types: Arena<Type<'gcx>>,
+    type_lists: Arena<TypeRef<'gcx>>,
+    // Tuples are keyed by the addresses of their (interned) element types.
+    tuple_types: RefCell<HashMap<Vec<*const Type<'gcx>>, TypeRef<'gcx>>>,
+    // Function types are keyed by their inputs followed by their output.
+    fn_types: RefCell<HashMap<Vec<*const Type<'gcx>>, TypeRef<'gcx>>>,
+    infer_types: RefCell<Vec<TypeRef<'gcx>>>,
+    param_types: RefCell<HashMap<ParamTy, TypeRef<'gcx>>>,
     adt_defs: Arena<AdtDef>,
+    adt_def_index: RefCell<HashMap<DefId, &'gcx AdtDef>>,
+    adt_types: RefCell<HashMap<DefId, TypeRef<'gcx>>>,
+    bool_type: Type<'gcx>,
+    int_type: Type<'gcx>,
+    never_type: Type<'gcx>,
+    error_type: Type<'gcx>,
 }
 
 impl<'gcx> TypeCache<'gcx> {
     fn new() -> Self {
         TypeCache {
             types: Arena::new(),
+            type_lists: Arena::new(),
+            tuple_types: RefCell::new(HashMap::new()),
+            fn_types: RefCell::new(HashMap::new()),
+            infer_types: RefCell::new(vec![]),
+            param_types: RefCell::new(HashMap::new()),
             adt_defs: Arena::new(),
+            adt_def_index: RefCell::new(HashMap::new()),
+            adt_types: RefCell::new(HashMap::new()),
+            bool_type: Type::make_bool(),
+            int_type: Type::make_int(),
+            never_type: Type::make_never(),
+            error_type: Type::make_error(),
+        }
+    }
+
+    fn register_adt_def(&'gcx self, adt_def: AdtDef) -> &'gcx AdtDef {
+        let adt_def = &*self.adt_defs.alloc(adt_def);
+        self.adt_def_index
+            .borrow_mut()
+            .insert(adt_def.def_id.clone(), adt_def);
+        adt_def
+    }
+
+    // ADTs which were not registered (e.g. coming from another unit without
+    // its BUI loaded) get an opaque definition without fields.
+    fn adt_def(&'gcx self, def_id: DefId) -> &'gcx AdtDef {
+        if let Some(adt_def) = self.adt_def_index.borrow().get(&def_id) {
+            return adt_def;
+        }
+        let name = def_id.to_string();
+        self.register_adt_def(AdtDef::new(def_id, name, vec![], HashMap::new()))
+    }
+
+    fn find_adt_def(&self, def_id: &DefId) -> Option<&'gcx AdtDef> {
+        self.adt_def_index.borrow().get(def_id).cloned()
+    }
+
+    fn adt(&'gcx self, adt_def: &'gcx AdtDef) -> &'gcx Type<'gcx> {
+        self
+            .adt_types
+            .borrow_mut()
+            .entry(adt_def.def_id.clone())
+            .or_insert_with(|| &*self.types.alloc(Type::make_adt(adt_def)))
+    }
+
+    fn tuple(&'gcx self, elems: &[TypeRef<'gcx>]) -> TypeRef<'gcx> {
+        let key = elems.iter().map(|t| *t as *const Type).collect::<Vec<_>>();
+        self.tuple_types.borrow_mut().entry(key).or_insert_with(|| {
+            let elems = &*self.type_lists.alloc_extend(elems.iter().cloned());
+            &*self.types.alloc(Type::make_tuple(elems))
+        })
+    }
+
+    fn infer(&'gcx self, var: u32) -> TypeRef<'gcx> {
+        let mut infer_types = self.infer_types.borrow_mut();
+        while infer_types.len() <= var as usize {
+            let next = u32::try_from(infer_types.len()).unwrap();
+            infer_types.push(&*self.types.alloc(Type::make_infer(next)));
         }
+        infer_types[var as usize]
     }
 
-    fn adt_def(&self, def_id: DefId) -> &AdtDef {
-        &*self.adt_defs.alloc(AdtDef::new(def_id))
+    fn param(&'gcx self, param: ParamTy) -> TypeRef<'gcx> {
+        self.param_types
+            .borrow_mut()
+            .entry(param.clone())
+            .or_insert_with(|| &*self.types.alloc(Type::make_param(param)))
     }
 
-    fn adt(&self, adt_def: &'gcx AdtDef) -> &Type<'gcx> {
-        &*self.types.alloc(Type::make_adt(adt_def))
+    fn fn_ptr(&'gcx self, inputs: &[TypeRef<'gcx>], output: TypeRef<'gcx>) -> TypeRef<'gcx> {
+        let key = inputs
+            .iter()
+            .chain(std::iter::once(&output))
+            .map(|t| *t as *const Type)
+            .collect::<Vec<_>>();
+        self.fn_types.borrow_mut().entry(key).or_insert_with(|| {
+            let inputs = &*self.type_lists.alloc_extend(inputs.iter().cloned());
+            &*self.types.alloc(Type::make_fn(inputs, output))
+        })
     }
 }
 
 pub struct GlobalContext<'gcx> {
     unit_cache: RefCell<UnitCache>,
     type_cache: TypeCache<'gcx>,
-    pub unit_type: Type<'gcx>,
+    def_types: RefCell<HashMap<DefId, TypeRef<'gcx>>>,
+    generics: RefCell<HashMap<DefId, Vec<GenericParamDef>>>,
+    trait_defs: Arena<TraitDef>,
+    trait_index: RefCell<HashMap<DefId, &'gcx TraitDef>>,
+    impl_defs: Arena<ImplDef<'gcx>>,
+    impls: RefCell<Vec<&'gcx ImplDef<'gcx>>>,
+    // Types named by `ty` bindings and transparent `ty` declarations.
+    ty_aliases: RefCell<HashMap<DefId, TypeRef<'gcx>>>,
+    module_instances: RefCell<HashMap<DefId, ModuleInstance<'gcx>>>,
+    // Members of interfaces, including the ones of the interfaces they
+    // include.
+    interfaces: RefCell<HashMap<DefId, ModuleSig>>,
+    // Opaque types of included interfaces made transparent by `with type`,
+    // per including interface.
+    refinements: RefCell<HashMap<DefId, HashMap<DefId, TypeRef<'gcx>>>>,
+    // Evaluated initializers of consts and statics.
+    const_values: RefCell<HashMap<DefId, ConstValue>>,
+    // TIL bodies of the defns of every registered unit.
+    fn_bodies: RefCell<HashMap<DefId, (Rc<Til<'gcx>>, BodyId)>>,
+    // TIL bodies which the BUIs of imported units give for inlining.
+    imported_bodies: RefCell<HashMap<DefId, (Rc<Til<'gcx>>, BodyId)>>,
+}
+
+impl<'gcx> Default for GlobalContext<'gcx> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl<'gcx> GlobalContext<'gcx> {
@@ -63,22 +188,318 @@ impl<'gcx> GlobalContext<'gcx> {
         GlobalContext {
             unit_cache: RefCell::new(UnitCache::new()),
             type_cache: TypeCache::new(),
-            unit_type: Type::make_tuple(&[]),
+            def_types: RefCell::new(HashMap::new()),
+            generics: RefCell::new(HashMap::new()),
+            trait_defs: Arena::new(),
+            trait_index: RefCell::new(HashMap::new()),
+            impl_defs: Arena::new(),
+            impls: RefCell::new(vec![]),
+            ty_aliases: RefCell::new(HashMap::new()),
+            module_instances: RefCell::new(HashMap::new()),
+            interfaces: RefCell::new(HashMap::new()),
+            refinements: RefCell::new(HashMap::new()),
+            const_values: RefCell::new(HashMap::new()),
+            fn_bodies: RefCell::new(HashMap::new()),
+            imported_bodies: RefCell::new(HashMap::new()),
+        }
+    }
+
+    // Makes the items of the imported unit `unit_path` known from its BUI,
+    // returning the number its `DefId`s have.
+    pub fn load_unit(&'gcx self, unit_path: &UnitPath, bui: Bui) -> u16 {
+        let mut unit_cache = self.unit_cache.borrow_mut();
+        let (unit_num, is_new) = unit_cache.load(unit_path, bui);
+        if is_new {
+            import_items(self, unit_num, unit_cache.units.last().unwrap());
+        }
+        unit_num
+    }
+
+    pub fn bool_type(&'gcx self) -> TypeRef<'gcx> {
+        &self.type_cache.bool_type
+    }
+
+    pub fn int_type(&'gcx self) -> TypeRef<'gcx> {
+        &self.type_cache.int_type
+    }
+
+    pub fn never_type(&'gcx self) -> TypeRef<'gcx> {
+        &self.type_cache.never_type
+    }
+
+    pub fn error_type(&'gcx self) -> TypeRef<'gcx> {
+        &self.type_cache.error_type
+    }
+
+    pub fn tuple_type(&'gcx self, elems: &[TypeRef<'gcx>]) -> TypeRef<'gcx> {
+        self.type_cache.tuple(elems)
+    }
+
+    pub fn fn_type(
+        &'gcx self,
+        inputs: &[TypeRef<'gcx>],
+        output: TypeRef<'gcx>,
+    ) -> TypeRef<'gcx> {
+        self.type_cache.fn_ptr(inputs, output)
+    }
+
+    pub fn param_type(&'gcx self, index: u32, name: &str) -> TypeRef<'gcx> {
+        self.type_cache.param(ParamTy {
+            index,
+            name: name.to_string(),
+        })
+    }
+
+    // `Self` is the first parameter of a trait.
+    pub fn self_type(&'gcx self) -> TypeRef<'gcx> {
+        self.param_type(0, "Self")
+    }
+
+    // Replaces the generic parameters of `t` by `params`.
+    pub fn subst_params(&'gcx self, t: TypeRef<'gcx>, params: &[TypeRef<'gcx>]) -> TypeRef<'gcx> {
+        match t.kind() {
+            TypeKind::Param(param) => params.get(param.index as usize).cloned().unwrap_or(t),
+            TypeKind::Tuple(ts) => {
+                let ts = ts.iter().map(|t| self.subst_params(t, params)).collect::<Vec<_>>();
+                self.tuple_type(&ts)
+            }
+            TypeKind::Fn(ts, u) => {
+                let ts = ts.iter().map(|t| self.subst_params(t, params)).collect::<Vec<_>>();
+                self.fn_type(&ts, self.subst_params(u, params))
+            }
+            _ => t,
+        }
+    }
+
+    // Replaces the opaque types declared by interfaces in `t` by the types
+    // of `subst`, keyed by declaration.
+    pub fn subst_opaque(
+        &'gcx self,
+        t: TypeRef<'gcx>,
+        subst: &HashMap<DefId, TypeRef<'gcx>>,
+    ) -> TypeRef<'gcx> {
+        match t.kind() {
+            TypeKind::Adt(adt_def, _) => subst.get(&adt_def.def_id).cloned().unwrap_or(t),
+            TypeKind::Tuple(ts) => {
+                let ts = ts.iter().map(|t| self.subst_opaque(t, subst)).collect::<Vec<_>>();
+                self.tuple_type(&ts)
+            }
+            TypeKind::Fn(ts, u) => {
+                let ts = ts.iter().map(|t| self.subst_opaque(t, subst)).collect::<Vec<_>>();
+                self.fn_type(&ts, self.subst_opaque(u, subst))
+            }
+            _ => t,
+        }
+    }
+
+    pub fn ty_alias(&self, def_id: &DefId) -> Option<TypeRef<'gcx>> {
+        self.ty_aliases.borrow().get(def_id).cloned()
+    }
+
+    pub fn set_ty_alias(&self, def_id: DefId, t: TypeRef<'gcx>) {
+        self.ty_aliases.borrow_mut().insert(def_id, t);
+    }
+
+    pub fn refinements_of(&self, iface: &DefId) -> HashMap<DefId, TypeRef<'gcx>> {
+        self.refinements.borrow().get(iface).cloned().unwrap_or_default()
+    }
+
+    pub fn set_refinements(&self, iface: DefId, refinements: HashMap<DefId, TypeRef<'gcx>>) {
+        self.refinements.borrow_mut().insert(iface, refinements);
+    }
+
+    // Module produced by applying a functor.
+    pub fn module_instance(&self, def_id: &DefId) -> Option<ModuleInstance<'gcx>> {
+        self.module_instances.borrow().get(def_id).cloned()
+    }
+
+    pub fn set_module_instance(&self, def_id: DefId, instance: ModuleInstance<'gcx>) {
+        self.module_instances.borrow_mut().insert(def_id, instance);
+    }
+
+    pub fn interface(&self, def_id: &DefId) -> Option<ModuleSig> {
+        self.interfaces.borrow().get(def_id).cloned()
+    }
+
+    pub fn register_interface(&self, def_id: DefId, sig: ModuleSig) {
+        self.interfaces.borrow_mut().insert(def_id, sig);
+    }
+
+    pub fn const_value(&self, def_id: &DefId) -> Option<ConstValue> {
+        self.const_values.borrow().get(def_id).cloned()
+    }
+
+    pub fn set_const_value(&self, def_id: DefId, value: ConstValue) {
+        self.const_values.borrow_mut().insert(def_id, value);
+    }
+
+    // Makes the defns of `til`, the TIL of the unit being compiled, callable
+    // by the interpreter.
+    pub fn register_til(&self, til: Rc<Til<'gcx>>) {
+        let mut fn_bodies = self.fn_bodies.borrow_mut();
+        for (i, body) in til.bodies.iter().enumerate() {
+            if let BodyTy::Fn(def_id) = &body.ty {
+                let body_id = BodyId(u32::try_from(i).unwrap());
+                fn_bodies.insert(def_id.clone(), (til.clone(), body_id));
+            }
         }
     }
 
-    pub fn load_unit(&self, unit_path: &UnitPath) {
-        self.unit_cache.borrow_mut().load(unit_path);
+    // Makes the defns of `til`, the TIL of the imported unit `unit_num`
+    // compiled in the same session, callable under the `DefId`s the units
+    // importing it refer to them by. `numbers` are the ones of its BUI.
+    pub fn register_unit_til(
+        &self,
+        unit_num: u16,
+        numbers: &HashMap<DefId, u32>,
+        til: &Til<'gcx>,
+    ) {
+        self.register_til(Rc::new(renumber_til(til, unit_num, numbers)));
+    }
+
+    pub fn fn_body(&self, def_id: &DefId) -> Option<(Rc<Til<'gcx>>, BodyId)> {
+        self.fn_bodies.borrow().get(def_id).cloned()
+    }
+
+    // Makes the bodies `til::import::import_bodies` read from the BUI of an
+    // imported unit available to the inline pass.
+    pub fn register_imported_til(&self, til: Rc<Til<'gcx>>) {
+        let mut imported_bodies = self.imported_bodies.borrow_mut();
+        for (i, body) in til.bodies.iter().enumerate() {
+            if let BodyTy::Fn(def_id) = &body.ty {
+                let body_id = BodyId(u32::try_from(i).unwrap());
+                imported_bodies.insert(def_id.clone(), (til.clone(), body_id));
+            }
+        }
+    }
+
+    pub fn imported_body(&self, def_id: &DefId) -> Option<(Rc<Til<'gcx>>, BodyId)> {
+        self.imported_bodies.borrow().get(def_id).cloned()
+    }
+
+    pub fn generics_of(&self, def_id: &DefId) -> Vec<GenericParamDef> {
+        self.generics.borrow().get(def_id).cloned().unwrap_or_default()
+    }
+
+    pub fn set_generics_of(&self, def_id: DefId, generics: Vec<GenericParamDef>) {
+        self.generics.borrow_mut().insert(def_id, generics);
+    }
+
+    pub fn register_trait_def(&'gcx self, trait_def: TraitDef) -> &'gcx TraitDef {
+        let trait_def = &*self.trait_defs.alloc(trait_def);
+        self.trait_index
+            .borrow_mut()
+            .insert(trait_def.def_id.clone(), trait_def);
+        trait_def
+    }
+
+    pub fn trait_def(&self, def_id: &DefId) -> Option<&'gcx TraitDef> {
+        self.trait_index.borrow().get(def_id).cloned()
+    }
+
+    pub fn register_impl_def(&'gcx self, impl_def: ImplDef<'gcx>) -> &'gcx ImplDef<'gcx> {
+        let impl_def = &*self.impl_defs.alloc(impl_def);
+        self.impls.borrow_mut().push(impl_def);
+        impl_def
+    }
+
+    // Impls of `trait_def_id` for exactly the type `t`, types being
+    // interned.
+    pub fn find_impls(&self, trait_def_id: &DefId, t: TypeRef<'gcx>) -> Vec<&'gcx ImplDef<'gcx>> {
+        self.impls
+            .borrow()
+            .iter()
+            .filter(|x| x.trait_def_id == *trait_def_id && std::ptr::eq(x.self_ty, t))
+            .cloned()
+            .collect()
+    }
+
+    pub fn impls_for(&self, t: TypeRef<'gcx>) -> Vec<&'gcx ImplDef<'gcx>> {
+        self.impls
+            .borrow()
+            .iter()
+            .filter(|x| std::ptr::eq(x.self_ty, t))
+            .cloned()
+            .collect()
+    }
+
+    pub fn infer_type(&'gcx self, var: u32) -> TypeRef<'gcx> {
+        self.type_cache.infer(var)
+    }
+
+    // The unit type is the empty tuple.
+    pub fn unit_type(&'gcx self) -> TypeRef<'gcx> {
+        self.tuple_type(&[])
+    }
+
+    pub fn adt_type(&'gcx self, adt_def: &'gcx AdtDef) -> TypeRef<'gcx> {
+        self.type_cache.adt(adt_def)
+    }
+
+    pub fn register_adt_def(&'gcx self, adt_def: AdtDef) -> &'gcx AdtDef {
+        self.type_cache.register_adt_def(adt_def)
+    }
+
+    pub fn adt_def(&'gcx self, def_id: DefId) -> &'gcx AdtDef {
+        self.type_cache.adt_def(def_id)
+    }
+
+    pub fn find_adt_def(&self, def_id: &DefId) -> Option<&'gcx AdtDef> {
+        self.type_cache.find_adt_def(def_id)
+    }
+
+    pub fn type_of(&self, def_id: &DefId) -> Option<TypeRef<'gcx>> {
+        self.def_types.borrow().get(def_id).cloned()
+    }
+
+    pub fn set_type_of(&self, def_id: DefId, t: TypeRef<'gcx>) {
+        self.def_types.borrow_mut().insert(def_id, t);
     }
 
     pub fn interned_type(&'gcx self, node: &Node) -> &'gcx Type<'gcx> {
         assert_eq!(node.car().as_symbol().unwrap(), "Ty");
         let kind = node_get_attr(node, "kind").as_keyword().unwrap();
-        if kind == "qpath" {
+        if kind == "bool" {
+            self.bool_type()
+        } else if kind == "int" {
+            self.int_type()
+        } else if kind == "qpath" {
             let path = node_get_field(node, 0).as_cons().unwrap();
             let def_id = DefId::from_s_expr(node_get_field(path, 0));
-            let adt_def = self.type_cache.adt_def(def_id);
-            self.type_cache.adt(adt_def)
+            let t = match self.ty_alias(&def_id) {
+                Some(t) => t,
+                None => self.type_cache.adt(self.type_cache.adt_def(def_id)),
+            };
+            // Members of functor parameters see the refinements of the
+            // parameter's interface.
+            match node_find_attr(path, "via") {
+                Some(via) => self.subst_opaque(t, &self.refinements_of(&DefId::from_s_expr(via))),
+                None => t,
+            }
+        } else if kind == "tuple" {
+            let elems = node_get_field(node, 0)
+                .as_slice()
+                .unwrap()
+                .iter()
+                .map(|x| self.interned_type(x.as_cons().unwrap()))
+                .collect::<Vec<_>>();
+            self.tuple_type(&elems)
+        } else if kind == "fn" {
+            let fields = node_get_fields(node);
+            let inputs = fields[0]
+                .as_slice()
+                .unwrap()
+                .iter()
+                .map(|x| self.interned_type(x.as_cons().unwrap()))
+                .collect::<Vec<_>>();
+            let output = self.interned_type(fields[1].as_cons().unwrap());
+            self.fn_type(&inputs, output)
+        } else if kind == "param" {
+            let index = node_get_attr(node, "index").as_u64().unwrap();
+            let name = node_get_field(node, 0).as_symbol().unwrap();
+            self.param_type(u32::try_from(index).unwrap(), name)
+        } else if kind == "self" {
+            self.self_type()
         } else {
             panic!("unsupported type: {}", kind);
         }
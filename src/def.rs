@@ -1,3 +1,5 @@
+use std::fmt;
+
 use lexpr::{sexp, Value};
 
 use crate::s_expr::node_get_field;
@@ -9,13 +11,19 @@ const LOCAL_UNIT: u16 = 0;
 
 impl UnitNum {
     fn new(x: u16) -> Self {
-        UnitNum { 0: x }
+        UnitNum(x)
     }
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
 pub struct DefLocalId(u32);
 
+impl fmt::Display for DefLocalId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
 impl DefLocalId {
     pub fn new() -> Self {
         DefLocalId(0)
@@ -25,10 +33,10 @@ impl DefLocalId {
         DefLocalId(self.0 + 1)
     }
 
-    pub fn to_def_id(&self) -> DefId {
+    pub fn to_def_id(self) -> DefId {
         DefId {
             unit: UnitNum::new(LOCAL_UNIT),
-            offset: *self,
+            offset: self,
         }
     }
 
@@ -36,7 +44,7 @@ impl DefLocalId {
         DefLocalId(u32::try_from(value.as_u64().unwrap()).unwrap())
     }
 
-    pub fn to_s_expr(&self) -> Value {
+    pub fn to_s_expr(self) -> Value {
         let def = self.0;
         sexp!(,def)
     }
@@ -58,8 +66,8 @@ impl DefId {
     pub fn from_s_expr(value: &Value) -> Self {
         let node = value.as_cons().unwrap();
         assert_eq!(node.car().as_symbol().unwrap(), "DefId");
-        let unit = node_get_field(&node, 0).as_u64().unwrap();
-        let offset = node_get_field(&node, 1).as_u64().unwrap();
+        let unit = node_get_field(node, 0).as_u64().unwrap();
+        let offset = node_get_field(node, 1).as_u64().unwrap();
         DefId {
             unit: UnitNum::new(u16::try_from(unit).unwrap()),
             offset: DefLocalId(u32::try_from(offset).unwrap()),
@@ -70,9 +78,34 @@ impl DefId {
         self.offset
     }
 
+    pub fn is_local(&self) -> bool {
+        self.unit.0 == LOCAL_UNIT
+    }
+
+    pub fn unit_num(&self) -> u16 {
+        self.unit.0
+    }
+
     pub fn serialize(&self, builder: &mut crate::bui_capnp::item_ref::Builder) {
         builder.set_unit(self.unit.0);
         builder.set_def(self.offset.0)
     }
+
+    // Unit number and offset, for formats other than BUIs.
+    pub fn to_raw(&self) -> (u16, u32) {
+        (self.unit.0, self.offset.0)
+    }
+
+    pub fn from_raw(unit: u16, offset: u32) -> Self {
+        DefId {
+            unit: UnitNum::new(unit),
+            offset: DefLocalId(offset),
+        }
+    }
 }
 
+impl fmt::Display for DefId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "DefId({}, {})", self.unit.0, self.offset.0)
+    }
+}
@@ -4,20 +4,179 @@ use std::sync::Mutex;
 use once_cell::sync::Lazy;
 
 static ERRORS: Lazy<Mutex<Vec<String>>> = Lazy::new(|| Mutex::new(vec![]));
+static WARNINGS: Lazy<Mutex<Vec<String>>> = Lazy::new(|| Mutex::new(vec![]));
 
 pub enum Error {
     UndefinedName(String),
+    MismatchedTypes(String, String),
+    InvalidBinaryOperands(String, String, String),
+    InvalidUnaryOperand(String, String),
+    ChainedOperators(String, String),
+    IncompatibleBranches(String, String),
+    OutsideOfLoop(String),
+    InvalidAssignment,
+    TypeAnnotationsNeeded(String),
+    BreakWithValue,
+    UnknownField(String, String),
+    DuplicateField(String),
+    MissingField(String, String),
+    WrongArgumentCount(usize, usize),
+    NotCallable(String),
+    UnknownMethod(String, String),
+    UnsatisfiedBound(String, String),
+    ConflictingImpls(String, String),
+    MissingTraitMethod(String, String),
+    NotATraitMethod(String, String),
+    AmbiguousMethod(String, String),
+    IncompatibleMethod(String, String, String, String),
+    NotATrait(String),
+    NotAFunctor(String),
+    NotAModule(String),
+    MissingMember(String, String),
+    IncompatibleMember(String, String, String, String),
+    DuplicateMember(String),
+    NotAnOpaqueType(String, String),
+    NotAnInterface(String),
+    PrivateMember(String, String),
+    NotConstant(String),
+    ConstEvalFailed(String, String),
+    CyclicConst(String),
+    UnsupportedByBackend(String, String),
+    PossiblyUninitialized(String),
+    NotAClass(String),
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let (code, msg) = match self {
             Error::UndefinedName(name) => (1, format!("Undefined name: {}", name)),
+            Error::MismatchedTypes(expected, found) => (
+                2,
+                format!("Mismatched types: expected {}, found {}", expected, found),
+            ),
+            Error::InvalidBinaryOperands(op, lhs, rhs) => (
+                3,
+                format!("Cannot apply `{}` to operands of type {} and {}", op, lhs, rhs),
+            ),
+            Error::InvalidUnaryOperand(op, ty) => (
+                4,
+                format!("Cannot apply `{}` to an operand of type {}", op, ty),
+            ),
+            Error::ChainedOperators(op, next) => (
+                5,
+                format!("Operators `{}` and `{}` cannot be chained", op, next),
+            ),
+            Error::IncompatibleBranches(t, u) => (
+                6,
+                format!("`if` and `else` have incompatible types: {} and {}", t, u),
+            ),
+            Error::OutsideOfLoop(kw) => (7, format!("`{}` outside of a loop", kw)),
+            Error::InvalidAssignment => (8, "Invalid left-hand side of assignment".to_string()),
+            Error::TypeAnnotationsNeeded(ident) => {
+                (9, format!("Type annotations needed for `{}`", ident))
+            }
+            Error::BreakWithValue => (10, "`break` with value inside of a `while` loop".to_string()),
+            Error::UnknownField(field, ty) => (11, format!("No field `{}` on type {}", field, ty)),
+            Error::DuplicateField(field) => {
+                (12, format!("Field `{}` specified more than once", field))
+            }
+            Error::MissingField(field, ty) => {
+                (13, format!("Missing field `{}` in initializer of {}", field, ty))
+            }
+            Error::WrongArgumentCount(expected, found) => (
+                14,
+                format!("Expected {} arguments, found {}", expected, found),
+            ),
+            Error::NotCallable(ty) => (15, format!("Expected function, found {}", ty)),
+            Error::UnknownMethod(method, ty) => {
+                (16, format!("No method `{}` found for type {}", method, ty))
+            }
+            Error::UnsatisfiedBound(tr, ty) => (
+                17,
+                format!("The trait `{}` is not implemented for {}", tr, ty),
+            ),
+            Error::ConflictingImpls(tr, ty) => (
+                18,
+                format!("Conflicting implementations of trait `{}` for type {}", tr, ty),
+            ),
+            Error::MissingTraitMethod(method, tr) => (
+                19,
+                format!("Missing method `{}` in implementation of `{}`", method, tr),
+            ),
+            Error::NotATraitMethod(method, tr) => (
+                20,
+                format!("Method `{}` is not a member of trait `{}`", method, tr),
+            ),
+            Error::AmbiguousMethod(method, ty) => (
+                21,
+                format!("Multiple applicable methods `{}` for type {}", method, ty),
+            ),
+            Error::IncompatibleMethod(method, tr, expected, found) => (
+                22,
+                format!(
+                    "Method `{}` has an incompatible type for trait `{}`: expected {}, found {}",
+                    method, tr, expected, found
+                ),
+            ),
+            Error::NotATrait(name) => (23, format!("`{}` is not a trait", name)),
+            Error::NotAFunctor(name) => (24, format!("`{}` is not a functor", name)),
+            Error::NotAModule(name) => (25, format!("`{}` is not a module", name)),
+            Error::MissingMember(member, iface) => (
+                26,
+                format!("Missing member `{}` required by interface `{}`", member, iface),
+            ),
+            Error::IncompatibleMember(member, iface, expected, found) => (
+                27,
+                format!(
+                    "Member `{}` has an incompatible type for interface `{}`: expected {}, found {}",
+                    member, iface, expected, found
+                ),
+            ),
+            Error::DuplicateMember(member) => {
+                (28, format!("Member `{}` is declared more than once", member))
+            }
+            Error::NotAnOpaqueType(ty, iface) => {
+                (29, format!("`{}` is not an opaque type of interface `{}`", ty, iface))
+            }
+            Error::NotAnInterface(name) => (30, format!("`{}` is not an interface", name)),
+            Error::PrivateMember(member, owner) => {
+                (31, format!("Member `{}` of `{}` is private", member, owner))
+            }
+            Error::NotConstant(what) => {
+                (32, format!("{} cannot be evaluated at compile time", what))
+            }
+            Error::ConstEvalFailed(name, reason) => (
+                33,
+                format!("Evaluation of constant `{}` failed: {}", name, reason),
+            ),
+            Error::CyclicConst(name) => {
+                (34, format!("Cycle detected when evaluating constant `{}`", name))
+            }
+            Error::UnsupportedByBackend(what, backend) => {
+                (35, format!("{} is not supported by the {} backend", what, backend))
+            }
+            Error::PossiblyUninitialized(name) => {
+                (36, format!("Use of possibly-uninitialized `{}`", name))
+            }
+            Error::NotAClass(name) => (37, format!("`{}` is not a class", name)),
         };
         write!(f, "E{:04} {}", code, msg)
     }
 }
 
+pub enum Warning {
+    UnreachableCode,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (code, msg) = match self {
+            Warning::UnreachableCode => (1, "Unreachable code".to_string()),
+        };
+        write!(f, "W{:04} {}", code, msg)
+    }
+}
+
 pub fn report_error(error: Error) {
     let mut errors = ERRORS.lock().unwrap();
     errors.push(format!("{}", error));
@@ -27,3 +186,16 @@ pub fn dump_errors() {
     let errors = ERRORS.lock().unwrap();
     errors.iter().for_each(|e| println!("{}", e));
 }
+
+pub fn take_errors() -> Vec<String> {
+    std::mem::take(&mut *ERRORS.lock().unwrap())
+}
+
+pub fn report_warning(warning: Warning) {
+    let mut warnings = WARNINGS.lock().unwrap();
+    warnings.push(format!("{}", warning));
+}
+
+pub fn take_warnings() -> Vec<String> {
+    std::mem::take(&mut *WARNINGS.lock().unwrap())
+}
@@ -1,13 +1,53 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+
 use capnp::message::TypedBuilder;
+use capnp::serialize::write_message_to_words;
+use lexpr::sexp;
 
+use crate::ast::{is_inline, is_public};
 use crate::bui::BuiMessage;
 use crate::bui_capnp;
 use crate::context::GlobalContext;
+use crate::def::DefLocalId;
 use crate::hil::{DefId, HilVisitor};
-use crate::s_expr::{node_get_attr, node_get_field, node_get_fields};
+use crate::s_expr::{node_find_attr, node_get_attr, node_get_field, node_get_fields};
+use crate::t::const_eval::ConstValue;
+use crate::t::{ModuleInstance, TypeKind, TypeRef};
+use crate::til::opt::inline::{body_size, is_exportable, INLINE_THRESHOLD};
+use crate::til::{Body, Operand, Place, Rvalue, Statement, Terminator};
 
 pub struct ToBuiContext<'gcx> {
     ctx: &'gcx GlobalContext<'gcx>,
+    // Numbers of the items of the unit in the BUI. The top-level items written
+    // to it come first, in order, followed by the other items they refer to
+    // as they are met, so that private items do not change the numbering.
+    numbers: RefCell<HashMap<DefId, u32>>,
+    exported: usize,
+}
+
+impl<'gcx> ToBuiContext<'gcx> {
+    fn is_exported(&self, def_id: &DefId) -> bool {
+        self.numbers
+            .borrow()
+            .get(def_id)
+            .is_some_and(|x| (*x as usize) < self.exported)
+    }
+
+    fn number(&self, def_id: &DefId) -> u32 {
+        let mut numbers = self.numbers.borrow_mut();
+        let next = u32::try_from(numbers.len()).unwrap();
+        *numbers.entry(def_id.clone()).or_insert(next)
+    }
+
+    fn set_item_ref(&self, builder: &mut bui_capnp::item_ref::Builder, def_id: &DefId) {
+        if def_id.is_local() {
+            builder.set_unit(0);
+            builder.set_def(self.number(def_id));
+        } else {
+            def_id.serialize(builder);
+        }
+    }
 }
 
 pub struct ToBuiVisitor<'gcx> {
@@ -18,23 +58,45 @@ pub struct ToBuiVisitor<'gcx> {
 }
 
 impl<'gcx> ToBuiVisitor<'gcx> {
-    pub fn new(item_num: usize, ctx: &'gcx GlobalContext<'gcx>) -> Self {
+    // `exported` are the top-level items written to the BUI, in order.
+    pub fn new(exported: Vec<DefId>, ctx: &'gcx GlobalContext<'gcx>) -> Self {
         let mut builder = TypedBuilder::<bui_capnp::unit::Owned>::new_default();
         let root = builder.init_root();
-        root.init_items(u32::try_from(item_num).unwrap());
+        root.init_items(u32::try_from(exported.len()).unwrap());
+        let numbers = exported
+            .iter()
+            .enumerate()
+            .map(|(i, def_id)| (def_id.clone(), u32::try_from(i).unwrap()))
+            .collect::<HashMap<_, _>>();
         ToBuiVisitor {
             builder,
             namespace: vec![],
             item_num: 0,
-            ctx: ToBuiContext { ctx },
+            ctx: ToBuiContext {
+                ctx,
+                numbers: RefCell::new(numbers),
+                exported: exported.len(),
+            },
         }
     }
 
-    pub fn collect(self) -> BuiMessage {
-        BuiMessage::new(&self.builder)
+    pub fn collect(mut self) -> BuiMessage {
+        // Taken over the message without its hash.
+        let hash = fnv1a(&write_message_to_words(self.builder.borrow_inner()));
+        self.builder.get_root().unwrap().set_hash(&hash.to_le_bytes());
+        BuiMessage::new(&self.builder, self.ctx.numbers.into_inner())
     }
 }
 
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in bytes {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
 fn set_ty(builder: &mut bui_capnp::ty::Builder, node: &crate::hil::Node, ctx: &ToBuiContext) {
     let kind = node_get_attr(node, "kind").as_keyword().unwrap();
     if kind == "bool" {
@@ -44,9 +106,7 @@ fn set_ty(builder: &mut bui_capnp::ty::Builder, node: &crate::hil::Node, ctx: &T
     } else if kind == "qpath" {
         let qpath = node_get_field(node, 0).as_cons().unwrap();
         let def_id = DefId::from_s_expr(node_get_field(qpath, 0));
-        let mut adt_builder = builder.reborrow().init_adt();
-
-        def_id.serialize(&mut adt_builder);
+        ctx.set_item_ref(&mut builder.reborrow().init_adt(), &def_id);
     } else if kind == "tuple" {
         let fields = node_get_field(node, 0).as_slice().unwrap();
         let mut fields_builder = builder
@@ -56,179 +116,611 @@ fn set_ty(builder: &mut bui_capnp::ty::Builder, node: &crate::hil::Node, ctx: &T
             let mut ty_builder = fields_builder.reborrow().get(u32::try_from(i).unwrap());
             set_ty(&mut ty_builder, field.as_cons().unwrap(), ctx);
         }
+    } else if kind == "fn" {
+        let fn_decl_builder = builder.reborrow().init_fn();
+        let fields = node_get_fields(node);
+        set_fn_decl(
+            fn_decl_builder,
+            fields[0].as_slice().unwrap().iter().map(|x| x.as_cons().unwrap()),
+            fields[1].as_cons().unwrap(),
+            ctx,
+        );
+    } else if kind == "param" {
+        let index = node_get_attr(node, "index").as_u64().unwrap();
+        builder.set_param(u32::try_from(index).unwrap());
+    } else if kind == "self" {
+        builder.set_param(0);
     }
 }
 
+fn set_item_ref(
+    builder: &mut bui_capnp::item_ref::Builder,
+    path: &crate::hil::Node,
+    ctx: &ToBuiContext,
+) {
+    ctx.set_item_ref(builder, &DefId::from_s_expr(node_get_field(path, 0)));
+}
+
+fn set_fn_decl<'a>(
+    mut builder: bui_capnp::fn_decl::Builder,
+    inputs: impl ExactSizeIterator<Item = &'a crate::hil::Node>,
+    output: &crate::hil::Node,
+    ctx: &ToBuiContext,
+) {
+    let mut inputs_builder = builder
+        .reborrow()
+        .init_inputs(u32::try_from(inputs.len()).unwrap());
+    for (i, input) in inputs.enumerate() {
+        let mut ty_builder = inputs_builder.reborrow().get(u32::try_from(i).unwrap());
+        set_ty(&mut ty_builder, input, ctx);
+    }
+    set_ty(&mut builder.init_output(), output, ctx);
+}
+
 fn set_defn(
     defn_builder: &mut bui_capnp::defn::Builder,
     node: &crate::hil::Node,
     ctx: &ToBuiContext,
 ) {
     let fn_sig_builder = defn_builder.reborrow().init_fn_sig();
-    let fn_decl_builder = fn_sig_builder.init_decl();
-    let inputs = node_get_field(
-        node_get_field(node_get_field(node, 1).as_cons().unwrap(), 0)
-            .as_cons()
-            .unwrap(),
-        0,
-    )
-    .as_slice()
-    .unwrap();
-    let mut inputs_builder = fn_decl_builder.init_inputs(u32::try_from(inputs.len()).unwrap());
-    for (i, input) in inputs.iter().enumerate() {
-        let mut ty_builder = inputs_builder.reborrow().get(u32::try_from(i).unwrap());
+    let fn_decl = node_get_field(node_get_field(node, 1).as_cons().unwrap(), 0)
+        .as_cons()
+        .unwrap();
+    let fields = node_get_fields(fn_decl);
+    let inputs = fields[0]
+        .as_slice()
+        .unwrap()
+        .iter()
+        .map(|x| node_get_field(x.as_cons().unwrap(), 1).as_cons().unwrap());
+    // A missing return type is the unit type.
+    let unit = sexp!((Ty #:kind #:tuple #()));
+    let output = match fields.get(1) {
+        Some(fn_ret_ty) => node_get_field(fn_ret_ty.as_cons().unwrap(), 0),
+        None => &unit,
+    };
+    set_fn_decl(
+        fn_sig_builder.init_decl(),
+        inputs,
+        output.as_cons().unwrap(),
+        ctx,
+    );
+    let generics = node_get_fields(node_get_field(node, 0).as_cons().unwrap());
+    let mut generics_builder = defn_builder
+        .reborrow()
+        .init_generics(u32::try_from(generics.len()).unwrap());
+    for (i, generic) in generics.iter().enumerate() {
+        let fields = node_get_fields(generic.as_cons().unwrap());
+        let mut generic_builder = generics_builder.reborrow().get(u32::try_from(i).unwrap());
+        generic_builder.set_name(fields[0].as_symbol().unwrap());
+        let mut bounds_builder =
+            generic_builder.init_bounds(u32::try_from(fields.len() - 1).unwrap());
+        for (j, bound) in fields[1..].iter().enumerate() {
+            let path = node_get_field(bound.as_cons().unwrap(), 0).as_cons().unwrap();
+            let mut bound_builder = bounds_builder.reborrow().get(u32::try_from(j).unwrap());
+            set_item_ref(&mut bound_builder, path, ctx);
+        }
+    }
+}
+
+fn set_module(
+    mut module_builder: bui_capnp::module::Builder,
+    structure: &crate::hil::Node,
+    ctx: &ToBuiContext,
+) {
+    module_builder.reborrow().init_modules(0);
+    let bindings = node_get_fields(structure)
+        .into_iter()
+        .filter(|x| is_public(x.as_cons().unwrap()))
+        .collect::<Vec<_>>();
+    let types = bindings
+        .iter()
+        .filter(|&x| {
+            node_get_attr(x.as_cons().unwrap(), "kind")
+                .as_keyword()
+                .unwrap()
+                == "ty"
+        })
+        .collect::<Vec<_>>();
+    let mut types_builder = module_builder
+        .reborrow()
+        .init_types(u32::try_from(types.len()).unwrap());
+    for (i, ty) in types.iter().enumerate() {
+        let mut ty_builder = types_builder.reborrow().get(u32::try_from(i).unwrap());
+        ty_builder
+            .set_key(
+                node_get_attr(ty.as_cons().unwrap(), "ident")
+                    .as_symbol()
+                    .unwrap(),
+            )
+            .unwrap();
+        let t = node_get_field(ty.as_cons().unwrap(), 0);
         set_ty(
-            &mut ty_builder,
-            node_get_field(input.as_cons().unwrap(), 1)
-                .as_cons()
-                .unwrap(),
+            &mut ty_builder.init_value(),
+            t.as_cons().unwrap(),
+            ctx,
+        );
+    }
+    let defns = bindings
+        .iter()
+        .filter(|&x| {
+            node_get_attr(x.as_cons().unwrap(), "kind")
+                .as_keyword()
+                .unwrap()
+                == "defn"
+        })
+        .collect::<Vec<_>>();
+    let mut defns_builder = module_builder
+        .reborrow()
+        .init_defns(u32::try_from(defns.len()).unwrap());
+    for (i, defn) in defns.iter().enumerate() {
+        let mut defn_builder = defns_builder.reborrow().get(u32::try_from(i).unwrap());
+        defn_builder
+            .set_key(
+                node_get_attr(defn.as_cons().unwrap(), "ident")
+                    .as_symbol()
+                    .unwrap(),
+            )
+            .unwrap();
+        set_defn(
+            &mut defn_builder.init_value(),
+            defn.as_cons().unwrap(),
             ctx,
         );
     }
 }
 
+fn set_type(builder: &mut bui_capnp::ty::Builder, t: TypeRef, ctx: &ToBuiContext) {
+    match t.kind() {
+        TypeKind::Bool => builder.set_bool(()),
+        TypeKind::Int => builder.set_int(()),
+        TypeKind::Adt(adt_def, _) => {
+            ctx.set_item_ref(&mut builder.reborrow().init_adt(), &adt_def.def_id)
+        }
+        TypeKind::Tuple(ts) => {
+            let mut elems_builder = builder.reborrow().init_tuple(u32::try_from(ts.len()).unwrap());
+            for (i, t) in ts.iter().enumerate() {
+                set_type(&mut elems_builder.reborrow().get(u32::try_from(i).unwrap()), t, ctx);
+            }
+        }
+        TypeKind::Fn(..) => set_fn_decl_type(builder.reborrow().init_fn(), t, ctx),
+        TypeKind::Param(param) => builder.set_param(param.index),
+        _ => panic!("Cannot serialize type {}", t),
+    }
+}
+
+fn set_fn_decl_type(mut builder: bui_capnp::fn_decl::Builder, t: TypeRef, ctx: &ToBuiContext) {
+    let (inputs, output) = match t.kind() {
+        TypeKind::Fn(inputs, output) => (inputs, output),
+        _ => panic!("Expected function type, found {}", t),
+    };
+    let mut inputs_builder = builder
+        .reborrow()
+        .init_inputs(u32::try_from(inputs.len()).unwrap());
+    for (i, t) in inputs.iter().enumerate() {
+        set_type(&mut inputs_builder.reborrow().get(u32::try_from(i).unwrap()), t, ctx);
+    }
+    set_type(&mut builder.init_output(), output, ctx);
+}
+
+// Writes the members of an interface as type checking expanded its includes,
+// the types refined by `with type` becoming transparent.
+fn set_interface(
+    mut interface_builder: bui_capnp::interface::Builder,
+    def_id: &DefId,
+    ctx: &ToBuiContext,
+) {
+    let sig = ctx.ctx.interface(def_id).unwrap_or_default();
+    let refinements = ctx.ctx.refinements_of(def_id);
+    let mut types_builder = interface_builder
+        .reborrow()
+        .init_types(u32::try_from(sig.types.len()).unwrap());
+    for (i, (name, decl)) in sig.types.iter().enumerate() {
+        let mut ty_builder = types_builder.reborrow().get(u32::try_from(i).unwrap());
+        ty_builder.set_key(name).unwrap();
+        match refinements.get(decl).cloned().or_else(|| ctx.ctx.ty_alias(decl)) {
+            Some(t) => set_type(&mut ty_builder.init_value().init_transparent(), t, ctx),
+            None => ty_builder.init_value().set_opaque(()),
+        }
+    }
+    let mut defns_builder = interface_builder
+        .reborrow()
+        .init_defns(u32::try_from(sig.defns.len()).unwrap());
+    for (i, (name, decl)) in sig.defns.iter().enumerate() {
+        let mut defn_builder = defns_builder.reborrow().get(u32::try_from(i).unwrap());
+        defn_builder.set_key(name).unwrap();
+        let mut defn_builder = defn_builder.init_value();
+        let generics = ctx.ctx.generics_of(decl);
+        let mut generics_builder = defn_builder
+            .reborrow()
+            .init_generics(u32::try_from(generics.len()).unwrap());
+        for (j, generic) in generics.iter().enumerate() {
+            let mut generic_builder = generics_builder.reborrow().get(u32::try_from(j).unwrap());
+            generic_builder.set_name(&generic.name);
+            let mut bounds_builder =
+                generic_builder.init_bounds(u32::try_from(generic.bounds.len()).unwrap());
+            for (k, bound) in generic.bounds.iter().enumerate() {
+                let mut bound_builder = bounds_builder.reborrow().get(u32::try_from(k).unwrap());
+                ctx.set_item_ref(&mut bound_builder, bound);
+            }
+        }
+        let t = ctx.ctx.type_of(decl).unwrap();
+        set_fn_decl_type(defn_builder.init_fn_sig().init_decl(), t, ctx);
+    }
+    let mut type_defs_builder = interface_builder
+        .reborrow()
+        .init_type_defs(u32::try_from(sig.types.len()).unwrap());
+    for (i, (_, decl)) in sig.types.iter().enumerate() {
+        let mut def_builder = type_defs_builder.reborrow().get(u32::try_from(i).unwrap());
+        ctx.set_item_ref(&mut def_builder, decl);
+    }
+    let mut defn_defs_builder =
+        interface_builder.init_defn_defs(u32::try_from(sig.defns.len()).unwrap());
+    for (i, (_, decl)) in sig.defns.iter().enumerate() {
+        let mut def_builder = defn_defs_builder.reborrow().get(u32::try_from(i).unwrap());
+        ctx.set_item_ref(&mut def_builder, decl);
+    }
+}
+
+fn set_module_instance(
+    mut module_builder: bui_capnp::module::Builder,
+    instance: &ModuleInstance,
+    ctx: &ToBuiContext,
+) {
+    module_builder.reborrow().init_modules(0);
+    let mut types_builder = module_builder
+        .reborrow()
+        .init_types(u32::try_from(instance.types.len()).unwrap());
+    for (i, (name, t)) in instance.types.iter().enumerate() {
+        let mut ty_builder = types_builder.reborrow().get(u32::try_from(i).unwrap());
+        ty_builder.set_key(name).unwrap();
+        set_type(&mut ty_builder.init_value(), t, ctx);
+    }
+    let mut defns_builder = module_builder
+        .reborrow()
+        .init_defns(u32::try_from(instance.defns.len()).unwrap());
+    for (i, (name, t)) in instance.defns.iter().enumerate() {
+        let mut defn_builder = defns_builder.reborrow().get(u32::try_from(i).unwrap());
+        defn_builder.set_key(name).unwrap();
+        let mut defn_builder = defn_builder.init_value();
+        defn_builder.reborrow().init_generics(0);
+        set_fn_decl_type(defn_builder.init_fn_sig().init_decl(), t, ctx);
+    }
+}
+
+fn set_const_value(
+    builder: &mut bui_capnp::const_value::Builder,
+    value: &ConstValue,
+    ctx: &ToBuiContext,
+) {
+    match value {
+        ConstValue::Bool(b) => builder.set_bool(*b),
+        ConstValue::Int(i) => builder.set_int(*i),
+        ConstValue::Tuple(elems) => {
+            let mut elems_builder = builder
+                .reborrow()
+                .init_tuple(u32::try_from(elems.len()).unwrap());
+            for (i, elem) in elems.iter().enumerate() {
+                let mut elem_builder = elems_builder.reborrow().get(u32::try_from(i).unwrap());
+                set_const_value(&mut elem_builder, elem, ctx);
+            }
+        }
+        ConstValue::Adt(def_id, fields) => {
+            let mut adt_builder = builder.reborrow().init_adt();
+            ctx.set_item_ref(&mut adt_builder.reborrow().init_adt(), def_id);
+            let mut fields_builder = adt_builder.init_fields(u32::try_from(fields.len()).unwrap());
+            for (i, field) in fields.iter().enumerate() {
+                let mut field_builder = fields_builder.reborrow().get(u32::try_from(i).unwrap());
+                set_const_value(&mut field_builder, field, ctx);
+            }
+        }
+    }
+}
+
+fn set_place(builder: &mut bui_capnp::til_place::Builder, place: &Place) {
+    builder.set_local(place.local.0);
+    let mut projection_builder = builder
+        .reborrow()
+        .init_projection(u32::try_from(place.projection.len()).unwrap());
+    for (i, index) in place.projection.iter().enumerate() {
+        projection_builder.set(u32::try_from(i).unwrap(), u32::try_from(*index).unwrap());
+    }
+}
+
+fn set_operand(
+    builder: &mut bui_capnp::til_operand::Builder,
+    operand: &Operand,
+    ctx: &ToBuiContext,
+) {
+    match operand {
+        Operand::Copy(place) => set_place(&mut builder.reborrow().init_copy(), place),
+        Operand::Const(value) => set_const_value(&mut builder.reborrow().init_const(), value, ctx),
+        Operand::Fn(def_id) => ctx.set_item_ref(&mut builder.reborrow().init_fn(), def_id),
+        Operand::Static(def_id) => ctx.set_item_ref(&mut builder.reborrow().init_static(), def_id),
+    }
+}
+
+fn set_rvalue(builder: &mut bui_capnp::til_rvalue::Builder, rvalue: &Rvalue, ctx: &ToBuiContext) {
+    match rvalue {
+        Rvalue::Use(operand) => set_operand(&mut builder.reborrow().init_use(), operand, ctx),
+        Rvalue::Unary(op, operand) => {
+            let mut unary_builder = builder.reborrow().init_unary();
+            unary_builder.set_op(op.keyword());
+            set_operand(&mut unary_builder.init_operand(), operand, ctx);
+        }
+        Rvalue::Binary(op, lhs, rhs) => {
+            let mut binary_builder = builder.reborrow().init_binary();
+            binary_builder.set_op(op.keyword());
+            set_operand(&mut binary_builder.reborrow().init_lhs(), lhs, ctx);
+            set_operand(&mut binary_builder.init_rhs(), rhs, ctx);
+        }
+        Rvalue::Tuple(elems) => {
+            let mut elems_builder = builder
+                .reborrow()
+                .init_tuple(u32::try_from(elems.len()).unwrap());
+            for (i, elem) in elems.iter().enumerate() {
+                let mut elem_builder = elems_builder.reborrow().get(u32::try_from(i).unwrap());
+                set_operand(&mut elem_builder, elem, ctx);
+            }
+        }
+        Rvalue::Adt(..) | Rvalue::Closure(..) => panic!("Cannot serialize {}", rvalue),
+    }
+}
+
+fn set_terminator(
+    builder: &mut bui_capnp::til_terminator::Builder,
+    terminator: &Terminator,
+    ctx: &ToBuiContext,
+) {
+    match terminator {
+        Terminator::Goto(target) => builder.set_goto(target.0),
+        Terminator::Branch {
+            cond,
+            then_block,
+            else_block,
+        } => {
+            let mut branch_builder = builder.reborrow().init_branch();
+            set_operand(&mut branch_builder.reborrow().init_cond(), cond, ctx);
+            branch_builder.set_then_block(then_block.0);
+            branch_builder.set_else_block(else_block.0);
+        }
+        Terminator::Return => builder.set_return(()),
+        Terminator::Call {
+            func,
+            args,
+            dest,
+            target,
+        } => {
+            let mut call_builder = builder.reborrow().init_call();
+            set_operand(&mut call_builder.reborrow().init_func(), func, ctx);
+            let mut args_builder = call_builder
+                .reborrow()
+                .init_args(u32::try_from(args.len()).unwrap());
+            for (i, arg) in args.iter().enumerate() {
+                set_operand(&mut args_builder.reborrow().get(u32::try_from(i).unwrap()), arg, ctx);
+            }
+            set_place(&mut call_builder.reborrow().init_dest(), dest);
+            call_builder.set_target(target.0);
+        }
+        Terminator::Unreachable => builder.set_unreachable(()),
+    }
+}
+
+fn set_til_body(mut builder: bui_capnp::til_body::Builder, body: &Body, ctx: &ToBuiContext) {
+    builder.set_arg_count(u32::try_from(body.arg_count).unwrap());
+    let mut locals_builder = builder
+        .reborrow()
+        .init_locals(u32::try_from(body.locals.len()).unwrap());
+    for (i, decl) in body.locals.iter().enumerate() {
+        let mut local_builder = locals_builder.reborrow().get(u32::try_from(i).unwrap());
+        set_type(&mut local_builder.reborrow().init_ty(), decl.ty, ctx);
+        if let Some(name) = &decl.name {
+            local_builder.set_name(name);
+        }
+    }
+    let mut blocks_builder = builder.init_blocks(u32::try_from(body.blocks.len()).unwrap());
+    for (i, block) in body.blocks.iter().enumerate() {
+        let mut block_builder = blocks_builder.reborrow().get(u32::try_from(i).unwrap());
+        let mut statements_builder = block_builder
+            .reborrow()
+            .init_statements(u32::try_from(block.statements.len()).unwrap());
+        for (j, statement) in block.statements.iter().enumerate() {
+            let Statement::Assign(place, rvalue) = statement;
+            let mut statement_builder =
+                statements_builder.reborrow().get(u32::try_from(j).unwrap());
+            set_place(&mut statement_builder.reborrow().init_place(), place);
+            set_rvalue(&mut statement_builder.init_rvalue(), rvalue, ctx);
+        }
+        set_terminator(&mut block_builder.init_terminator(), &block.terminator, ctx);
+    }
+}
+
+// Only public items are written to the BUI, impls being as visible as their
+// trait.
+pub fn is_exported(node: &crate::hil::Node) -> bool {
+    is_public(node) || node_get_attr(node, "kind").as_keyword() == Some("impl")
+}
+
 impl<'gcx> HilVisitor for ToBuiVisitor<'gcx> {
     fn visit_item(&mut self, node: &super::Node) {
         let kind = node_get_attr(node, "kind").as_keyword().unwrap();
+        if !is_exported(node) {
+            return;
+        }
         let root = self.builder.get_root().unwrap();
         let mut builder = root.get_items().unwrap().get(self.item_num);
-        let ident = node_get_attr(node, "ident").as_symbol().unwrap();
-        builder.set_ident(ident);
+        // Impls are anonymous.
+        let ident = node_find_attr(node, "ident").and_then(|x| x.as_symbol());
+        builder.set_ident(ident.unwrap_or(""));
         let mut namespace_builder = builder
             .reborrow()
             .init_namespace(u32::try_from(self.namespace.len()).unwrap());
         for (i, name) in self.namespace.iter().enumerate() {
             namespace_builder.set(u32::try_from(i).unwrap(), name);
         }
-        let def_id = node_get_attr(node, "def_id").as_u64().unwrap();
-        builder.reborrow().set_def(u32::try_from(def_id).unwrap());
+        let def_id = DefLocalId::from_s_expr(node_get_attr(node, "def_id")).to_def_id();
+        builder.reborrow().set_def(self.ctx.number(&def_id));
         let kind_builder = builder.reborrow().init_kind();
         if kind == "class" {
-            let class_builder = kind_builder.init_class();
-            class_builder.init_fields(0);
-        } else if kind == "defn" {
-            set_defn(&mut kind_builder.init_defn().reborrow(), node, &self.ctx);
-        } else if kind == "interface" {
-            let mut interface_builder = kind_builder.init_interface();
-            let decls = node_get_fields(node_get_field(node, 0).as_cons().unwrap());
-            let types = decls
-                .iter()
-                .filter(|&x| {
-                    node_get_attr(x.as_cons().unwrap(), "kind")
-                        .as_keyword()
-                        .unwrap()
-                        == "ty"
-                })
+            let mut class_builder = kind_builder.init_class();
+            let fields = node_get_fields(node);
+            let methods = fields
+                .get(1)
+                .map(|x| node_get_fields(x.as_cons().unwrap()))
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|x| is_public(x.as_cons().unwrap()))
                 .collect::<Vec<_>>();
-            let mut types_builder = interface_builder
+            let mut methods_builder = class_builder
                 .reborrow()
-                .init_types(u32::try_from(types.len()).unwrap());
-            for (i, ty) in types.iter().enumerate() {
-                let mut ty_builder = types_builder.reborrow().get(u32::try_from(i).unwrap());
-                ty_builder
-                    .set_key(
-                        node_get_attr(ty.as_cons().unwrap(), "ident")
-                            .as_symbol()
-                            .unwrap(),
-                    )
+                .init_methods(u32::try_from(methods.len()).unwrap());
+            for (i, method) in methods.iter().enumerate() {
+                let method = method.as_cons().unwrap();
+                let mut method_builder = methods_builder.reborrow().get(u32::try_from(i).unwrap());
+                method_builder
+                    .set_key(node_get_attr(method, "ident").as_symbol().unwrap())
                     .unwrap();
-                let components = node_get_fields(ty.as_cons().unwrap());
-                if components.len() == 0 {
-                    ty_builder.init_value().set_opaque(());
-                } else {
-                    set_ty(
-                        &mut ty_builder.init_value().init_transparent(),
-                        components[0].as_cons().unwrap(),
-                        &self.ctx,
-                    );
-                }
+                set_defn(&mut method_builder.init_value(), method, &self.ctx);
             }
-            let defns = decls
-                .iter()
-                .filter(|&x| {
-                    node_get_attr(x.as_cons().unwrap(), "kind")
-                        .as_keyword()
-                        .unwrap()
-                        == "defn"
-                })
-                .collect::<Vec<_>>();
-            let mut defns_builder = interface_builder
+            let mut method_defs_builder = class_builder
                 .reborrow()
-                .init_defns(u32::try_from(defns.len()).unwrap());
-            for (i, defn) in defns.iter().enumerate() {
-                let mut defn_builder = defns_builder.reborrow().get(u32::try_from(i).unwrap());
-                defn_builder
-                    .set_key(
-                        node_get_attr(defn.as_cons().unwrap(), "ident")
-                            .as_symbol()
-                            .unwrap(),
-                    )
-                    .unwrap();
-                set_defn(
-                    &mut defn_builder.init_value(),
-                    defn.as_cons().unwrap(),
+                .init_method_defs(u32::try_from(methods.len()).unwrap());
+            for (i, method) in methods.iter().enumerate() {
+                let def_id = node_get_attr(method.as_cons().unwrap(), "def_id");
+                let def_id = DefLocalId::from_s_expr(def_id).to_def_id();
+                method_defs_builder.set(u32::try_from(i).unwrap(), self.ctx.number(&def_id));
+            }
+            let variant = fields[0].as_cons().unwrap();
+            let field_defs = node_get_fields(variant);
+            let mut fields_builder =
+                class_builder.init_fields(u32::try_from(field_defs.len()).unwrap());
+            for (i, field_def) in field_defs.iter().enumerate() {
+                let field_def = field_def.as_cons().unwrap();
+                let mut field_builder = fields_builder.reborrow().get(u32::try_from(i).unwrap());
+                field_builder.set_name(node_get_field(field_def, 0).as_symbol().unwrap());
+                field_builder.set_public(is_public(field_def));
+                let def_id = DefLocalId::from_s_expr(node_get_attr(field_def, "def_id")).to_def_id();
+                field_builder.set_def(self.ctx.number(&def_id));
+                set_ty(
+                    &mut field_builder.init_type(),
+                    node_get_field(field_def, 1).as_cons().unwrap(),
                     &self.ctx,
                 );
             }
-        } else if kind == "module" {
-            let mut module_builder = kind_builder.init_module();
-            module_builder.reborrow().init_modules(0);
-            let bindings = node_get_fields(node_get_field(node, 0).as_cons().unwrap());
-            let types = bindings
-                .iter()
-                .filter(|&x| {
-                    node_get_attr(x.as_cons().unwrap(), "kind")
-                        .as_keyword()
-                        .unwrap()
-                        == "ty"
-                })
-                .collect::<Vec<_>>();
-            let mut types_builder = module_builder
+        } else if kind == "defn" {
+            let mut defn_builder = kind_builder.init_defn();
+            set_defn(&mut defn_builder, node, &self.ctx);
+            // Small defns also give their body, for other units to inline.
+            let def_id = DefLocalId::from_s_expr(node_get_attr(node, "def_id")).to_def_id();
+            if let Some((til, body_id)) = self.ctx.ctx.fn_body(&def_id) {
+                let body = til.body(body_id);
+                if (is_inline(node) || body_size(body) <= INLINE_THRESHOLD)
+                    && is_exportable(body, |x| self.ctx.is_exported(x))
+                {
+                    set_til_body(defn_builder.init_inline(), body, &self.ctx);
+                }
+            }
+        } else if kind == "trait" {
+            let mut trait_builder = kind_builder.init_trait();
+            let decls = node_get_fields(node_get_field(node, 0).as_cons().unwrap());
+            let mut method_defs_builder = trait_builder
                 .reborrow()
-                .init_types(u32::try_from(types.len()).unwrap());
-            for (i, ty) in types.iter().enumerate() {
-                let mut ty_builder = types_builder.reborrow().get(u32::try_from(i).unwrap());
-                ty_builder
-                    .set_key(
-                        node_get_attr(ty.as_cons().unwrap(), "ident")
-                            .as_symbol()
-                            .unwrap(),
-                    )
+                .init_method_defs(u32::try_from(decls.len()).unwrap());
+            for (i, decl) in decls.iter().enumerate() {
+                let def_id = node_get_attr(decl.as_cons().unwrap(), "def_id");
+                let def_id = DefLocalId::from_s_expr(def_id).to_def_id();
+                method_defs_builder.set(u32::try_from(i).unwrap(), self.ctx.number(&def_id));
+            }
+            let mut methods_builder =
+                trait_builder.init_methods(u32::try_from(decls.len()).unwrap());
+            for (i, decl) in decls.iter().enumerate() {
+                let decl = decl.as_cons().unwrap();
+                let mut method_builder = methods_builder.reborrow().get(u32::try_from(i).unwrap());
+                method_builder
+                    .set_key(node_get_attr(decl, "ident").as_symbol().unwrap())
                     .unwrap();
-                let t = node_get_field(ty.as_cons().unwrap(), 0);
-                set_ty(
-                    &mut ty_builder.init_value(),
-                    t.as_cons().unwrap(),
-                    &self.ctx,
-                );
+                set_defn(&mut method_builder.init_value(), decl, &self.ctx);
             }
-            let defns = bindings
-                .iter()
-                .filter(|&x| {
-                    node_get_attr(x.as_cons().unwrap(), "kind")
-                        .as_keyword()
-                        .unwrap()
-                        == "defn"
-                })
-                .collect::<Vec<_>>();
-            let mut defns_builder = module_builder
+        } else if kind == "impl" {
+            let mut impl_builder = kind_builder.init_impl();
+            let fields = node_get_fields(node);
+            let trait_ref = fields[0].as_cons().unwrap();
+            set_item_ref(
+                &mut impl_builder.reborrow().init_trait(),
+                node_get_field(trait_ref, 0).as_cons().unwrap(),
+                &self.ctx,
+            );
+            set_ty(
+                &mut impl_builder.reborrow().init_self_ty(),
+                fields[1].as_cons().unwrap(),
+                &self.ctx,
+            );
+            let bindings = node_get_fields(fields[2].as_cons().unwrap());
+            let mut methods_builder =
+                impl_builder.init_methods(u32::try_from(bindings.len()).unwrap());
+            for (i, binding) in bindings.iter().enumerate() {
+                let binding = binding.as_cons().unwrap();
+                let mut method_builder = methods_builder.reborrow().get(u32::try_from(i).unwrap());
+                method_builder
+                    .set_key(node_get_attr(binding, "ident").as_symbol().unwrap())
+                    .unwrap();
+                let def_id = DefLocalId::from_s_expr(node_get_attr(binding, "def_id")).to_def_id();
+                self.ctx.set_item_ref(&mut method_builder.init_value(), &def_id);
+            }
+        } else if kind == "interface" {
+            set_interface(kind_builder.init_interface(), &def_id, &self.ctx);
+        } else if kind == "module" {
+            set_module(
+                kind_builder.init_module(),
+                node_get_field(node, 0).as_cons().unwrap(),
+                &self.ctx,
+            );
+        } else if kind == "functor" {
+            let mut functor_builder = kind_builder.init_functor();
+            let params = node_get_field(node, 0).as_slice().unwrap();
+            let mut params_builder = functor_builder
                 .reborrow()
-                .init_defns(u32::try_from(defns.len()).unwrap());
-            for (i, defn) in defns.iter().enumerate() {
-                let mut defn_builder = defns_builder.reborrow().get(u32::try_from(i).unwrap());
-                defn_builder
-                    .set_key(
-                        node_get_attr(defn.as_cons().unwrap(), "ident")
-                            .as_symbol()
-                            .unwrap(),
-                    )
+                .init_params(u32::try_from(params.len()).unwrap());
+            for (i, param) in params.iter().enumerate() {
+                let param = param.as_cons().unwrap();
+                let mut param_builder = params_builder.reborrow().get(u32::try_from(i).unwrap());
+                param_builder
+                    .set_key(node_get_field(param, 0).as_symbol().unwrap())
                     .unwrap();
-                set_defn(
-                    &mut defn_builder.init_value(),
-                    defn.as_cons().unwrap(),
+                let iface = node_get_field(param, 1).as_cons().unwrap();
+                set_item_ref(
+                    &mut param_builder.init_value(),
+                    node_get_field(iface, 0).as_cons().unwrap(),
                     &self.ctx,
                 );
             }
+            set_module(
+                functor_builder.init_body(),
+                node_get_field(node, 1).as_cons().unwrap(),
+                &self.ctx,
+            );
+        } else if kind == "const" {
+            // Other units inline the value of a const instead of reading it.
+            let mut const_builder = kind_builder.init_const();
+            set_ty(
+                &mut const_builder.reborrow().init_ty(),
+                node_get_field(node, 0).as_cons().unwrap(),
+                &self.ctx,
+            );
+            let def_id = DefLocalId::from_s_expr(node_get_attr(node, "def_id")).to_def_id();
+            if let Some(value) = self.ctx.ctx.const_value(&def_id) {
+                set_const_value(&mut const_builder.init_value(), &value, &self.ctx);
+            }
+        } else if kind == "static" {
+            set_ty(
+                &mut kind_builder.init_static().init_ty(),
+                node_get_field(node, 0).as_cons().unwrap(),
+                &self.ctx,
+            );
+        } else if kind == "apply" {
+            // Other units see the result of the application as a module.
+            let def_id = DefLocalId::from_s_expr(node_get_attr(node, "def_id")).to_def_id();
+            let instance = self.ctx.ctx.module_instance(&def_id).unwrap_or_default();
+            set_module_instance(kind_builder.init_module(), &instance, &self.ctx);
         }
         self.item_num += 1;
     }
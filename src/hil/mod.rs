@@ -1,4 +1,5 @@
 mod bui_builder;
+mod til_builder;
 
 use std::sync::atomic::{AtomicU32, Ordering};
 
@@ -6,11 +7,13 @@ use lexpr::{Cons, Number, Value};
 
 use crate::bui::BuiMessage;
 use crate::context::GlobalContext;
-use crate::def::DefId;
-use crate::hil::bui_builder::ToBuiVisitor;
+use crate::def::{DefId, DefLocalId};
+use crate::hil::bui_builder::{is_exported, ToBuiVisitor};
+use crate::hil::til_builder::ToTilVisitor;
 use crate::s_expr::{node_get_attr, node_get_fields};
 use crate::t::TypeCheckContext;
 use crate::t::context::UnitPath;
+use crate::til::dataflow::init::check_init;
 use crate::til::Til;
 
 pub type Hil = Value; // high level intermediate language
@@ -35,6 +38,12 @@ pub struct HilId {
     private: u32,
 }
 
+impl Default for HilId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl HilId {
     pub fn new() -> Self {
         HilId {
@@ -102,7 +111,7 @@ pub fn walk_hil_node<T: ?Sized + HilVisitor>(v: &mut T, node: &Node) {
     }
 }
 
-fn walk_hil<T: ?Sized + HilVisitor>(v: &mut T, hil: &Hil) {
+pub fn walk_hil<T: ?Sized + HilVisitor>(v: &mut T, hil: &Hil) {
     match hil {
         Value::Cons(c) => {
             walk_hil_node(v, c);
@@ -118,11 +127,24 @@ pub fn to_bui<'a>(hil: &Hil, ctx: &'a GlobalContext<'a>) -> BuiMessage {
     assert_eq!(hil.as_cons().unwrap().car().as_symbol().unwrap(), "Unit");
     let fields = node_get_fields(hil.as_cons().unwrap());
     fields.iter().for_each(|x| assert_eq!(x.as_cons().unwrap().car().as_symbol().unwrap(), "Item"));
-    let mut collector = ToBuiVisitor::new(fields.len(), ctx);
+    let exported = fields
+        .iter()
+        .map(|x| x.as_cons().unwrap())
+        .filter(|x| is_exported(x))
+        .map(|x| DefLocalId::from_s_expr(node_get_attr(x, "def_id")).to_def_id())
+        .collect::<Vec<_>>();
+    let mut collector = ToBuiVisitor::new(exported, ctx);
     collector.visit(hil);
     collector.collect()
 }
 
-pub fn to_til<'a>(hil: &Hil, ctx: &'a GlobalContext<'a>, tctx: &TypeCheckContext<'a>) -> Til {
-    unimplemented!()
+pub fn to_til<'a>(hil: &Hil, ctx: &'a GlobalContext<'a>, tctx: &TypeCheckContext<'a>) -> Til<'a> {
+    assert_eq!(hil.as_cons().unwrap().car().as_symbol().unwrap(), "Unit");
+    let mut collector = ToTilVisitor::new(ctx, tctx);
+    collector.visit(hil);
+    let til = collector.collect(&hil_get_unit_path(hil));
+    for body in &til.bodies {
+        check_init(body);
+    }
+    til
 }
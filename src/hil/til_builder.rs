@@ -0,0 +1,711 @@
+use std::collections::{HashMap, HashSet};
+
+use lexpr::Value;
+
+use crate::context::GlobalContext;
+use crate::def::{DefId, DefLocalId};
+use crate::ast::is_public;
+use crate::hil::{walk_hil, Hil, HilId, HilVisitor, Node};
+use crate::op::{BinOp, UnOp};
+use crate::s_expr::{node_find_attr, node_get_attr, node_get_field, node_get_fields};
+use crate::t::context::{TyCtx, UnitPath};
+use crate::t::const_eval::ConstValue;
+use crate::t::{TypeCheckContext, TypeKind, TypeRef};
+use crate::til::{
+    BasicBlock, BlockId, Body, BodyId, BodyTy, Local, LocalDecl, Operand, Place, Rvalue,
+    Statement, Terminator, Til, field_ty,
+};
+
+// Lowers the type-checked defns of a unit, including the ones of modules,
+// classes and impls, to TIL bodies.
+pub struct ToTilVisitor<'a, 'gcx> {
+    global_ctx: &'gcx GlobalContext<'gcx>,
+    tctx: &'a TypeCheckContext<'gcx>,
+    statics: HashSet<DefId>,
+    // Enclosing namespaces, modules, classes and impls.
+    namespace: Vec<String>,
+    item_paths: HashMap<DefId, Vec<String>>,
+    bodies: Vec<Body<'gcx>>,
+}
+
+impl<'a, 'gcx> ToTilVisitor<'a, 'gcx> {
+    pub fn new(global_ctx: &'gcx GlobalContext<'gcx>, tctx: &'a TypeCheckContext<'gcx>) -> Self {
+        ToTilVisitor {
+            global_ctx,
+            tctx,
+            statics: HashSet::new(),
+            namespace: vec![],
+            item_paths: HashMap::new(),
+            bodies: vec![],
+        }
+    }
+
+    pub fn collect(self, unit_path: &UnitPath) -> Til<'gcx> {
+        Til {
+            unit_path: unit_path.segments().to_vec(),
+            item_paths: self.item_paths,
+            bodies: self.bodies,
+        }
+    }
+
+    fn record_path(&mut self, node: &Node) {
+        if let Some(ident) = node_find_attr(node, "ident") {
+            let def_id = DefLocalId::from_s_expr(node_get_attr(node, "def_id")).to_def_id();
+            let mut path = self.namespace.clone();
+            path.push(ident.to_string());
+            self.item_paths.insert(def_id, path);
+        }
+    }
+
+    fn lower_defn(&mut self, node: &Node) {
+        let def_id = DefLocalId::from_s_expr(node_get_attr(node, "def_id")).to_def_id();
+        let Some(ty_ctx) = self.tctx.ty_ctx(&def_id) else {
+            return;
+        };
+        let output = match self.global_ctx.type_of(&def_id).map(|t| t.kind()) {
+            Some(TypeKind::Fn(_, output)) => *output,
+            _ => self.global_ctx.error_type(),
+        };
+        let fields = node_get_fields(node);
+        let decl = node_get_field(fields[1].as_cons().unwrap(), 0).as_cons().unwrap();
+        let mut builder = BodyBuilder::new(
+            self.global_ctx,
+            ty_ctx,
+            &self.statics,
+            &mut self.bodies,
+            (def_id.clone(), node_get_attr(node, "ident").to_string()),
+            output,
+        );
+        builder.lower_params(node_get_field(decl, 0));
+        let mut body = builder.lower_body(BodyTy::Fn(def_id), fields[2]);
+        body.public = is_public(node);
+        self.bodies.push(body);
+    }
+}
+
+impl<'a, 'gcx> HilVisitor for ToTilVisitor<'a, 'gcx> {
+    // Members of modules, functors, classes and impls are in the scope of
+    // their item, impls being named by their `DefId`.
+    fn visit(&mut self, hil: &Hil) {
+        let item = hil
+            .as_cons()
+            .filter(|x| x.car().as_symbol() == Some("Item"));
+        let scope = item.and_then(|item| {
+            let kind = node_get_attr(item, "kind").as_keyword().unwrap();
+            match kind {
+                "module" | "functor" | "class" => Some(node_get_attr(item, "ident").to_string()),
+                "impl" => Some(format!("impl{}", node_get_attr(item, "def_id"))),
+                _ => None,
+            }
+        });
+        if let Some(item) = item {
+            self.record_path(item);
+        }
+        if let Some(scope) = &scope {
+            self.namespace.push(scope.clone());
+        }
+        walk_hil(self, hil);
+        if scope.is_some() {
+            self.namespace.pop();
+        }
+    }
+
+    fn visit_item(&mut self, node: &Node) {
+        let kind = node_get_attr(node, "kind").as_keyword().unwrap();
+        if kind == "defn" {
+            self.lower_defn(node);
+        } else if kind == "static" {
+            let def_id = DefLocalId::from_s_expr(node_get_attr(node, "def_id")).to_def_id();
+            self.statics.insert(def_id);
+        }
+    }
+
+    fn visit_binding(&mut self, node: &Node) {
+        self.record_path(node);
+        if node_get_attr(node, "kind").as_keyword() == Some("defn") {
+            self.lower_defn(node);
+        }
+    }
+
+    fn visit_pre_namespace(&mut self, node: &Node) {
+        let n = node_get_field(node, 0).as_symbol().unwrap();
+        self.namespace.push(n.to_string());
+    }
+
+    fn visit_post_namespace(&mut self, _: &Node) {
+        self.namespace.pop();
+    }
+}
+
+struct LoopScope {
+    break_block: BlockId,
+    continue_block: BlockId,
+    // Where the value of `break` goes.
+    dest: Place,
+}
+
+struct BlockBuilder {
+    statements: Vec<Statement>,
+    terminator: Option<Terminator>,
+}
+
+// Builds the CFG of one defn or closure. Code following a diverging
+// expression goes to a fresh block without predecessors.
+struct BodyBuilder<'a, 'gcx> {
+    global_ctx: &'gcx GlobalContext<'gcx>,
+    ty_ctx: &'a TyCtx<'gcx>,
+    statics: &'a HashSet<DefId>,
+    // Bodies of the unit, closures being pushed before their owner.
+    bodies: &'a mut Vec<Body<'gcx>>,
+    // Defn the body belongs to and its name.
+    owner: (DefId, String),
+    locals: Vec<LocalDecl<'gcx>>,
+    arg_count: usize,
+    blocks: Vec<BlockBuilder>,
+    current: BlockId,
+    local_map: HashMap<HilId, Local>,
+    loops: Vec<LoopScope>,
+}
+
+impl<'a, 'gcx> BodyBuilder<'a, 'gcx> {
+    fn new(
+        global_ctx: &'gcx GlobalContext<'gcx>,
+        ty_ctx: &'a TyCtx<'gcx>,
+        statics: &'a HashSet<DefId>,
+        bodies: &'a mut Vec<Body<'gcx>>,
+        owner: (DefId, String),
+        output: TypeRef<'gcx>,
+    ) -> Self {
+        BodyBuilder {
+            global_ctx,
+            ty_ctx,
+            statics,
+            bodies,
+            owner,
+            locals: vec![LocalDecl {
+                ty: output,
+                name: None,
+            }],
+            arg_count: 0,
+            blocks: vec![BlockBuilder {
+                statements: vec![],
+                terminator: None,
+            }],
+            current: BlockId::ENTRY,
+            local_map: HashMap::new(),
+            loops: vec![],
+        }
+    }
+
+    fn new_local(&mut self, ty: TypeRef<'gcx>, name: Option<String>) -> Local {
+        let local = Local(u32::try_from(self.locals.len()).unwrap());
+        self.locals.push(LocalDecl { ty, name });
+        local
+    }
+
+    fn new_temp(&mut self, ty: TypeRef<'gcx>) -> Place {
+        Place::local(self.new_local(ty, None))
+    }
+
+    // Declares the local of a `Param` or `Local` node.
+    fn declare(&mut self, node: &Node) -> Local {
+        let hil_id = HilId::from_s_expr(node_get_attr(node, "hil_id"));
+        let ty = self
+            .ty_ctx
+            .local_type(hil_id)
+            .unwrap_or_else(|| self.global_ctx.error_type());
+        let name = node_get_field(node, 0).as_symbol().map(str::to_string);
+        let local = self.new_local(ty, name);
+        self.local_map.insert(hil_id, local);
+        local
+    }
+
+    fn lower_params(&mut self, params: &Value) {
+        for param in params.as_slice().unwrap() {
+            self.declare(param.as_cons().unwrap());
+            self.arg_count += 1;
+        }
+    }
+
+    fn lower_body(mut self, ty: BodyTy, block: &Value) -> Body<'gcx> {
+        let name = match ty {
+            BodyTy::Fn(_) => self.owner.1.clone(),
+            BodyTy::Closure { .. } => format!("{}::{{closure}}", self.owner.1),
+        };
+        self.lower_block(Place::local(Local::RETURN), block);
+        self.terminate(Terminator::Return);
+        let blocks = self
+            .blocks
+            .into_iter()
+            .map(|block| BasicBlock {
+                phis: vec![],
+                statements: block.statements,
+                terminator: block.terminator.unwrap_or(Terminator::Unreachable),
+            })
+            .collect();
+        Body {
+            ty,
+            name,
+            public: false,
+            locals: self.locals,
+            arg_count: self.arg_count,
+            blocks,
+        }
+    }
+
+    fn new_block(&mut self) -> BlockId {
+        let block = BlockId(u32::try_from(self.blocks.len()).unwrap());
+        self.blocks.push(BlockBuilder {
+            statements: vec![],
+            terminator: None,
+        });
+        block
+    }
+
+    fn push(&mut self, statement: Statement) {
+        self.blocks[self.current.index()].statements.push(statement);
+    }
+
+    fn assign(&mut self, place: Place, rvalue: Rvalue) {
+        self.push(Statement::Assign(place, rvalue));
+    }
+
+    fn terminate(&mut self, terminator: Terminator) {
+        let block = &mut self.blocks[self.current.index()];
+        if block.terminator.is_none() {
+            block.terminator = Some(terminator);
+        }
+    }
+
+    fn goto(&mut self, target: BlockId) {
+        self.terminate(Terminator::Goto(target));
+        self.current = target;
+    }
+
+    // Ends the current block by `terminator` when the code after it cannot
+    // be reached.
+    fn diverge(&mut self, terminator: Terminator) {
+        self.terminate(terminator);
+        self.current = self.new_block();
+    }
+
+    fn expr_type(&self, expr: &Node) -> TypeRef<'gcx> {
+        let hil_id = HilId::from_s_expr(node_get_attr(expr, "hil_id"));
+        self.ty_ctx
+            .expr_type(hil_id)
+            .unwrap_or_else(|| self.global_ctx.error_type())
+    }
+
+    fn place_type(&self, place: &Place) -> TypeRef<'gcx> {
+        place
+            .projection
+            .iter()
+            .fold(self.locals[place.local.index()].ty, |t, index| {
+                field_ty(self.global_ctx, t, *index)
+            })
+    }
+
+    // Blocks without a value which are not typed `()` diverge, so that
+    // nothing is stored into the unreachable block following them.
+    fn assign_unit(&mut self, dest: Place) {
+        if matches!(self.place_type(&dest).kind(), TypeKind::Tuple(elems) if elems.is_empty()) {
+            self.assign(dest, Rvalue::Use(Operand::unit()));
+        }
+    }
+
+    fn lower_block(&mut self, dest: Place, block: &Value) {
+        let block = block.as_cons().unwrap();
+        assert_eq!(block.car().as_symbol().unwrap(), "Block");
+        let mut has_value = false;
+        for stmt in node_get_fields(block) {
+            let stmt = stmt.as_cons().unwrap();
+            let kind = node_get_attr(stmt, "kind").as_keyword().unwrap();
+            let inner = node_get_field(stmt, 0).as_cons().unwrap();
+            if kind == "expr" {
+                self.lower_into(dest.clone(), inner);
+                has_value = true;
+            } else if kind == "semi" {
+                let temp = self.new_temp(self.expr_type(inner));
+                self.lower_into(temp, inner);
+            } else if kind == "let" {
+                self.lower_local(inner);
+            }
+        }
+        if !has_value {
+            self.assign_unit(dest);
+        }
+    }
+
+    fn lower_local(&mut self, node: &Node) {
+        let init = node_get_fields(node)
+            .into_iter()
+            .skip(1)
+            .map(|x| x.as_cons().unwrap())
+            .find(|x| x.car().as_symbol() == Some("Expr"));
+        let local = self.declare(node);
+        if let Some(init) = init {
+            self.lower_into(Place::local(local), init);
+        }
+    }
+
+    // Lowers `expr` to an operand, using a temporary unless it is a
+    // literal, a path or a field of a place.
+    fn lower_operand(&mut self, expr: &Node) -> Operand {
+        let kind = node_get_attr(expr, "kind").as_keyword().unwrap();
+        let fields = node_get_fields(expr);
+        if kind == "lit" {
+            return Operand::Const(lower_lit(fields[0]));
+        }
+        if kind == "qpath" {
+            return self.lower_path(fields[0].as_cons().unwrap());
+        }
+        if kind == "field" {
+            if let Some(place) = self.lower_place(expr) {
+                return Operand::Copy(place);
+            }
+        }
+        let temp = self.new_temp(self.expr_type(expr));
+        self.lower_into(temp.clone(), expr);
+        Operand::Copy(temp)
+    }
+
+    fn lower_path(&mut self, path: &Node) -> Operand {
+        if node_get_attr(path, "kind").as_keyword() == Some("local") {
+            let hil_id = HilId::from_s_expr(node_get_attr(path, "local"));
+            return Operand::Copy(Place::local(self.local_map[&hil_id]));
+        }
+        let def_id = DefId::from_s_expr(node_get_attr(path, "def"));
+        if self.statics.contains(&def_id) {
+            return Operand::Static(def_id);
+        }
+        match self.global_ctx.const_value(&def_id) {
+            Some(value) => Operand::Const(value),
+            None => Operand::Fn(def_id),
+        }
+    }
+
+    // Place of a local or of a field of a place, if `expr` is one.
+    fn lower_place(&mut self, expr: &Node) -> Option<Place> {
+        let kind = node_get_attr(expr, "kind").as_keyword().unwrap();
+        let fields = node_get_fields(expr);
+        if kind == "qpath" {
+            match self.lower_path(fields[0].as_cons().unwrap()) {
+                Operand::Copy(place) => Some(place),
+                _ => None,
+            }
+        } else if kind == "field" {
+            let base = fields[0].as_cons().unwrap();
+            let base = match self.lower_place(base) {
+                Some(place) => place,
+                None => {
+                    let temp = self.new_temp(self.expr_type(base));
+                    self.lower_into(temp.clone(), base);
+                    temp
+                }
+            };
+            let hil_id = HilId::from_s_expr(node_get_attr(expr, "hil_id"));
+            Some(base.field(self.ty_ctx.field_index(hil_id)?))
+        } else {
+            None
+        }
+    }
+
+    fn lower_operands(&mut self, exprs: &Value) -> Vec<Operand> {
+        let exprs = exprs
+            .as_slice()
+            .unwrap()
+            .iter()
+            .map(|x| x.as_cons().unwrap())
+            .collect::<Vec<_>>();
+        self.lower_operand_list(&exprs)
+    }
+
+    // Lowers `exprs` from left to right. An operand reading a place is copied
+    // to a temporary when a later expression could assign to the place
+    // before the operand is used.
+    fn lower_operand_list(&mut self, exprs: &[&Node]) -> Vec<Operand> {
+        let mut operands = vec![];
+        for (i, expr) in exprs.iter().enumerate() {
+            let operand = self.lower_operand(expr);
+            let operand = match operand {
+                Operand::Copy(place) if exprs[i + 1..].iter().any(|x| has_effects(x)) => {
+                    let temp = self.new_temp(self.place_type(&place));
+                    self.assign(temp.clone(), Rvalue::Use(Operand::Copy(place)));
+                    Operand::Copy(temp)
+                }
+                operand => operand,
+            };
+            operands.push(operand);
+        }
+        operands
+    }
+
+    // Lowers `expr`, storing its value into `dest`.
+    fn lower_into(&mut self, dest: Place, expr: &Node) {
+        let kind = node_get_attr(expr, "kind").as_keyword().unwrap();
+        let fields = node_get_fields(expr);
+        if kind == "lit" || kind == "qpath" || kind == "field" {
+            let operand = self.lower_operand(expr);
+            self.assign(dest, Rvalue::Use(operand));
+        } else if kind == "unary" {
+            let op = UnOp::from_s_expr(node_get_attr(expr, "op"));
+            let operand = self.lower_operand(fields[0].as_cons().unwrap());
+            self.assign(dest, Rvalue::Unary(op, operand));
+        } else if kind == "binary" {
+            let op = BinOp::from_s_expr(node_get_attr(expr, "op"));
+            let lhs = fields[0].as_cons().unwrap();
+            let rhs = fields[1].as_cons().unwrap();
+            if op == BinOp::And || op == BinOp::Or {
+                self.lower_logical(dest, op, lhs, rhs);
+            } else {
+                let mut operands = self.lower_operand_list(&[lhs, rhs]);
+                let rhs = operands.pop().unwrap();
+                let lhs = operands.pop().unwrap();
+                self.assign(dest, Rvalue::Binary(op, lhs, rhs));
+            }
+        } else if kind == "if" {
+            self.lower_if(dest, &fields);
+        } else if kind == "assign" {
+            let place = self.lower_place(fields[0].as_cons().unwrap()).unwrap();
+            self.lower_into(place, fields[1].as_cons().unwrap());
+            self.assign_unit(dest);
+        } else if kind == "loop" || kind == "while" {
+            self.lower_loop(dest, &fields, kind == "while");
+        } else if kind == "break" {
+            let scope = self.loops.last().unwrap();
+            let (target, loop_dest) = (scope.break_block, scope.dest.clone());
+            match fields.first() {
+                Some(value) => self.lower_into(loop_dest, value.as_cons().unwrap()),
+                None => self.assign_unit(loop_dest),
+            }
+            self.diverge(Terminator::Goto(target));
+        } else if kind == "continue" {
+            let target = self.loops.last().unwrap().continue_block;
+            self.diverge(Terminator::Goto(target));
+        } else if kind == "return" {
+            let ret = Place::local(Local::RETURN);
+            match fields.first() {
+                Some(value) => self.lower_into(ret, value.as_cons().unwrap()),
+                None => self.assign_unit(ret),
+            }
+            self.diverge(Terminator::Return);
+        } else if kind == "tuple" {
+            let elems = self.lower_operands(fields[0]);
+            self.assign(dest, Rvalue::Tuple(elems));
+        } else if kind == "struct" {
+            self.lower_struct(dest, &fields);
+        } else if kind == "call" {
+            let mut exprs = vec![fields[0].as_cons().unwrap()];
+            exprs.extend(fields[1].as_slice().unwrap().iter().map(|x| x.as_cons().unwrap()));
+            let mut args = self.lower_operand_list(&exprs);
+            let func = args.remove(0);
+            self.lower_call(dest, func, args);
+        } else if kind == "method_call" {
+            let hil_id = HilId::from_s_expr(node_get_attr(expr, "hil_id"));
+            let func = Operand::Fn(self.ty_ctx.method_resolution(hil_id).unwrap().clone());
+            let mut exprs = vec![fields[0].as_cons().unwrap()];
+            exprs.extend(fields[2].as_slice().unwrap().iter().map(|x| x.as_cons().unwrap()));
+            let args = self.lower_operand_list(&exprs);
+            self.lower_call(dest, func, args);
+        } else if kind == "closure" {
+            self.lower_closure(dest, expr, &fields);
+        } else {
+            panic!("Cannot lower Expr kind {}", kind);
+        }
+    }
+
+    fn lower_call(&mut self, dest: Place, func: Operand, args: Vec<Operand>) {
+        let target = self.new_block();
+        self.terminate(Terminator::Call {
+            func,
+            args,
+            dest,
+            target,
+        });
+        self.current = target;
+    }
+
+    // `a && b` is `if a { b } else { false }` and `a || b` is
+    // `if a { true } else { b }`.
+    fn lower_logical(&mut self, dest: Place, op: BinOp, lhs: &Node, rhs: &Node) {
+        let cond = self.lower_operand(lhs);
+        let rhs_block = self.new_block();
+        let short_block = self.new_block();
+        let join = self.new_block();
+        let (then_block, else_block) = if op == BinOp::And {
+            (rhs_block, short_block)
+        } else {
+            (short_block, rhs_block)
+        };
+        self.terminate(Terminator::Branch {
+            cond,
+            then_block,
+            else_block,
+        });
+        self.current = rhs_block;
+        self.lower_into(dest.clone(), rhs);
+        self.goto(join);
+        self.current = short_block;
+        let value = ConstValue::Bool(op == BinOp::Or);
+        self.assign(dest, Rvalue::Use(Operand::Const(value)));
+        self.goto(join);
+    }
+
+    fn lower_if(&mut self, dest: Place, fields: &[&Value]) {
+        let cond = self.lower_operand(fields[0].as_cons().unwrap());
+        let then_block = self.new_block();
+        let else_block = self.new_block();
+        let join = self.new_block();
+        self.terminate(Terminator::Branch {
+            cond,
+            then_block,
+            else_block,
+        });
+        self.current = then_block;
+        self.lower_block(dest.clone(), fields[1]);
+        self.goto(join);
+        self.current = else_block;
+        match fields.get(2) {
+            Some(els) if els.as_cons().unwrap().car().as_symbol() == Some("Block") => {
+                self.lower_block(dest, els)
+            }
+            Some(els) => self.lower_into(dest, els.as_cons().unwrap()),
+            None => self.assign_unit(dest),
+        }
+        self.goto(join);
+    }
+
+    fn lower_loop(&mut self, dest: Place, fields: &[&Value], is_while: bool) {
+        let header = self.new_block();
+        let exit = self.new_block();
+        self.goto(header);
+        let body = if is_while {
+            let cond = self.lower_operand(fields[0].as_cons().unwrap());
+            let body_block = self.new_block();
+            self.terminate(Terminator::Branch {
+                cond,
+                then_block: body_block,
+                else_block: exit,
+            });
+            self.current = body_block;
+            fields[1]
+        } else {
+            fields[0]
+        };
+        self.loops.push(LoopScope {
+            break_block: exit,
+            continue_block: header,
+            dest: dest.clone(),
+        });
+        let unit = self.new_temp(self.global_ctx.unit_type());
+        self.lower_block(unit, body);
+        self.loops.pop();
+        self.goto(header);
+        self.current = exit;
+        if is_while {
+            self.assign_unit(dest);
+        }
+    }
+
+    // Fields are evaluated in source order and stored in the order of the
+    // class. An initializer missing a field or giving one twice was reported
+    // by the type checker and is lowered as unreachable.
+    fn lower_struct(&mut self, dest: Place, fields: &[&Value]) {
+        let path = fields[0].as_cons().unwrap();
+        let def_id = DefId::from_s_expr(node_get_field(path, 0));
+        let fields = fields
+            .iter()
+            .skip(1)
+            .map(|x| x.as_cons().unwrap())
+            .collect::<Vec<_>>();
+        let exprs = fields
+            .iter()
+            .map(|x| node_get_field(x, 1).as_cons().unwrap())
+            .collect::<Vec<_>>();
+        let mut operands = HashMap::new();
+        let mut complete = true;
+        for (field, operand) in fields.iter().zip(self.lower_operand_list(&exprs)) {
+            if let Some(field_def_id) = node_find_attr(field, "field") {
+                let field_def_id = DefId::from_s_expr(field_def_id);
+                complete &= operands.insert(field_def_id, operand).is_none();
+            }
+        }
+        let operands = self
+            .global_ctx
+            .adt_def(def_id.clone())
+            .fields()
+            .iter()
+            .map(|x| operands.remove(&x.def_id))
+            .collect::<Option<Vec<_>>>();
+        match operands {
+            Some(operands) if complete => self.assign(dest, Rvalue::Adt(def_id, operands)),
+            _ => self.diverge(Terminator::Unreachable),
+        }
+    }
+
+    fn lower_closure(&mut self, dest: Place, expr: &Node, fields: &[&Value]) {
+        let captures = node_find_attr(expr, "captures")
+            .and_then(|x| x.as_slice())
+            .unwrap_or(&[])
+            .iter()
+            .map(HilId::from_s_expr)
+            .collect::<Vec<_>>();
+        let output = match self.expr_type(expr).kind() {
+            TypeKind::Fn(_, output) => *output,
+            _ => self.global_ctx.error_type(),
+        };
+        let mut builder = BodyBuilder::new(
+            self.global_ctx,
+            self.ty_ctx,
+            self.statics,
+            &mut *self.bodies,
+            self.owner.clone(),
+            output,
+        );
+        for hil_id in &captures {
+            let decl = self.locals[self.local_map[hil_id].index()].clone();
+            let local = builder.new_local(decl.ty, decl.name);
+            builder.local_map.insert(*hil_id, local);
+            builder.arg_count += 1;
+        }
+        let decl = fields[0].as_cons().unwrap();
+        builder.lower_params(node_get_field(decl, 0));
+        let ty = BodyTy::Closure {
+            owner: self.owner.0.clone(),
+            captures: captures.len(),
+        };
+        let body = builder.lower_body(ty, fields[1]);
+        let body_id = BodyId(u32::try_from(self.bodies.len()).unwrap());
+        self.bodies.push(body);
+        let captures = captures
+            .iter()
+            .map(|hil_id| Operand::Copy(Place::local(self.local_map[hil_id])))
+            .collect();
+        self.assign(dest, Rvalue::Closure(body_id, captures));
+    }
+}
+
+// Whether evaluating `expr` could assign to a local. Literals, paths and
+// operations on them cannot.
+fn has_effects(expr: &Node) -> bool {
+    let kind = node_get_attr(expr, "kind").as_keyword().unwrap();
+    let fields = node_get_fields(expr);
+    match kind {
+        "lit" | "qpath" => false,
+        "field" | "unary" => has_effects(fields[0].as_cons().unwrap()),
+        "binary" => fields.iter().any(|x| has_effects(x.as_cons().unwrap())),
+        "tuple" => fields[0]
+            .as_slice()
+            .unwrap()
+            .iter()
+            .any(|x| has_effects(x.as_cons().unwrap())),
+        _ => true,
+    }
+}
+
+fn lower_lit(lit: &Value) -> ConstValue {
+    match lit.as_bool() {
+        Some(b) => ConstValue::Bool(b),
+        None => ConstValue::Int(lit.as_i64().unwrap()),
+    }
+}
@@ -3,10 +3,16 @@ pub mod bui;
 pub mod bui_capnp {
     include!(concat!(env!("OUT_DIR"), "/bui_capnp.rs"));
 }
+pub mod bytecode;
+pub mod bytecode_capnp {
+    include!(concat!(env!("OUT_DIR"), "/bytecode_capnp.rs"));
+}
+pub mod codegen;
 pub mod context;
 mod def;
-mod error;
+pub mod error;
 pub mod hil;
+pub mod op;
 mod s_expr;
 pub mod t;
 pub mod til;
@@ -14,9 +20,18 @@ pub mod til;
 #[cfg(test)]
 mod tests {
     use std::collections::HashMap;
+    use std::sync::{Mutex, MutexGuard};
 
     use lexpr::{sexp, Value};
 
+    // Errors and warnings are collected for the whole process, so tests which
+    // compile something run one at a time.
+    static SERIAL: Mutex<()> = Mutex::new(());
+
+    fn serial() -> MutexGuard<'static, ()> {
+        SERIAL.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
     struct MatchContext {
         vars: HashMap<u32, Value>,
     }
@@ -39,10 +54,10 @@ mod tests {
             }
         }
         if let Some(k) = b.as_keyword() {
-            if k.starts_with("_") {
-                if k.len() == 1 {
+            if let Some(var) = k.strip_prefix('_') {
+                if var.is_empty() {
                     return true;
-                } else if let Ok(i) = k[1..].parse::<u32>() {
+                } else if let Ok(i) = var.parse::<u32>() {
                     if let Some(v) = ctx.vars.get(&i) {
                         return v == a;
                     } else {
@@ -100,7 +115,10 @@ mod tests {
     }
 
     fn get_ast() -> Value {
-        // class Bar {}
+        // class Bar {
+        //   a: int,
+        //   b: bool,
+        // }
         // defn foo(x: Bar, y: Bar) -> Bar {
         //   x;
         //   y
@@ -117,11 +135,11 @@ mod tests {
         // }
         sexp!(
             (Unit #:path #(test)
-             (Item #:ident Bar #:kind #:class
+             (Item #:ident Bar #:vis #:pub #:kind #:class
               (Variant
-               (FieldDef)
-               (FieldDef)))
-             (Item #:ident foo #:kind #:defn (Generics)
+               (FieldDef a (Ty #:kind #:path #(int)))
+               (FieldDef b (Ty #:kind #:path #(bool)))))
+             (Item #:ident foo #:vis #:pub #:kind #:defn (Generics)
               (FnSig
                (FnDecl
                 #((Param x (Ty #:kind #:path #(Bar)))
@@ -130,15 +148,15 @@ mod tests {
               (Block
                (Stmt #:kind #:semi (Expr #:kind #:path #(x)))
                (Stmt #:kind #:expr (Expr #:kind #:path #(y)))))
-             (Item #:ident iface #:kind #:interface
+             (Item #:ident iface #:vis #:pub #:kind #:interface
               (Signature
                (Decl #:ident t #:kind #:ty (Ty #:kind #:path #(Bar)))
                (Decl #:ident f #:kind #:defn (Generics)
                 (FnSig (FnDecl #((Param z (Ty #:kind #:path #(Bar)))))))))
-             (Item #:ident mdl #:kind #:module
+             (Item #:ident mdl #:vis #:pub #:kind #:module
               (Structure
-               (Binding #:ident t #:kind #:ty (Ty #:kind #:path #(Bar)))
-               (Binding #:ident f #:kind #:defn (Generics)
+               (Binding #:ident t #:vis #:pub #:kind #:ty (Ty #:kind #:path #(Bar)))
+               (Binding #:ident f #:vis #:pub #:kind #:defn (Generics)
                 (FnSig (FnDecl
                         #((Param z (Ty #:kind #:path #(Bar))))
                         (FnRetTy (Ty #:kind #:path #(Bar)))))
@@ -149,6 +167,7 @@ mod tests {
     #[test]
     fn test_ast_to_hil() {
         use crate::ast::to_hil;
+        let _serial = serial();
         let ast = get_ast();
         let hil = to_hil(&ast);
         assert!(
@@ -156,11 +175,11 @@ mod tests {
                 &hil,
                 &sexp!(
                    (Unit #:path #(test)
-                    (Item #:def_id 0 #:ident Bar #:kind #:class
+                    (Item #:def_id 0 #:ident Bar #:vis #:pub #:kind #:class
                      (Variant #:hil_id #:_ #:def_id 1
-                      (FieldDef #:hil_id #:_ #:def_id 2)
-                      (FieldDef #:hil_id #:_ #:def_id 3)))
-                    (Item #:def_id 4 #:ident foo #:kind #:defn (Generics #:hil_id #:_)
+                      (FieldDef #:hil_id #:_ #:def_id 2 a (Ty #:hil_id #:_ #:kind #:int))
+                      (FieldDef #:hil_id #:_ #:def_id 3 b (Ty #:hil_id #:_ #:kind #:bool))))
+                    (Item #:def_id 4 #:ident foo #:vis #:pub #:kind #:defn (Generics #:hil_id #:_)
                      (FnSig #:hil_id #:_
                       (FnDecl #:hil_id #:_
                        #((Param #:hil_id #:_1 x
@@ -173,7 +192,7 @@ mod tests {
                        (Expr #:hil_id #:_ #:kind #:qpath (Path #:kind #:local #:_1)))
                       (Stmt #:hil_id #:_ #:kind #:expr
                        (Expr #:hil_id #:_ #:kind #:qpath (Path #:kind #:local #:_2)))))
-                   (Item #:def_id 5 #:ident iface #:kind #:interface
+                   (Item #:def_id 5 #:ident iface #:vis #:pub #:kind #:interface
                     (Signature #:hil_id #:_
                      (Decl #:hil_id #:_ #:def_id 6 #:ident t #:kind #:ty
                       (Ty #:hil_id #:_ #:kind #:qpath (Path #:kind #:def (DefId 0 0))))
@@ -182,11 +201,11 @@ mod tests {
                        (FnDecl #:hil_id #:_
                         #((Param #:hil_id #:_ z
                            (Ty #:hil_id #:_ #:kind #:qpath (Path #:kind #:def (DefId 0 0))))))))))
-                    (Item #:def_id 8 #:ident mdl #:kind #:module
+                    (Item #:def_id 8 #:ident mdl #:vis #:pub #:kind #:module
                      (Structure #:hil_id #:_
-                      (Binding #:hil_id #:_ #:def_id 9 #:ident t #:kind #:ty
+                      (Binding #:hil_id #:_ #:def_id 9 #:ident t #:vis #:pub #:kind #:ty
                        (Ty #:hil_id #:_ #:kind #:qpath (Path #:kind #:def (DefId 0 0))))
-                      (Binding #:hil_id #:_ #:def_id 10 #:ident f #:kind #:defn (Generics #:hil_id #:_)
+                      (Binding #:hil_id #:_ #:def_id 10 #:ident f #:vis #:pub #:kind #:defn (Generics #:hil_id #:_)
                        (FnSig #:hil_id #:_
                         (FnDecl #:hil_id #:_
                          #((Param #:hil_id #:_3 z
@@ -205,17 +224,26 @@ mod tests {
         use crate::ast::to_hil;
         use crate::hil::to_bui;
         use crate::context::GlobalContext;
+        use crate::t::ty_check;
+        let _serial = serial();
         let ast = get_ast();
         let hil = to_hil(&ast);
         let ctx = GlobalContext::new();
+        // Interfaces are written as type checking expanded them.
+        ty_check(&ctx, &hil);
         let bui = to_bui(&hil, &ctx).deserialize();
         let items = bui.items();
         assert_eq!(items.len(), 4);
         assert_eq!(items[0].ident(), "Bar");
         if let Some(items_0) = items[0].class() {
-            assert_eq!(items_0.fields().len(), 0);
+            let fields = items_0.fields();
+            assert_eq!(fields.len(), 2);
+            assert_eq!(fields[0].name(), "a");
+            assert!(fields[0].ty().is_int());
+            assert_eq!(fields[1].name(), "b");
+            assert!(fields[1].ty().is_bool());
         } else {
-            assert!(false, "iterm 0 must a class.");
+            panic!("iterm 0 must a class.");
         }
         assert_eq!(items[1].ident(), "foo");
         if let Some(items_1) = items[1].defn() {
@@ -235,7 +263,7 @@ mod tests {
             assert_eq!(t.unit(), 0);
             assert_eq!(t.def(), 0);
         } else {
-            assert!(false, "item 1 must be a defn.");
+            panic!("item 1 must be a defn.");
         }
         assert_eq!(items[2].ident(), "iface");
         if let Some(items_2) = items[2].interface() {
@@ -255,7 +283,7 @@ mod tests {
             let (ident, _) = items_2.defns()[0];
             assert_eq!(ident, "f");
         } else {
-            assert!(false, "item 2 must be an iface");
+            panic!("item 2 must be an iface");
         }
         assert_eq!(items[3].ident(), "mdl");
         if let Some(items_3) = items[3].module() {
@@ -275,20 +303,3619 @@ mod tests {
             assert_eq!(ident, "f");
             assert_eq!(items_3.defns().len(), 1);
         } else {
-            assert!(false, "item 3 must be a module");
+            panic!("item 3 must be a module");
+        }
+    }
+
+    #[test]
+    fn test_prim_types() {
+        use crate::ast::to_hil;
+        use crate::context::GlobalContext;
+        use crate::error::take_errors;
+        use crate::hil::to_bui;
+        use crate::t::ty_check;
+        let _serial = serial();
+        // defn one(b: bool) -> int {
+        //   b;
+        //   1
+        // }
+        // defn wrong(x: int) -> bool {
+        //   x
+        // }
+        let ast = sexp!(
+            (Unit #:path #(test)
+             (Item #:ident one #:vis #:pub #:kind #:defn (Generics)
+              (FnSig
+               (FnDecl
+                #((Param b (Ty #:kind #:path #(bool))))
+                (FnRetTy (Ty #:kind #:path #(int)))))
+              (Block
+               (Stmt #:kind #:semi (Expr #:kind #:path #(b)))
+               (Stmt #:kind #:expr (Expr #:kind #:lit 1))))
+             (Item #:ident wrong #:vis #:pub #:kind #:defn (Generics)
+              (FnSig
+               (FnDecl
+                #((Param x (Ty #:kind #:path #(int))))
+                (FnRetTy (Ty #:kind #:path #(bool)))))
+              (Block
+               (Stmt #:kind #:expr (Expr #:kind #:path #(x)))))));
+        let hil = to_hil(&ast);
+        assert!(sexp_match(
+            &hil,
+            &sexp!(
+                (Unit #:path #(test)
+                 (Item #:def_id 0 #:ident one #:vis #:pub #:kind #:defn (Generics #:hil_id #:_)
+                  (FnSig #:hil_id #:_
+                   (FnDecl #:hil_id #:_
+                    #((Param #:hil_id #:_1 b (Ty #:hil_id #:_ #:kind #:bool)))
+                    (FnRetTy #:hil_id #:_ (Ty #:hil_id #:_ #:kind #:int))))
+                  (Block #:hil_id #:_
+                   (Stmt #:hil_id #:_ #:kind #:semi
+                    (Expr #:hil_id #:_ #:kind #:qpath (Path #:kind #:local #:_1)))
+                   (Stmt #:hil_id #:_ #:kind #:expr (Expr #:hil_id #:_ #:kind #:lit 1))))
+                 (Item #:def_id 1 #:ident wrong #:vis #:pub #:kind #:defn #:_ #:_ #:_))),
+            &mut MatchContext::new(),
+        ));
+        let ctx = GlobalContext::new();
+        ty_check(&ctx, &hil);
+        assert_eq!(
+            take_errors(),
+            vec!["E0002 Mismatched types: expected bool, found int"]
+        );
+        let bui = to_bui(&hil, &ctx).deserialize();
+        let items = bui.items();
+        let defn = items[0].defn().unwrap();
+        let fn_sig = defn.fn_sig();
+        let decl = fn_sig.decl();
+        assert!(decl.inputs()[0].is_bool());
+        let defn = items[1].defn().unwrap();
+        let fn_sig = defn.fn_sig();
+        let decl = fn_sig.decl();
+        assert!(decl.inputs()[0].is_int());
+    }
+
+    #[test]
+    fn test_operators() {
+        use crate::ast::{fold_infix, make_unary, to_hil};
+        use crate::context::GlobalContext;
+        use crate::error::take_errors;
+        use crate::op::{BinOp, UnOp};
+        use crate::t::ty_check;
+        let _serial = serial();
+        let x = sexp!((Expr #:kind #:path #(x)));
+        let y = sexp!((Expr #:kind #:path #(y)));
+        let b = sexp!((Expr #:kind #:path #(b)));
+        let two = sexp!((Expr #:kind #:lit 2));
+        let ten = sexp!((Expr #:kind #:lit 10));
+
+        // x - y - 2 == (x - y) - 2
+        let e = fold_infix(vec![x.clone(), y.clone(), two.clone()], &[BinOp::Sub, BinOp::Sub]);
+        assert!(sexp_match(
+            &e,
+            &sexp!((Expr #:kind #:binary #:op #:sub
+                    (Expr #:kind #:binary #:op #:sub (Expr #:kind #:path #(x)) (Expr #:kind #:path #(y)))
+                    (Expr #:kind #:lit 2))),
+            &mut MatchContext::new(),
+        ));
+
+        // x + y * 2 > 10 || b && b == (x + (y * 2) > 10) || (b && b)
+        let e = fold_infix(
+            vec![x.clone(), y.clone(), two.clone(), ten.clone(), b.clone(), b.clone()],
+            &[BinOp::Add, BinOp::Mul, BinOp::Gt, BinOp::Or, BinOp::And],
+        );
+        assert!(sexp_match(
+            &e,
+            &sexp!((Expr #:kind #:binary #:op #:or
+                    (Expr #:kind #:binary #:op #:gt
+                     (Expr #:kind #:binary #:op #:add
+                      (Expr #:kind #:path #(x))
+                      (Expr #:kind #:binary #:op #:mul (Expr #:kind #:path #(y)) (Expr #:kind #:lit 2)))
+                     (Expr #:kind #:lit 10))
+                    (Expr #:kind #:binary #:op #:and (Expr #:kind #:path #(b)) (Expr #:kind #:path #(b))))),
+            &mut MatchContext::new(),
+        ));
+
+        // x < y < 2
+        fold_infix(vec![x.clone(), y.clone(), two.clone()], &[BinOp::Lt, BinOp::Lt]);
+        assert_eq!(
+            take_errors(),
+            vec!["E0005 Operators `<` and `<` cannot be chained"]
+        );
+
+        // defn calc(x: int, y: int, b: bool) -> bool {
+        //   x + y * 2 > 10 || b && !(x == y)
+        // }
+        // defn bad(x: int, y: int, b: bool) -> int {
+        //   -b;
+        //   x + b
+        // }
+        let not_eq = make_unary(
+            UnOp::Not,
+            fold_infix(vec![x.clone(), y.clone()], &[BinOp::Eq]),
+        );
+        let calc = fold_infix(
+            vec![x.clone(), y.clone(), two, ten, b.clone(), not_eq],
+            &[BinOp::Add, BinOp::Mul, BinOp::Gt, BinOp::Or, BinOp::And],
+        );
+        let neg_b = make_unary(UnOp::Neg, b.clone());
+        let x_plus_b = fold_infix(vec![x, b], &[BinOp::Add]);
+        let ast = sexp!(
+            (Unit #:path #(test)
+             (Item #:ident calc #:kind #:defn (Generics)
+              (FnSig
+               (FnDecl
+                #((Param x (Ty #:kind #:path #(int)))
+                  (Param y (Ty #:kind #:path #(int)))
+                  (Param b (Ty #:kind #:path #(bool))))
+                (FnRetTy (Ty #:kind #:path #(bool)))))
+              (Block
+               (Stmt #:kind #:expr ,calc)))
+             (Item #:ident bad #:kind #:defn (Generics)
+              (FnSig
+               (FnDecl
+                #((Param x (Ty #:kind #:path #(int)))
+                  (Param y (Ty #:kind #:path #(int)))
+                  (Param b (Ty #:kind #:path #(bool))))
+                (FnRetTy (Ty #:kind #:path #(int)))))
+              (Block
+               (Stmt #:kind #:semi ,neg_b)
+               (Stmt #:kind #:expr ,x_plus_b)))));
+        let hil = to_hil(&ast);
+        let ctx = GlobalContext::new();
+        ty_check(&ctx, &hil);
+        assert_eq!(
+            take_errors(),
+            vec![
+                "E0004 Cannot apply `-` to an operand of type bool",
+                "E0003 Cannot apply `+` to operands of type int and bool",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_if_else() {
+        use crate::ast::to_hil;
+        use crate::context::GlobalContext;
+        use crate::error::take_errors;
+        use crate::t::ty_check;
+        let _serial = serial();
+        // defn max(x: int, y: int) -> int {
+        //   if x > y { x } else { y }
+        // }
+        // defn pick(a: bool, b: bool, x: int) -> int {
+        //   (if a { x } else if b { 0 } else { 1 }) + 1
+        // }
+        // defn noop(b: bool) {
+        //   if b { 1; }
+        // }
+        // defn bad(x: int, b: bool) -> int {
+        //   if x { 1 } else { b }
+        // }
+        // defn bad2(b: bool) -> int {
+        //   (if b { 1 } else { b }) + 1
+        // }
+        // defn bad3(b: bool) -> int {
+        //   if b { 1 }
+        // }
+        let ast = sexp!(
+            (Unit #:path #(test)
+             (Item #:ident max #:kind #:defn (Generics)
+              (FnSig
+               (FnDecl
+                #((Param x (Ty #:kind #:path #(int)))
+                  (Param y (Ty #:kind #:path #(int))))
+                (FnRetTy (Ty #:kind #:path #(int)))))
+              (Block
+               (Stmt #:kind #:expr
+                (Expr #:kind #:if
+                 (Expr #:kind #:binary #:op #:gt (Expr #:kind #:path #(x)) (Expr #:kind #:path #(y)))
+                 (Block (Stmt #:kind #:expr (Expr #:kind #:path #(x))))
+                 (Block (Stmt #:kind #:expr (Expr #:kind #:path #(y))))))))
+             (Item #:ident pick #:kind #:defn (Generics)
+              (FnSig
+               (FnDecl
+                #((Param a (Ty #:kind #:path #(bool)))
+                  (Param b (Ty #:kind #:path #(bool)))
+                  (Param x (Ty #:kind #:path #(int))))
+                (FnRetTy (Ty #:kind #:path #(int)))))
+              (Block
+               (Stmt #:kind #:expr
+                (Expr #:kind #:binary #:op #:add
+                 (Expr #:kind #:if (Expr #:kind #:path #(a))
+                  (Block (Stmt #:kind #:expr (Expr #:kind #:path #(x))))
+                  (Expr #:kind #:if (Expr #:kind #:path #(b))
+                   (Block (Stmt #:kind #:expr (Expr #:kind #:lit 0)))
+                   (Block (Stmt #:kind #:expr (Expr #:kind #:lit 1)))))
+                 (Expr #:kind #:lit 1)))))
+             (Item #:ident noop #:kind #:defn (Generics)
+              (FnSig (FnDecl #((Param b (Ty #:kind #:path #(bool))))))
+              (Block
+               (Stmt #:kind #:expr
+                (Expr #:kind #:if (Expr #:kind #:path #(b))
+                 (Block (Stmt #:kind #:semi (Expr #:kind #:lit 1)))))))
+             (Item #:ident bad #:kind #:defn (Generics)
+              (FnSig
+               (FnDecl
+                #((Param x (Ty #:kind #:path #(int)))
+                  (Param b (Ty #:kind #:path #(bool))))
+                (FnRetTy (Ty #:kind #:path #(int)))))
+              (Block
+               (Stmt #:kind #:expr
+                (Expr #:kind #:if (Expr #:kind #:path #(x))
+                 (Block (Stmt #:kind #:expr (Expr #:kind #:lit 1)))
+                 (Block (Stmt #:kind #:expr (Expr #:kind #:path #(b))))))))
+             (Item #:ident bad2 #:kind #:defn (Generics)
+              (FnSig
+               (FnDecl
+                #((Param b (Ty #:kind #:path #(bool))))
+                (FnRetTy (Ty #:kind #:path #(int)))))
+              (Block
+               (Stmt #:kind #:expr
+                (Expr #:kind #:binary #:op #:add
+                 (Expr #:kind #:if (Expr #:kind #:path #(b))
+                  (Block (Stmt #:kind #:expr (Expr #:kind #:lit 1)))
+                  (Block (Stmt #:kind #:expr (Expr #:kind #:path #(b)))))
+                 (Expr #:kind #:lit 1)))))
+             (Item #:ident bad3 #:kind #:defn (Generics)
+              (FnSig
+               (FnDecl
+                #((Param b (Ty #:kind #:path #(bool))))
+                (FnRetTy (Ty #:kind #:path #(int)))))
+              (Block
+               (Stmt #:kind #:expr
+                (Expr #:kind #:if (Expr #:kind #:path #(b))
+                 (Block (Stmt #:kind #:expr (Expr #:kind #:lit 1)))))))));
+        let hil = to_hil(&ast);
+        let ctx = GlobalContext::new();
+        ty_check(&ctx, &hil);
+        assert_eq!(
+            take_errors(),
+            vec![
+                "E0002 Mismatched types: expected bool, found int",
+                "E0002 Mismatched types: expected int, found bool",
+                "E0006 `if` and `else` have incompatible types: int and bool",
+                "E0002 Mismatched types: expected (), found int",
+                "E0002 Mismatched types: expected int, found ()",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_loops() {
+        use crate::ast::to_hil;
+        use crate::context::GlobalContext;
+        use crate::error::take_errors;
+        use crate::t::ty_check;
+        let _serial = serial();
+        // defn sum(n: int) -> int {
+        //   let i: int = 0;
+        //   let acc = 0;
+        //   while i < n {
+        //     i = i + 1;
+        //     if i % 2 == 0 { continue; }
+        //     acc = acc + i;
+        //   }
+        //   acc
+        // }
+        // defn root(n: int) -> int {
+        //   let i = 0;
+        //   loop {
+        //     if i * i >= n { break i; }
+        //     i = i + 1;
+        //   }
+        // }
+        // defn bad(b: bool) -> int {
+        //   let x;
+        //   while b { break 1; }
+        //   let y: int = loop { break b; };
+        //   b = 1;
+        //   continue
+        // }
+        let ast = sexp!(
+            (Unit #:path #(test)
+             (Item #:ident sum #:kind #:defn (Generics)
+              (FnSig
+               (FnDecl
+                #((Param n (Ty #:kind #:path #(int))))
+                (FnRetTy (Ty #:kind #:path #(int)))))
+              (Block
+               (Stmt #:kind #:let (Local i (Ty #:kind #:path #(int)) (Expr #:kind #:lit 0)))
+               (Stmt #:kind #:let (Local acc (Expr #:kind #:lit 0)))
+               (Stmt #:kind #:semi
+                (Expr #:kind #:while
+                 (Expr #:kind #:binary #:op #:lt (Expr #:kind #:path #(i)) (Expr #:kind #:path #(n)))
+                 (Block
+                  (Stmt #:kind #:semi
+                   (Expr #:kind #:assign (Expr #:kind #:path #(i))
+                    (Expr #:kind #:binary #:op #:add (Expr #:kind #:path #(i)) (Expr #:kind #:lit 1))))
+                  (Stmt #:kind #:semi
+                   (Expr #:kind #:if
+                    (Expr #:kind #:binary #:op #:eq
+                     (Expr #:kind #:binary #:op #:rem (Expr #:kind #:path #(i)) (Expr #:kind #:lit 2))
+                     (Expr #:kind #:lit 0))
+                    (Block (Stmt #:kind #:semi (Expr #:kind #:continue)))))
+                  (Stmt #:kind #:semi
+                   (Expr #:kind #:assign (Expr #:kind #:path #(acc))
+                    (Expr #:kind #:binary #:op #:add (Expr #:kind #:path #(acc)) (Expr #:kind #:path #(i))))))))
+               (Stmt #:kind #:expr (Expr #:kind #:path #(acc)))))
+             (Item #:ident root #:kind #:defn (Generics)
+              (FnSig
+               (FnDecl
+                #((Param n (Ty #:kind #:path #(int))))
+                (FnRetTy (Ty #:kind #:path #(int)))))
+              (Block
+               (Stmt #:kind #:let (Local i (Expr #:kind #:lit 0)))
+               (Stmt #:kind #:expr
+                (Expr #:kind #:loop
+                 (Block
+                  (Stmt #:kind #:semi
+                   (Expr #:kind #:if
+                    (Expr #:kind #:binary #:op #:ge
+                     (Expr #:kind #:binary #:op #:mul (Expr #:kind #:path #(i)) (Expr #:kind #:path #(i)))
+                     (Expr #:kind #:path #(n)))
+                    (Block (Stmt #:kind #:semi (Expr #:kind #:break (Expr #:kind #:path #(i)))))))
+                  (Stmt #:kind #:semi
+                   (Expr #:kind #:assign (Expr #:kind #:path #(i))
+                    (Expr #:kind #:binary #:op #:add (Expr #:kind #:path #(i)) (Expr #:kind #:lit 1)))))))))
+             (Item #:ident bad #:kind #:defn (Generics)
+              (FnSig
+               (FnDecl
+                #((Param b (Ty #:kind #:path #(bool))))
+                (FnRetTy (Ty #:kind #:path #(int)))))
+              (Block
+               (Stmt #:kind #:let (Local x))
+               (Stmt #:kind #:semi
+                (Expr #:kind #:while (Expr #:kind #:path #(b))
+                 (Block (Stmt #:kind #:semi (Expr #:kind #:break (Expr #:kind #:lit 1))))))
+               (Stmt #:kind #:let
+                (Local y (Ty #:kind #:path #(int))
+                 (Expr #:kind #:loop
+                  (Block (Stmt #:kind #:semi (Expr #:kind #:break (Expr #:kind #:path #(b))))))))
+               (Stmt #:kind #:semi (Expr #:kind #:assign (Expr #:kind #:path #(b)) (Expr #:kind #:lit 1)))
+               (Stmt #:kind #:expr (Expr #:kind #:continue))))));
+        let hil = to_hil(&ast);
+        assert_eq!(take_errors(), vec!["E0007 `continue` outside of a loop"]);
+        let ctx = GlobalContext::new();
+        ty_check(&ctx, &hil);
+        assert_eq!(
+            take_errors(),
+            vec![
+                "E0010 `break` with value inside of a `while` loop",
+                "E0002 Mismatched types: expected int, found bool",
+                "E0002 Mismatched types: expected bool, found int",
+                "E0009 Type annotations needed for `x`",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_return() {
+        use std::rc::Rc;
+        use crate::ast::to_hil;
+        use crate::context::GlobalContext;
+        use crate::error::{take_errors, take_warnings};
+        use crate::hil::to_til;
+        use crate::t::ty_check;
+        use crate::til::interp::Interpreter;
+        use crate::til::BodyTy;
+        let _serial = serial();
+        // defn abs(x: int) -> int {
+        //   if x < 0 { return -x; }
+        //   x
+        // }
+        // defn early(x: int) -> int {
+        //   return x;
+        //   x + 1;
+        //   x
+        // }
+        // defn unit() {
+        //   return;
+        // }
+        // defn bad(x: int) -> int {
+        //   while true {
+        //     if x > 1 { return true; }
+        //   }
+        //   return;
+        // }
+        let ast = sexp!(
+            (Unit #:path #(test)
+             (Item #:ident abs #:kind #:defn (Generics)
+              (FnSig
+               (FnDecl
+                #((Param x (Ty #:kind #:path #(int))))
+                (FnRetTy (Ty #:kind #:path #(int)))))
+              (Block
+               (Stmt #:kind #:semi
+                (Expr #:kind #:if
+                 (Expr #:kind #:binary #:op #:lt (Expr #:kind #:path #(x)) (Expr #:kind #:lit 0))
+                 (Block
+                  (Stmt #:kind #:semi
+                   (Expr #:kind #:return (Expr #:kind #:unary #:op #:neg (Expr #:kind #:path #(x))))))))
+               (Stmt #:kind #:expr (Expr #:kind #:path #(x)))))
+             (Item #:ident early #:kind #:defn (Generics)
+              (FnSig
+               (FnDecl
+                #((Param x (Ty #:kind #:path #(int))))
+                (FnRetTy (Ty #:kind #:path #(int)))))
+              (Block
+               (Stmt #:kind #:semi (Expr #:kind #:return (Expr #:kind #:path #(x))))
+               (Stmt #:kind #:semi
+                (Expr #:kind #:binary #:op #:add (Expr #:kind #:path #(x)) (Expr #:kind #:lit 1)))
+               (Stmt #:kind #:expr (Expr #:kind #:path #(x)))))
+             (Item #:ident unit #:kind #:defn (Generics)
+              (FnSig (FnDecl #()))
+              (Block
+               (Stmt #:kind #:semi (Expr #:kind #:return))))
+             (Item #:ident bad #:kind #:defn (Generics)
+              (FnSig
+               (FnDecl
+                #((Param x (Ty #:kind #:path #(int))))
+                (FnRetTy (Ty #:kind #:path #(int)))))
+              (Block
+               (Stmt #:kind #:semi
+                (Expr #:kind #:while (Expr #:kind #:lit #t)
+                 (Block
+                  (Stmt #:kind #:semi
+                   (Expr #:kind #:if
+                    (Expr #:kind #:binary #:op #:gt (Expr #:kind #:path #(x)) (Expr #:kind #:lit 1))
+                    (Block (Stmt #:kind #:semi (Expr #:kind #:return (Expr #:kind #:lit #t)))))))))
+               (Stmt #:kind #:semi (Expr #:kind #:return))))));
+        let hil = to_hil(&ast);
+        let ctx = GlobalContext::new();
+        ty_check(&ctx, &hil);
+        assert_eq!(
+            take_errors(),
+            vec![
+                "E0002 Mismatched types: expected int, found bool",
+                "E0002 Mismatched types: expected int, found ()",
+            ]
+        );
+        assert_eq!(take_warnings(), vec!["W0001 Unreachable code"]);
+        // defn let_return() -> int {
+        //   let x = return 1;
+        // }
+        // defn after_let() -> int {
+        //   let x = return 1;
+        //   2
+        // }
+        // defn in_operand() -> int {
+        //   3 * (return 1 + 2)
+        // }
+        let ast = sexp!(
+            (Unit #:path #(test)
+             (Item #:ident let_return #:kind #:defn (Generics)
+              (FnSig (FnDecl #() (FnRetTy (Ty #:kind #:path #(int)))))
+              (Block
+               (Stmt #:kind #:let (Local x (Expr #:kind #:return (Expr #:kind #:lit 1))))))
+             (Item #:ident after_let #:kind #:defn (Generics)
+              (FnSig (FnDecl #() (FnRetTy (Ty #:kind #:path #(int)))))
+              (Block
+               (Stmt #:kind #:let (Local x (Expr #:kind #:return (Expr #:kind #:lit 1))))
+               (Stmt #:kind #:expr (Expr #:kind #:lit 2))))
+             (Item #:ident in_operand #:kind #:defn (Generics)
+              (FnSig (FnDecl #() (FnRetTy (Ty #:kind #:path #(int)))))
+              (Block
+               (Stmt #:kind #:expr
+                (Expr #:kind #:binary #:op #:mul
+                 (Expr #:kind #:lit 3)
+                 (Expr #:kind #:return
+                  (Expr #:kind #:binary #:op #:add (Expr #:kind #:lit 1) (Expr #:kind #:lit 2)))))))));
+        let hil = to_hil(&ast);
+        let ctx = GlobalContext::new();
+        let tctx = ty_check(&ctx, &hil);
+        assert_eq!(take_errors(), Vec::<String>::new());
+        assert_eq!(take_warnings(), vec!["W0001 Unreachable code"]);
+        let til = to_til(&hil, &ctx, &tctx);
+        assert_eq!(take_errors(), Vec::<String>::new());
+        let til = Rc::new(til);
+        ctx.register_til(til.clone());
+        let mut interpreter = Interpreter::new(&ctx);
+        for (name, expected) in [("let_return", 1), ("after_let", 1), ("in_operand", 3)] {
+            let body = til.bodies.iter().find(|x| x.name == name).unwrap();
+            let BodyTy::Fn(def_id) = &body.ty else {
+                unreachable!();
+            };
+            let result = interpreter.call(def_id, vec![]).ok().unwrap();
+            assert_eq!(result.as_int(), Some(expected));
+        }
+    }
+
+    #[test]
+    fn test_classes() {
+        use crate::ast::to_hil;
+        use crate::context::GlobalContext;
+        use crate::error::take_errors;
+        use crate::t::ty_check;
+        let _serial = serial();
+        // class Point {
+        //   x: int,
+        //   y: int,
+        // }
+        // defn sum(p: Point) -> int {
+        //   let q = Point { y: 1, x: p.x };
+        //   q.x = q.y + p.y;
+        //   q.x
+        // }
+        // defn bad(p: Point) -> bool {
+        //   let q = Point { x: 1, x: 2, z: 3 };
+        //   Nope { x: 1 };
+        //   sum { x: true };
+        //   p.z + 1;
+        //   p.x
+        // }
+        let ast = sexp!(
+            (Unit #:path #(test)
+             (Item #:ident Point #:kind #:class
+              (Variant
+               (FieldDef x (Ty #:kind #:path #(int)))
+               (FieldDef y (Ty #:kind #:path #(int)))))
+             (Item #:ident sum #:kind #:defn (Generics)
+              (FnSig
+               (FnDecl
+                #((Param p (Ty #:kind #:path #(Point))))
+                (FnRetTy (Ty #:kind #:path #(int)))))
+              (Block
+               (Stmt #:kind #:let
+                (Local q
+                 (Expr #:kind #:struct #(Point)
+                  (Field y (Expr #:kind #:lit 1))
+                  (Field x (Expr #:kind #:field (Expr #:kind #:path #(p)) x)))))
+               (Stmt #:kind #:semi
+                (Expr #:kind #:assign
+                 (Expr #:kind #:field (Expr #:kind #:path #(q)) x)
+                 (Expr #:kind #:binary #:op #:add
+                  (Expr #:kind #:field (Expr #:kind #:path #(q)) y)
+                  (Expr #:kind #:field (Expr #:kind #:path #(p)) y))))
+               (Stmt #:kind #:expr (Expr #:kind #:field (Expr #:kind #:path #(q)) x))))
+             (Item #:ident bad #:kind #:defn (Generics)
+              (FnSig
+               (FnDecl
+                #((Param p (Ty #:kind #:path #(Point))))
+                (FnRetTy (Ty #:kind #:path #(bool)))))
+              (Block
+               (Stmt #:kind #:let
+                (Local q
+                 (Expr #:kind #:struct #(Point)
+                  (Field x (Expr #:kind #:lit 1))
+                  (Field x (Expr #:kind #:lit 2))
+                  (Field z (Expr #:kind #:lit 3)))))
+               (Stmt #:kind #:semi
+                (Expr #:kind #:struct #(Nope) (Field x (Expr #:kind #:lit 1))))
+               (Stmt #:kind #:semi
+                (Expr #:kind #:struct #(sum) (Field x (Expr #:kind #:lit #t))))
+               (Stmt #:kind #:semi
+                (Expr #:kind #:binary #:op #:add
+                 (Expr #:kind #:field (Expr #:kind #:path #(p)) z)
+                 (Expr #:kind #:lit 1)))
+               (Stmt #:kind #:expr (Expr #:kind #:field (Expr #:kind #:path #(p)) x))))));
+        let hil = to_hil(&ast);
+        assert_eq!(
+            take_errors(),
+            vec![
+                "E0011 No field `z` on type Point",
+                "E0001 Undefined name: Nope",
+                "E0037 `sum` is not a class",
+            ]
+        );
+        assert!(sexp_match(
+            &hil,
+            &sexp!(
+                (Unit #:path #(test)
+                 (Item #:def_id 0 #:ident Point #:kind #:class
+                  (Variant #:hil_id #:_ #:def_id 1
+                   (FieldDef #:hil_id #:_ #:def_id 2 x (Ty #:hil_id #:_ #:kind #:int))
+                   (FieldDef #:hil_id #:_ #:def_id 3 y (Ty #:hil_id #:_ #:kind #:int))))
+                 (Item #:def_id 4 #:ident sum #:kind #:defn #:_ #:_
+                  (Block #:hil_id #:_
+                   (Stmt #:hil_id #:_ #:kind #:let
+                    (Local #:hil_id #:_ q
+                     (Expr #:hil_id #:_ #:kind #:struct (Path #:kind #:def (DefId 0 0))
+                      (Field #:hil_id #:_ #:field (DefId 0 3) y (Expr #:hil_id #:_ #:kind #:lit 1))
+                      (Field #:hil_id #:_ #:field (DefId 0 2) x #:_))))
+                   #:_
+                   #:_))
+                 #:_)),
+            &mut MatchContext::new(),
+        ));
+        let ctx = GlobalContext::new();
+        ty_check(&ctx, &hil);
+        assert_eq!(
+            take_errors(),
+            vec![
+                "E0012 Field `x` specified more than once",
+                "E0013 Missing field `y` in initializer of Point",
+                "E0011 No field `z` on type Point",
+                "E0002 Mismatched types: expected bool, found int",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tuples() {
+        use crate::ast::to_hil;
+        use crate::context::GlobalContext;
+        use crate::error::take_errors;
+        use crate::hil::to_bui;
+        use crate::t::ty_check;
+        let _serial = serial();
+        // defn swap(p: (int, bool)) -> (bool, int) {
+        //   (p.1, p.0)
+        // }
+        // defn unit() -> () {
+        //   ()
+        // }
+        // defn bad(p: (int, bool)) -> (int, int) {
+        //   p.2;
+        //   (p.0, p.1)
+        // }
+        let ast = sexp!(
+            (Unit #:path #(test)
+             (Item #:ident swap #:vis #:pub #:kind #:defn (Generics)
+              (FnSig
+               (FnDecl
+                #((Param p (Ty #:kind #:tuple #((Ty #:kind #:path #(int)) (Ty #:kind #:path #(bool))))))
+                (FnRetTy (Ty #:kind #:tuple #((Ty #:kind #:path #(bool)) (Ty #:kind #:path #(int)))))))
+              (Block
+               (Stmt #:kind #:expr
+                (Expr #:kind #:tuple
+                 #((Expr #:kind #:field (Expr #:kind #:path #(p)) 1)
+                   (Expr #:kind #:field (Expr #:kind #:path #(p)) 0))))))
+             (Item #:ident unit #:vis #:pub #:kind #:defn (Generics)
+              (FnSig (FnDecl #() (FnRetTy (Ty #:kind #:tuple #()))))
+              (Block
+               (Stmt #:kind #:expr (Expr #:kind #:tuple #()))))
+             (Item #:ident bad #:vis #:pub #:kind #:defn (Generics)
+              (FnSig
+               (FnDecl
+                #((Param p (Ty #:kind #:tuple #((Ty #:kind #:path #(int)) (Ty #:kind #:path #(bool))))))
+                (FnRetTy (Ty #:kind #:tuple #((Ty #:kind #:path #(int)) (Ty #:kind #:path #(int)))))))
+              (Block
+               (Stmt #:kind #:semi (Expr #:kind #:field (Expr #:kind #:path #(p)) 2))
+               (Stmt #:kind #:expr
+                (Expr #:kind #:tuple
+                 #((Expr #:kind #:field (Expr #:kind #:path #(p)) 0)
+                   (Expr #:kind #:field (Expr #:kind #:path #(p)) 1))))))));
+        let hil = to_hil(&ast);
+        let ctx = GlobalContext::new();
+        ty_check(&ctx, &hil);
+        assert_eq!(
+            take_errors(),
+            vec![
+                "E0011 No field `2` on type (int, bool)",
+                "E0002 Mismatched types: expected int, found bool",
+            ]
+        );
+
+        let int_type = ctx.int_type();
+        let single = ctx.tuple_type(&[int_type]);
+        assert!(std::ptr::eq(single, ctx.tuple_type(&[int_type])));
+        assert!(std::ptr::eq(ctx.unit_type(), ctx.tuple_type(&[])));
+        assert_eq!(single.to_string(), "(int,)");
+
+        let bui = to_bui(&hil, &ctx).deserialize();
+        let items = bui.items();
+        let defn = items[0].defn().unwrap();
+        let fn_sig = defn.fn_sig();
+        let decl = fn_sig.decl();
+        let inputs = decl.inputs();
+        let elems = inputs[0].tuple().unwrap();
+        assert_eq!(elems.len(), 2);
+        assert!(elems[0].is_int());
+        assert!(elems[1].is_bool());
+    }
+
+    #[test]
+    fn test_closures() {
+        use crate::ast::to_hil;
+        use crate::context::GlobalContext;
+        use crate::error::take_errors;
+        use crate::hil::to_bui;
+        use crate::t::ty_check;
+        let _serial = serial();
+        // defn apply(f: fn(int) -> int, x: int) -> int {
+        //   f(x)
+        // }
+        // defn add(x: int, y: int) -> int {
+        //   x + y
+        // }
+        // defn main(n: int) -> int {
+        //   let k = 1;
+        //   let g: fn(int) -> int = |y| y + k + n;
+        //   apply(g, add(n, 2)) + apply(|z: int| { return z; }, 3)
+        // }
+        // defn bad(x: int) -> int {
+        //   let h: fn(int) -> int = |y| y;
+        //   x(1);
+        //   add(1)
+        // }
+        let fn_int_int = sexp!((Ty #:kind #:fn #((Ty #:kind #:path #(int))) (Ty #:kind #:path #(int))));
+        let f_ty = fn_int_int.clone();
+        let h_ty = fn_int_int.clone();
+        let ast = sexp!(
+            (Unit #:path #(test)
+             (Item #:ident apply #:vis #:pub #:kind #:defn (Generics)
+              (FnSig
+               (FnDecl
+                #((Param f ,f_ty) (Param x (Ty #:kind #:path #(int))))
+                (FnRetTy (Ty #:kind #:path #(int)))))
+              (Block
+               (Stmt #:kind #:expr
+                (Expr #:kind #:call (Expr #:kind #:path #(f)) #((Expr #:kind #:path #(x)))))))
+             (Item #:ident add #:vis #:pub #:kind #:defn (Generics)
+              (FnSig
+               (FnDecl
+                #((Param x (Ty #:kind #:path #(int))) (Param y (Ty #:kind #:path #(int))))
+                (FnRetTy (Ty #:kind #:path #(int)))))
+              (Block
+               (Stmt #:kind #:expr
+                (Expr #:kind #:binary #:op #:add (Expr #:kind #:path #(x)) (Expr #:kind #:path #(y))))))
+             (Item #:ident main #:vis #:pub #:kind #:defn (Generics)
+              (FnSig
+               (FnDecl
+                #((Param n (Ty #:kind #:path #(int))))
+                (FnRetTy (Ty #:kind #:path #(int)))))
+              (Block
+               (Stmt #:kind #:let (Local k (Expr #:kind #:lit 1)))
+               (Stmt #:kind #:let
+                (Local g ,fn_int_int
+                 (Expr #:kind #:closure (FnDecl #((Param y)))
+                  (Block
+                   (Stmt #:kind #:expr
+                    (Expr #:kind #:binary #:op #:add
+                     (Expr #:kind #:binary #:op #:add (Expr #:kind #:path #(y)) (Expr #:kind #:path #(k)))
+                     (Expr #:kind #:path #(n))))))))
+               (Stmt #:kind #:expr
+                (Expr #:kind #:binary #:op #:add
+                 (Expr #:kind #:call (Expr #:kind #:path #(apply))
+                  #((Expr #:kind #:path #(g))
+                    (Expr #:kind #:call (Expr #:kind #:path #(add))
+                     #((Expr #:kind #:path #(n)) (Expr #:kind #:lit 2)))))
+                 (Expr #:kind #:call (Expr #:kind #:path #(apply))
+                  #((Expr #:kind #:closure (FnDecl #((Param z (Ty #:kind #:path #(int)))))
+                     (Block
+                      (Stmt #:kind #:semi (Expr #:kind #:return (Expr #:kind #:path #(z))))))
+                    (Expr #:kind #:lit 3)))))))
+             (Item #:ident bad #:vis #:pub #:kind #:defn (Generics)
+              (FnSig
+               (FnDecl
+                #((Param x (Ty #:kind #:path #(int))))
+                (FnRetTy (Ty #:kind #:path #(int)))))
+              (Block
+               (Stmt #:kind #:let
+                (Local h ,h_ty
+                 (Expr #:kind #:closure (FnDecl #((Param y)))
+                  (Block (Stmt #:kind #:expr (Expr #:kind #:path #(y)))))))
+               (Stmt #:kind #:semi
+                (Expr #:kind #:call (Expr #:kind #:path #(x)) #((Expr #:kind #:lit 1))))
+               (Stmt #:kind #:expr
+                (Expr #:kind #:call (Expr #:kind #:path #(add)) #((Expr #:kind #:lit 1))))))));
+        let hil = to_hil(&ast);
+        assert!(sexp_match(
+            &hil,
+            &sexp!(
+                (Unit #:path #(test)
+                 #:_
+                 #:_
+                 (Item #:def_id 2 #:ident main #:vis #:pub #:kind #:defn (Generics #:hil_id #:_)
+                  (FnSig #:hil_id #:_
+                   (FnDecl #:hil_id #:_ #((Param #:hil_id #:_2 n #:_)) #:_))
+                  (Block #:hil_id #:_
+                   (Stmt #:hil_id #:_ #:kind #:let (Local #:hil_id #:_1 k #:_))
+                   (Stmt #:hil_id #:_ #:kind #:let
+                    (Local #:hil_id #:_ g #:_
+                     (Expr #:hil_id #:_ #:captures #(#:_1 #:_2) #:kind #:closure
+                      (FnDecl #:hil_id #:_ #((Param #:hil_id #:_ y)))
+                      #:_)))
+                   #:_))
+                 #:_)),
+            &mut MatchContext::new(),
+        ));
+        let ctx = GlobalContext::new();
+        ty_check(&ctx, &hil);
+        assert_eq!(
+            take_errors(),
+            vec![
+                "E0015 Expected function, found int",
+                "E0014 Expected 2 arguments, found 1",
+            ]
+        );
+
+        let bui = to_bui(&hil, &ctx).deserialize();
+        let items = bui.items();
+        let defn = items[0].defn().unwrap();
+        let fn_sig = defn.fn_sig();
+        let decl = fn_sig.decl();
+        assert!(decl.output().is_int());
+        let inputs = decl.inputs();
+        let f = inputs[0].fn_decl().unwrap();
+        assert_eq!(f.inputs().len(), 1);
+        assert!(f.inputs()[0].is_int());
+        assert!(f.output().is_int());
+    }
+
+    #[test]
+    fn test_inference() {
+        use crate::ast::to_hil;
+        use crate::context::GlobalContext;
+        use crate::error::take_errors;
+        use crate::t::ty_check;
+        let _serial = serial();
+        // defn infer(b: bool) -> int {
+        //   let x;
+        //   let id = |y| y;
+        //   let twice = |f, z| f(f(z));
+        //   x = id(1);
+        //   if id(b) { twice(|n| n + x, 2) } else { x }
+        // }
+        // defn bad(b: bool) -> int {
+        //   let u;
+        //   let v = |w| w;
+        //   let k = (v, 1);
+        //   if u == u { 1 } else { b + 1 }
+        // }
+        let ast = sexp!(
+            (Unit #:path #(test)
+             (Item #:ident infer #:kind #:defn (Generics)
+              (FnSig
+               (FnDecl
+                #((Param b (Ty #:kind #:path #(bool))))
+                (FnRetTy (Ty #:kind #:path #(int)))))
+              (Block
+               (Stmt #:kind #:let (Local x))
+               (Stmt #:kind #:let
+                (Local id
+                 (Expr #:kind #:closure (FnDecl #((Param y)))
+                  (Block (Stmt #:kind #:expr (Expr #:kind #:path #(y)))))))
+               (Stmt #:kind #:let
+                (Local twice
+                 (Expr #:kind #:closure (FnDecl #((Param f) (Param z)))
+                  (Block
+                   (Stmt #:kind #:expr
+                    (Expr #:kind #:call (Expr #:kind #:path #(f))
+                     #((Expr #:kind #:call (Expr #:kind #:path #(f)) #((Expr #:kind #:path #(z)))))))))))
+               (Stmt #:kind #:semi
+                (Expr #:kind #:assign (Expr #:kind #:path #(x))
+                 (Expr #:kind #:call (Expr #:kind #:path #(id)) #((Expr #:kind #:lit 1)))))
+               (Stmt #:kind #:expr
+                (Expr #:kind #:if
+                 (Expr #:kind #:call (Expr #:kind #:path #(id)) #((Expr #:kind #:path #(b))))
+                 (Block
+                  (Stmt #:kind #:expr
+                   (Expr #:kind #:call (Expr #:kind #:path #(twice))
+                    #((Expr #:kind #:closure (FnDecl #((Param n)))
+                       (Block
+                        (Stmt #:kind #:expr
+                         (Expr #:kind #:binary #:op #:add (Expr #:kind #:path #(n)) (Expr #:kind #:path #(x))))))
+                      (Expr #:kind #:lit 2)))))
+                 (Block (Stmt #:kind #:expr (Expr #:kind #:path #(x))))))))
+             (Item #:ident bad #:kind #:defn (Generics)
+              (FnSig
+               (FnDecl
+                #((Param b (Ty #:kind #:path #(bool))))
+                (FnRetTy (Ty #:kind #:path #(int)))))
+              (Block
+               (Stmt #:kind #:let (Local u))
+               (Stmt #:kind #:let
+                (Local v
+                 (Expr #:kind #:closure (FnDecl #((Param w)))
+                  (Block (Stmt #:kind #:expr (Expr #:kind #:path #(w)))))))
+               (Stmt #:kind #:let
+                (Local k (Expr #:kind #:tuple #((Expr #:kind #:path #(v)) (Expr #:kind #:lit 1)))))
+               (Stmt #:kind #:expr
+                (Expr #:kind #:if
+                 (Expr #:kind #:binary #:op #:eq (Expr #:kind #:path #(u)) (Expr #:kind #:path #(u)))
+                 (Block (Stmt #:kind #:expr (Expr #:kind #:lit 1)))
+                 (Block
+                  (Stmt #:kind #:expr
+                   (Expr #:kind #:binary #:op #:add (Expr #:kind #:path #(b)) (Expr #:kind #:lit 1))))))))));
+        let hil = to_hil(&ast);
+        let ctx = GlobalContext::new();
+        ty_check(&ctx, &hil);
+        assert_eq!(
+            take_errors(),
+            vec![
+                "E0003 Cannot apply `+` to operands of type bool and int",
+                "E0009 Type annotations needed for `u`",
+                "E0009 Type annotations needed for `w`",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_traits() {
+        use crate::ast::to_hil;
+        use crate::context::GlobalContext;
+        use crate::error::take_errors;
+        use crate::hil::to_bui;
+        use crate::t::ty_check;
+        let _serial = serial();
+        // trait Show {
+        //   defn show(self: Self) -> int;
+        // }
+        // trait Named {
+        //   defn name(self: Self) -> int;
+        // }
+        // class Bar { a: int }
+        // class Baz { b: bool }
+        // impl Show for Bar {
+        //   defn show(self: Bar) -> int { self.a }
+        // }
+        // impl Show for Bar {
+        //   defn show(self: Bar) -> int { 1 }
+        // }
+        // impl Show for Baz {
+        //   defn show(self: Baz) -> bool { self.b }
+        //   defn shout(self: Baz) -> int { 1 }
+        // }
+        // impl Named for Bar {}
+        // defn twice<T: Show>(x: T) -> int {
+        //   x.show() + x.show()
+        // }
+        // defn main(b: Bar) -> int {
+        //   twice(b) + twice(1) + b.show() + b.hide()
+        // }
+        // defn shown<T: Bar>(t: T) -> int {
+        //   t.show()
+        // }
+        // defn show_bar(b: Bar) -> int {
+        //   shown(b)
+        // }
+        let self_ty = sexp!((Ty #:kind #:path #(Self)));
+        let self_ty_2 = self_ty.clone();
+        let int_ty = sexp!((Ty #:kind #:path #(int)));
+        let ast = sexp!(
+            (Unit #:path #(test)
+             (Item #:ident Show #:vis #:pub #:kind #:trait
+              (Signature
+               (Decl #:ident show #:kind #:defn (Generics)
+                (FnSig (FnDecl #((Param self ,self_ty)) (FnRetTy (Ty #:kind #:path #(int))))))))
+             (Item #:ident Named #:vis #:pub #:kind #:trait
+              (Signature
+               (Decl #:ident name #:kind #:defn (Generics)
+                (FnSig (FnDecl #((Param self ,self_ty_2)) (FnRetTy ,int_ty))))))
+             (Item #:ident Bar #:vis #:pub #:kind #:class
+              (Variant (FieldDef a (Ty #:kind #:path #(int)))))
+             (Item #:ident Baz #:vis #:pub #:kind #:class
+              (Variant (FieldDef b (Ty #:kind #:path #(bool)))))
+             (Item #:kind #:impl (TraitRef #(Show)) (Ty #:kind #:path #(Bar))
+              (Structure
+               (Binding #:ident show #:kind #:defn (Generics)
+                (FnSig
+                 (FnDecl #((Param self (Ty #:kind #:path #(Bar)))) (FnRetTy (Ty #:kind #:path #(int)))))
+                (Block (Stmt #:kind #:expr (Expr #:kind #:field (Expr #:kind #:path #(self)) a))))))
+             (Item #:kind #:impl (TraitRef #(Show)) (Ty #:kind #:path #(Bar))
+              (Structure
+               (Binding #:ident show #:kind #:defn (Generics)
+                (FnSig
+                 (FnDecl #((Param self (Ty #:kind #:path #(Bar)))) (FnRetTy (Ty #:kind #:path #(int)))))
+                (Block (Stmt #:kind #:expr (Expr #:kind #:lit 1))))))
+             (Item #:kind #:impl (TraitRef #(Show)) (Ty #:kind #:path #(Baz))
+              (Structure
+               (Binding #:ident show #:kind #:defn (Generics)
+                (FnSig
+                 (FnDecl #((Param self (Ty #:kind #:path #(Baz)))) (FnRetTy (Ty #:kind #:path #(bool)))))
+                (Block (Stmt #:kind #:expr (Expr #:kind #:field (Expr #:kind #:path #(self)) b))))
+               (Binding #:ident shout #:kind #:defn (Generics)
+                (FnSig
+                 (FnDecl #((Param self (Ty #:kind #:path #(Baz)))) (FnRetTy (Ty #:kind #:path #(int)))))
+                (Block (Stmt #:kind #:expr (Expr #:kind #:lit 1))))))
+             (Item #:kind #:impl (TraitRef #(Named)) (Ty #:kind #:path #(Bar)) (Structure))
+             (Item #:ident twice #:vis #:pub #:kind #:defn (Generics (GenericParam T (TraitRef #(Show))))
+              (FnSig
+               (FnDecl #((Param x (Ty #:kind #:path #(T)))) (FnRetTy (Ty #:kind #:path #(int)))))
+              (Block
+               (Stmt #:kind #:expr
+                (Expr #:kind #:binary #:op #:add
+                 (Expr #:kind #:method_call (Expr #:kind #:path #(x)) show #())
+                 (Expr #:kind #:method_call (Expr #:kind #:path #(x)) show #())))))
+             (Item #:ident main #:vis #:pub #:kind #:defn (Generics)
+              (FnSig
+               (FnDecl #((Param b (Ty #:kind #:path #(Bar)))) (FnRetTy (Ty #:kind #:path #(int)))))
+              (Block
+               (Stmt #:kind #:expr
+                (Expr #:kind #:binary #:op #:add
+                 (Expr #:kind #:binary #:op #:add
+                  (Expr #:kind #:binary #:op #:add
+                   (Expr #:kind #:call (Expr #:kind #:path #(twice)) #((Expr #:kind #:path #(b))))
+                   (Expr #:kind #:call (Expr #:kind #:path #(twice)) #((Expr #:kind #:lit 1))))
+                  (Expr #:kind #:method_call (Expr #:kind #:path #(b)) show #()))
+                 (Expr #:kind #:method_call (Expr #:kind #:path #(b)) hide #())))))
+             (Item #:ident shown #:kind #:defn (Generics (GenericParam T (TraitRef #(Bar))))
+              (FnSig
+               (FnDecl #((Param t (Ty #:kind #:path #(T)))) (FnRetTy (Ty #:kind #:path #(int)))))
+              (Block
+               (Stmt #:kind #:expr (Expr #:kind #:method_call (Expr #:kind #:path #(t)) show #()))))
+             (Item #:ident show_bar #:kind #:defn (Generics)
+              (FnSig
+               (FnDecl #((Param b (Ty #:kind #:path #(Bar)))) (FnRetTy (Ty #:kind #:path #(int)))))
+              (Block
+               (Stmt #:kind #:expr
+                (Expr #:kind #:call (Expr #:kind #:path #(shown)) #((Expr #:kind #:path #(b)))))))));
+        let hil = to_hil(&ast);
+        assert_eq!(take_errors(), Vec::<String>::new());
+        let ctx = GlobalContext::new();
+        ty_check(&ctx, &hil);
+        assert_eq!(
+            take_errors(),
+            vec![
+                "E0023 `Bar` is not a trait",
+                "E0018 Conflicting implementations of trait `Show` for type Bar",
+                "E0022 Method `show` has an incompatible type for trait `Show`: expected fn(Baz) -> int, found fn(Baz) -> bool",
+                "E0020 Method `shout` is not a member of trait `Show`",
+                "E0019 Missing method `name` in implementation of `Named`",
+                "E0016 No method `hide` found for type Bar",
+                "E0017 The trait `Show` is not implemented for int",
+                "E0016 No method `show` found for type T",
+            ]
+        );
+        let bui = to_bui(&hil, &ctx).deserialize();
+        let items = bui.items();
+        assert_eq!(items[0].ident(), "Show");
+        let trait_def = items[0].trait_def().unwrap();
+        let methods = trait_def.methods();
+        assert_eq!(methods.len(), 1);
+        assert_eq!(methods[0].0, "show");
+        let fn_sig = methods[0].1.fn_sig();
+        let decl = fn_sig.decl();
+        assert_eq!(decl.inputs()[0].param(), Some(0));
+        assert_eq!(items[4].ident(), "");
+        let impl_def = items[4].impl_def().unwrap();
+        assert_eq!(impl_def.trait_ref().def(), 0);
+        assert_eq!(items[2].ident(), "Bar");
+        assert_eq!(impl_def.self_ty().adt().unwrap().def(), items[2].def());
+        assert_eq!(impl_def.methods()[0].0, "show");
+        let twice = items[8].defn().unwrap();
+        let generics = twice.generics();
+        assert_eq!(generics.len(), 1);
+        assert_eq!(generics[0].name(), "T");
+        assert_eq!(generics[0].bounds()[0].def(), 0);
+    }
+
+    #[test]
+    fn test_methods() {
+        use crate::ast::to_hil;
+        use crate::context::GlobalContext;
+        use crate::error::take_errors;
+        use crate::hil::to_bui;
+        use crate::t::ty_check;
+        let _serial = serial();
+        // class Counter {
+        //   n: int,
+        //   defn get(self) -> int { self.n }
+        //   defn add(self, k: int) -> int { self.n + k }
+        //   defn show(self) -> bool { true }
+        // }
+        // trait Show {
+        //   defn show(self) -> int;
+        // }
+        // impl Show for Counter {
+        //   defn show(self) -> int { 1 }
+        // }
+        // defn main(c: Counter) -> int {
+        //   let b: bool = c.show();
+        //   c.add(true);
+        //   c.add();
+        //   c.add(c.get())
+        // }
+        let ast = sexp!(
+            (Unit #:path #(test)
+             (Item #:ident Counter #:vis #:pub #:kind #:class
+              (Variant (FieldDef n (Ty #:kind #:path #(int))))
+              (Structure
+               (Binding #:ident get #:vis #:pub #:kind #:defn (Generics)
+                (FnSig (FnDecl #((Param self)) (FnRetTy (Ty #:kind #:path #(int)))))
+                (Block (Stmt #:kind #:expr (Expr #:kind #:field (Expr #:kind #:path #(self)) n))))
+               (Binding #:ident add #:vis #:pub #:kind #:defn (Generics)
+                (FnSig
+                 (FnDecl
+                  #((Param self) (Param k (Ty #:kind #:path #(int))))
+                  (FnRetTy (Ty #:kind #:path #(int)))))
+                (Block
+                 (Stmt #:kind #:expr
+                  (Expr #:kind #:binary #:op #:add
+                   (Expr #:kind #:field (Expr #:kind #:path #(self)) n)
+                   (Expr #:kind #:path #(k))))))
+               (Binding #:ident show #:vis #:pub #:kind #:defn (Generics)
+                (FnSig (FnDecl #((Param self)) (FnRetTy (Ty #:kind #:path #(bool)))))
+                (Block (Stmt #:kind #:expr (Expr #:kind #:lit #t))))))
+             (Item #:ident Show #:vis #:pub #:kind #:trait
+              (Signature
+               (Decl #:ident show #:kind #:defn (Generics)
+                (FnSig (FnDecl #((Param self)) (FnRetTy (Ty #:kind #:path #(int))))))))
+             (Item #:kind #:impl (TraitRef #(Show)) (Ty #:kind #:path #(Counter))
+              (Structure
+               (Binding #:ident show #:kind #:defn (Generics)
+                (FnSig (FnDecl #((Param self)) (FnRetTy (Ty #:kind #:path #(int)))))
+                (Block (Stmt #:kind #:expr (Expr #:kind #:lit 1))))))
+             (Item #:ident main #:vis #:pub #:kind #:defn (Generics)
+              (FnSig
+               (FnDecl
+                #((Param c (Ty #:kind #:path #(Counter))))
+                (FnRetTy (Ty #:kind #:path #(int)))))
+              (Block
+               (Stmt #:kind #:let
+                (Local b (Ty #:kind #:path #(bool))
+                 (Expr #:kind #:method_call (Expr #:kind #:path #(c)) show #())))
+               (Stmt #:kind #:semi
+                (Expr #:kind #:method_call (Expr #:kind #:path #(c)) add #((Expr #:kind #:lit #t))))
+               (Stmt #:kind #:semi (Expr #:kind #:method_call (Expr #:kind #:path #(c)) add #()))
+               (Stmt #:kind #:expr
+                (Expr #:kind #:method_call (Expr #:kind #:path #(c)) add
+                 #((Expr #:kind #:method_call (Expr #:kind #:path #(c)) get #()))))))));
+        let hil = to_hil(&ast);
+        assert_eq!(take_errors(), Vec::<String>::new());
+        assert!(sexp_match(
+            &hil,
+            &sexp!(
+                (Unit #:path #(test)
+                 (Item #:def_id 0 #:ident Counter #:vis #:pub #:kind #:class
+                  #:_
+                  (Structure #:hil_id #:_
+                   (Binding #:hil_id #:_ #:def_id 3 #:ident get #:vis #:pub #:kind #:defn #:_
+                    (FnSig #:hil_id #:_
+                     (FnDecl #:hil_id #:_
+                      #((Param #:hil_id #:_ self
+                         (Ty #:hil_id #:_ #:kind #:qpath (Path #:kind #:def (DefId 0 0)))))
+                      #:_))
+                    #:_)
+                   #:_
+                   #:_))
+                 #:_
+                 #:_
+                 #:_)),
+            &mut MatchContext::new(),
+        ));
+        let ctx = GlobalContext::new();
+        ty_check(&ctx, &hil);
+        assert_eq!(
+            take_errors(),
+            vec![
+                "E0002 Mismatched types: expected int, found bool",
+                "E0014 Expected 1 arguments, found 0",
+            ]
+        );
+        let bui = to_bui(&hil, &ctx).deserialize();
+        let items = bui.items();
+        let class = items[0].class().unwrap();
+        let methods = class.methods();
+        assert_eq!(methods.len(), 3);
+        assert_eq!(methods[1].0, "add");
+        let fn_sig = methods[1].1.fn_sig();
+        let decl = fn_sig.decl();
+        let inputs = decl.inputs();
+        assert_eq!(inputs.len(), 2);
+        assert_eq!(inputs[0].adt().unwrap().def(), 0);
+        assert!(inputs[1].is_int());
+    }
+
+    #[test]
+    fn test_functors() {
+        use crate::ast::to_hil;
+        use crate::context::GlobalContext;
+        use crate::error::take_errors;
+        use crate::hil::to_bui;
+        use crate::t::ty_check;
+        let _serial = serial();
+        // interface ORD {
+        //   type t;
+        //   defn lt(a: t, b: t) -> bool;
+        // }
+        // module IntOrd {
+        //   type t = int;
+        //   defn lt(a: int, b: int) -> bool { a < b }
+        // }
+        // module BoolOrd {
+        //   type t = bool;
+        //   defn lt(a: bool, b: int) -> bool { a }
+        // }
+        // functor Max(X: ORD) {
+        //   type t = X::t;
+        //   defn max(a: X::t, b: t) -> t { if X::lt(a, b) { b } else { a } }
+        // }
+        // module IntMax = Max(IntOrd);
+        // module BoolMax = Max(BoolOrd);
+        // module Bad = IntOrd(IntOrd);
+        let a_int = sexp!((Param a (Ty #:kind #:path #(int))));
+        let b_int = sexp!((Param b (Ty #:kind #:path #(int))));
+        let ast = sexp!(
+            (Unit #:path #(test)
+             (Item #:ident ORD #:vis #:pub #:kind #:interface
+              (Signature
+               (Decl #:ident t #:kind #:ty)
+               (Decl #:ident lt #:kind #:defn (Generics)
+                (FnSig
+                 (FnDecl
+                  #((Param a (Ty #:kind #:path #(t))) (Param b (Ty #:kind #:path #(t))))
+                  (FnRetTy (Ty #:kind #:path #(bool))))))))
+             (Item #:ident IntOrd #:vis #:pub #:kind #:module
+              (Structure
+               (Binding #:ident t #:vis #:pub #:kind #:ty (Ty #:kind #:path #(int)))
+               (Binding #:ident lt #:vis #:pub #:kind #:defn (Generics)
+                (FnSig (FnDecl #(,a_int ,b_int) (FnRetTy (Ty #:kind #:path #(bool)))))
+                (Block
+                 (Stmt #:kind #:expr
+                  (Expr #:kind #:binary #:op #:lt (Expr #:kind #:path #(a)) (Expr #:kind #:path #(b))))))))
+             (Item #:ident BoolOrd #:vis #:pub #:kind #:module
+              (Structure
+               (Binding #:ident t #:vis #:pub #:kind #:ty (Ty #:kind #:path #(bool)))
+               (Binding #:ident lt #:vis #:pub #:kind #:defn (Generics)
+                (FnSig
+                 (FnDecl
+                  #((Param a (Ty #:kind #:path #(bool))) (Param b (Ty #:kind #:path #(int))))
+                  (FnRetTy (Ty #:kind #:path #(bool)))))
+                (Block (Stmt #:kind #:expr (Expr #:kind #:path #(a)))))))
+             (Item #:ident Max #:vis #:pub #:kind #:functor
+              #((FunctorParam X (IfaceRef #(ORD))))
+              (Structure
+               (Binding #:ident t #:vis #:pub #:kind #:ty (Ty #:kind #:path #(X t)))
+               (Binding #:ident max #:vis #:pub #:kind #:defn (Generics)
+                (FnSig
+                 (FnDecl
+                  #((Param a (Ty #:kind #:path #(X t))) (Param b (Ty #:kind #:path #(t))))
+                  (FnRetTy (Ty #:kind #:path #(t)))))
+                (Block
+                 (Stmt #:kind #:expr
+                  (Expr #:kind #:if
+                   (Expr #:kind #:call (Expr #:kind #:path #(X lt))
+                    #((Expr #:kind #:path #(a)) (Expr #:kind #:path #(b))))
+                   (Block (Stmt #:kind #:expr (Expr #:kind #:path #(b))))
+                   (Block (Stmt #:kind #:expr (Expr #:kind #:path #(a))))))))))
+             (Item #:ident IntMax #:vis #:pub #:kind #:apply (ModRef #(Max)) (ModRef #(IntOrd)))
+             (Item #:ident BoolMax #:vis #:pub #:kind #:apply (ModRef #(Max)) (ModRef #(BoolOrd)))
+             (Item #:ident Bad #:vis #:pub #:kind #:apply (ModRef #(IntOrd)) (ModRef #(IntOrd)))));
+        let hil = to_hil(&ast);
+        assert_eq!(take_errors(), Vec::<String>::new());
+        let ctx = GlobalContext::new();
+        ty_check(&ctx, &hil);
+        assert_eq!(
+            take_errors(),
+            vec![
+                "E0027 Member `lt` has an incompatible type for interface `ORD`: expected fn(bool, bool) -> bool, found fn(bool, int) -> bool",
+                "E0024 `IntOrd` is not a functor",
+            ]
+        );
+        let bui = to_bui(&hil, &ctx).deserialize();
+        let items = bui.items();
+        let functor = items[3].functor().unwrap();
+        let params = functor.params();
+        assert_eq!(params.len(), 1);
+        assert_eq!(params[0].0, "X");
+        assert_eq!(params[0].1.def(), 0);
+        assert_eq!(functor.body().defns()[0].0, "max");
+        assert_eq!(items[4].ident(), "IntMax");
+        let int_max = items[4].module().unwrap();
+        let types = int_max.types();
+        assert_eq!(types[0].0, "t");
+        assert!(types[0].1.is_int());
+        let defns = int_max.defns();
+        assert_eq!(defns[0].0, "max");
+        let fn_sig = defns[0].1.fn_sig();
+        let decl = fn_sig.decl();
+        assert!(decl.inputs().iter().all(|x| x.is_int()));
+        assert!(decl.output().is_int());
+    }
+
+    #[test]
+    fn test_interface_include() {
+        use crate::ast::to_hil;
+        use crate::context::GlobalContext;
+        use crate::error::take_errors;
+        use crate::hil::to_bui;
+        use crate::t::ty_check;
+        let _serial = serial();
+        // interface INT_ORD {
+        //   include ORD with type t = int;
+        //   defn zero() -> t;
+        // }
+        // interface ORD {
+        //   type t;
+        //   defn lt(a: t, b: t) -> bool;
+        // }
+        // interface BAD {
+        //   type lt;
+        //   include ORD with type u = int;
+        // }
+        // module BoolOrd {
+        //   type t = bool;
+        //   defn lt(a: bool, b: bool) -> bool { a }
+        //   defn zero() -> bool { true }
+        // }
+        // functor Pos(X: INT_ORD) {
+        //   defn pos(a: int) -> bool { X::lt(X::zero(), a) }
+        // }
+        // module BoolPos = Pos(BoolOrd);
+        let bool_ty = sexp!((FnRetTy (Ty #:kind #:path #(bool))));
+        let bool_ty_2 = bool_ty.clone();
+        let ast = sexp!(
+            (Unit #:path #(test)
+             (Item #:ident INT_ORD #:vis #:pub #:kind #:interface
+              (Signature
+               (Include (IfaceRef #(ORD) (With t (Ty #:kind #:path #(int)))))
+               (Decl #:ident zero #:kind #:defn (Generics)
+                (FnSig (FnDecl #() (FnRetTy (Ty #:kind #:path #(t))))))))
+             (Item #:ident ORD #:vis #:pub #:kind #:interface
+              (Signature
+               (Decl #:ident t #:kind #:ty)
+               (Decl #:ident lt #:kind #:defn (Generics)
+                (FnSig
+                 (FnDecl
+                  #((Param a (Ty #:kind #:path #(t))) (Param b (Ty #:kind #:path #(t))))
+                  (FnRetTy (Ty #:kind #:path #(bool))))))))
+             (Item #:ident BAD #:vis #:pub #:kind #:interface
+              (Signature
+               (Decl #:ident lt #:kind #:ty)
+               (Include (IfaceRef #(ORD) (With u (Ty #:kind #:path #(int)))))))
+             (Item #:ident BoolOrd #:vis #:pub #:kind #:module
+              (Structure
+               (Binding #:ident t #:vis #:pub #:kind #:ty (Ty #:kind #:path #(bool)))
+               (Binding #:ident lt #:vis #:pub #:kind #:defn (Generics)
+                (FnSig
+                 (FnDecl
+                  #((Param a (Ty #:kind #:path #(bool))) (Param b (Ty #:kind #:path #(bool))))
+                  ,bool_ty))
+                (Block (Stmt #:kind #:expr (Expr #:kind #:path #(a)))))
+               (Binding #:ident zero #:vis #:pub #:kind #:defn (Generics)
+                (FnSig (FnDecl #() ,bool_ty_2))
+                (Block (Stmt #:kind #:expr (Expr #:kind #:lit #t))))))
+             (Item #:ident Pos #:vis #:pub #:kind #:functor
+              #((FunctorParam X (IfaceRef #(INT_ORD))))
+              (Structure
+               (Binding #:ident pos #:vis #:pub #:kind #:defn (Generics)
+                (FnSig
+                 (FnDecl
+                  #((Param a (Ty #:kind #:path #(int))))
+                  (FnRetTy (Ty #:kind #:path #(bool)))))
+                (Block
+                 (Stmt #:kind #:expr
+                  (Expr #:kind #:call (Expr #:kind #:path #(X lt))
+                   #((Expr #:kind #:call (Expr #:kind #:path #(X zero)) #())
+                     (Expr #:kind #:path #(a)))))))))
+             (Item #:ident BoolPos #:vis #:pub #:kind #:apply (ModRef #(Pos)) (ModRef #(BoolOrd)))));
+        let hil = to_hil(&ast);
+        assert_eq!(take_errors(), vec!["E0028 Member `lt` is declared more than once"]);
+        let ctx = GlobalContext::new();
+        ty_check(&ctx, &hil);
+        assert_eq!(
+            take_errors(),
+            vec![
+                "E0029 `u` is not an opaque type of interface `ORD`",
+                "E0027 Member `t` has an incompatible type for interface `INT_ORD`: expected int, found bool",
+                "E0027 Member `lt` has an incompatible type for interface `INT_ORD`: expected fn(int, int) -> bool, found fn(bool, bool) -> bool",
+                "E0027 Member `zero` has an incompatible type for interface `INT_ORD`: expected fn() -> int, found fn() -> bool",
+            ]
+        );
+        let bui = to_bui(&hil, &ctx).deserialize();
+        let items = bui.items();
+        let int_ord = items[0].interface().unwrap();
+        let types = int_ord.types();
+        assert_eq!(types.len(), 1);
+        assert_eq!(types[0].0, "t");
+        assert!(types[0].1.transparent().unwrap().is_int());
+        let defns = int_ord.defns();
+        assert_eq!(defns.len(), 2);
+        assert_eq!(defns[0].0, "lt");
+        assert_eq!(defns[1].0, "zero");
+    }
+
+    #[test]
+    fn test_visibility() {
+        use crate::ast::to_hil;
+        use crate::bui::Bui;
+        use crate::context::GlobalContext;
+        use crate::error::take_errors;
+        use crate::hil::to_bui;
+        use crate::t::ty_check;
+        let _serial = serial();
+        // pub class Point {
+        //   pub x: int,
+        //   y: int,
+        // }
+        // defn origin() -> Point {
+        //   Point { x: 0, y: 0 }
+        // }
+        // pub module M {
+        //   pub defn get() -> int { helper() }
+        //   defn helper() -> int { 1 }
+        // }
+        // interface I {
+        //   defn helper() -> int;
+        // }
+        // functor F(X: I) {
+        //   pub defn f() -> int { X::helper() }
+        // }
+        // pub module N = F(M);
+        // pub defn main() -> int {
+        //   M::get() + M::helper()
+        // }
+        let int_ty = sexp!((FnRetTy (Ty #:kind #:path #(int))));
+        let int_ty_2 = int_ty.clone();
+        let int_ty_3 = int_ty.clone();
+        let ast = sexp!(
+            (Unit #:path #(test)
+             (Item #:ident Point #:vis #:pub #:kind #:class
+              (Variant
+               (FieldDef #:vis #:pub x (Ty #:kind #:path #(int)))
+               (FieldDef y (Ty #:kind #:path #(int)))))
+             (Item #:ident origin #:kind #:defn (Generics)
+              (FnSig (FnDecl #() (FnRetTy (Ty #:kind #:path #(Point)))))
+              (Block
+               (Stmt #:kind #:expr
+                (Expr #:kind #:struct #(Point)
+                 (Field x (Expr #:kind #:lit 0))
+                 (Field y (Expr #:kind #:lit 0))))))
+             (Item #:ident M #:vis #:pub #:kind #:module
+              (Structure
+               (Binding #:ident get #:vis #:pub #:kind #:defn (Generics)
+                (FnSig (FnDecl #() ,int_ty))
+                (Block
+                 (Stmt #:kind #:expr
+                  (Expr #:kind #:call (Expr #:kind #:path #(helper)) #()))))
+               (Binding #:ident helper #:kind #:defn (Generics)
+                (FnSig (FnDecl #() ,int_ty_2))
+                (Block (Stmt #:kind #:expr (Expr #:kind #:lit 1))))))
+             (Item #:ident I #:kind #:interface
+              (Signature
+               (Decl #:ident helper #:kind #:defn (Generics)
+                (FnSig (FnDecl #() (FnRetTy (Ty #:kind #:path #(int))))))))
+             (Item #:ident F #:kind #:functor
+              #((FunctorParam X (IfaceRef #(I))))
+              (Structure
+               (Binding #:ident f #:vis #:pub #:kind #:defn (Generics)
+                (FnSig (FnDecl #() ,int_ty_3))
+                (Block
+                 (Stmt #:kind #:expr
+                  (Expr #:kind #:call (Expr #:kind #:path #(X helper)) #()))))))
+             (Item #:ident N #:vis #:pub #:kind #:apply (ModRef #(F)) (ModRef #(M)))
+             (Item #:ident main #:vis #:pub #:kind #:defn (Generics)
+              (FnSig (FnDecl #() (FnRetTy (Ty #:kind #:path #(int)))))
+              (Block
+               (Stmt #:kind #:expr
+                (Expr #:kind #:binary #:op #:add
+                 (Expr #:kind #:call (Expr #:kind #:path #(M get)) #())
+                 (Expr #:kind #:call (Expr #:kind #:path #(M helper)) #())))))));
+        let hil = to_hil(&ast);
+        assert_eq!(take_errors(), vec!["E0031 Member `helper` of `M` is private"]);
+        let ctx = GlobalContext::new();
+        ty_check(&ctx, &hil);
+        assert_eq!(
+            take_errors(),
+            vec!["E0026 Missing member `helper` required by interface `I`"]
+        );
+        let bui = to_bui(&hil, &ctx).deserialize();
+        let items = bui.items();
+        let idents = items.iter().map(|x| x.ident()).collect::<Vec<_>>();
+        assert_eq!(idents, vec!["Point", "M", "N", "main"]);
+        let class = items[0].class().unwrap();
+        let fields = class.fields();
+        assert_eq!(fields.len(), 2);
+        assert!(fields[0].is_public());
+        assert!(!fields[1].is_public());
+        let module = items[1].module().unwrap();
+        let defns = module.defns();
+        assert_eq!(defns.len(), 1);
+        assert_eq!(defns[0].0, "get");
+        // Private items, wherever they are added, leave the BUI unchanged.
+        let mut fields = ast.as_cons().unwrap().to_vec().0;
+        fields.insert(
+            3,
+            sexp!((Item #:ident Hidden #:kind #:class
+                   (Variant (FieldDef h (Ty #:kind #:path #(int)))))),
+        );
+        fields.insert(
+            6,
+            sexp!((Item #:ident hidden #:kind #:defn (Generics)
+                   (FnSig (FnDecl #() (FnRetTy (Ty #:kind #:path #(int)))))
+                   (Block (Stmt #:kind #:expr (Expr #:kind #:lit 2))))),
+        );
+        let hil = to_hil(&Value::list(fields));
+        take_errors();
+        let ctx = GlobalContext::new();
+        ty_check(&ctx, &hil);
+        take_errors();
+        let private = to_bui(&hil, &ctx).deserialize();
+        assert_eq!(private.hash(), bui.hash());
+        let defs = |bui: &Bui| bui.items().iter().map(|x| x.def()).collect::<Vec<_>>();
+        assert_eq!(defs(&private), vec![0, 1, 2, 3]);
+        assert_eq!(defs(&private), defs(&bui));
+    }
+
+    #[test]
+    fn test_consts() {
+        use crate::ast::to_hil;
+        use crate::context::GlobalContext;
+        use crate::error::take_errors;
+        use crate::hil::to_bui;
+        use crate::t::ty_check;
+        let _serial = serial();
+        // pub class Point {
+        //   pub x: int,
+        //   pub y: int,
+        // }
+        // pub const ORIGIN: Point = Point { x: ZERO, y: ZERO + 1 };
+        // const ZERO: int = 0;
+        // pub const PAIR: (int, bool) = (ORIGIN.y * 10, !false && true);
+        // const BAD: int = 1 / ZERO;
+        // const BIG: int = 9223372036854775807 + 1;
+        // const A: int = B;
+        // const B: int = A;
+        // pub static S: int = 2;
+        // const C: int = S;
+        // pub defn f() -> int { ORIGIN.y + ZERO }
+        // const D: int = f();
+        let int_ty = sexp!((Ty #:kind #:path #(int)));
+        let max = i64::MAX;
+        let ast = sexp!(
+            (Unit #:path #(test)
+             (Item #:ident Point #:vis #:pub #:kind #:class
+              (Variant
+               (FieldDef #:vis #:pub x (Ty #:kind #:path #(int)))
+               (FieldDef #:vis #:pub y (Ty #:kind #:path #(int)))))
+             (Item #:ident ORIGIN #:vis #:pub #:kind #:const (Ty #:kind #:path #(Point))
+              (Expr #:kind #:struct #(Point)
+               (Field x (Expr #:kind #:path #(ZERO)))
+               (Field y
+                (Expr #:kind #:binary #:op #:add
+                 (Expr #:kind #:path #(ZERO))
+                 (Expr #:kind #:lit 1)))))
+             (Item #:ident ZERO #:kind #:const ,int_ty (Expr #:kind #:lit 0))
+             (Item #:ident PAIR #:vis #:pub #:kind #:const
+              (Ty #:kind #:tuple #((Ty #:kind #:path #(int)) (Ty #:kind #:path #(bool))))
+              (Expr #:kind #:tuple
+               #((Expr #:kind #:binary #:op #:mul
+                  (Expr #:kind #:field (Expr #:kind #:path #(ORIGIN)) y)
+                  (Expr #:kind #:lit 10))
+                 (Expr #:kind #:binary #:op #:and
+                  (Expr #:kind #:unary #:op #:not (Expr #:kind #:lit #f))
+                  (Expr #:kind #:lit #t)))))
+             (Item #:ident BAD #:kind #:const (Ty #:kind #:path #(int))
+              (Expr #:kind #:binary #:op #:div
+               (Expr #:kind #:lit 1)
+               (Expr #:kind #:path #(ZERO))))
+             (Item #:ident BIG #:kind #:const (Ty #:kind #:path #(int))
+              (Expr #:kind #:binary #:op #:add
+               (Expr #:kind #:lit ,max)
+               (Expr #:kind #:lit 1)))
+             (Item #:ident A #:kind #:const (Ty #:kind #:path #(int)) (Expr #:kind #:path #(B)))
+             (Item #:ident B #:kind #:const (Ty #:kind #:path #(int)) (Expr #:kind #:path #(A)))
+             (Item #:ident S #:vis #:pub #:kind #:static (Ty #:kind #:path #(int))
+              (Expr #:kind #:lit 2))
+             (Item #:ident C #:kind #:const (Ty #:kind #:path #(int)) (Expr #:kind #:path #(S)))
+             (Item #:ident f #:vis #:pub #:kind #:defn (Generics)
+              (FnSig (FnDecl #() (FnRetTy (Ty #:kind #:path #(int)))))
+              (Block
+               (Stmt #:kind #:expr
+                (Expr #:kind #:binary #:op #:add
+                 (Expr #:kind #:field (Expr #:kind #:path #(ORIGIN)) y)
+                 (Expr #:kind #:path #(ZERO))))))
+             (Item #:ident D #:kind #:const (Ty #:kind #:path #(int))
+              (Expr #:kind #:call (Expr #:kind #:path #(f)) #()))));
+        let hil = to_hil(&ast);
+        assert_eq!(take_errors(), Vec::<String>::new());
+        let ctx = GlobalContext::new();
+        ty_check(&ctx, &hil);
+        assert_eq!(
+            take_errors(),
+            vec![
+                "E0033 Evaluation of constant `BAD` failed: attempt to divide by zero",
+                "E0033 Evaluation of constant `BIG` failed: attempt to add with overflow",
+                "E0034 Cycle detected when evaluating constant `A`",
+                "E0032 Static `S` cannot be evaluated at compile time",
+                "E0032 `call` expression cannot be evaluated at compile time",
+            ]
+        );
+        let bui = to_bui(&hil, &ctx).deserialize();
+        let items = bui.items();
+        let idents = items.iter().map(|x| x.ident()).collect::<Vec<_>>();
+        assert_eq!(idents, vec!["Point", "ORIGIN", "PAIR", "S", "f"]);
+        let origin = items[1].const_def().unwrap();
+        assert_eq!(origin.ty().adt().unwrap().def(), 0);
+        let value = origin.value();
+        let (adt, fields) = value.adt().unwrap();
+        assert_eq!(adt.def(), 0);
+        assert_eq!(fields[0].int(), Some(0));
+        assert_eq!(fields[1].int(), Some(1));
+        let pair = items[2].const_def().unwrap();
+        let value = pair.value();
+        let elems = value.tuple().unwrap();
+        assert_eq!(elems[0].int(), Some(10));
+        assert_eq!(elems[1].bool(), Some(true));
+        assert!(items[3].static_ty().unwrap().is_int());
+    }
+
+    fn unit_ast(path: &str, items: &[Value]) -> Value {
+        let path = Value::vector([Value::symbol(path)]);
+        let header = [Value::symbol("Unit"), Value::keyword("path"), path];
+        Value::list(header.into_iter().chain(items.iter().cloned()))
+    }
+
+    // HIL of a unit `test` made of `items` of the unit whose BUI is `bui`,
+    // declared again for their names to resolve, and `rest`, whose paths to
+    // them, to the fields of their classes and to the members of their
+    // interfaces are then made to refer to the ones of the BUI as unit 1.
+    fn import_unit(items: &[Value], rest: &[Value], bui: &crate::bui::Bui) -> Value {
+        use crate::ast::to_hil;
+        use crate::s_expr::{node_find_attr, node_get_attr, node_get_field, node_get_fields};
+        fn ident(node: &lexpr::Cons) -> Option<&str> {
+            node_find_attr(node, "ident").and_then(|x| x.as_symbol())
+        }
+        fn import_refs(value: &Value, idents: &[&str], defs: &HashMap<u64, u32>) -> Value {
+            match value {
+                Value::Cons(cons) => {
+                    let car = cons.car().as_symbol();
+                    if car == Some("Item") && !ident(cons).is_some_and(|x| idents.contains(&x)) {
+                        return value.clone();
+                    } else if car == Some("DefId") {
+                        let offset = node_get_field(cons, 1).as_u64().unwrap();
+                        if let Some(&def) = defs.get(&offset) {
+                            return sexp!((DefId 1 ,def));
+                        }
+                    }
+                    let car = import_refs(cons.car(), idents, defs);
+                    Value::cons(car, import_refs(cons.cdr(), idents, defs))
+                }
+                Value::Vector(elems) => {
+                    Value::vector(elems.iter().map(|x| import_refs(x, idents, defs)))
+                }
+                _ => value.clone(),
+            }
+        }
+        let idents = rest
+            .iter()
+            .filter_map(|x| ident(x.as_cons().unwrap()))
+            .collect::<Vec<_>>();
+        let hil = to_hil(&unit_ast("test", &[items, rest].concat()));
+        let bui_items = bui.items();
+        let mut defs = HashMap::new();
+        for item in node_get_fields(hil.as_cons().unwrap()) {
+            let item = item.as_cons().unwrap();
+            if let Some(x) = bui_items.iter().find(|x| Some(x.ident()) == ident(item)) {
+                defs.insert(node_get_attr(item, "def_id").as_u64().unwrap(), x.def());
+                let members = if let Some(class) = x.class() {
+                    class
+                        .fields()
+                        .iter()
+                        .map(|x| (x.name().to_string(), x.def()))
+                        .collect::<HashMap<_, _>>()
+                } else if let Some(iface) = x.interface() {
+                    iface
+                        .types()
+                        .into_iter()
+                        .map(|(name, _)| name)
+                        .zip(iface.type_defs())
+                        .chain(iface.defns().into_iter().map(|(name, _)| name).zip(iface.defn_defs()))
+                        .map(|(name, def)| (name.to_string(), def.def()))
+                        .collect::<HashMap<_, _>>()
+                } else {
+                    continue;
+                };
+                // Fields are named by their first field, declarations by
+                // their ident.
+                for member in node_get_fields(node_get_field(item, 0).as_cons().unwrap()) {
+                    let member = member.as_cons().unwrap();
+                    let name = ident(member).or_else(|| node_get_field(member, 0).as_symbol());
+                    if let Some(&def) = name.and_then(|x| members.get(x)) {
+                        defs.insert(node_get_attr(member, "def_id").as_u64().unwrap(), def);
+                    }
+                }
+            }
+        }
+        import_refs(&hil, &idents, &defs)
+    }
+
+    #[test]
+    fn test_import_items() {
+        use crate::ast::to_hil;
+        use crate::context::GlobalContext;
+        use crate::def::DefId;
+        use crate::error::take_errors;
+        use crate::hil::to_bui;
+        use crate::s_expr::{node_find_attr, node_get_attr, node_get_fields};
+        use crate::t::const_eval::ConstValue;
+        use crate::t::context::UnitPath;
+        use crate::t::ty_check;
+        let _serial = serial();
+        // pub class Point {
+        //   pub x: int,
+        //   pub y: int,
+        // }
+        // pub defn make(a: int) -> Point { Point { x: a, y: a } }
+        // pub const ORIGIN: Point = Point { x: 1, y: 2 };
+        // pub trait Show {
+        //   defn show(self: Self) -> int;
+        // }
+        // impl Show for Point {
+        //   defn show(self: Point) -> int { self.x }
+        // }
+        // pub defn twice<T: Show>(x: T) -> int { x.show() + x.show() }
+        // pub class Secret {
+        //   pub a: int,
+        //   b: int,
+        // }
+        // pub defn secret() -> Secret { Secret { a: 1, b: 2 } }
+        let items = sexp!(
+            #((Item #:ident Point #:vis #:pub #:kind #:class
+               (Variant
+                (FieldDef #:vis #:pub x (Ty #:kind #:path #(int)))
+                (FieldDef #:vis #:pub y (Ty #:kind #:path #(int)))))
+              (Item #:ident make #:vis #:pub #:kind #:defn (Generics)
+               (FnSig (FnDecl #((Param a (Ty #:kind #:path #(int)))) (FnRetTy (Ty #:kind #:path #(Point)))))
+               (Block
+                (Stmt #:kind #:expr
+                 (Expr #:kind #:struct #(Point)
+                  (Field x (Expr #:kind #:path #(a)))
+                  (Field y (Expr #:kind #:path #(a)))))))
+              (Item #:ident ORIGIN #:vis #:pub #:kind #:const (Ty #:kind #:path #(Point))
+               (Expr #:kind #:struct #(Point)
+                (Field x (Expr #:kind #:lit 1))
+                (Field y (Expr #:kind #:lit 2))))
+              (Item #:ident Show #:vis #:pub #:kind #:trait
+               (Signature
+                (Decl #:ident show #:kind #:defn (Generics)
+                 (FnSig
+                  (FnDecl #((Param self (Ty #:kind #:path #(Self)))) (FnRetTy (Ty #:kind #:path #(int))))))))
+              (Item #:kind #:impl (TraitRef #(Show)) (Ty #:kind #:path #(Point))
+               (Structure
+                (Binding #:ident show #:kind #:defn (Generics)
+                 (FnSig (FnDecl #((Param self (Ty #:kind #:path #(Point)))) (FnRetTy (Ty #:kind #:path #(int)))))
+                 (Block
+                  (Stmt #:kind #:expr (Expr #:kind #:field (Expr #:kind #:path #(self)) x))))))
+              (Item #:ident twice #:vis #:pub #:kind #:defn
+               (Generics (GenericParam T (TraitRef #(Show))))
+               (FnSig (FnDecl #((Param x (Ty #:kind #:path #(T)))) (FnRetTy (Ty #:kind #:path #(int)))))
+               (Block
+                (Stmt #:kind #:expr
+                 (Expr #:kind #:binary #:op #:add
+                  (Expr #:kind #:method_call (Expr #:kind #:path #(x)) show #())
+                  (Expr #:kind #:method_call (Expr #:kind #:path #(x)) show #())))))
+              (Item #:ident Secret #:vis #:pub #:kind #:class
+               (Variant
+                (FieldDef #:vis #:pub a (Ty #:kind #:path #(int)))
+                (FieldDef b (Ty #:kind #:path #(int)))))
+              (Item #:ident secret #:vis #:pub #:kind #:defn (Generics)
+               (FnSig (FnDecl #() (FnRetTy (Ty #:kind #:path #(Secret)))))
+               (Block
+                (Stmt #:kind #:expr
+                 (Expr #:kind #:struct #(Secret)
+                  (Field a (Expr #:kind #:lit 1))
+                  (Field b (Expr #:kind #:lit 2)))))))
+        );
+        let items = items.as_slice().unwrap();
+        let lib = unit_ast("lib", items);
+        let lib_ctx = GlobalContext::new();
+        let lib_hil = to_hil(&lib);
+        ty_check(&lib_ctx, &lib_hil);
+        assert_eq!(take_errors(), Vec::<String>::new());
+        let bui = to_bui(&lib_hil, &lib_ctx).deserialize();
+        // const Y: int = ORIGIN.y * 10;
+        // pub defn main() -> int {
+        //   make(1).x + make(true).y + twice(make(Y)) + twice(1) + ORIGIN.z
+        // }
+        // defn peek() -> int {
+        //   secret().a + secret().b + Secret { a: 1, b: 2 }.a
+        // }
+        let rest = sexp!(
+            #((Item #:ident Y #:kind #:const (Ty #:kind #:path #(int))
+              (Expr #:kind #:binary #:op #:mul
+               (Expr #:kind #:field (Expr #:kind #:path #(ORIGIN)) y)
+               (Expr #:kind #:lit 10)))
+             (Item #:ident main #:vis #:pub #:kind #:defn (Generics)
+              (FnSig (FnDecl #() (FnRetTy (Ty #:kind #:path #(int)))))
+              (Block
+               (Stmt #:kind #:expr
+                (Expr #:kind #:binary #:op #:add
+                 (Expr #:kind #:binary #:op #:add
+                  (Expr #:kind #:binary #:op #:add
+                   (Expr #:kind #:binary #:op #:add
+                    (Expr #:kind #:field
+                     (Expr #:kind #:call (Expr #:kind #:path #(make)) #((Expr #:kind #:lit 1)))
+                     x)
+                    (Expr #:kind #:field
+                     (Expr #:kind #:call (Expr #:kind #:path #(make)) #((Expr #:kind #:lit #t)))
+                     y))
+                   (Expr #:kind #:call (Expr #:kind #:path #(twice))
+                    #((Expr #:kind #:call (Expr #:kind #:path #(make)) #((Expr #:kind #:path #(Y)))))))
+                  (Expr #:kind #:call (Expr #:kind #:path #(twice)) #((Expr #:kind #:lit 1))))
+                 (Expr #:kind #:field (Expr #:kind #:path #(ORIGIN)) z)))))
+             (Item #:ident peek #:kind #:defn (Generics)
+              (FnSig (FnDecl #() (FnRetTy (Ty #:kind #:path #(int)))))
+              (Block
+               (Stmt #:kind #:expr
+                (Expr #:kind #:binary #:op #:add
+                 (Expr #:kind #:binary #:op #:add
+                  (Expr #:kind #:field (Expr #:kind #:call (Expr #:kind #:path #(secret)) #()) a)
+                  (Expr #:kind #:field (Expr #:kind #:call (Expr #:kind #:path #(secret)) #()) b))
+                 (Expr #:kind #:field
+                  (Expr #:kind #:struct #(Secret)
+                   (Field a (Expr #:kind #:lit 1))
+                   (Field b (Expr #:kind #:lit 2)))
+                  a))))))
+        );
+        let hil = import_unit(items, rest.as_slice().unwrap(), &bui);
+        assert_eq!(take_errors(), Vec::<String>::new());
+        let bui_items = bui.items();
+        let def = |ident: &str| bui_items.iter().find(|x| x.ident() == ident).unwrap().def();
+        let (point, origin) = (def("Point"), def("ORIGIN"));
+        drop(bui_items);
+        let y = node_get_fields(hil.as_cons().unwrap())
+            .into_iter()
+            .map(|x| x.as_cons().unwrap())
+            .find(|x| node_find_attr(x, "ident").and_then(|x| x.as_symbol()) == Some("Y"))
+            .map(|x| node_get_attr(x, "def_id").as_u64().unwrap())
+            .map(|x| DefId::from_raw(0, u32::try_from(x).unwrap()));
+        let ctx = GlobalContext::new();
+        assert_eq!(ctx.load_unit(&UnitPath::new(vec!["lib".to_string()]), bui), 1);
+        ty_check(&ctx, &hil);
+        assert_eq!(
+            take_errors(),
+            vec![
+                "E0002 Mismatched types: expected int, found bool",
+                "E0011 No field `z` on type Point",
+                "E0017 The trait `Show` is not implemented for int",
+                "E0031 Member `b` of `Secret` is private",
+                "E0031 Member `b` of `Secret` is private",
+            ]
+        );
+        let fields = vec![ConstValue::Int(1), ConstValue::Int(2)];
+        let value = ConstValue::Adt(DefId::from_raw(1, point), fields);
+        assert!(ctx.const_value(&DefId::from_raw(1, origin)) == Some(value));
+        assert!(ctx.const_value(&y.unwrap()) == Some(ConstValue::Int(20)));
+    }
+
+    #[test]
+    fn test_interface_import() {
+        use crate::ast::to_hil;
+        use crate::context::GlobalContext;
+        use crate::error::take_errors;
+        use crate::hil::to_bui;
+        use crate::t::context::UnitPath;
+        use crate::t::ty_check;
+        let _serial = serial();
+        // pub interface ORD {
+        //   type t;
+        //   defn lt(a: t, b: t) -> bool;
+        // }
+        let items = sexp!(
+            #((Item #:ident ORD #:vis #:pub #:kind #:interface
+               (Signature
+                (Decl #:ident t #:kind #:ty)
+                (Decl #:ident lt #:kind #:defn (Generics)
+                 (FnSig
+                  (FnDecl
+                   #((Param a (Ty #:kind #:path #(t))) (Param b (Ty #:kind #:path #(t))))
+                   (FnRetTy (Ty #:kind #:path #(bool)))))))))
+        );
+        let items = items.as_slice().unwrap();
+        let lib_ctx = GlobalContext::new();
+        let lib_hil = to_hil(&unit_ast("lib", items));
+        ty_check(&lib_ctx, &lib_hil);
+        assert_eq!(take_errors(), Vec::<String>::new());
+        let bui = to_bui(&lib_hil, &lib_ctx).deserialize();
+        // pub interface INT_ORD {
+        //   include ORD with type t = int;
+        //   defn zero() -> t;
+        // }
+        // interface BAD {
+        //   include ORD with type u = int;
+        // }
+        // module IntOrd {
+        //   type t = int;
+        //   defn lt(a: int, b: int) -> bool { a < b }
+        //   defn zero() -> int { 0 }
+        // }
+        // module BoolOrd {
+        //   type t = bool;
+        //   defn lt(a: bool, b: bool) -> bool { a }
+        //   defn zero() -> bool { true }
+        // }
+        // functor Pos(X: INT_ORD) {
+        //   defn pos(a: int) -> bool { X::lt(X::zero(), a) }
+        // }
+        // module IntPos = Pos(IntOrd);
+        // module BoolPos = Pos(BoolOrd);
+        let rest = sexp!(
+            #((Item #:ident INT_ORD #:vis #:pub #:kind #:interface
+               (Signature
+                (Include (IfaceRef #(ORD) (With t (Ty #:kind #:path #(int)))))
+                (Decl #:ident zero #:kind #:defn (Generics)
+                 (FnSig (FnDecl #() (FnRetTy (Ty #:kind #:path #(t))))))))
+              (Item #:ident BAD #:kind #:interface
+               (Signature
+                (Include (IfaceRef #(ORD) (With u (Ty #:kind #:path #(int)))))))
+              (Item #:ident IntOrd #:kind #:module
+               (Structure
+                (Binding #:ident t #:vis #:pub #:kind #:ty (Ty #:kind #:path #(int)))
+                (Binding #:ident lt #:vis #:pub #:kind #:defn (Generics)
+                 (FnSig
+                  (FnDecl
+                   #((Param a (Ty #:kind #:path #(int))) (Param b (Ty #:kind #:path #(int))))
+                   (FnRetTy (Ty #:kind #:path #(bool)))))
+                 (Block
+                  (Stmt #:kind #:expr
+                   (Expr #:kind #:binary #:op #:lt (Expr #:kind #:path #(a)) (Expr #:kind #:path #(b))))))
+                (Binding #:ident zero #:vis #:pub #:kind #:defn (Generics)
+                 (FnSig (FnDecl #() (FnRetTy (Ty #:kind #:path #(int)))))
+                 (Block (Stmt #:kind #:expr (Expr #:kind #:lit 0))))))
+              (Item #:ident BoolOrd #:kind #:module
+               (Structure
+                (Binding #:ident t #:vis #:pub #:kind #:ty (Ty #:kind #:path #(bool)))
+                (Binding #:ident lt #:vis #:pub #:kind #:defn (Generics)
+                 (FnSig
+                  (FnDecl
+                   #((Param a (Ty #:kind #:path #(bool))) (Param b (Ty #:kind #:path #(bool))))
+                   (FnRetTy (Ty #:kind #:path #(bool)))))
+                 (Block (Stmt #:kind #:expr (Expr #:kind #:path #(a)))))
+                (Binding #:ident zero #:vis #:pub #:kind #:defn (Generics)
+                 (FnSig (FnDecl #() (FnRetTy (Ty #:kind #:path #(bool)))))
+                 (Block (Stmt #:kind #:expr (Expr #:kind #:lit #t))))))
+              (Item #:ident Pos #:kind #:functor
+               #((FunctorParam X (IfaceRef #(INT_ORD))))
+               (Structure
+                (Binding #:ident pos #:vis #:pub #:kind #:defn (Generics)
+                 (FnSig
+                  (FnDecl
+                   #((Param a (Ty #:kind #:path #(int))))
+                   (FnRetTy (Ty #:kind #:path #(bool)))))
+                 (Block
+                  (Stmt #:kind #:expr
+                   (Expr #:kind #:call (Expr #:kind #:path #(X lt))
+                    #((Expr #:kind #:call (Expr #:kind #:path #(X zero)) #())
+                      (Expr #:kind #:path #(a)))))))))
+              (Item #:ident IntPos #:kind #:apply (ModRef #(Pos)) (ModRef #(IntOrd)))
+              (Item #:ident BoolPos #:kind #:apply (ModRef #(Pos)) (ModRef #(BoolOrd))))
+        );
+        let hil = import_unit(items, rest.as_slice().unwrap(), &bui);
+        assert_eq!(take_errors(), Vec::<String>::new());
+        let ctx = GlobalContext::new();
+        assert_eq!(ctx.load_unit(&UnitPath::new(vec!["lib".to_string()]), bui), 1);
+        ty_check(&ctx, &hil);
+        assert_eq!(
+            take_errors(),
+            vec![
+                "E0029 `u` is not an opaque type of interface `ORD`",
+                "E0027 Member `t` has an incompatible type for interface `INT_ORD`: expected int, found bool",
+                "E0027 Member `lt` has an incompatible type for interface `INT_ORD`: expected fn(int, int) -> bool, found fn(bool, bool) -> bool",
+                "E0027 Member `zero` has an incompatible type for interface `INT_ORD`: expected fn() -> int, found fn() -> bool",
+            ]
+        );
+        // The included members keep the numbers of the imported unit.
+        let bui = to_bui(&hil, &ctx).deserialize();
+        let items = bui.items();
+        let int_ord = items
+            .iter()
+            .find(|x| x.ident() == "INT_ORD")
+            .and_then(|x| x.interface())
+            .unwrap();
+        let types = int_ord.types();
+        assert_eq!(types.len(), 1);
+        assert_eq!(types[0].0, "t");
+        assert!(types[0].1.transparent().unwrap().is_int());
+        assert_eq!(int_ord.type_defs()[0].unit(), 1);
+        let defns = int_ord.defns();
+        assert_eq!(defns.len(), 2);
+        assert_eq!(defns[0].0, "lt");
+        assert_eq!(defns[1].0, "zero");
+        assert_eq!(int_ord.defn_defs()[0].unit(), 1);
+        assert_eq!(int_ord.defn_defs()[1].unit(), 0);
+    }
+
+    #[test]
+    fn test_til_cfg() {
+        use crate::ast::to_hil;
+        use crate::context::GlobalContext;
+        use crate::error::take_errors;
+        use crate::hil::to_til;
+        use crate::t::ty_check;
+        let _serial = serial();
+        // class Point {
+        //   x: int,
+        //   y: int,
+        // }
+        // defn f(p: Point, n: int) -> int {
+        //   let k = 0;
+        //   while k < n && p.x > 0 {
+        //     k = k + 1;
+        //   }
+        //   let g = |d: int| d + k;
+        //   let q = Point { y: p.y, x: k };
+        //   if q.x > 0 { g(q.y) } else { return 0; }
+        // }
+        let int_ty = || sexp!((Ty #:kind #:path #(int)));
+        let (x_ty, y_ty, n_ty, ret_ty, d_ty) = (int_ty(), int_ty(), int_ty(), int_ty(), int_ty());
+        let ast = sexp!(
+            (Unit #:path #(test)
+             (Item #:ident Point #:kind #:class
+              (Variant (FieldDef x ,x_ty) (FieldDef y ,y_ty)))
+             (Item #:ident f #:kind #:defn (Generics)
+              (FnSig
+               (FnDecl
+                #((Param p (Ty #:kind #:path #(Point))) (Param n ,n_ty))
+                (FnRetTy ,ret_ty)))
+              (Block
+               (Stmt #:kind #:let (Local k (Expr #:kind #:lit 0)))
+               (Stmt #:kind #:semi
+                (Expr #:kind #:while
+                 (Expr #:kind #:binary #:op #:and
+                  (Expr #:kind #:binary #:op #:lt (Expr #:kind #:path #(k)) (Expr #:kind #:path #(n)))
+                  (Expr #:kind #:binary #:op #:gt
+                   (Expr #:kind #:field (Expr #:kind #:path #(p)) x)
+                   (Expr #:kind #:lit 0)))
+                 (Block
+                  (Stmt #:kind #:semi
+                   (Expr #:kind #:assign (Expr #:kind #:path #(k))
+                    (Expr #:kind #:binary #:op #:add (Expr #:kind #:path #(k)) (Expr #:kind #:lit 1)))))))
+               (Stmt #:kind #:let
+                (Local g
+                 (Expr #:kind #:closure (FnDecl #((Param d ,d_ty)))
+                  (Block
+                   (Stmt #:kind #:expr
+                    (Expr #:kind #:binary #:op #:add (Expr #:kind #:path #(d)) (Expr #:kind #:path #(k))))))))
+               (Stmt #:kind #:let
+                (Local q
+                 (Expr #:kind #:struct #(Point)
+                  (Field y (Expr #:kind #:field (Expr #:kind #:path #(p)) y))
+                  (Field x (Expr #:kind #:path #(k))))))
+               (Stmt #:kind #:expr
+                (Expr #:kind #:if
+                 (Expr #:kind #:binary #:op #:gt
+                  (Expr #:kind #:field (Expr #:kind #:path #(q)) x)
+                  (Expr #:kind #:lit 0))
+                 (Block
+                  (Stmt #:kind #:expr
+                   (Expr #:kind #:call (Expr #:kind #:path #(g))
+                    #((Expr #:kind #:field (Expr #:kind #:path #(q)) y)))))
+                 (Block
+                  (Stmt #:kind #:semi (Expr #:kind #:return (Expr #:kind #:lit 0))))))))));
+        let hil = to_hil(&ast);
+        let ctx = GlobalContext::new();
+        let tctx = ty_check(&ctx, &hil);
+        assert_eq!(take_errors(), Vec::<String>::new());
+        let til = to_til(&hil, &ctx, &tctx);
+        assert_eq!(til.bodies.len(), 2);
+        assert_eq!(
+            til.to_string().lines().collect::<Vec<_>>(),
+            vec![
+                "// body0",
+                "closure in DefId(0, 4)(_1: int, _2: int) -> int {",
+                "    bb0: {",
+                "        _0 = add(_2, _1);",
+                "        return;",
+                "    }",
+                "}",
+                "",
+                "// body1",
+                "fn DefId(0, 4)(_1: Point, _2: int) -> int {",
+                "    let _3: int; // k",
+                "    let _4: ();",
+                "    let _5: bool;",
+                "    let _6: bool;",
+                "    let _7: ();",
+                "    let _8: ();",
+                "    let _9: fn(int) -> int; // g",
+                "    let _10: Point; // q",
+                "    let _11: bool;",
+                "    let _12: !;",
+                "    bb0: {",
+                "        _3 = const 0;",
+                "        goto bb1;",
+                "    }",
+                "    bb1: {",
+                "        _6 = lt(_3, _2);",
+                "        branch _6 [bb3, bb4];",
+                "    }",
+                "    bb2: {",
+                "        _4 = const ();",
+                "        _9 = closure body0 [_3];",
+                "        _10 = DefId(0, 0) { _3, _1.1 };",
+                "        _11 = gt(_10.0, const 0);",
+                "        branch _11 [bb7, bb8];",
+                "    }",
+                "    bb3: {",
+                "        _5 = gt(_1.0, const 0);",
+                "        goto bb5;",
+                "    }",
+                "    bb4: {",
+                "        _5 = const false;",
+                "        goto bb5;",
+                "    }",
+                "    bb5: {",
+                "        branch _5 [bb6, bb2];",
+                "    }",
+                "    bb6: {",
+                "        _3 = add(_3, const 1);",
+                "        _8 = const ();",
+                "        _7 = const ();",
+                "        goto bb1;",
+                "    }",
+                "    bb7: {",
+                "        _0 = call _9(_10.1) -> bb10;",
+                "    }",
+                "    bb8: {",
+                "        _0 = const 0;",
+                "        return;",
+                "    }",
+                "    bb9: {",
+                "        return;",
+                "    }",
+                "    bb10: {",
+                "        goto bb9;",
+                "    }",
+                "    bb11: {",
+                "        goto bb9;",
+                "    }",
+                "}",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_interp() {
+        use std::rc::Rc;
+        use crate::ast::to_hil;
+        use crate::context::GlobalContext;
+        use crate::error::take_errors;
+        use crate::hil::to_til;
+        use crate::t::ty_check;
+        use crate::til::interp::{self, Interpreter};
+        use crate::til::BodyTy;
+        let _serial = serial();
+        // defn fact(n: int) -> int {
+        //   if n <= 1 { 1 } else { n * fact(n - 1) }
+        // }
+        // defn div(a: int, b: int) -> int { a / b }
+        // defn ratio(n: int) -> int { div(100, n) }
+        // trait Show {
+        //   defn show(self: Self) -> int;
+        // }
+        // class Point { x: int, y: int }
+        // impl Show for Point {
+        //   defn show(self: Point) -> int { self.x * 10 + self.y }
+        // }
+        // defn twice<T: Show>(v: T) -> int { v.show() + v.show() }
+        // defn main(k: int) -> int {
+        //   let p = Point { x: 1, y: 2 };
+        //   let add_k = |z: int| z + k;
+        //   add_k(twice(p)) + fact(5)
+        // }
+        // defn spin() -> int { loop {} }
+        let int_ty = || sexp!((Ty #:kind #:path #(int)));
+        let int_param = |name: &str| {
+            let name = Value::symbol(name);
+            let ty = int_ty();
+            sexp!((Param ,name ,ty))
+        };
+        let (n, a, b, ratio_n, k, z) = (
+            int_param("n"),
+            int_param("a"),
+            int_param("b"),
+            int_param("n"),
+            int_param("k"),
+            int_param("z"),
+        );
+        let (x_ty, y_ty) = (int_ty(), int_ty());
+        let ast = sexp!(
+            (Unit #:path #(test)
+             (Item #:ident fact #:kind #:defn (Generics)
+              (FnSig (FnDecl #(,n) (FnRetTy (Ty #:kind #:path #(int)))))
+              (Block
+               (Stmt #:kind #:expr
+                (Expr #:kind #:if
+                 (Expr #:kind #:binary #:op #:le (Expr #:kind #:path #(n)) (Expr #:kind #:lit 1))
+                 (Block (Stmt #:kind #:expr (Expr #:kind #:lit 1)))
+                 (Block
+                  (Stmt #:kind #:expr
+                   (Expr #:kind #:binary #:op #:mul
+                    (Expr #:kind #:path #(n))
+                    (Expr #:kind #:call (Expr #:kind #:path #(fact))
+                     #((Expr #:kind #:binary #:op #:sub
+                        (Expr #:kind #:path #(n))
+                        (Expr #:kind #:lit 1)))))))))))
+             (Item #:ident div #:kind #:defn (Generics)
+              (FnSig (FnDecl #(,a ,b) (FnRetTy (Ty #:kind #:path #(int)))))
+              (Block
+               (Stmt #:kind #:expr
+                (Expr #:kind #:binary #:op #:div (Expr #:kind #:path #(a)) (Expr #:kind #:path #(b))))))
+             (Item #:ident ratio #:kind #:defn (Generics)
+              (FnSig (FnDecl #(,ratio_n) (FnRetTy (Ty #:kind #:path #(int)))))
+              (Block
+               (Stmt #:kind #:expr
+                (Expr #:kind #:call (Expr #:kind #:path #(div))
+                 #((Expr #:kind #:lit 100) (Expr #:kind #:path #(n)))))))
+             (Item #:ident Show #:kind #:trait
+              (Signature
+               (Decl #:ident show #:kind #:defn (Generics)
+                (FnSig
+                 (FnDecl #((Param self (Ty #:kind #:path #(Self)))) (FnRetTy (Ty #:kind #:path #(int))))))))
+             (Item #:ident Point #:kind #:class
+              (Variant (FieldDef x ,x_ty) (FieldDef y ,y_ty)))
+             (Item #:kind #:impl (TraitRef #(Show)) (Ty #:kind #:path #(Point))
+              (Structure
+               (Binding #:ident show #:kind #:defn (Generics)
+                (FnSig
+                 (FnDecl #((Param self (Ty #:kind #:path #(Point)))) (FnRetTy (Ty #:kind #:path #(int)))))
+                (Block
+                 (Stmt #:kind #:expr
+                  (Expr #:kind #:binary #:op #:add
+                   (Expr #:kind #:binary #:op #:mul
+                    (Expr #:kind #:field (Expr #:kind #:path #(self)) x)
+                    (Expr #:kind #:lit 10))
+                   (Expr #:kind #:field (Expr #:kind #:path #(self)) y)))))))
+             (Item #:ident twice #:kind #:defn (Generics (GenericParam T (TraitRef #(Show))))
+              (FnSig
+               (FnDecl #((Param v (Ty #:kind #:path #(T)))) (FnRetTy (Ty #:kind #:path #(int)))))
+              (Block
+               (Stmt #:kind #:expr
+                (Expr #:kind #:binary #:op #:add
+                 (Expr #:kind #:method_call (Expr #:kind #:path #(v)) show #())
+                 (Expr #:kind #:method_call (Expr #:kind #:path #(v)) show #())))))
+             (Item #:ident main #:kind #:defn (Generics)
+              (FnSig (FnDecl #(,k) (FnRetTy (Ty #:kind #:path #(int)))))
+              (Block
+               (Stmt #:kind #:let
+                (Local p
+                 (Expr #:kind #:struct #(Point)
+                  (Field x (Expr #:kind #:lit 1))
+                  (Field y (Expr #:kind #:lit 2)))))
+               (Stmt #:kind #:let
+                (Local add_k
+                 (Expr #:kind #:closure (FnDecl #(,z))
+                  (Block
+                   (Stmt #:kind #:expr
+                    (Expr #:kind #:binary #:op #:add (Expr #:kind #:path #(z)) (Expr #:kind #:path #(k))))))))
+               (Stmt #:kind #:expr
+                (Expr #:kind #:binary #:op #:add
+                 (Expr #:kind #:call (Expr #:kind #:path #(add_k))
+                  #((Expr #:kind #:call (Expr #:kind #:path #(twice)) #((Expr #:kind #:path #(p))))))
+                 (Expr #:kind #:call (Expr #:kind #:path #(fact)) #((Expr #:kind #:lit 5)))))))
+             (Item #:ident spin #:kind #:defn (Generics)
+              (FnSig (FnDecl #() (FnRetTy (Ty #:kind #:path #(int)))))
+              (Block (Stmt #:kind #:expr (Expr #:kind #:loop (Block)))))));
+        let hil = to_hil(&ast);
+        let ctx = GlobalContext::new();
+        let tctx = ty_check(&ctx, &hil);
+        assert_eq!(take_errors(), Vec::<String>::new());
+        let til = Rc::new(to_til(&hil, &ctx, &tctx));
+        ctx.register_til(til.clone());
+        let def_id = |name: &str| match &til.bodies.iter().find(|x| x.name == name).unwrap().ty {
+            BodyTy::Fn(def_id) => def_id.clone(),
+            BodyTy::Closure { .. } => unreachable!(),
+        };
+        let mut interpreter = Interpreter::new(&ctx);
+        let result = interpreter.call(&def_id("fact"), vec![interp::Value::Int(10)]).ok().unwrap();
+        assert_eq!(result.as_int(), Some(3628800));
+        let result = interpreter.call(&def_id("main"), vec![interp::Value::Int(3)]).ok().unwrap();
+        assert_eq!(result.as_int(), Some(147));
+        let result = interpreter.call(&def_id("ratio"), vec![interp::Value::Int(7)]).ok().unwrap();
+        assert_eq!(result.as_int(), Some(14));
+        let error = interpreter.call(&def_id("ratio"), vec![interp::Value::Int(0)]).err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "Runtime error: attempt to divide by zero\n  in div\n  in ratio"
+        );
+        interpreter.set_step_limit(1000);
+        let error = interpreter.call(&def_id("spin"), vec![]).err().unwrap();
+        assert_eq!(error.message, "step limit of 1000 exceeded");
+        interpreter.set_frame_limit(50);
+        let error = interpreter.call(&def_id("fact"), vec![interp::Value::Int(100)]).err().unwrap();
+        assert_eq!(error.message, "frame limit of 50 exceeded");
+        assert_eq!(error.backtrace.len(), 50);
+    }
+
+    #[test]
+    fn test_interp_units() {
+        use std::rc::Rc;
+        use crate::ast::to_hil;
+        use crate::context::GlobalContext;
+        use crate::error::take_errors;
+        use crate::hil::{to_bui, to_til};
+        use crate::t::context::UnitPath;
+        use crate::t::ty_check;
+        use crate::til::interp::Interpreter;
+        use crate::til::BodyTy;
+        let _serial = serial();
+        // defn double(x: int) -> int { x * 2 }
+        // pub class Pair { pub a: int, pub b: int }
+        // pub defn make(a: int) -> Pair {
+        //   let f = |y: int| double(y);
+        //   Pair { a: f(a), b: a }
+        // }
+        // pub defn sum(p: Pair) -> int { p.a + p.b }
+        let items = sexp!(
+            #((Item #:ident double #:kind #:defn (Generics)
+               (FnSig
+                (FnDecl #((Param x (Ty #:kind #:path #(int)))) (FnRetTy (Ty #:kind #:path #(int)))))
+               (Block
+                (Stmt #:kind #:expr
+                 (Expr #:kind #:binary #:op #:mul (Expr #:kind #:path #(x)) (Expr #:kind #:lit 2)))))
+              (Item #:ident Pair #:vis #:pub #:kind #:class
+               (Variant
+                (FieldDef #:vis #:pub a (Ty #:kind #:path #(int)))
+                (FieldDef #:vis #:pub b (Ty #:kind #:path #(int)))))
+              (Item #:ident make #:vis #:pub #:kind #:defn (Generics)
+               (FnSig
+                (FnDecl #((Param a (Ty #:kind #:path #(int)))) (FnRetTy (Ty #:kind #:path #(Pair)))))
+               (Block
+                (Stmt #:kind #:let
+                 (Local f
+                  (Expr #:kind #:closure (FnDecl #((Param y (Ty #:kind #:path #(int)))))
+                   (Block
+                    (Stmt #:kind #:expr
+                     (Expr #:kind #:call (Expr #:kind #:path #(double)) #((Expr #:kind #:path #(y)))))))))
+                (Stmt #:kind #:expr
+                 (Expr #:kind #:struct #(Pair)
+                  (Field a (Expr #:kind #:call (Expr #:kind #:path #(f)) #((Expr #:kind #:path #(a)))))
+                  (Field b (Expr #:kind #:path #(a)))))))
+              (Item #:ident sum #:vis #:pub #:kind #:defn (Generics)
+               (FnSig
+                (FnDecl #((Param p (Ty #:kind #:path #(Pair)))) (FnRetTy (Ty #:kind #:path #(int)))))
+               (Block
+                (Stmt #:kind #:expr
+                 (Expr #:kind #:binary #:op #:add
+                  (Expr #:kind #:field (Expr #:kind #:path #(p)) a)
+                  (Expr #:kind #:field (Expr #:kind #:path #(p)) b))))))
+        );
+        let items = items.as_slice().unwrap();
+        // Both units are compiled in the same context, `lib` first.
+        let ctx = GlobalContext::new();
+        let lib_hil = to_hil(&unit_ast("lib", items));
+        let lib_tctx = ty_check(&ctx, &lib_hil);
+        let lib_til = to_til(&lib_hil, &ctx, &lib_tctx);
+        assert_eq!(take_errors(), Vec::<String>::new());
+        let message = to_bui(&lib_hil, &ctx);
+        let path = UnitPath::new(vec!["lib".to_string()]);
+        let unit_num = ctx.load_unit(&path, message.deserialize());
+        assert_eq!(unit_num, 1);
+        ctx.register_unit_til(unit_num, message.numbers(), &lib_til);
+        // defn main() -> int { sum(make(3)) + make(4).a }
+        let rest = sexp!(
+            #((Item #:ident main #:kind #:defn (Generics)
+               (FnSig (FnDecl #() (FnRetTy (Ty #:kind #:path #(int)))))
+               (Block
+                (Stmt #:kind #:expr
+                 (Expr #:kind #:binary #:op #:add
+                  (Expr #:kind #:call (Expr #:kind #:path #(sum))
+                   #((Expr #:kind #:call (Expr #:kind #:path #(make)) #((Expr #:kind #:lit 3)))))
+                  (Expr #:kind #:field
+                   (Expr #:kind #:call (Expr #:kind #:path #(make)) #((Expr #:kind #:lit 4)))
+                   a))))))
+        );
+        let hil = import_unit(items, rest.as_slice().unwrap(), &message.deserialize());
+        let tctx = ty_check(&ctx, &hil);
+        let til = Rc::new(to_til(&hil, &ctx, &tctx));
+        assert_eq!(take_errors(), Vec::<String>::new());
+        ctx.register_til(til.clone());
+        let main = match &til.bodies.iter().find(|x| x.name == "main").unwrap().ty {
+            BodyTy::Fn(def_id) => def_id.clone(),
+            BodyTy::Closure { .. } => unreachable!(),
+        };
+        let mut interpreter = Interpreter::new(&ctx);
+        let result = interpreter.call(&main, vec![]).ok().unwrap();
+        assert_eq!(result.as_int(), Some(17));
+    }
+
+    fn get_codegen_ast() -> Value {
+        // class Point { x: int, y: int }
+        // pub defn fact(n: int) -> int {
+        //   if n <= 1 { 1 } else { n * fact(n - 1) }
+        // }
+        // defn norm(p: Point) -> int { p.x * 10 + p.y }
+        // defn div(a: int, b: int) -> int { a / b }
+        // defn id<T>(v: T) -> T { v }
+        // defn main() -> int {
+        //   let k = 3;
+        //   let t = (Point { x: 1, y: 2 }, k);
+        //   let add_k = |z: int| z + t.1;
+        //   let f = fact;
+        //   add_k(norm(t.0)) + f(5) + f(3)
+        // }
+        // defn crash() -> int { div(1, 0) }
+        let int_ty = || sexp!((Ty #:kind #:path #(int)));
+        let int_param = |name: &str| {
+            let name = Value::symbol(name);
+            let ty = int_ty();
+            sexp!((Param ,name ,ty))
+        };
+        let (n, a, b, z) = (int_param("n"), int_param("a"), int_param("b"), int_param("z"));
+        let (x_ty, y_ty) = (int_ty(), int_ty());
+        sexp!(
+            (Unit #:path #(test)
+             (Item #:ident Point #:kind #:class
+              (Variant (FieldDef x ,x_ty) (FieldDef y ,y_ty)))
+             (Item #:ident fact #:vis #:pub #:kind #:defn (Generics)
+              (FnSig (FnDecl #(,n) (FnRetTy (Ty #:kind #:path #(int)))))
+              (Block
+               (Stmt #:kind #:expr
+                (Expr #:kind #:if
+                 (Expr #:kind #:binary #:op #:le (Expr #:kind #:path #(n)) (Expr #:kind #:lit 1))
+                 (Block (Stmt #:kind #:expr (Expr #:kind #:lit 1)))
+                 (Block
+                  (Stmt #:kind #:expr
+                   (Expr #:kind #:binary #:op #:mul
+                    (Expr #:kind #:path #(n))
+                    (Expr #:kind #:call (Expr #:kind #:path #(fact))
+                     #((Expr #:kind #:binary #:op #:sub
+                        (Expr #:kind #:path #(n))
+                        (Expr #:kind #:lit 1)))))))))))
+             (Item #:ident norm #:kind #:defn (Generics)
+              (FnSig
+               (FnDecl #((Param p (Ty #:kind #:path #(Point)))) (FnRetTy (Ty #:kind #:path #(int)))))
+              (Block
+               (Stmt #:kind #:expr
+                (Expr #:kind #:binary #:op #:add
+                 (Expr #:kind #:binary #:op #:mul
+                  (Expr #:kind #:field (Expr #:kind #:path #(p)) x)
+                  (Expr #:kind #:lit 10))
+                 (Expr #:kind #:field (Expr #:kind #:path #(p)) y)))))
+             (Item #:ident div #:kind #:defn (Generics)
+              (FnSig (FnDecl #(,a ,b) (FnRetTy (Ty #:kind #:path #(int)))))
+              (Block
+               (Stmt #:kind #:expr
+                (Expr #:kind #:binary #:op #:div (Expr #:kind #:path #(a)) (Expr #:kind #:path #(b))))))
+             (Item #:ident id #:kind #:defn (Generics (GenericParam T))
+              (FnSig
+               (FnDecl #((Param v (Ty #:kind #:path #(T)))) (FnRetTy (Ty #:kind #:path #(T)))))
+              (Block (Stmt #:kind #:expr (Expr #:kind #:path #(v)))))
+             (Item #:ident main #:kind #:defn (Generics)
+              (FnSig (FnDecl #() (FnRetTy (Ty #:kind #:path #(int)))))
+              (Block
+               (Stmt #:kind #:let (Local k (Expr #:kind #:lit 3)))
+               (Stmt #:kind #:let
+                (Local t
+                 (Expr #:kind #:tuple
+                  #((Expr #:kind #:struct #(Point)
+                     (Field x (Expr #:kind #:lit 1))
+                     (Field y (Expr #:kind #:lit 2)))
+                    (Expr #:kind #:path #(k))))))
+               (Stmt #:kind #:let
+                (Local add_k
+                 (Expr #:kind #:closure (FnDecl #(,z))
+                  (Block
+                   (Stmt #:kind #:expr
+                    (Expr #:kind #:binary #:op #:add
+                     (Expr #:kind #:path #(z))
+                     (Expr #:kind #:field (Expr #:kind #:path #(t)) 1)))))))
+               (Stmt #:kind #:let (Local f (Expr #:kind #:path #(fact))))
+               (Stmt #:kind #:expr
+                (Expr #:kind #:binary #:op #:add
+                 (Expr #:kind #:binary #:op #:add
+                  (Expr #:kind #:call (Expr #:kind #:path #(add_k))
+                   #((Expr #:kind #:call (Expr #:kind #:path #(norm))
+                      #((Expr #:kind #:field (Expr #:kind #:path #(t)) 0)))))
+                  (Expr #:kind #:call (Expr #:kind #:path #(f)) #((Expr #:kind #:lit 5))))
+                 (Expr #:kind #:call (Expr #:kind #:path #(f)) #((Expr #:kind #:lit 3)))))))
+             (Item #:ident crash #:kind #:defn (Generics)
+              (FnSig (FnDecl #() (FnRetTy (Ty #:kind #:path #(int)))))
+              (Block
+               (Stmt #:kind #:expr
+                (Expr #:kind #:call (Expr #:kind #:path #(div))
+                 #((Expr #:kind #:lit 1) (Expr #:kind #:lit 0))))))))
+    }
+
+    // Makes the recursive call of `fact` in `til` call the `fact` of the
+    // unit whose BUI is `bui`, imported as unit 1. The codegen unit, built
+    // as `lib`, provides `fact` to itself built as `test`.
+    fn import_fact(til: &mut crate::til::Til, bui: &crate::bui::Bui) {
+        use crate::def::DefId;
+        use crate::til::{Operand, Terminator};
+        let offset = bui.items().into_iter().find(|x| x.ident() == "fact").unwrap().def();
+        let foreign = DefId::from_raw(1, offset);
+        let body = til.bodies.iter_mut().find(|x| x.name == "fact").unwrap();
+        for block in &mut body.blocks {
+            if let Terminator::Call { func, .. } = &mut block.terminator {
+                *func = Operand::Fn(foreign.clone());
+            }
+        }
+    }
+
+    #[test]
+    fn test_c_codegen() {
+        use std::process::Command;
+        use crate::ast::to_hil;
+        use crate::codegen::c::to_c;
+        use crate::codegen::Import;
+        use crate::context::GlobalContext;
+        use crate::def::DefId;
+        use crate::error::take_errors;
+        use crate::hil::{to_bui, to_til};
+        use crate::t::context::UnitPath;
+        use crate::t::ty_check;
+        use crate::til::BodyTy;
+        let _serial = serial();
+        let ast = get_codegen_ast();
+        let hil = to_hil(&ast);
+        let ctx = GlobalContext::new();
+        let tctx = ty_check(&ctx, &hil);
+        assert_eq!(take_errors(), Vec::<String>::new());
+        let bui = to_bui(&hil, &ctx).deserialize();
+        let mut til = to_til(&hil, &ctx, &tctx);
+        let def_id = |name: &str| match &til.bodies.iter().find(|x| x.name == name).unwrap().ty {
+            BodyTy::Fn(def_id) => def_id.clone(),
+            BodyTy::Closure { .. } => unreachable!(),
+        };
+        let (main, crash) = (def_id("main"), def_id("crash"));
+        til.unit_path = vec!["lib".to_string()];
+        let lib = to_c(&til, &ctx, &[], None);
+        take_errors();
+        til.unit_path = vec!["test".to_string()];
+        import_fact(&mut til, &bui);
+        let path = UnitPath::new(vec!["lib".to_string()]);
+        let imports = [Import {
+            unit_num: 1,
+            path: &path,
+            bui: &bui,
+        }];
+        let run = |entry: &DefId| {
+            let source = to_c(&til, &ctx, &imports, Some(entry));
+            let name = format!("ctype_c_{}_{}", std::process::id(), entry.local());
+            let dir = std::env::temp_dir().join(name);
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(dir.join("main.c"), &source).unwrap();
+            std::fs::write(dir.join("lib.c"), &lib).unwrap();
+            let status = Command::new("cc")
+                .args(["-std=c99", "-pedantic-errors", "-Werror", "-o", "out", "main.c", "lib.c"])
+                .current_dir(&dir)
+                .status()
+                .unwrap();
+            assert!(status.success(), "{}", source);
+            let output = Command::new(dir.join("out")).output().unwrap();
+            std::fs::remove_dir_all(&dir).unwrap();
+            (source, output)
+        };
+        let (source, output) = run(&main);
+        assert_eq!(
+            take_errors(),
+            vec!["E0035 Generic defn `id` is not supported by the C backend"]
+        );
+        assert!(source.contains("\nextern int64_t ctN3lib4factE(int64_t);"));
+        assert!(source.contains("\nint64_t ctN4test4factE(int64_t _1);"));
+        assert!(source.contains("ctN3lib4factE(_"));
+        assert!(source.contains("\nstatic int64_t ctN4test4mainE(void);"));
+        assert!(source.contains("} ctN4test5PointE;"));
+        assert!(lib.contains("\nint64_t ctN3lib4factE(int64_t _1);"));
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "141\n");
+        let (_, output) = run(&crash);
+        take_errors();
+        assert_eq!(output.status.code(), Some(101));
+        assert_eq!(
+            String::from_utf8(output.stderr).unwrap(),
+            "panic: attempt to divide by zero\n"
+        );
+    }
+
+    #[test]
+    fn test_wat_codegen() {
+        use crate::ast::to_hil;
+        use crate::codegen::wat::to_wat;
+        use crate::codegen::wat::verify::verify;
+        use crate::codegen::Import;
+        use crate::context::GlobalContext;
+        use crate::error::take_errors;
+        use crate::hil::{to_bui, to_til};
+        use crate::t::context::UnitPath;
+        use crate::t::ty_check;
+        let _serial = serial();
+        let hil = to_hil(&get_codegen_ast());
+        let ctx = GlobalContext::new();
+        let tctx = ty_check(&ctx, &hil);
+        assert_eq!(take_errors(), Vec::<String>::new());
+        let bui = to_bui(&hil, &ctx).deserialize();
+        let mut til = to_til(&hil, &ctx, &tctx);
+        import_fact(&mut til, &bui);
+        let path = UnitPath::new(vec!["lib".to_string()]);
+        let imports = [Import {
+            unit_num: 1,
+            path: &path,
+            bui: &bui,
+        }];
+        let wat = to_wat(&til, &ctx, &imports);
+        assert_eq!(
+            take_errors(),
+            vec!["E0035 Generic defn `id` is not supported by the WebAssembly backend"]
+        );
+        assert_eq!(verify(&wat), Ok(()));
+        let import = "\n  (import \"ct\" \"ctN3lib4factE\" (func $ctN3lib4factE (param i64) (result i64)))";
+        assert!(wat.contains(import));
+        assert!(wat.contains("call $ctN3lib4factE\n"));
+        let fact = "\n  (func $ctN4test4factE (export \"ctN4test4factE\") (param $_1 i64)";
+        assert!(wat.contains(fact));
+        assert!(wat.contains("\n  (func $ctN4test4mainE (result i64)\n"));
+        assert!(wat.contains("\n  (elem (i32.const 0) "));
+        let broken = wat.replace("call $ctN4test4normE", "call $ctN4test4factE");
+        assert_eq!(
+            verify(&broken),
+            Err("In function $ctN4test4mainE: `call`: Expected i64, found i32".to_string())
+        );
+        let broken = wat.replace("call $ct_add\n", "call $ct_sum\n");
+        assert!(verify(&broken).unwrap_err().ends_with("`call`: Unknown function $ct_sum"));
+        let broken = wat.replacen("    local.get $r)\n", "    local.get $r\n    drop)\n", 1);
+        assert_eq!(
+            verify(&broken),
+            Err("In function $ct_add: Expected i64 but the stack is empty".to_string())
+        );
+    }
+
+    #[test]
+    fn test_llvm_codegen() {
+        use std::process::Command;
+        use crate::ast::to_hil;
+        use crate::codegen::llvm::to_llvm;
+        use crate::codegen::Import;
+        use crate::context::GlobalContext;
+        use crate::def::DefId;
+        use crate::error::take_errors;
+        use crate::hil::{to_bui, to_til};
+        use crate::t::context::UnitPath;
+        use crate::t::ty_check;
+        use crate::til::BodyTy;
+        let _serial = serial();
+        let hil = to_hil(&get_codegen_ast());
+        let ctx = GlobalContext::new();
+        let tctx = ty_check(&ctx, &hil);
+        assert_eq!(take_errors(), Vec::<String>::new());
+        let bui = to_bui(&hil, &ctx).deserialize();
+        let mut til = to_til(&hil, &ctx, &tctx);
+        let def_id = |name: &str| {
+            let body = til.bodies.iter().find(|x| x.name == name).unwrap();
+            match &body.ty {
+                BodyTy::Fn(def_id) => def_id.clone(),
+                BodyTy::Closure { .. } => unreachable!(),
+            }
+        };
+        let (main, crash) = (def_id("main"), def_id("crash"));
+        til.unit_path = vec!["lib".to_string()];
+        let lib = to_llvm(&til, &ctx, &[], None);
+        take_errors();
+        til.unit_path = vec!["test".to_string()];
+        import_fact(&mut til, &bui);
+        let path = UnitPath::new(vec!["lib".to_string()]);
+        let imports = [Import {
+            unit_num: 1,
+            path: &path,
+            bui: &bui,
+        }];
+        let run = |entry: &DefId| {
+            let source = to_llvm(&til, &ctx, &imports, Some(entry));
+            let version = match Command::new("llc").arg("--version").output() {
+                Ok(output) => String::from_utf8(output.stdout).unwrap(),
+                Err(_) => return (source, None),
+            };
+            let major = version
+                .split("LLVM version ")
+                .nth(1)
+                .and_then(|x| x.split('.').next())
+                .and_then(|x| x.parse::<u32>().ok())
+                .unwrap();
+            let name = format!("ctype_llvm_{}_{}", std::process::id(), entry.local());
+            let dir = std::env::temp_dir().join(name);
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(dir.join("main.ll"), &source).unwrap();
+            std::fs::write(dir.join("lib.ll"), &lib).unwrap();
+            for unit in ["main", "lib"] {
+                let mut llc = Command::new("llc");
+                if major < 15 {
+                    llc.arg("-opaque-pointers");
+                }
+                let status = llc
+                    .args(["-relocation-model=pic", "-filetype=obj", &format!("{}.ll", unit)])
+                    .current_dir(&dir)
+                    .status()
+                    .unwrap();
+                assert!(status.success(), "{}", source);
+            }
+            let status = Command::new("cc")
+                .args(["-o", "out", "main.o", "lib.o"])
+                .current_dir(&dir)
+                .status()
+                .unwrap();
+            assert!(status.success());
+            let output = Command::new(dir.join("out")).output().unwrap();
+            std::fs::remove_dir_all(&dir).unwrap();
+            (source, Some(output))
+        };
+        let (source, output) = run(&main);
+        assert_eq!(
+            take_errors(),
+            vec!["E0035 Generic defn `id` is not supported by the LLVM backend"]
+        );
+        assert!(source.contains("\n%ctN4test5PointE = type { i64, i64 }\n"));
+        assert!(source.contains("\ndeclare i64 @ctN3lib4factE(i64)\n"));
+        assert!(source.contains("\ndefine i64 @ctN4test4factE(i64 %a1) {\n"));
+        assert!(source.contains("call i64 @ctN3lib4factE(i64 %t"));
+        assert!(source.contains("\ndefine internal i64 @ctN4test4mainE() {\n"));
+        assert!(lib.contains("\ndefine i64 @ctN3lib4factE(i64 %a1) {\n"));
+        if let Some(output) = output {
+            assert!(output.status.success());
+            assert_eq!(String::from_utf8(output.stdout).unwrap(), "141\n");
+        }
+        let (_, output) = run(&crash);
+        take_errors();
+        if let Some(output) = output {
+            assert_eq!(output.status.code(), Some(101));
+            assert_eq!(
+                String::from_utf8(output.stderr).unwrap(),
+                "panic: attempt to divide by zero\n"
+            );
+        }
+    }
+
+    #[test]
+    fn test_bytecode_vm() {
+        use crate::ast::to_hil;
+        use crate::bytecode::vm::{Value, Vm};
+        use crate::bytecode::{to_bytecode, Constant, Function, FunctionKind, Instr, Unit};
+        use crate::context::GlobalContext;
+        use crate::def::DefId;
+        use crate::error::take_errors;
+        use crate::hil::to_til;
+        use crate::op::BinOp;
+        use crate::t::ty_check;
+        use crate::til::BodyTy;
+        let _serial = serial();
+        let hil = to_hil(&get_codegen_ast());
+        let ctx = GlobalContext::new();
+        let tctx = ty_check(&ctx, &hil);
+        assert_eq!(take_errors(), Vec::<String>::new());
+        let til = to_til(&hil, &ctx, &tctx);
+        let def_id = |name: &str| match &til.bodies.iter().find(|x| x.name == name).unwrap().ty {
+            BodyTy::Fn(def_id) => def_id.clone(),
+            BodyTy::Closure { .. } => unreachable!(),
+        };
+        // Generic defns need no monomorphization in the VM.
+        let compiled = to_bytecode(&til, &ctx);
+        assert_eq!(take_errors(), Vec::<String>::new());
+        let unit = Unit::deserialize(&compiled.serialize()).unwrap();
+        assert!(unit == compiled);
+        assert_eq!(std::mem::size_of::<Value>(), 16);
+        let fact = unit.function(&def_id("fact")).unwrap();
+        let disassembly = unit.functions[fact as usize].disassemble();
+        assert!(disassembly.contains(" jump_if_not "), "{}", disassembly);
+        assert!(disassembly.contains(&format!(" fn {}\n", fact)), "{}", disassembly);
+        let mut vm = Vm::new(&unit);
+        let result = vm.call(&def_id("main"), vec![]).ok().unwrap();
+        assert_eq!(result.as_int(), Some(141));
+        let result = vm.call(&def_id("fact"), vec![Value::Int(20)]).ok().unwrap();
+        assert_eq!(result.as_int(), Some(2432902008176640000));
+        let err = vm.call(&def_id("fact"), vec![Value::Int(21)]).err().unwrap();
+        assert_eq!(err.message, "attempt to multiply with overflow");
+        let err = vm.call(&def_id("crash"), vec![]).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "Runtime error: attempt to divide by zero\n  in div\n  in crash"
+        );
+        vm.set_frame_limit(10);
+        let err = vm.call(&def_id("fact"), vec![Value::Int(20)]).err().unwrap();
+        assert_eq!(err.message, "frame limit of 10 exceeded");
+        assert_eq!(err.backtrace.len(), 10);
+        vm.set_step_limit(20);
+        let err = vm.call(&def_id("main"), vec![]).err().unwrap();
+        assert_eq!(err.message, "step limit of 20 exceeded");
+        let result = vm.call(&def_id("id"), vec![Value::Int(1)]).ok().unwrap();
+        assert_eq!(result.as_int(), Some(1));
+        let err = vm.call(&def_id("fact"), vec![]).err().unwrap();
+        assert_eq!(err.message, "fact takes 1 arguments but 0 were given");
+        // Units whose indices are out of range are rejected when loaded.
+        let corrupt = |instr: Instr| {
+            let mut unit = compiled.clone();
+            let FunctionKind::Code { instructions, .. } = &mut unit.functions[fact as usize].kind
+            else {
+                unreachable!();
+            };
+            instr.encode(instructions);
+            Unit::deserialize(&unit.serialize()).err().unwrap().description
+        };
+        let constants = compiled.constants.len() as u32;
+        let functions = compiled.functions.len() as u32;
+        let classes = compiled.classes.len() as u32;
+        assert_eq!(
+            corrupt(Instr::Const(constants)),
+            format!("Constant {} is out of range in fact", constants)
+        );
+        assert_eq!(
+            corrupt(Instr::Fn(functions)),
+            format!("Function {} is out of range in fact", functions)
+        );
+        assert_eq!(corrupt(Instr::Load(1000)), "Local 1000 is out of range in fact");
+        assert_eq!(
+            corrupt(Instr::Adt(classes, 0)),
+            format!("Class {} is out of range in fact", classes)
+        );
+        assert_eq!(corrupt(Instr::Jump(1)), "Jump target 1 is not an instruction in fact");
+        let mut unit = compiled.clone();
+        unit.constants.push(Constant::Aggregate(Some(classes), vec![]));
+        let err = Unit::deserialize(&unit.serialize()).err().unwrap();
+        assert_eq!(err.description, format!("Class {} is out of range", classes));
+        // Operands of the wrong kind or missing ones fail at run time.
+        let run = |instrs: &[Instr]| {
+            let mut instructions = vec![];
+            for instr in instrs {
+                instr.encode(&mut instructions);
+            }
+            let mut unit = Unit {
+                path: vec!["bad".to_string()],
+                constants: vec![Constant::Int(1)],
+                classes: vec![],
+                functions: vec![],
+            };
+            unit.functions.push(Function {
+                name: "f".to_string(),
+                public: true,
+                def_id: Some(DefId::from_raw(0, 0)),
+                kind: FunctionKind::Code {
+                    arg_count: 0,
+                    local_count: 1,
+                    instructions,
+                },
+            });
+            let unit = Unit::deserialize(&unit.serialize()).unwrap();
+            let err = Vm::new(&unit).call(&DefId::from_raw(0, 0), vec![]).err().unwrap();
+            err.message
+        };
+        assert_eq!(run(&[Instr::Field(0)]), "operand stack underflow");
+        assert_eq!(run(&[Instr::Const(0), Instr::Field(0)]), "expected an aggregate, found 1");
+        assert_eq!(run(&[Instr::Const(0), Instr::Not]), "expected a bool, found 1");
+        assert_eq!(run(&[Instr::Const(0), Instr::Call(0)]), "expected a function, found 1");
+        assert_eq!(
+            run(&[Instr::Fn(0), Instr::Const(0), Instr::Call(1)]),
+            "f takes 0 arguments but 1 were given"
+        );
+        assert_eq!(
+            run(&[Instr::Const(0), Instr::Const(0), Instr::Binary(BinOp::And)]),
+            "invalid operands 1 and 1 of `&&`"
+        );
+        assert_eq!(run(&[Instr::Return]), "operand stack underflow");
+    }
+
+    #[test]
+    fn test_til_ssa() {
+        use std::rc::Rc;
+        use crate::ast::to_hil;
+        use crate::codegen::llvm::to_llvm;
+        use crate::context::GlobalContext;
+        use crate::error::take_errors;
+        use crate::hil::to_til;
+        use crate::t::ty_check;
+        use crate::til::dom::Dominators;
+        use crate::til::interp::{self, Interpreter};
+        use crate::til::ssa::{from_ssa, promotable_locals, to_ssa, verify_ssa};
+        use crate::til::{BlockId, BodyTy, Til};
+        let _serial = serial();
+        // defn sum(n: int) -> int {
+        //   let i = 0;
+        //   let acc = 0;
+        //   while i < n {
+        //     i = i + 1;
+        //     if i % 2 == 0 { continue; }
+        //     acc = acc + i;
+        //   }
+        //   acc
+        // }
+        // defn fib(n: int) -> int {
+        //   let t = (0, 1);
+        //   let i = 0;
+        //   while i < n {
+        //     let a = t.0;
+        //     t.0 = t.1;
+        //     t.1 = a + t.1;
+        //     i = i + 1;
+        //   }
+        //   t.0
+        // }
+        let ast = sexp!(
+            (Unit #:path #(test)
+             (Item #:ident sum #:kind #:defn (Generics)
+              (FnSig
+               (FnDecl #((Param n (Ty #:kind #:path #(int)))) (FnRetTy (Ty #:kind #:path #(int)))))
+              (Block
+               (Stmt #:kind #:let (Local i (Expr #:kind #:lit 0)))
+               (Stmt #:kind #:let (Local acc (Expr #:kind #:lit 0)))
+               (Stmt #:kind #:semi
+                (Expr #:kind #:while
+                 (Expr #:kind #:binary #:op #:lt (Expr #:kind #:path #(i)) (Expr #:kind #:path #(n)))
+                 (Block
+                  (Stmt #:kind #:semi
+                   (Expr #:kind #:assign (Expr #:kind #:path #(i))
+                    (Expr #:kind #:binary #:op #:add (Expr #:kind #:path #(i)) (Expr #:kind #:lit 1))))
+                  (Stmt #:kind #:semi
+                   (Expr #:kind #:if
+                    (Expr #:kind #:binary #:op #:eq
+                     (Expr #:kind #:binary #:op #:rem (Expr #:kind #:path #(i)) (Expr #:kind #:lit 2))
+                     (Expr #:kind #:lit 0))
+                    (Block (Stmt #:kind #:semi (Expr #:kind #:continue)))))
+                  (Stmt #:kind #:semi
+                   (Expr #:kind #:assign (Expr #:kind #:path #(acc))
+                    (Expr #:kind #:binary #:op #:add
+                     (Expr #:kind #:path #(acc))
+                     (Expr #:kind #:path #(i))))))))
+               (Stmt #:kind #:expr (Expr #:kind #:path #(acc)))))
+             (Item #:ident fib #:kind #:defn (Generics)
+              (FnSig
+               (FnDecl #((Param n (Ty #:kind #:path #(int)))) (FnRetTy (Ty #:kind #:path #(int)))))
+              (Block
+               (Stmt #:kind #:let
+                (Local t (Expr #:kind #:tuple #((Expr #:kind #:lit 0) (Expr #:kind #:lit 1)))))
+               (Stmt #:kind #:let (Local i (Expr #:kind #:lit 0)))
+               (Stmt #:kind #:semi
+                (Expr #:kind #:while
+                 (Expr #:kind #:binary #:op #:lt (Expr #:kind #:path #(i)) (Expr #:kind #:path #(n)))
+                 (Block
+                  (Stmt #:kind #:let (Local a (Expr #:kind #:field (Expr #:kind #:path #(t)) 0)))
+                  (Stmt #:kind #:semi
+                   (Expr #:kind #:assign (Expr #:kind #:field (Expr #:kind #:path #(t)) 0)
+                    (Expr #:kind #:field (Expr #:kind #:path #(t)) 1)))
+                  (Stmt #:kind #:semi
+                   (Expr #:kind #:assign (Expr #:kind #:field (Expr #:kind #:path #(t)) 1)
+                    (Expr #:kind #:binary #:op #:add
+                     (Expr #:kind #:path #(a))
+                     (Expr #:kind #:field (Expr #:kind #:path #(t)) 1))))
+                  (Stmt #:kind #:semi
+                   (Expr #:kind #:assign (Expr #:kind #:path #(i))
+                    (Expr #:kind #:binary #:op #:add (Expr #:kind #:path #(i)) (Expr #:kind #:lit 1)))))))
+               (Stmt #:kind #:expr (Expr #:kind #:field (Expr #:kind #:path #(t)) 0))))));
+        let hil = to_hil(&ast);
+        let ctx = GlobalContext::new();
+        let tctx = ty_check(&ctx, &hil);
+        assert_eq!(take_errors(), Vec::<String>::new());
+        let til = to_til(&hil, &ctx, &tctx);
+        // The LLVM backend keeps the promotable locals in registers.
+        let source = to_llvm(&til, &ctx, &[], None);
+        assert!(source.contains("\n  %_15 = phi i64 [ 0, %bb0 ], [ %t2, %bb4 ], [ %t2, %bb6 ]\n"));
+        assert!(source.contains("\n  %_2 = alloca { i64, i64 }\n"));
+        assert!(!source.contains("%_15 = alloca"));
+        let mut bodies = vec![];
+        for body in &til.bodies {
+            let dominators = Dominators::new(body);
+            for &block in dominators.reverse_postorder() {
+                assert!(dominators.dominates(BlockId::ENTRY, block));
+                if let Some(idom) = dominators.immediate_dominator(block) {
+                    assert!(dominators.dominates(idom, block));
+                    assert!(!dominators.dominates(block, idom));
+                }
+                for frontier in dominators.frontier(block) {
+                    assert!(!dominators.dominates(block, *frontier) || block == *frontier);
+                }
+            }
+            let mut ssa = body.clone();
+            to_ssa(&mut ssa);
+            assert_eq!(verify_ssa(&ssa), Ok(()), "{}", ssa);
+            let phis = ssa.blocks.iter().map(|x| x.phis.len()).sum::<usize>();
+            // `i` and `acc`, or `i` alone, in the loop header, which
+            // `continue` jumps to.
+            let expected = if body.name == "sum" { 2 } else { 1 };
+            assert_eq!(phis, expected, "{}", ssa);
+            if body.name == "fib" {
+                let t = body.locals.iter().position(|x| x.name.as_deref() == Some("t"));
+                assert!(!promotable_locals(&ssa)[t.unwrap()]);
+            }
+            assert!(verify_ssa(body).is_err());
+            from_ssa(&mut ssa);
+            assert!(ssa.blocks.iter().all(|x| x.phis.is_empty()));
+            bodies.push(ssa);
+        }
+        let til = Rc::new(Til {
+            unit_path: til.unit_path.clone(),
+            item_paths: til.item_paths.clone(),
+            bodies,
+        });
+        ctx.register_til(til.clone());
+        let def_id = |name: &str| match &til.bodies.iter().find(|x| x.name == name).unwrap().ty {
+            BodyTy::Fn(def_id) => def_id.clone(),
+            BodyTy::Closure { .. } => unreachable!(),
+        };
+        let mut interpreter = Interpreter::new(&ctx);
+        for (name, arg, expected) in [("sum", 10, 25), ("sum", 0, 0), ("fib", 10, 55)] {
+            let args = vec![interp::Value::Int(arg)];
+            let result = interpreter.call(&def_id(name), args).ok().unwrap();
+            assert_eq!(result.as_int(), Some(expected));
+        }
+    }
+
+    #[test]
+    fn test_til_dataflow() {
+        use crate::ast::to_hil;
+        use crate::context::GlobalContext;
+        use crate::error::take_errors;
+        use crate::hil::to_til;
+        use crate::t::ty_check;
+        use crate::til::dataflow::init::MaybeUninit;
+        use crate::til::dataflow::liveness::Liveness;
+        use crate::til::dataflow::reaching::{DefSite, ReachingDefs};
+        use crate::til::dataflow::{solve, BitSet};
+        use crate::til::{BlockId, Local, Statement, Terminator};
+        let _serial = serial();
+        // defn maybe(b: bool) -> int {
+        //   let x: int;
+        //   let y: int;
+        //   if b { x = 1; }
+        //   y = 2;
+        //   x + y
+        // }
+        // defn dead(n: int) -> int {
+        //   let a = n + 1;
+        //   let c = 5;
+        //   a * 2
+        // }
+        // defn twice(b: bool) -> int {
+        //   let x = 1;
+        //   if b { x = 2; }
+        //   x
+        // }
+        let ast = sexp!(
+            (Unit #:path #(test)
+             (Item #:ident maybe #:kind #:defn (Generics)
+              (FnSig
+               (FnDecl #((Param b (Ty #:kind #:path #(bool)))) (FnRetTy (Ty #:kind #:path #(int)))))
+              (Block
+               (Stmt #:kind #:let (Local x (Ty #:kind #:path #(int))))
+               (Stmt #:kind #:let (Local y (Ty #:kind #:path #(int))))
+               (Stmt #:kind #:semi
+                (Expr #:kind #:if (Expr #:kind #:path #(b))
+                 (Block
+                  (Stmt #:kind #:semi
+                   (Expr #:kind #:assign (Expr #:kind #:path #(x)) (Expr #:kind #:lit 1))))))
+               (Stmt #:kind #:semi (Expr #:kind #:assign (Expr #:kind #:path #(y)) (Expr #:kind #:lit 2)))
+               (Stmt #:kind #:expr
+                (Expr #:kind #:binary #:op #:add (Expr #:kind #:path #(x)) (Expr #:kind #:path #(y))))))
+             (Item #:ident dead #:kind #:defn (Generics)
+              (FnSig
+               (FnDecl #((Param n (Ty #:kind #:path #(int)))) (FnRetTy (Ty #:kind #:path #(int)))))
+              (Block
+               (Stmt #:kind #:let
+                (Local a (Expr #:kind #:binary #:op #:add (Expr #:kind #:path #(n)) (Expr #:kind #:lit 1))))
+               (Stmt #:kind #:let (Local c (Expr #:kind #:lit 5)))
+               (Stmt #:kind #:expr
+                (Expr #:kind #:binary #:op #:mul (Expr #:kind #:path #(a)) (Expr #:kind #:lit 2)))))
+             (Item #:ident twice #:kind #:defn (Generics)
+              (FnSig
+               (FnDecl #((Param b (Ty #:kind #:path #(bool)))) (FnRetTy (Ty #:kind #:path #(int)))))
+              (Block
+               (Stmt #:kind #:let (Local x (Expr #:kind #:lit 1)))
+               (Stmt #:kind #:semi
+                (Expr #:kind #:if (Expr #:kind #:path #(b))
+                 (Block
+                  (Stmt #:kind #:semi
+                   (Expr #:kind #:assign (Expr #:kind #:path #(x)) (Expr #:kind #:lit 2))))))
+               (Stmt #:kind #:expr (Expr #:kind #:path #(x)))))));
+        let hil = to_hil(&ast);
+        let ctx = GlobalContext::new();
+        let tctx = ty_check(&ctx, &hil);
+        assert_eq!(take_errors(), Vec::<String>::new());
+        let til = to_til(&hil, &ctx, &tctx);
+        // `y` is assigned on every path before it is read, unlike `x`.
+        assert_eq!(take_errors(), vec!["E0036 Use of possibly-uninitialized `x`"]);
+        let body = |name: &str| til.bodies.iter().find(|x| x.name == name).unwrap();
+        let local = |name: &str, local: &str| {
+            let index = body(name).locals.iter().position(|x| x.name.as_deref() == Some(local));
+            Local(u32::try_from(index.unwrap()).unwrap())
+        };
+        let returns = |name: &str| {
+            let blocks = body(name).blocks.iter().enumerate();
+            let mut returns = blocks.filter(|(_, x)| x.terminator == Terminator::Return);
+            BlockId(u32::try_from(returns.next().unwrap().0).unwrap())
+        };
+
+        let maybe = body("maybe");
+        let results = solve(MaybeUninit, maybe);
+        let exit = results.exit(returns("maybe"));
+        assert!(exit.contains(local("maybe", "x").index()));
+        assert!(!exit.contains(local("maybe", "y").index()));
+        assert!(!results.entry(BlockId::ENTRY).contains(local("maybe", "b").index()));
+
+        // Only the argument is live on entry, and `c` is dead once assigned.
+        let dead = body("dead");
+        let results = solve(Liveness, dead);
+        let live = results.entry(BlockId::ENTRY).iter().collect::<Vec<_>>();
+        assert_eq!(live, vec![local("dead", "n").index()]);
+        let c = local("dead", "c");
+        let mut visited = false;
+        for block in 0..dead.blocks.len() {
+            let block = BlockId(u32::try_from(block).unwrap());
+            results.visit_block(dead, block, |state, location| {
+                let statements = &dead.block(location.block).statements;
+                if let Some(Statement::Assign(place, _)) = statements.get(location.statement) {
+                    if place.local == c {
+                        assert!(!state.contains(c.index()));
+                        visited = true;
+                    }
+                }
+            });
+        }
+        assert!(visited);
+
+        // Both assignments of `x` reach the end, only one reaches the branch.
+        let twice = body("twice");
+        let results = solve(ReachingDefs::new(twice), twice);
+        let x = local("twice", "x");
+        let reaching = |state: &BitSet| {
+            let defs = results.analysis.defs_of(x);
+            defs.iter().filter(|x| state.contains(**x)).count()
+        };
+        assert_eq!(results.analysis.defs_of(x).len(), 2);
+        assert_eq!(reaching(results.exit(returns("twice"))), 2);
+        assert_eq!(reaching(results.exit(BlockId::ENTRY)), 1);
+        let b = local("twice", "b");
+        assert_eq!(reaching(results.entry(BlockId::ENTRY)), 0);
+        let entry = results.entry(BlockId::ENTRY).iter().collect::<Vec<_>>();
+        assert_eq!(entry, results.analysis.defs_of(b));
+        assert_eq!(results.analysis.defs[entry[0]].site, DefSite::Arg);
+    }
+
+    #[test]
+    fn test_til_opt() {
+        use std::rc::Rc;
+        use crate::ast::to_hil;
+        use crate::context::GlobalContext;
+        use crate::error::take_errors;
+        use crate::hil::to_til;
+        use crate::t::ty_check;
+        use crate::til::interp::{self, Interpreter};
+        use crate::til::opt::{verify, OptLevel, Pass, PassManager};
+        use crate::til::{BlockId, BodyTy, Terminator, Til};
+        let _serial = serial();
+        // defn folded() -> int {
+        //   let a = 2;
+        //   let b = a * 3;
+        //   let c = b;
+        //   if c > 5 { c + 1 } else { 0 }
+        // }
+        // defn sum(n: int) -> int {
+        //   let i = 0;
+        //   let acc = 0;
+        //   while i < n {
+        //     let step = 1;
+        //     i = i + step;
+        //     acc = acc + i;
+        //   }
+        //   acc
+        // }
+        // defn boom() -> int {
+        //   let big = 9223372036854775807;
+        //   let unused = big + 1;
+        //   0
+        // }
+        let max = i64::MAX;
+        let ast = sexp!(
+            (Unit #:path #(test)
+             (Item #:ident folded #:kind #:defn (Generics)
+              (FnSig (FnDecl #() (FnRetTy (Ty #:kind #:path #(int)))))
+              (Block
+               (Stmt #:kind #:let (Local a (Expr #:kind #:lit 2)))
+               (Stmt #:kind #:let
+                (Local b (Expr #:kind #:binary #:op #:mul (Expr #:kind #:path #(a)) (Expr #:kind #:lit 3))))
+               (Stmt #:kind #:let (Local c (Expr #:kind #:path #(b))))
+               (Stmt #:kind #:expr
+                (Expr #:kind #:if
+                 (Expr #:kind #:binary #:op #:gt (Expr #:kind #:path #(c)) (Expr #:kind #:lit 5))
+                 (Block
+                  (Stmt #:kind #:expr
+                   (Expr #:kind #:binary #:op #:add (Expr #:kind #:path #(c)) (Expr #:kind #:lit 1))))
+                 (Block (Stmt #:kind #:expr (Expr #:kind #:lit 0)))))))
+             (Item #:ident sum #:kind #:defn (Generics)
+              (FnSig
+               (FnDecl #((Param n (Ty #:kind #:path #(int)))) (FnRetTy (Ty #:kind #:path #(int)))))
+              (Block
+               (Stmt #:kind #:let (Local i (Expr #:kind #:lit 0)))
+               (Stmt #:kind #:let (Local acc (Expr #:kind #:lit 0)))
+               (Stmt #:kind #:semi
+                (Expr #:kind #:while
+                 (Expr #:kind #:binary #:op #:lt (Expr #:kind #:path #(i)) (Expr #:kind #:path #(n)))
+                 (Block
+                  (Stmt #:kind #:let (Local step (Expr #:kind #:lit 1)))
+                  (Stmt #:kind #:semi
+                   (Expr #:kind #:assign (Expr #:kind #:path #(i))
+                    (Expr #:kind #:binary #:op #:add (Expr #:kind #:path #(i)) (Expr #:kind #:path #(step)))))
+                  (Stmt #:kind #:semi
+                   (Expr #:kind #:assign (Expr #:kind #:path #(acc))
+                    (Expr #:kind #:binary #:op #:add
+                     (Expr #:kind #:path #(acc))
+                     (Expr #:kind #:path #(i))))))))
+               (Stmt #:kind #:expr (Expr #:kind #:path #(acc)))))
+             (Item #:ident boom #:kind #:defn (Generics)
+              (FnSig (FnDecl #() (FnRetTy (Ty #:kind #:path #(int)))))
+              (Block
+               (Stmt #:kind #:let (Local big (Expr #:kind #:lit ,max)))
+               (Stmt #:kind #:let
+                (Local unused
+                 (Expr #:kind #:binary #:op #:add (Expr #:kind #:path #(big)) (Expr #:kind #:lit 1))))
+               (Stmt #:kind #:expr (Expr #:kind #:lit 0))))));
+        let hil = to_hil(&ast);
+        let ctx = GlobalContext::new();
+        let tctx = ty_check(&ctx, &hil);
+        let til = to_til(&hil, &ctx, &tctx);
+        assert_eq!(take_errors(), Vec::<String>::new());
+        assert_eq!(Pass::from_name("dead-code"), Some(Pass::DeadCode));
+        assert_eq!(Pass::from_name("inline"), Some(Pass::Inline));
+        assert_eq!(Pass::from_name("unroll"), None);
+        assert!(PassManager::new(OptLevel::O0).passes().is_empty());
+        for level in [OptLevel::O0, OptLevel::O1, OptLevel::O2] {
+            let mut optimized = Til {
+                unit_path: til.unit_path.clone(),
+                item_paths: til.item_paths.clone(),
+                bodies: til.bodies.clone(),
+            };
+            let mut manager = PassManager::new(level);
+            manager.set_verify(true);
+            manager.run(&ctx, &mut optimized);
+            for (body, original) in optimized.bodies.iter().zip(&til.bodies) {
+                assert_eq!(verify(body, false), Ok(()));
+                let unchanged = body.to_string() == original.to_string();
+                assert_eq!(unchanged, level == OptLevel::O0);
+                if level == OptLevel::O0 {
+                    continue;
+                }
+                match body.name.as_str() {
+                    "folded" => {
+                        assert_eq!(body.blocks.len(), 1);
+                        assert_eq!(body.blocks[0].statements.len(), 1);
+                        assert!(body.locals.len() == 1);
+                    }
+                    "sum" => assert!(body.locals.iter().all(|x| x.name.as_deref() != Some("step"))),
+                    _ => assert_eq!(body.blocks[0].statements.len(), 2),
+                }
+            }
+            let optimized = Rc::new(optimized);
+            ctx.register_til(optimized.clone());
+            let def_id = |name: &str| {
+                match &optimized.bodies.iter().find(|x| x.name == name).unwrap().ty {
+                    BodyTy::Fn(def_id) => def_id.clone(),
+                    BodyTy::Closure { .. } => unreachable!(),
+                }
+            };
+            let mut interpreter = Interpreter::new(&ctx);
+            for (name, args, expected) in [("folded", vec![], 7), ("sum", vec![4], 10)] {
+                let args = args.into_iter().map(interp::Value::Int).collect();
+                let result = interpreter.call(&def_id(name), args).ok().unwrap();
+                assert_eq!(result.as_int(), Some(expected));
+            }
+            // The overflow is not folded away, even though its result is unused.
+            let error = interpreter.call(&def_id("boom"), vec![]).err().unwrap();
+            assert_eq!(error.message, "attempt to add with overflow");
+        }
+        // Without the other passes, the folded branch leaves dead blocks.
+        let mut manager = PassManager::new(OptLevel::O1);
+        manager.set_passes(vec![Pass::ConstProp]);
+        let mut folded = til.bodies.iter().find(|x| x.name == "folded").unwrap().clone();
+        manager.run_body(&ctx, &mut folded);
+        assert_eq!(folded.blocks.len(), 4);
+        assert!(folded.blocks[0].terminator == Terminator::Goto(BlockId(1)));
+        folded.blocks[0].terminator = Terminator::Goto(BlockId(9));
+        assert_eq!(verify(&folded, false), Err("bb9 is not a block of `folded`".to_string()));
+    }
+
+    #[test]
+    fn test_til_inline() {
+        use std::rc::Rc;
+        use crate::ast::to_hil;
+        use crate::bui::{Bui, BuiMessage};
+        use crate::context::GlobalContext;
+        use crate::def::DefId;
+        use crate::error::take_errors;
+        use crate::hil::{to_bui, to_til};
+        use crate::t::ty_check;
+        use crate::til::import::import_bodies;
+        use crate::til::interp::Interpreter;
+        use crate::til::opt::{inline, OptLevel, PassManager};
+        use crate::til::{BodyTy, Operand, Rvalue, Statement, Terminator};
+        let _serial = serial();
+        // pub defn sq(x: int) -> int { x * x }
+        // #[inline] pub defn pow9(x: int) -> int { x * x * x * x * x * x * x * x * x }
+        // pub defn bump(x: int) -> int { helper(x) }
+        // defn helper(x: int) -> int { x + 1 }
+        // defn main() -> int { sq(3) + pow9(1) }
+        let get_ast = |sq_rhs: Option<i32>, marked: bool, main_op: &str| {
+            let int_param = || sexp!((Param x (Ty #:kind #:path #(int))));
+            let x = || sexp!((Expr #:kind #:path #(x)));
+            let defn = |ident: &str, vis: Value, body: Value| {
+                let (ident, param) = (Value::symbol(ident), int_param());
+                sexp!((Item #:ident ,ident #:vis ,vis #:kind #:defn (Generics)
+                       (FnSig (FnDecl #(,param) (FnRetTy (Ty #:kind #:path #(int)))))
+                       (Block (Stmt #:kind #:expr ,body))))
+            };
+            let lhs = x();
+            let rhs = match sq_rhs {
+                Some(lit) => sexp!((Expr #:kind #:lit ,lit)),
+                None => x(),
+            };
+            let product = sexp!((Expr #:kind #:binary #:op #:mul ,lhs ,rhs));
+            let sq = defn("sq", Value::keyword("pub"), product);
+            let mut pow = x();
+            for _ in 0..8 {
+                let x = x();
+                pow = sexp!((Expr #:kind #:binary #:op #:mul ,pow ,x));
+            }
+            let mut pow9 = defn("pow9", Value::keyword("pub"), pow);
+            if marked {
+                let fields = pow9.as_cons().unwrap().to_vec().0;
+                let mut attrs = fields[..5].to_vec();
+                attrs.extend([Value::keyword("inline"), Value::keyword("always")]);
+                attrs.extend(fields[5..].iter().cloned());
+                pow9 = Value::list(attrs);
+            }
+            let call = |name: &str, arg: i32| {
+                let name = Value::symbol(name);
+                sexp!((Expr #:kind #:call (Expr #:kind #:path #(,name))
+                       #((Expr #:kind #:lit ,arg))))
+            };
+            let bump = defn("bump", Value::keyword("pub"), {
+                let x = x();
+                sexp!((Expr #:kind #:call (Expr #:kind #:path #(helper)) #(,x)))
+            });
+            let helper = defn("helper", Value::keyword("priv"), {
+                let x = x();
+                sexp!((Expr #:kind #:binary #:op #:add ,x (Expr #:kind #:lit 1)))
+            });
+            let (sq_call, pow9_call) = (call("sq", 3), call("pow9", 1));
+            let op = Value::keyword(main_op);
+            let main = sexp!(
+                (Item #:ident main #:kind #:defn (Generics)
+                 (FnSig (FnDecl #() (FnRetTy (Ty #:kind #:path #(int)))))
+                 (Block (Stmt #:kind #:expr (Expr #:kind #:binary #:op ,op ,sq_call ,pow9_call)))));
+            sexp!((Unit #:path #(lib) ,sq ,pow9 ,bump ,helper ,main))
+        };
+        // Builds the unit as `lib`, optimized before its BUI is written.
+        fn build(ast: &Value) -> BuiMessage {
+            let hil = to_hil(ast);
+            let ctx = GlobalContext::new();
+            let tctx = ty_check(&ctx, &hil);
+            let mut til = to_til(&hil, &ctx, &tctx);
+            assert_eq!(take_errors(), Vec::<String>::new());
+            PassManager::new(OptLevel::O2).run(&ctx, &mut til);
+            ctx.register_til(Rc::new(til));
+            to_bui(&hil, &ctx)
+        }
+        let bui = build(&get_ast(None, true, "add")).deserialize();
+        let inlinable = |name: &str| {
+            let item = bui.items().into_iter().find(|x| x.ident() == name).unwrap();
+            let defn = item.defn().unwrap();
+            defn.inline_body().is_some()
+        };
+        assert!(inlinable("sq"));
+        assert!(inlinable("pow9"));
+        // `helper` has no symbol in other units.
+        assert!(!inlinable("bump"));
+        // The hash covers the inlined bodies, and only them.
+        let unmarked = build(&get_ast(None, false, "add")).deserialize();
+        let pow9 = unmarked.items().into_iter().find(|x| x.ident() == "pow9").unwrap();
+        assert!(pow9.defn().unwrap().inline_body().is_none());
+        assert_ne!(unmarked.hash(), bui.hash());
+        assert_eq!(build(&get_ast(None, true, "sub")).deserialize().hash(), bui.hash());
+        assert_ne!(build(&get_ast(Some(2), true, "add")).deserialize().hash(), bui.hash());
+        assert_eq!(bui.hash().map(|x| x.len()), Some(8));
+        assert_eq!(Bui::empty().hash(), None);
+        // The same unit, built as `test`, calls `sq` and `pow9` of `lib`.
+        let hil = to_hil(&get_ast(None, true, "add"));
+        let ctx = GlobalContext::new();
+        let tctx = ty_check(&ctx, &hil);
+        let mut til = to_til(&hil, &ctx, &tctx);
+        assert_eq!(take_errors(), Vec::<String>::new());
+        til.unit_path = vec!["test".to_string()];
+        let imported = Rc::new(import_bodies(&ctx, 1, &["lib".to_string()], &bui));
+        let items = bui.items();
+        let main = til.bodies.iter_mut().find(|x| x.name == "main").unwrap();
+        for block in &mut main.blocks {
+            if let Terminator::Call { func: Operand::Fn(def_id), .. } = &mut block.terminator {
+                let ident = til.item_paths[&*def_id].last().unwrap();
+                let item = items.iter().find(|x| x.ident() == ident).unwrap();
+                *def_id = DefId::from_raw(1, item.def());
+            }
+        }
+        // Bodies only registered to be called are not inlined.
+        ctx.register_til(imported.clone());
+        let main = til.bodies.iter().find(|x| x.name == "main").unwrap();
+        assert!(!inline::run(&ctx, &mut main.clone()));
+        ctx.register_imported_til(imported);
+        let mut manager = PassManager::new(OptLevel::O2);
+        manager.set_verify(true);
+        manager.run(&ctx, &mut til);
+        let main = til.bodies.iter().find(|x| x.name == "main").unwrap();
+        assert_eq!(main.blocks.len(), 1);
+        assert!(matches!(
+            &main.blocks[0].statements[..],
+            [Statement::Assign(_, Rvalue::Use(Operand::Const(value)))] if value.to_string() == "10"
+        ));
+        let BodyTy::Fn(def_id) = main.ty.clone() else {
+            unreachable!();
+        };
+        ctx.register_til(Rc::new(til));
+        let result = Interpreter::new(&ctx).call(&def_id, vec![]).ok().unwrap();
+        assert_eq!(result.as_int(), Some(10));
+        // Bodies whose locals do not hold their arguments are called instead.
+        let hil = to_hil(&get_ast(None, true, "add"));
+        let ctx = GlobalContext::new();
+        let tctx = ty_check(&ctx, &hil);
+        let mut til = to_til(&hil, &ctx, &tctx);
+        for body in &mut til.bodies {
+            body.arg_count = body.locals.len();
+        }
+        ctx.register_til(Rc::new(til));
+        let bui = to_bui(&hil, &ctx).deserialize();
+        let sq = bui.items().into_iter().find(|x| x.ident() == "sq").unwrap();
+        assert!(sq.defn().unwrap().inline_body().is_some());
+        let ctx = GlobalContext::new();
+        assert!(import_bodies(&ctx, 1, &["lib".to_string()], &bui).bodies.is_empty());
+    }
+
+    #[test]
+    fn test_til_eval_order() {
+        use std::rc::Rc;
+        use crate::ast::to_hil;
+        use crate::context::GlobalContext;
+        use crate::error::take_errors;
+        use crate::hil::to_til;
+        use crate::t::ty_check;
+        use crate::til::interp::{self, Interpreter};
+        use crate::til::{BodyTy, Terminator};
+        let _serial = serial();
+        // class Point { x: int, y: int }
+        // defn sub(a: int, b: int) -> int { a - b }
+        // defn binary(c: bool) -> int {
+        //   let x = 1;
+        //   x + if c { x = 5; 1 } else { 2 }
+        // }
+        // defn tuple(c: bool) -> int {
+        //   let x = 1;
+        //   let t = (x, if c { x = 5; 1 } else { 2 });
+        //   t.0
+        // }
+        // defn call(c: bool) -> int {
+        //   let x = 10;
+        //   sub(x, if c { x = 5; 1 } else { 2 })
+        // }
+        // defn point(c: bool) -> int {
+        //   let x = 1;
+        //   let p = Point { x: x, y: if c { x = 5; 1 } else { 2 } };
+        //   p.x
+        // }
+        let assign_x = || {
+            sexp!((Expr #:kind #:if (Expr #:kind #:path #(c))
+                   (Block
+                    (Stmt #:kind #:semi
+                     (Expr #:kind #:assign (Expr #:kind #:path #(x)) (Expr #:kind #:lit 5)))
+                    (Stmt #:kind #:expr (Expr #:kind #:lit 1)))
+                   (Block (Stmt #:kind #:expr (Expr #:kind #:lit 2)))))
+        };
+        let defn = |ident: &str, init: i32, stmts: Vec<Value>| {
+            let ident = Value::symbol(ident);
+            let mut block = vec![
+                Value::symbol("Block"),
+                sexp!((Stmt #:kind #:let (Local x (Expr #:kind #:lit ,init)))),
+            ];
+            block.extend(stmts);
+            let block = Value::list(block);
+            sexp!((Item #:ident ,ident #:kind #:defn (Generics)
+                   (FnSig
+                    (FnDecl #((Param c (Ty #:kind #:path #(bool))))
+                     (FnRetTy (Ty #:kind #:path #(int)))))
+                   ,block))
+        };
+        let binary = defn("binary", 1, {
+            let rhs = assign_x();
+            vec![sexp!((Stmt #:kind #:expr
+                        (Expr #:kind #:binary #:op #:add (Expr #:kind #:path #(x)) ,rhs)))]
+        });
+        let tuple = defn("tuple", 1, {
+            let elem = assign_x();
+            vec![
+                sexp!((Stmt #:kind #:let
+                       (Local t (Expr #:kind #:tuple #((Expr #:kind #:path #(x)) ,elem))))),
+                sexp!((Stmt #:kind #:expr (Expr #:kind #:field (Expr #:kind #:path #(t)) 0))),
+            ]
+        });
+        let call = defn("call", 10, {
+            let arg = assign_x();
+            vec![sexp!((Stmt #:kind #:expr
+                        (Expr #:kind #:call (Expr #:kind #:path #(sub))
+                         #((Expr #:kind #:path #(x)) ,arg))))]
+        });
+        let point = defn("point", 1, {
+            let y = assign_x();
+            vec![
+                sexp!((Stmt #:kind #:let
+                       (Local p
+                        (Expr #:kind #:struct #(Point)
+                         (Field x (Expr #:kind #:path #(x)))
+                         (Field y ,y))))),
+                sexp!((Stmt #:kind #:expr (Expr #:kind #:field (Expr #:kind #:path #(p)) x))),
+            ]
+        });
+        let ast = sexp!(
+            (Unit #:path #(test)
+             (Item #:ident Point #:kind #:class
+              (Variant
+               (FieldDef x (Ty #:kind #:path #(int)))
+               (FieldDef y (Ty #:kind #:path #(int)))))
+             (Item #:ident sub #:kind #:defn (Generics)
+              (FnSig
+               (FnDecl
+                #((Param a (Ty #:kind #:path #(int)))
+                  (Param b (Ty #:kind #:path #(int))))
+                (FnRetTy (Ty #:kind #:path #(int)))))
+              (Block
+               (Stmt #:kind #:expr
+                (Expr #:kind #:binary #:op #:sub (Expr #:kind #:path #(a)) (Expr #:kind #:path #(b))))))
+             ,binary ,tuple ,call ,point));
+        let hil = to_hil(&ast);
+        let ctx = GlobalContext::new();
+        let tctx = ty_check(&ctx, &hil);
+        assert_eq!(take_errors(), Vec::<String>::new());
+        let til = Rc::new(to_til(&hil, &ctx, &tctx));
+        ctx.register_til(til.clone());
+        let def_id = |name: &str| match &til.bodies.iter().find(|x| x.name == name).unwrap().ty {
+            BodyTy::Fn(def_id) => def_id.clone(),
+            BodyTy::Closure { .. } => unreachable!(),
+        };
+        // Operands are read before the expressions to their right assign to `x`.
+        let mut interpreter = Interpreter::new(&ctx);
+        let cases = [("binary", 2, 3), ("tuple", 1, 1), ("call", 9, 8), ("point", 1, 1)];
+        for (name, taken, not_taken) in cases {
+            let result = interpreter.call(&def_id(name), vec![interp::Value::Bool(true)]);
+            assert_eq!(result.ok().unwrap().as_int(), Some(taken), "{}", name);
+            let result = interpreter.call(&def_id(name), vec![interp::Value::Bool(false)]);
+            assert_eq!(result.ok().unwrap().as_int(), Some(not_taken), "{}", name);
+        }
+
+        // class Point { x: int, y: int }
+        // defn twice() -> int { Point { x: 1, x: 2, y: 3 }.x }
+        // defn missing() -> int { Point { x: 1 }.x }
+        let ast = sexp!(
+            (Unit #:path #(test)
+             (Item #:ident Point #:kind #:class
+              (Variant
+               (FieldDef x (Ty #:kind #:path #(int)))
+               (FieldDef y (Ty #:kind #:path #(int)))))
+             (Item #:ident twice #:kind #:defn (Generics)
+              (FnSig (FnDecl #() (FnRetTy (Ty #:kind #:path #(int)))))
+              (Block
+               (Stmt #:kind #:expr
+                (Expr #:kind #:field
+                 (Expr #:kind #:struct #(Point)
+                  (Field x (Expr #:kind #:lit 1))
+                  (Field x (Expr #:kind #:lit 2))
+                  (Field y (Expr #:kind #:lit 3)))
+                 x))))
+             (Item #:ident missing #:kind #:defn (Generics)
+              (FnSig (FnDecl #() (FnRetTy (Ty #:kind #:path #(int)))))
+              (Block
+               (Stmt #:kind #:expr
+                (Expr #:kind #:field
+                 (Expr #:kind #:struct #(Point) (Field x (Expr #:kind #:lit 1)))
+                 x))))));
+        let hil = to_hil(&ast);
+        let ctx = GlobalContext::new();
+        let tctx = ty_check(&ctx, &hil);
+        assert_eq!(
+            take_errors(),
+            vec![
+                "E0012 Field `x` specified more than once",
+                "E0013 Missing field `y` in initializer of Point",
+            ]
+        );
+        // Initializers with a field missing or given twice are unreachable.
+        let til = to_til(&hil, &ctx, &tctx);
+        for body in &til.bodies {
+            let unreachable = body.blocks.iter().any(|x| x.terminator == Terminator::Unreachable);
+            assert!(unreachable, "{}", body.name);
         }
     }
 
     #[test]
     fn test_hil_to_til() {
         use crate::ast::to_hil;
+        use crate::def::DefId;
         use crate::hil::to_til;
         use crate::t::ty_check;
+        use crate::til::Terminator;
         use crate::context::GlobalContext;
+        let _serial = serial();
         let ast = get_ast();
         let hil = to_hil(&ast);
         let ctx = GlobalContext::new();
         let tctx = ty_check(&ctx, &hil);
         let til = to_til(&hil, &ctx, &tctx);
+        assert_eq!(til.bodies.len(), 2);
+        let foo = til.body(til.fn_body(&DefId::from_s_expr(&sexp!((DefId 0 4)))).unwrap());
+        assert_eq!(foo.arg_count, 2);
+        assert_eq!(foo.locals.len(), 4);
+        assert_eq!(foo.blocks.len(), 1);
+        assert!(foo.blocks[0].terminator == Terminator::Return);
     }
 }
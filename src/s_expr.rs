@@ -3,15 +3,14 @@ use lexpr::{Cons, Value};
 pub type Node = Cons;
 
 pub fn node_get_attr<'a>(node: &'a Node, name: &str) -> &'a Value {
+    node_find_attr(node, name).unwrap()
+}
+
+pub fn node_find_attr<'a>(node: &'a Node, name: &str) -> Option<&'a Value> {
     node.iter()
         .skip(1)
-        .filter(|&x| x.car().as_keyword() == Some(name))
-        .nth(0)
-        .unwrap()
-        .cdr()
-        .as_cons()
-        .unwrap()
-        .car()
+        .find(|&x| x.car().as_keyword() == Some(name))
+        .map(|x| x.cdr().as_cons().unwrap().car())
 }
 
 pub fn node_add_attr(node: Node, name: &str, value: Value) -> Node {
@@ -22,11 +21,11 @@ pub fn node_add_attr(node: Node, name: &str, value: Value) -> Node {
     )
 }
 
-pub fn node_get_field<'a>(node: &'a Node, i: usize) -> &'a Value {
+pub fn node_get_field(node: &Node, i: usize) -> &Value {
     node_get_fields(node)[i]
 }
 
-pub fn node_get_fields<'a>(node: &'a Node) -> Vec<&'a Value> {
+pub fn node_get_fields(node: &Node) -> Vec<&Value> {
     let mut res = vec![];
     let mut skip = false;
     for elem in node.list_iter().skip(1) {
@@ -0,0 +1,287 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use lexpr::Value;
+
+use crate::context::GlobalContext;
+use crate::def::{DefId, DefLocalId};
+use crate::error::{report_error, Error};
+use crate::hil::{HilVisitor, Node};
+use crate::op::{BinOp, UnOp};
+use crate::s_expr::{node_find_attr, node_get_attr, node_get_field, node_get_fields};
+
+#[derive(Clone, PartialEq, Eq)]
+pub enum ConstValue {
+    Bool(bool),
+    Int(i64),
+    Tuple(Vec<ConstValue>),
+    // Field values in the order of the fields of the class.
+    Adt(DefId, Vec<ConstValue>),
+}
+
+impl fmt::Display for ConstValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let write_list = |f: &mut fmt::Formatter, values: &[ConstValue]| {
+            for (i, value) in values.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{}", value)?;
+            }
+            Ok(())
+        };
+        match self {
+            ConstValue::Bool(b) => write!(f, "{}", b),
+            ConstValue::Int(i) => write!(f, "{}", i),
+            ConstValue::Tuple(values) => {
+                write!(f, "(")?;
+                write_list(f, values)?;
+                if values.len() == 1 {
+                    write!(f, ",")?;
+                }
+                write!(f, ")")
+            }
+            ConstValue::Adt(def_id, values) => {
+                write!(f, "{} {{ ", def_id)?;
+                write_list(f, values)?;
+                write!(f, " }}")
+            }
+        }
+    }
+}
+
+struct ConstItem {
+    name: String,
+    init: Node,
+    is_static: bool,
+}
+
+// Evaluates the initializers of the consts and statics of a unit after type
+// checking. Consts can use each other in any order, so they are evaluated on
+// demand. Errors of ill-typed initializers were already reported by the type
+// checker and only stop the evaluation.
+pub struct ConstEvaluator<'gcx> {
+    global_ctx: &'gcx GlobalContext<'gcx>,
+    items: HashMap<DefId, ConstItem>,
+    // Consts being evaluated, innermost last.
+    stack: Vec<DefId>,
+    // Consts whose evaluation failed, so that it is reported only once.
+    failed: HashSet<DefId>,
+}
+
+impl<'gcx> ConstEvaluator<'gcx> {
+    pub fn new(global_ctx: &'gcx GlobalContext<'gcx>) -> Self {
+        ConstEvaluator {
+            global_ctx,
+            items: HashMap::new(),
+            stack: vec![],
+            failed: HashSet::new(),
+        }
+    }
+
+    pub fn eval_all(&mut self) {
+        let mut def_ids = self.items.keys().cloned().collect::<Vec<_>>();
+        def_ids.sort_by_key(|def_id| def_id.local());
+        for def_id in def_ids {
+            self.eval_item(&def_id);
+        }
+    }
+
+    fn eval_item(&mut self, def_id: &DefId) -> Option<ConstValue> {
+        if let Some(value) = self.global_ctx.const_value(def_id) {
+            return Some(value);
+        }
+        if self.failed.contains(def_id) {
+            return None;
+        }
+        if self.stack.contains(def_id) {
+            report_error(Error::CyclicConst(self.items[def_id].name.clone()));
+            self.failed.insert(def_id.clone());
+            return None;
+        }
+        self.stack.push(def_id.clone());
+        let init = self.items[def_id].init.clone();
+        let value = self.eval_expr(&init);
+        self.stack.pop();
+        match &value {
+            Some(value) => self.global_ctx.set_const_value(def_id.clone(), value.clone()),
+            None => {
+                self.failed.insert(def_id.clone());
+            }
+        }
+        value
+    }
+
+    fn fail(&self, reason: &str) -> Option<ConstValue> {
+        let name = self.items[self.stack.last().unwrap()].name.clone();
+        report_error(Error::ConstEvalFailed(name, reason.to_string()));
+        None
+    }
+
+    fn eval_expr(&mut self, expr: &Node) -> Option<ConstValue> {
+        let kind = node_get_attr(expr, "kind").as_keyword().unwrap();
+        let fields = node_get_fields(expr);
+        if kind == "lit" {
+            eval_lit(fields[0])
+        } else if kind == "qpath" {
+            self.eval_path(fields[0].as_cons().unwrap())
+        } else if kind == "unary" {
+            let op = UnOp::from_s_expr(node_get_attr(expr, "op"));
+            let operand = self.eval_expr(fields[0].as_cons().unwrap())?;
+            self.eval_unary(op, operand)
+        } else if kind == "binary" {
+            let op = BinOp::from_s_expr(node_get_attr(expr, "op"));
+            self.eval_binary(op, fields[0].as_cons().unwrap(), fields[1].as_cons().unwrap())
+        } else if kind == "tuple" {
+            fields[0]
+                .as_slice()
+                .unwrap()
+                .iter()
+                .map(|x| self.eval_expr(x.as_cons().unwrap()))
+                .collect::<Option<Vec<_>>>()
+                .map(ConstValue::Tuple)
+        } else if kind == "struct" {
+            self.eval_struct(&fields)
+        } else if kind == "field" {
+            let base = self.eval_expr(fields[0].as_cons().unwrap())?;
+            match (base, fields[1].as_symbol(), fields[1].as_u64()) {
+                (ConstValue::Tuple(elems), _, Some(i)) => {
+                    elems.get(usize::try_from(i).ok()?).cloned()
+                }
+                (ConstValue::Adt(def_id, values), Some(name), _) => {
+                    let index = self.global_ctx.adt_def(def_id).field_index(name)?;
+                    values.get(index).cloned()
+                }
+                _ => None,
+            }
+        } else {
+            report_error(Error::NotConstant(format!("`{}` expression", kind)));
+            None
+        }
+    }
+
+    fn eval_path(&mut self, path: &Node) -> Option<ConstValue> {
+        if node_get_attr(path, "kind").as_keyword() == Some("def") {
+            let def_id = DefId::from_s_expr(node_get_attr(path, "def"));
+            match self.items.get(&def_id) {
+                Some(item) if item.is_static => {
+                    let name = item.name.clone();
+                    report_error(Error::NotConstant(format!("Static `{}`", name)));
+                    return None;
+                }
+                Some(_) => return self.eval_item(&def_id),
+                // Consts of other units come from their BUI.
+                None => {
+                    if let Some(value) = self.global_ctx.const_value(&def_id) {
+                        return Some(value);
+                    }
+                }
+            }
+        }
+        report_error(Error::NotConstant("Non-constant path".to_string()));
+        None
+    }
+
+    fn eval_unary(&self, op: UnOp, operand: ConstValue) -> Option<ConstValue> {
+        match (op, operand) {
+            (UnOp::Neg, ConstValue::Int(i)) => match i.checked_neg() {
+                Some(i) => Some(ConstValue::Int(i)),
+                None => self.fail("attempt to negate with overflow"),
+            },
+            (UnOp::Not, ConstValue::Bool(b)) => Some(ConstValue::Bool(!b)),
+            _ => None,
+        }
+    }
+
+    // `&&` and `||` short-circuit like at run time, so that their right
+    // operand can only fail when it would be evaluated.
+    fn eval_binary(&mut self, op: BinOp, lhs: &Node, rhs: &Node) -> Option<ConstValue> {
+        let lhs = self.eval_expr(lhs)?;
+        match (op, &lhs) {
+            (BinOp::And, ConstValue::Bool(false)) | (BinOp::Or, ConstValue::Bool(true)) => {
+                return Some(lhs)
+            }
+            _ => (),
+        }
+        let rhs = self.eval_expr(rhs)?;
+        if op == BinOp::Eq || op == BinOp::Ne {
+            return Some(ConstValue::Bool((lhs == rhs) == (op == BinOp::Eq)));
+        }
+        let (a, b) = match (lhs, rhs) {
+            (ConstValue::Int(a), ConstValue::Int(b)) => (a, b),
+            (ConstValue::Bool(_), ConstValue::Bool(b)) if op == BinOp::And || op == BinOp::Or => {
+                return Some(ConstValue::Bool(b))
+            }
+            _ => return None,
+        };
+        let (value, reason) = match op {
+            BinOp::Add => (a.checked_add(b), "attempt to add with overflow"),
+            BinOp::Sub => (a.checked_sub(b), "attempt to subtract with overflow"),
+            BinOp::Mul => (a.checked_mul(b), "attempt to multiply with overflow"),
+            BinOp::Div if b == 0 => (None, "attempt to divide by zero"),
+            BinOp::Div => (a.checked_div(b), "attempt to divide with overflow"),
+            BinOp::Rem if b == 0 => (
+                None,
+                "attempt to calculate the remainder with a divisor of zero",
+            ),
+            BinOp::Rem => (
+                a.checked_rem(b),
+                "attempt to calculate the remainder with overflow",
+            ),
+            BinOp::Lt => return Some(ConstValue::Bool(a < b)),
+            BinOp::Le => return Some(ConstValue::Bool(a <= b)),
+            BinOp::Gt => return Some(ConstValue::Bool(a > b)),
+            BinOp::Ge => return Some(ConstValue::Bool(a >= b)),
+            _ => return None,
+        };
+        match value {
+            Some(value) => Some(ConstValue::Int(value)),
+            None => self.fail(reason),
+        }
+    }
+
+    fn eval_struct(&mut self, fields: &[&Value]) -> Option<ConstValue> {
+        let path = fields[0].as_cons().unwrap();
+        let def_id = DefId::from_s_expr(node_get_field(path, 0));
+        let mut values = HashMap::new();
+        for field in fields.iter().skip(1) {
+            let field = field.as_cons().unwrap();
+            let value = self.eval_expr(node_get_field(field, 1).as_cons().unwrap())?;
+            let field_def_id = DefId::from_s_expr(node_find_attr(field, "field")?);
+            values.insert(field_def_id, value);
+        }
+        let adt_def = self.global_ctx.adt_def(def_id.clone());
+        adt_def
+            .fields()
+            .iter()
+            .map(|x| values.remove(&x.def_id))
+            .collect::<Option<Vec<_>>>()
+            .map(|values| ConstValue::Adt(def_id, values))
+    }
+}
+
+fn eval_lit(lit: &Value) -> Option<ConstValue> {
+    if let Some(b) = lit.as_bool() {
+        Some(ConstValue::Bool(b))
+    } else {
+        lit.as_i64().map(ConstValue::Int)
+    }
+}
+
+impl<'gcx> HilVisitor for ConstEvaluator<'gcx> {
+    fn visit_item(&mut self, node: &Node) {
+        let kind = node_get_attr(node, "kind").as_keyword().unwrap();
+        if kind != "const" && kind != "static" {
+            return;
+        }
+        let def_id = DefLocalId::from_s_expr(node_get_attr(node, "def_id")).to_def_id();
+        self.items.insert(
+            def_id,
+            ConstItem {
+                name: node_get_attr(node, "ident").to_string(),
+                init: node_get_field(node, 1).as_cons().unwrap().clone(),
+                is_static: kind == "static",
+            },
+        );
+    }
+}
@@ -1,8 +1,15 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+
+use lexpr::Value;
+
 use crate::context::GlobalContext;
+use crate::error::{report_error, Error};
 use crate::hil::{Node, HilId};
-use crate::s_expr::{node_get_attr, node_get_field};
-use crate::t::{TypeRef, TypeKind};
+use crate::op::{BinOp, UnOp};
+use crate::s_expr::{node_find_attr, node_get_attr};
+use crate::def::DefId;
+use crate::t::infer::InferTable;
+use crate::t::{AdtDef, GenericParamDef, ImplDef, TraitDef, TypeRef, TypeKind};
 
 #[derive(Hash, PartialEq, Eq, Clone)]
 pub struct UnitPath {
@@ -13,11 +20,25 @@ impl UnitPath {
     pub fn new(name: Vec<String>) -> Self {
         UnitPath { name }
     }
+
+    pub fn segments(&self) -> &[String] {
+        &self.name
+    }
 }
 
 pub struct TyCtx<'gcx> {
     global_ctx: &'gcx GlobalContext<'gcx>,
     locals: HashMap<HilId, TypeRef<'gcx>>,
+    expr_types: HashMap<HilId, TypeRef<'gcx>>,
+    field_indices: HashMap<HilId, usize>,
+    infer: InferTable<'gcx>,
+    // Generalized variables of let-bound locals, instantiated on each use.
+    schemes: HashMap<HilId, Vec<u32>>,
+    // Generic parameters of the defn being checked.
+    generics: Vec<GenericParamDef>,
+    // Trait bounds which must hold once inference is done.
+    obligations: Vec<(TypeRef<'gcx>, DefId)>,
+    method_resolutions: HashMap<HilId, DefId>,
 }
 
 impl<'gcx> TyCtx<'gcx> {
@@ -25,58 +46,480 @@ impl<'gcx> TyCtx<'gcx> {
         TyCtx {
             global_ctx,
             locals: HashMap::new(),
+            expr_types: HashMap::new(),
+            field_indices: HashMap::new(),
+            infer: InferTable::new(),
+            schemes: HashMap::new(),
+            generics: vec![],
+            obligations: vec![],
+            method_resolutions: HashMap::new(),
         }
     }
 
+    pub fn set_generics(&mut self, generics: Vec<GenericParamDef>) {
+        self.generics = generics;
+    }
+
+    pub fn generics(&self) -> &[GenericParamDef] {
+        &self.generics
+    }
+
+    pub fn trait_def(&self, def_id: &DefId) -> Option<&'gcx TraitDef> {
+        self.global_ctx.trait_def(def_id)
+    }
+
+    pub fn impls_for(&self, t: TypeRef<'gcx>) -> Vec<&'gcx ImplDef<'gcx>> {
+        self.global_ctx.impls_for(t)
+    }
+
+    pub fn subst_params(&self, t: TypeRef<'gcx>, params: &[TypeRef<'gcx>]) -> TypeRef<'gcx> {
+        self.global_ctx.subst_params(t, params)
+    }
+
+    pub fn set_method_resolution(&mut self, hil_id: HilId, def_id: DefId) {
+        self.method_resolutions.insert(hil_id, def_id);
+    }
+
+    // Definition a `method_call` expression dispatches to.
+    pub fn method_resolution(&self, hil_id: HilId) -> Option<&DefId> {
+        self.method_resolutions.get(&hil_id)
+    }
+
+    pub fn set_field_index(&mut self, hil_id: HilId, index: usize) {
+        self.field_indices.insert(hil_id, index);
+    }
+
+    // Index of the field projected by a `field` expression.
+    pub fn field_index(&self, hil_id: HilId) -> Option<usize> {
+        self.field_indices.get(&hil_id).cloned()
+    }
+
+    pub fn adt_def(&self, def_id: DefId) -> &'gcx AdtDef {
+        self.global_ctx.adt_def(def_id)
+    }
+
+    // Registered ADTs with a variant, unlike opaque types.
+    pub fn class_def(&self, def_id: DefId) -> Option<&'gcx AdtDef> {
+        self.global_ctx
+            .find_adt_def(&def_id)
+            .filter(|x| !x.variants().is_empty())
+    }
+
+    pub fn adt_type(&self, adt_def: &'gcx AdtDef) -> TypeRef<'gcx> {
+        self.global_ctx.adt_type(adt_def)
+    }
+
+    pub fn type_of(&self, def_id: &DefId) -> TypeRef<'gcx> {
+        self.global_ctx
+            .type_of(def_id)
+            .unwrap_or_else(|| panic!("Type of {} is unknown", def_id))
+    }
+
     pub fn add_local(&mut self, hil_id: HilId, t: TypeRef<'gcx>) {
         self.locals.insert(hil_id, t);
     }
 
+    pub fn local_type(&self, hil_id: HilId) -> Option<TypeRef<'gcx>> {
+        self.locals.get(&hil_id).cloned()
+    }
+
+    pub fn set_expr_type(&mut self, hil_id: HilId, t: TypeRef<'gcx>) {
+        self.expr_types.insert(hil_id, t);
+    }
+
+    // Type of an expression, resolved once the defn is checked.
+    pub fn expr_type(&self, hil_id: HilId) -> Option<TypeRef<'gcx>> {
+        self.expr_types.get(&hil_id).cloned()
+    }
+
     pub fn parse_ty(&mut self, node: &Node) -> TypeRef<'gcx> {
         self.global_ctx.interned_type(node)
     }
 
     pub fn unit_type(&self) -> TypeRef<'gcx> {
-        &self.global_ctx.unit_type
+        self.global_ctx.unit_type()
+    }
+
+    pub fn bool_type(&self) -> TypeRef<'gcx> {
+        self.global_ctx.bool_type()
+    }
+
+    pub fn int_type(&self) -> TypeRef<'gcx> {
+        self.global_ctx.int_type()
+    }
+
+    pub fn never_type(&self) -> TypeRef<'gcx> {
+        self.global_ctx.never_type()
+    }
+
+    pub fn error_type(&self) -> TypeRef<'gcx> {
+        self.global_ctx.error_type()
+    }
+
+    pub fn tuple_type(&self, elems: &[TypeRef<'gcx>]) -> TypeRef<'gcx> {
+        self.global_ctx.tuple_type(elems)
+    }
+
+    pub fn fn_type(&self, inputs: &[TypeRef<'gcx>], output: TypeRef<'gcx>) -> TypeRef<'gcx> {
+        self.global_ctx.fn_type(inputs, output)
+    }
+
+    pub fn new_var(&mut self, origin: &str) -> TypeRef<'gcx> {
+        let var = self.infer.new_var(origin.to_string());
+        self.global_ctx.infer_type(var)
+    }
+
+    // Origin of the inference variable `t` resolves to, if any.
+    pub fn var_origin(&mut self, t: TypeRef<'gcx>) -> Option<String> {
+        match self.shallow_resolve(t).kind() {
+            TypeKind::Infer(var) => Some(self.infer.origin(*var).to_string()),
+            _ => None,
+        }
+    }
+
+    // Replaces `t` by the value of its variable, or by the root of its
+    // equivalence class when it is still unbound.
+    pub fn shallow_resolve(&mut self, t: TypeRef<'gcx>) -> TypeRef<'gcx> {
+        match t.kind() {
+            TypeKind::Infer(var) => match self.infer.probe(*var) {
+                Some(u) => self.shallow_resolve(u),
+                None => self.global_ctx.infer_type(self.infer.find(*var)),
+            },
+            _ => t,
+        }
+    }
+
+    pub fn resolve_type(&mut self, t: TypeRef<'gcx>) -> TypeRef<'gcx> {
+        self.substitute(t, &HashMap::new())
+    }
+
+    // Resolves `t` deeply, replacing the unbound variables in `subst`.
+    fn substitute(
+        &mut self,
+        t: TypeRef<'gcx>,
+        subst: &HashMap<u32, TypeRef<'gcx>>,
+    ) -> TypeRef<'gcx> {
+        let t = self.shallow_resolve(t);
+        match t.kind() {
+            TypeKind::Infer(var) => subst.get(var).cloned().unwrap_or(t),
+            TypeKind::Tuple(ts) => {
+                let ts = ts.iter().map(|t| self.substitute(t, subst)).collect::<Vec<_>>();
+                self.tuple_type(&ts)
+            }
+            TypeKind::Fn(ts, u) => {
+                let ts = ts.iter().map(|t| self.substitute(t, subst)).collect::<Vec<_>>();
+                let u = self.substitute(u, subst);
+                self.fn_type(&ts, u)
+            }
+            _ => t,
+        }
     }
 
-    pub fn eq_type(&mut self, t: TypeRef<'gcx>, u: TypeRef<'gcx>) -> bool {
+    // Unbound variables occurring in `t`, as roots.
+    fn free_vars(&mut self, t: TypeRef<'gcx>, vars: &mut Vec<u32>) {
+        let t = self.shallow_resolve(t);
+        match t.kind() {
+            TypeKind::Infer(var) if !vars.contains(var) => vars.push(*var),
+            TypeKind::Tuple(ts) => ts.iter().for_each(|t| self.free_vars(t, vars)),
+            TypeKind::Fn(ts, u) => {
+                ts.iter().for_each(|t| self.free_vars(t, vars));
+                self.free_vars(u, vars);
+            }
+            _ => (),
+        }
+    }
+
+    pub fn local_ids(&self) -> Vec<HilId> {
+        self.locals.keys().cloned().collect()
+    }
+
+    // Generalizes the type of local `hil_id` over the variables which do not
+    // occur in the types of the locals in `env`.
+    pub fn generalize(&mut self, hil_id: HilId, env: &[HilId]) {
+        let mut vars = vec![];
+        self.free_vars(self.locals[&hil_id], &mut vars);
+        let env = env.iter().map(|id| self.locals[id]).collect::<Vec<_>>();
+        let mut env_vars = vec![];
+        env.into_iter().for_each(|t| self.free_vars(t, &mut env_vars));
+        vars.retain(|var| !env_vars.contains(var));
+        if !vars.is_empty() {
+            self.schemes.insert(hil_id, vars);
+        }
+    }
+
+    fn instantiate(&mut self, hil_id: HilId, t: TypeRef<'gcx>) -> TypeRef<'gcx> {
+        let vars = match self.schemes.get(&hil_id) {
+            Some(vars) => vars.clone(),
+            None => return t,
+        };
+        let subst = vars
+            .into_iter()
+            .map(|var| {
+                let origin = self.infer.origin(var).to_string();
+                (var, self.new_var(&origin))
+            })
+            .collect::<HashMap<_, _>>();
+        self.substitute(t, &subst)
+    }
+
+    fn occurs(&mut self, var: u32, t: TypeRef<'gcx>) -> bool {
+        let mut vars = vec![];
+        self.free_vars(t, &mut vars);
+        vars.contains(&self.infer.find(var))
+    }
+
+    // Makes `t` and `u` the same type by binding inference variables.
+    pub fn unify(&mut self, t: TypeRef<'gcx>, u: TypeRef<'gcx>) -> bool {
+        let t = self.shallow_resolve(t);
+        let u = self.shallow_resolve(u);
+        if std::ptr::eq(t, u) {
+            return true;
+        }
         match (t.kind(), u.kind()) {
+            (TypeKind::Error, _) | (_, TypeKind::Error) => true,
+            (TypeKind::Infer(a), TypeKind::Infer(b)) => {
+                self.infer.union(*a, *b);
+                true
+            }
+            (TypeKind::Infer(var), _) => self.bind_var(*var, u),
+            (_, TypeKind::Infer(var)) => self.bind_var(*var, t),
+            (TypeKind::Bool, TypeKind::Bool)
+            | (TypeKind::Int, TypeKind::Int)
+            | (TypeKind::Never, TypeKind::Never) => true,
             (TypeKind::Adt(adt_def_t, _), TypeKind::Adt(adt_def_u, _)) => {
-                return adt_def_t.def_id == adt_def_u.def_id;
+                adt_def_t.def_id == adt_def_u.def_id
+            }
+            (TypeKind::Tuple(ts), TypeKind::Tuple(us)) => {
+                ts.len() == us.len() && ts.iter().zip(us.iter()).all(|(t, u)| self.unify(t, u))
             }
-            _ => panic!(),
+            (TypeKind::Fn(ts, t), TypeKind::Fn(us, u)) => {
+                ts.len() == us.len()
+                    && ts.iter().zip(us.iter()).all(|(t, u)| self.unify(t, u))
+                    && self.unify(t, u)
+            }
+            _ => false,
         }
     }
 
-    pub fn sub_type(&mut self, t: TypeRef<'gcx>, u: TypeRef<'gcx>) -> bool {
-        self.eq_type(t, u)
+    fn bind_var(&mut self, var: u32, t: TypeRef<'gcx>) -> bool {
+        if self.occurs(var, t) {
+            return false;
+        }
+        self.infer.bind(var, t);
+        true
     }
 
-    pub fn check_expr(&mut self, dst: TypeRef<'gcx>, expr: &Node) {
-        assert_eq!(expr.car().as_symbol().unwrap(), "Expr");
+    // Whether a value of type `u` can be used where `t` is expected. A
+    // diverging expression never produces a value so it fits anywhere.
+    pub fn sub_type(&mut self, t: TypeRef<'gcx>, u: TypeRef<'gcx>) -> bool {
+        self.is_never(u) || self.unify(t, u)
+    }
 
-        let src = self.infer_expr(expr);
+    pub fn check_type(&mut self, dst: TypeRef<'gcx>, src: TypeRef<'gcx>) {
         if !self.sub_type(dst, src) {
-            panic!()
-        }
-    }
-
-    pub fn infer_expr(&mut self, expr: &Node) -> TypeRef<'gcx> {
-        assert_eq!(expr.car().as_symbol().unwrap(), "Expr");
-        let kind = node_get_attr(expr, "kind").as_keyword().unwrap();
-        if kind == "qpath" {
-            let qpath = node_get_field(expr, 0).as_cons().unwrap();
-            assert_eq!(qpath.car().as_symbol().unwrap(), "Path");
-            let kind = node_get_attr(qpath, "kind").as_keyword().unwrap();
-            if kind == "local" {
-                let hil_id = HilId::from_s_expr(node_get_attr(qpath, "local"));
-                return self.locals.get(&hil_id).unwrap();
-            } else {
-                panic!("Cannot infer type for Path kind {}", kind);
+            report_error(Error::MismatchedTypes(self.display(dst), self.display(src)));
+        }
+    }
+
+    pub fn join(&mut self, t: TypeRef<'gcx>, u: TypeRef<'gcx>) -> TypeRef<'gcx> {
+        if self.is_never(t) {
+            return u;
+        }
+        if !self.sub_type(t, u) {
+            report_error(Error::IncompatibleBranches(self.display(t), self.display(u)));
+        }
+        t
+    }
+
+    // Types in diagnostics are printed with their variables resolved.
+    pub fn display(&mut self, t: TypeRef<'gcx>) -> String {
+        self.resolve_type(t).to_string()
+    }
+
+    // Reports the variables which are still unbound at the end of a defn,
+    // once per equivalence class, and resolves the types of locals.
+    fn resolve_fully(&mut self, t: TypeRef<'gcx>, error_type: TypeRef<'gcx>) -> TypeRef<'gcx> {
+        let mut vars = vec![];
+        self.free_vars(t, &mut vars);
+        let subst = vars.into_iter().map(|var| (var, error_type)).collect();
+        self.substitute(t, &subst)
+    }
+
+    pub fn finish(&mut self) {
+        let mut generalized = vec![];
+        for vars in self.schemes.values() {
+            generalized.extend(vars.iter().cloned());
+        }
+        let generalized = generalized
+            .into_iter()
+            .map(|var| self.infer.find(var))
+            .collect::<HashSet<_>>();
+        let mut reported = HashSet::new();
+        for var in 0..u32::try_from(self.infer.len()).unwrap() {
+            let root = self.infer.find(var);
+            if self.infer.probe(root).is_none()
+                && !generalized.contains(&root)
+                && reported.insert(root)
+            {
+                report_error(Error::TypeAnnotationsNeeded(self.infer.origin(var).to_string()));
+            }
+        }
+        // The generalized variables of let-bound closures are left unbound
+        // too, as closures are not specialized per use after type checking.
+        let error_type = self.error_type();
+        let locals = self
+            .locals
+            .iter()
+            .map(|(hil_id, t)| (*hil_id, *t))
+            .collect::<Vec<_>>();
+        for (hil_id, t) in locals {
+            let t = self.resolve_fully(t, error_type);
+            self.locals.insert(hil_id, t);
+        }
+        let expr_types = self
+            .expr_types
+            .iter()
+            .map(|(hil_id, t)| (*hil_id, *t))
+            .collect::<Vec<_>>();
+        for (hil_id, t) in expr_types {
+            let t = self.resolve_fully(t, error_type);
+            self.expr_types.insert(hil_id, t);
+        }
+        // Bounds which are not traits were reported when collected.
+        for (t, trait_def_id) in std::mem::take(&mut self.obligations) {
+            let trait_def = match self.global_ctx.trait_def(&trait_def_id) {
+                Some(trait_def) => trait_def,
+                None => continue,
+            };
+            if !self.holds(t, &trait_def_id) {
+                report_error(Error::UnsatisfiedBound(trait_def.name.clone(), self.display(t)));
+            }
+        }
+    }
+
+    // Whether `t` implements the trait. Unresolved types were already
+    // reported so they are accepted.
+    fn holds(&mut self, t: TypeRef<'gcx>, trait_def_id: &DefId) -> bool {
+        let t = self.resolve_type(t);
+        match t.kind() {
+            TypeKind::Infer(_) | TypeKind::Error => true,
+            TypeKind::Param(param) => self
+                .generics
+                .get(param.index as usize)
+                .is_some_and(|x| x.bounds.contains(trait_def_id)),
+            _ => !self.global_ctx.find_impls(trait_def_id, t).is_empty(),
+        }
+    }
+
+    pub fn infer_path(&mut self, qpath: &Node) -> TypeRef<'gcx> {
+        assert_eq!(qpath.car().as_symbol().unwrap(), "Path");
+        let kind = node_get_attr(qpath, "kind").as_keyword().unwrap();
+        if kind == "local" {
+            let hil_id = HilId::from_s_expr(node_get_attr(qpath, "local"));
+            let t = self.locals[&hil_id];
+            self.instantiate(hil_id, t)
+        } else if kind == "def" {
+            let def_id = DefId::from_s_expr(node_get_attr(qpath, "def"));
+            let mut t = self.type_of(&def_id);
+            if let Some(via) = node_find_attr(qpath, "via") {
+                let refinements = self.global_ctx.refinements_of(&DefId::from_s_expr(via));
+                t = self.global_ctx.subst_opaque(t, &refinements);
+            }
+            let generics = self.global_ctx.generics_of(&def_id);
+            if generics.is_empty() {
+                return t;
             }
+            // Each use of a generic defn gets fresh variables for its
+            // parameters, which must satisfy the bounds.
+            let params = generics
+                .iter()
+                .map(|param| {
+                    let var = self.new_var(&param.name);
+                    for bound in &param.bounds {
+                        self.obligations.push((var, bound.clone()));
+                    }
+                    var
+                })
+                .collect::<Vec<_>>();
+            self.subst_params(t, &params)
         } else {
-            panic!("Cannot infer type for Expr kind {}", kind);
+            panic!("Cannot infer type for Path kind {}", kind);
+        }
+    }
+
+    pub fn infer_lit(&mut self, lit: &Value) -> TypeRef<'gcx> {
+        if lit.is_boolean() {
+            self.bool_type()
+        } else if lit.is_i64() {
+            self.int_type()
+        } else {
+            panic!("Cannot infer type for literal {}", lit);
+        }
+    }
+
+    pub fn is_error(&mut self, t: TypeRef<'gcx>) -> bool {
+        matches!(self.shallow_resolve(t).kind(), TypeKind::Error)
+    }
+
+    pub fn is_never(&mut self, t: TypeRef<'gcx>) -> bool {
+        matches!(self.shallow_resolve(t).kind(), TypeKind::Never)
+    }
+
+    // Operations on a diverging operand diverge too.
+    pub fn infer_unary(&mut self, op: UnOp, t: TypeRef<'gcx>) -> TypeRef<'gcx> {
+        if self.is_never(t) {
+            return self.never_type();
+        }
+        let ret = match op {
+            UnOp::Neg => self.int_type(),
+            UnOp::Not => self.bool_type(),
+        };
+        if !self.unify(ret, t) {
+            report_error(Error::InvalidUnaryOperand(
+                op.symbol().to_string(),
+                self.display(t),
+            ));
+        }
+        ret
+    }
+
+    pub fn infer_binary(
+        &mut self,
+        op: BinOp,
+        t: TypeRef<'gcx>,
+        u: TypeRef<'gcx>,
+    ) -> TypeRef<'gcx> {
+        let diverges = self.is_never(t) || self.is_never(u);
+        let (ok, ret) = if op.is_arith() {
+            let int_type = self.int_type();
+            (self.sub_type(int_type, t) && self.sub_type(int_type, u), int_type)
+        } else if op == BinOp::Eq || op == BinOp::Ne {
+            // Operands which are still unknown are allowed, their type is
+            // reported at the end of the defn if it never gets resolved.
+            let ok = diverges
+                || self.unify(t, u)
+                    && matches!(
+                        self.shallow_resolve(t).kind(),
+                        TypeKind::Int | TypeKind::Bool | TypeKind::Infer(_) | TypeKind::Error
+                    );
+            (ok, self.bool_type())
+        } else if op.is_comparison() {
+            let int_type = self.int_type();
+            (self.sub_type(int_type, t) && self.sub_type(int_type, u), self.bool_type())
+        } else {
+            let bool_type = self.bool_type();
+            (self.sub_type(bool_type, t) && self.sub_type(bool_type, u), bool_type)
+        };
+        if !ok {
+            report_error(Error::InvalidBinaryOperands(
+                op.symbol().to_string(),
+                self.display(t),
+                self.display(u),
+            ));
+        }
+        if diverges {
+            return self.never_type();
         }
+        ret
     }
 }
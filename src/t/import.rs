@@ -0,0 +1,254 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::bui::{Bui, BuiConstValue, BuiDefn, BuiInterface, BuiItemRef, BuiTy};
+use crate::context::GlobalContext;
+use crate::def::DefId;
+use crate::t::const_eval::ConstValue;
+use crate::t::{
+    AdtDef, FieldDef, GenericParamDef, ImplDef, ModuleSig, TraitDef, TypeRef, VariantDef,
+};
+
+// Registers the classes, signatures, traits, interfaces, impls and const
+// values which the BUI of an imported unit gives, for units importing it to
+// be type-checked against them. The unit numbers its own items 0 in its BUI,
+// they are renumbered to `unit_num`.
+pub fn import_items<'gcx>(ctx: &'gcx GlobalContext<'gcx>, unit_num: u16, bui: &Bui) {
+    let reader = ItemReader { ctx, unit_num };
+    let items = bui.items();
+    // Classes and the types declared by interfaces first, for the types of
+    // the other items to refer to them. Interfaces share the types they
+    // include.
+    let mut decls = HashSet::new();
+    for item in &items {
+        if let Some(iface) = item.interface() {
+            for ((name, _), def) in iface.types().iter().zip(iface.type_defs()) {
+                let Some(def_id) = reader.item_ref(&def) else {
+                    continue;
+                };
+                if decls.insert(def_id.clone()) {
+                    let adt_def = AdtDef::new(def_id, name.to_string(), vec![], HashMap::new());
+                    ctx.register_adt_def(adt_def);
+                }
+            }
+        }
+        let Some(class) = item.class() else {
+            continue;
+        };
+        let fields = class
+            .fields()
+            .iter()
+            .map(|x| FieldDef {
+                def_id: reader.def_id(x.def()),
+                name: x.name().to_string(),
+                is_public: x.is_public(),
+            })
+            .collect();
+        let variant = VariantDef {
+            name: item.ident().to_string(),
+            fields,
+        };
+        let methods = class
+            .methods()
+            .iter()
+            .zip(class.method_defs())
+            .map(|((name, _), def)| (name.to_string(), reader.def_id(def)))
+            .collect();
+        let def_id = reader.def_id(item.def());
+        ctx.register_adt_def(AdtDef::new(
+            def_id,
+            item.ident().to_string(),
+            vec![variant],
+            methods,
+        ));
+    }
+    for item in &items {
+        let def_id = reader.def_id(item.def());
+        if let Some(class) = item.class() {
+            for field in class.fields() {
+                ctx.set_type_of(reader.def_id(field.def()), reader.ty(&field.ty(), &[]));
+            }
+            for ((_, defn), def) in class.methods().iter().zip(class.method_defs()) {
+                reader.defn(reader.def_id(def), defn, false);
+            }
+        } else if let Some(defn) = item.defn() {
+            reader.defn(def_id, &defn, false);
+        } else if let Some(trait_def) = item.trait_def() {
+            let mut methods = vec![];
+            for ((name, defn), def) in trait_def.methods().iter().zip(trait_def.method_defs()) {
+                reader.defn(reader.def_id(def), defn, true);
+                methods.push((name.to_string(), reader.def_id(def)));
+            }
+            ctx.register_trait_def(TraitDef {
+                def_id,
+                name: item.ident().to_string(),
+                methods,
+            });
+        } else if let Some(iface) = item.interface() {
+            reader.interface(def_id, item.ident(), &iface);
+        } else if let Some(const_def) = item.const_def() {
+            ctx.set_type_of(def_id.clone(), reader.ty(&const_def.ty(), &[]));
+            if const_def.has_value() {
+                if let Some(value) = reader.const_value(&const_def.value()) {
+                    ctx.set_const_value(def_id, value);
+                }
+            }
+        } else if let Some(t) = item.static_ty() {
+            ctx.set_type_of(def_id, reader.ty(&t, &[]));
+        }
+    }
+    // Impls last, their methods being typed after the ones of their trait.
+    for item in &items {
+        let Some(impl_def) = item.impl_def() else {
+            continue;
+        };
+        let Some(trait_def) = reader
+            .item_ref(&impl_def.trait_ref())
+            .and_then(|x| ctx.trait_def(&x))
+        else {
+            continue;
+        };
+        let self_ty = reader.ty(&impl_def.self_ty(), &[]);
+        let mut methods = HashMap::new();
+        for (name, method) in impl_def.methods() {
+            let Some(method) = reader.item_ref(&method) else {
+                continue;
+            };
+            if let Some(decl) = trait_def.method(name).and_then(|x| ctx.type_of(x)) {
+                ctx.set_type_of(method.clone(), ctx.subst_params(decl, &[self_ty]));
+            }
+            methods.insert(name.to_string(), method);
+        }
+        ctx.register_impl_def(ImplDef {
+            def_id: reader.def_id(item.def()),
+            trait_def_id: trait_def.def_id.clone(),
+            self_ty,
+            methods,
+        });
+    }
+}
+
+struct ItemReader<'gcx> {
+    ctx: &'gcx GlobalContext<'gcx>,
+    unit_num: u16,
+}
+
+impl<'gcx> ItemReader<'gcx> {
+    fn def_id(&self, def: u32) -> DefId {
+        DefId::from_raw(self.unit_num, def)
+    }
+
+    // Items of other units are numbered by the imported unit, which only
+    // its own number can be translated from.
+    fn item_ref(&self, item_ref: &BuiItemRef) -> Option<DefId> {
+        (item_ref.unit() == 0).then(|| self.def_id(item_ref.def()))
+    }
+
+    // Registers the signature and the generic parameters of a defn, `Self`
+    // being parameter 0 of the methods of traits.
+    fn defn(&self, def_id: DefId, defn: &BuiDefn, in_trait: bool) {
+        let generics = defn
+            .generics()
+            .iter()
+            .map(|x| GenericParamDef {
+                name: x.name().to_string(),
+                bounds: x.bounds().iter().filter_map(|x| self.item_ref(x)).collect(),
+            })
+            .collect::<Vec<_>>();
+        let mut params = generics.iter().map(|x| x.name.as_str()).collect::<Vec<_>>();
+        if in_trait {
+            params.insert(0, "Self");
+        }
+        let sig = defn.fn_sig();
+        let decl = sig.decl();
+        let inputs = decl
+            .inputs()
+            .iter()
+            .map(|t| self.ty(t, &params))
+            .collect::<Vec<_>>();
+        let output = self.ty(&decl.output(), &params);
+        self.ctx.set_type_of(def_id.clone(), self.ctx.fn_type(&inputs, output));
+        if !generics.is_empty() {
+            self.ctx.set_generics_of(def_id, generics);
+        }
+    }
+
+    // Transparent types of the interface are the ones refined by `with type`,
+    // members of other units are left out.
+    fn interface(&self, def_id: DefId, ident: &str, iface: &BuiInterface) {
+        let mut sig = ModuleSig {
+            name: ident.to_string(),
+            ..Default::default()
+        };
+        let mut refinements = HashMap::new();
+        for ((name, decl), def) in iface.types().iter().zip(iface.type_defs()) {
+            let Some(decl_def_id) = self.item_ref(&def) else {
+                continue;
+            };
+            if let Some(t) = decl.transparent() {
+                refinements.insert(decl_def_id.clone(), self.ty(&t, &[]));
+            }
+            sig.types.push((name.to_string(), decl_def_id));
+        }
+        for ((name, defn), def) in iface.defns().iter().zip(iface.defn_defs()) {
+            let Some(decl_def_id) = self.item_ref(&def) else {
+                continue;
+            };
+            self.defn(decl_def_id.clone(), defn, false);
+            sig.defns.push((name.to_string(), decl_def_id));
+        }
+        if !refinements.is_empty() {
+            self.ctx.set_refinements(def_id.clone(), refinements);
+        }
+        self.ctx.register_interface(def_id, sig);
+    }
+
+    // Classes of other units are unknown, types naming them are errors.
+    fn ty(&self, t: &BuiTy, params: &[&str]) -> TypeRef<'gcx> {
+        if t.is_bool() {
+            self.ctx.bool_type()
+        } else if t.is_int() {
+            self.ctx.int_type()
+        } else if let Some(elems) = t.tuple() {
+            let elems = elems.iter().map(|t| self.ty(t, params)).collect::<Vec<_>>();
+            self.ctx.tuple_type(&elems)
+        } else if let Some(decl) = t.fn_decl() {
+            let inputs = decl
+                .inputs()
+                .iter()
+                .map(|t| self.ty(t, params))
+                .collect::<Vec<_>>();
+            self.ctx.fn_type(&inputs, self.ty(&decl.output(), params))
+        } else if let Some(index) = t.param() {
+            match params.get(index as usize) {
+                Some(name) => self.ctx.param_type(index, name),
+                None => self.ctx.error_type(),
+            }
+        } else {
+            match t.adt().and_then(|x| self.item_ref(&x)) {
+                Some(def_id) => self.ctx.adt_type(self.ctx.adt_def(def_id)),
+                None => self.ctx.error_type(),
+            }
+        }
+    }
+
+    fn const_value(&self, value: &BuiConstValue) -> Option<ConstValue> {
+        if let Some(b) = value.bool() {
+            Some(ConstValue::Bool(b))
+        } else if let Some(i) = value.int() {
+            Some(ConstValue::Int(i))
+        } else if let Some(elems) = value.tuple() {
+            let elems = elems
+                .iter()
+                .map(|x| self.const_value(x))
+                .collect::<Option<Vec<_>>>()?;
+            Some(ConstValue::Tuple(elems))
+        } else {
+            let (adt, fields) = value.adt()?;
+            let fields = fields
+                .iter()
+                .map(|x| self.const_value(x))
+                .collect::<Option<Vec<_>>>()?;
+            Some(ConstValue::Adt(self.item_ref(&adt)?, fields))
+        }
+    }
+}
@@ -0,0 +1,89 @@
+use crate::t::TypeRef;
+
+struct VarData<'gcx> {
+    parent: u32,
+    rank: u32,
+    value: Option<TypeRef<'gcx>>,
+    // Name of the local or parameter the variable was created for, used to
+    // report variables which are never resolved.
+    origin: String,
+}
+
+// Union-find table of the type inference variables of a defn. Only the root
+// of an equivalence class carries a value.
+pub struct InferTable<'gcx> {
+    vars: Vec<VarData<'gcx>>,
+}
+
+impl<'gcx> Default for InferTable<'gcx> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'gcx> InferTable<'gcx> {
+    pub fn new() -> Self {
+        InferTable { vars: vec![] }
+    }
+
+    pub fn len(&self) -> usize {
+        self.vars.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vars.is_empty()
+    }
+
+    pub fn new_var(&mut self, origin: String) -> u32 {
+        let var = u32::try_from(self.vars.len()).unwrap();
+        self.vars.push(VarData {
+            parent: var,
+            rank: 0,
+            value: None,
+            origin,
+        });
+        var
+    }
+
+    pub fn find(&mut self, var: u32) -> u32 {
+        let parent = self.vars[var as usize].parent;
+        if parent == var {
+            return var;
+        }
+        let root = self.find(parent);
+        self.vars[var as usize].parent = root;
+        root
+    }
+
+    pub fn probe(&mut self, var: u32) -> Option<TypeRef<'gcx>> {
+        let root = self.find(var);
+        self.vars[root as usize].value
+    }
+
+    pub fn origin(&self, var: u32) -> &str {
+        &self.vars[var as usize].origin
+    }
+
+    pub fn bind(&mut self, var: u32, t: TypeRef<'gcx>) {
+        let root = self.find(var);
+        assert!(self.vars[root as usize].value.is_none());
+        self.vars[root as usize].value = Some(t);
+    }
+
+    // Both variables must be unbound.
+    pub fn union(&mut self, a: u32, b: u32) {
+        let (a, b) = (self.find(a), self.find(b));
+        if a == b {
+            return;
+        }
+        let (rank_a, rank_b) = (self.vars[a as usize].rank, self.vars[b as usize].rank);
+        if rank_a < rank_b {
+            self.vars[a as usize].parent = b;
+        } else {
+            self.vars[b as usize].parent = a;
+            if rank_a == rank_b {
+                self.vars[a as usize].rank += 1;
+            }
+        }
+    }
+}
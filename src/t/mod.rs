@@ -1,14 +1,22 @@
+pub mod const_eval;
 pub mod context;
+pub mod import;
+pub mod infer;
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 
+use crate::ast::is_public;
 use crate::context::GlobalContext;
 use crate::hil::{
-    hil_get_unit_path, walk_hil_node, Hil, HilId, HilVisitor, Node,
+    hil_get_unit_path, walk_hil, walk_hil_node, Hil, HilId, HilVisitor, Node,
 };
 use crate::def::{DefId, DefLocalId};
-use crate::s_expr::{node_get_attr, node_get_field};
+use crate::error::{report_error, report_warning, Error, Warning};
+use crate::op::{BinOp, UnOp};
+use crate::s_expr::{node_find_attr, node_get_attr, node_get_field, node_get_fields};
 
+use const_eval::ConstEvaluator;
 use context::{TyCtx, UnitPath};
 
 pub type TypeRef<'gcx> = &'gcx Type<'gcx>;
@@ -20,6 +28,7 @@ pub enum GenericArg<'gcx> {
 pub struct FieldDef {
     pub def_id: DefId,
     pub name: String,
+    pub is_public: bool,
 }
 
 pub struct VariantDef {
@@ -29,22 +38,104 @@ pub struct VariantDef {
 
 pub struct AdtDef {
     pub def_id: DefId,
+    pub name: String,
     variants: Vec<VariantDef>,
+    // Methods defined inside of the class, by name.
+    methods: HashMap<String, DefId>,
 }
 
 impl AdtDef {
-    pub fn new(def_id: DefId) -> Self {
+    pub fn new(
+        def_id: DefId,
+        name: String,
+        variants: Vec<VariantDef>,
+        methods: HashMap<String, DefId>,
+    ) -> Self {
         AdtDef {
             def_id,
-            variants: vec![],
+            name,
+            variants,
+            methods,
         }
     }
+
+    pub fn method(&self, name: &str) -> Option<&DefId> {
+        self.methods.get(name)
+    }
+
+    pub fn variants(&self) -> &[VariantDef] {
+        &self.variants
+    }
+
+    // Classes have exactly one variant.
+    pub fn fields(&self) -> &[FieldDef] {
+        self.variants
+            .first()
+            .map(|v| v.fields.as_slice())
+            .unwrap_or(&[])
+    }
+
+    pub fn field_index(&self, name: &str) -> Option<usize> {
+        self.fields().iter().position(|f| f.name == name)
+    }
+}
+
+pub struct TraitDef {
+    pub def_id: DefId,
+    pub name: String,
+    // Method names and the `DefId`s of their declarations.
+    pub methods: Vec<(String, DefId)>,
+}
+
+impl TraitDef {
+    pub fn method(&self, name: &str) -> Option<&DefId> {
+        self.methods.iter().find(|(n, _)| n == name).map(|(_, d)| d)
+    }
+}
+
+pub struct ImplDef<'gcx> {
+    pub def_id: DefId,
+    pub trait_def_id: DefId,
+    pub self_ty: TypeRef<'gcx>,
+    pub methods: HashMap<String, DefId>,
+}
+
+// Types and defn signatures of a module, in declaration order.
+#[derive(Clone, Default)]
+pub struct ModuleInstance<'gcx> {
+    pub types: Vec<(String, TypeRef<'gcx>)>,
+    pub defns: Vec<(String, TypeRef<'gcx>)>,
+}
+
+#[derive(Clone)]
+pub struct GenericParamDef {
+    pub name: String,
+    // Traits the parameter is bounded by.
+    pub bounds: Vec<DefId>,
+}
+
+// Generic parameters are numbered per item, `Self` is parameter 0 of a
+// trait.
+#[derive(PartialEq, Eq, Hash, Clone)]
+pub struct ParamTy {
+    pub index: u32,
+    pub name: String,
 }
 
 pub enum TypeKind<'gcx> {
     Bool,
+    Int,
+    Never,
+    // The type of expressions which failed to type check, compatible with
+    // every other type so that an error is only reported once.
+    Error,
     Adt(&'gcx AdtDef, &'gcx [GenericArg<'gcx>]),
     Tuple(&'gcx [TypeRef<'gcx>]),
+    // Inputs and output of a function or closure.
+    Fn(&'gcx [TypeRef<'gcx>], TypeRef<'gcx>),
+    // Type inference variable, only meaningful inside its defn's `TyCtx`.
+    Infer(u32),
+    Param(ParamTy),
 }
 
 pub struct Type<'gcx> {
@@ -52,6 +143,30 @@ pub struct Type<'gcx> {
 }
 
 impl<'gcx> Type<'gcx> {
+    pub fn make_bool() -> Self {
+        Type {
+            kind: TypeKind::Bool,
+        }
+    }
+
+    pub fn make_int() -> Self {
+        Type {
+            kind: TypeKind::Int,
+        }
+    }
+
+    pub fn make_never() -> Self {
+        Type {
+            kind: TypeKind::Never,
+        }
+    }
+
+    pub fn make_error() -> Self {
+        Type {
+            kind: TypeKind::Error,
+        }
+    }
+
     pub fn make_adt(def: &'gcx AdtDef) -> Self {
         Type {
             kind: TypeKind::Adt(def, &[]),
@@ -64,11 +179,67 @@ impl<'gcx> Type<'gcx> {
         }
     }
 
-    pub fn kind(&self) -> &TypeKind {
+    pub fn make_fn(inputs: &'gcx [TypeRef<'gcx>], output: TypeRef<'gcx>) -> Self {
+        Type {
+            kind: TypeKind::Fn(inputs, output),
+        }
+    }
+
+    pub fn make_param(param: ParamTy) -> Self {
+        Type {
+            kind: TypeKind::Param(param),
+        }
+    }
+
+    pub fn make_infer(var: u32) -> Self {
+        Type {
+            kind: TypeKind::Infer(var),
+        }
+    }
+
+    pub fn kind(&self) -> &TypeKind<'gcx> {
         &self.kind
     }
 }
 
+impl<'gcx> fmt::Display for Type<'gcx> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.kind {
+            TypeKind::Bool => write!(f, "bool"),
+            TypeKind::Int => write!(f, "int"),
+            TypeKind::Never => write!(f, "!"),
+            TypeKind::Error => write!(f, "{{error}}"),
+            TypeKind::Infer(_) => write!(f, "_"),
+            TypeKind::Param(param) => write!(f, "{}", param.name),
+            TypeKind::Adt(adt_def, _) => write!(f, "{}", adt_def.name),
+            TypeKind::Tuple(elems) => {
+                write!(f, "(")?;
+                for (i, elem) in elems.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", elem)?;
+                }
+                // `(int,)` is a tuple, `(int)` is just a parenthesized type.
+                if elems.len() == 1 {
+                    write!(f, ",")?;
+                }
+                write!(f, ")")
+            }
+            TypeKind::Fn(inputs, output) => {
+                write!(f, "fn(")?;
+                for (i, input) in inputs.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", input)?;
+                }
+                write!(f, ") -> {}", output)
+            }
+        }
+    }
+}
+
 pub struct TypeCheckContext<'gcx> {
     ty_ctxs: HashMap<DefId, TyCtx<'gcx>>,
 }
@@ -79,6 +250,11 @@ impl<'gcx> TypeCheckContext<'gcx> {
             ty_ctxs: HashMap::new(),
         }
     }
+
+    // Typing results of a defn, const or static.
+    pub fn ty_ctx(&self, def_id: &DefId) -> Option<&TyCtx<'gcx>> {
+        self.ty_ctxs.get(def_id)
+    }
 }
 
 pub struct UnitTypeChecker<'gcx> {
@@ -102,16 +278,28 @@ impl<'gcx> UnitTypeChecker<'gcx> {
     }
 
     fn check_defn(&mut self, node: &Node) {
-        let ty_ctx = TyCtx::new(self.global_ctx, &self.unit_path);
+        let def_local_id = DefLocalId::from_s_expr(node_get_attr(node, "def_id"));
+        let mut ty_ctx = TyCtx::new(self.global_ctx, &self.unit_path);
+        ty_ctx.set_generics(self.global_ctx.generics_of(&def_local_id.to_def_id()));
         let mut defn_type_checker = DefnTypeChecker::new(ty_ctx);
         walk_hil_node(&mut defn_type_checker, node);
-        let def_local_id = DefLocalId::from_s_expr(node_get_attr(node, "def_id"));
         self.ctx.ty_ctxs.insert(
             def_local_id.to_def_id(),
             defn_type_checker.collect(),
         );
     }
 
+    // The initializer of a const or static is checked like the body of a
+    // defn returning its type.
+    fn check_const(&mut self, node: &Node) {
+        let def_id = DefLocalId::from_s_expr(node_get_attr(node, "def_id")).to_def_id();
+        let ty_ctx = TyCtx::new(self.global_ctx, &self.unit_path);
+        let mut defn_type_checker = DefnTypeChecker::new(ty_ctx);
+        let t = defn_type_checker.ty_ctx.type_of(&def_id);
+        defn_type_checker.check_expr(t, node_get_field(node, 1).as_cons().unwrap());
+        self.ctx.ty_ctxs.insert(def_id, defn_type_checker.collect());
+    }
+
     fn collect(self) -> TypeCheckContext<'gcx> {
         self.ctx
     }
@@ -122,6 +310,8 @@ impl<'a> HilVisitor for UnitTypeChecker<'a> {
         let kind = node_get_attr(node, "kind").as_keyword().unwrap();
         if kind == "defn" {
             self.check_defn(node);
+        } else if kind == "const" || kind == "static" {
+            self.check_const(node);
         }
         self.item_num += 1;
     }
@@ -134,9 +324,672 @@ impl<'a> HilVisitor for UnitTypeChecker<'a> {
     }
 }
 
+struct PendingImpl {
+    def_id: DefId,
+    trait_def_id: DefId,
+    self_ty: Node,
+    methods: Vec<(String, DefId)>,
+}
+
+// Include of an interface, whose members go at `types` and `defns` in the
+// signature of the including one.
+struct PendingInclude {
+    types: usize,
+    defns: usize,
+    include: Node,
+}
+
+struct PendingApply {
+    def_id: DefId,
+    functor: DefId,
+    args: Vec<DefId>,
+}
+
+// Members of an interface, module or functor body, by name.
+#[derive(Clone, Default)]
+pub struct ModuleSig {
+    pub name: String,
+    pub types: Vec<(String, DefId)>,
+    pub defns: Vec<(String, DefId)>,
+}
+
+// Registers the classes and function signatures of a unit before any
+// function body is checked, so that items can be used before they are
+// defined.
+struct ItemCollector<'gcx> {
+    global_ctx: &'gcx GlobalContext<'gcx>,
+    item_names: HashMap<DefId, String>,
+    ty_aliases: Vec<(DefId, Node)>,
+    // Declared types of fields, consts and statics.
+    def_tys: Vec<(DefId, Node)>,
+    fn_sigs: Vec<(DefId, Node)>,
+    impls: Vec<PendingImpl>,
+    ifaces: HashMap<DefId, ModuleSig>,
+    // Includes of each interface, expanded once every interface is collected
+    // as an interface may include one defined after it.
+    includes: Vec<(DefId, Vec<PendingInclude>)>,
+    // Opaque types declared by interfaces.
+    opaque_tys: HashSet<DefId>,
+    modules: HashMap<DefId, ModuleSig>,
+    // Interfaces of the parameters and body of each functor.
+    functors: HashMap<DefId, (Vec<DefId>, ModuleSig)>,
+    applications: Vec<PendingApply>,
+}
+
+fn path_def_id(node: &Node) -> DefId {
+    let path = node_get_field(node, 0).as_cons().unwrap();
+    DefId::from_s_expr(node_get_field(path, 0))
+}
+
+impl<'gcx> ItemCollector<'gcx> {
+    fn new(global_ctx: &'gcx GlobalContext<'gcx>) -> Self {
+        ItemCollector {
+            global_ctx,
+            item_names: HashMap::new(),
+            ty_aliases: vec![],
+            def_tys: vec![],
+            fn_sigs: vec![],
+            impls: vec![],
+            ifaces: HashMap::new(),
+            includes: vec![],
+            opaque_tys: HashSet::new(),
+            modules: HashMap::new(),
+            functors: HashMap::new(),
+            applications: vec![],
+        }
+    }
+
+    fn item_name(&self, def_id: &DefId) -> String {
+        self.item_names
+            .get(def_id)
+            .cloned()
+            .unwrap_or_else(|| def_id.to_string())
+    }
+
+    // Collects the `Decl`s of a `Signature` or the `Binding`s of a
+    // `Structure`. Opaque types of interfaces are distinct classes without
+    // fields, the others alias their definition. Private bindings are not
+    // part of the signature.
+    fn collect_members(&mut self, item: &Node, members: &Node) -> ModuleSig {
+        let mut sig = ModuleSig {
+            name: node_get_attr(item, "ident").to_string(),
+            types: vec![],
+            defns: vec![],
+        };
+        let mut includes = vec![];
+        for member in node_get_fields(members) {
+            let member = member.as_cons().unwrap();
+            if member.car().as_symbol() == Some("Include") {
+                includes.push(PendingInclude {
+                    types: sig.types.len(),
+                    defns: sig.defns.len(),
+                    include: member.clone(),
+                });
+                continue;
+            }
+            let ident = node_get_attr(member, "ident").as_symbol().unwrap();
+            let def_id = DefLocalId::from_s_expr(node_get_attr(member, "def_id")).to_def_id();
+            let public = member.car().as_symbol() == Some("Decl") || is_public(member);
+            match node_get_attr(member, "kind").as_keyword() {
+                Some("ty") => {
+                    match node_get_fields(member).first() {
+                        Some(ty) => self
+                            .ty_aliases
+                            .push((def_id.clone(), ty.as_cons().unwrap().clone())),
+                        None => {
+                            self.global_ctx.register_adt_def(AdtDef::new(
+                                def_id.clone(),
+                                ident.to_string(),
+                                vec![],
+                                HashMap::new(),
+                            ));
+                            self.opaque_tys.insert(def_id.clone());
+                        }
+                    }
+                    if public {
+                        sig.types.push((ident.to_string(), def_id));
+                    }
+                }
+                Some("defn") => {
+                    // Bindings are collected by `visit_binding`.
+                    if member.car().as_symbol() == Some("Decl") {
+                        self.collect_defn(member);
+                    }
+                    if public {
+                        sig.defns.push((ident.to_string(), def_id));
+                    }
+                }
+                _ => (),
+            }
+        }
+        if !includes.is_empty() {
+            let item_def_id = DefLocalId::from_s_expr(node_get_attr(item, "def_id")).to_def_id();
+            self.includes.push((item_def_id, includes));
+        }
+        sig
+    }
+
+    // Interfaces of the unit or of the units it imports.
+    fn interface(&self, def_id: &DefId) -> Option<ModuleSig> {
+        match self.ifaces.get(def_id) {
+            Some(sig) => Some(sig.clone()),
+            None => self.global_ctx.interface(def_id),
+        }
+    }
+
+    // Expands the includes of `iface`, after the ones of the interfaces it
+    // includes. They are taken out first, so that cyclic includes end.
+    fn expand_includes(&mut self, iface: &DefId) {
+        let includes = match self.includes.iter_mut().find(|(def_id, _)| def_id == iface) {
+            Some((_, includes)) => std::mem::take(includes),
+            None => return,
+        };
+        // Members of the earlier includes shift the later ones.
+        let (mut types, mut defns) = (0, 0);
+        for mut include in includes {
+            include.types += types;
+            include.defns += defns;
+            let (t, d) = self.collect_include(iface, include);
+            types += t;
+            defns += d;
+        }
+    }
+
+    // Adds the members of the included interface to the signature of
+    // `iface`, returning how many types and defns it added. Only opaque
+    // types which were not refined yet can be refined, all the types of an
+    // imported interface being opaque or refined.
+    fn collect_include(&mut self, iface: &DefId, pending: PendingInclude) -> (usize, usize) {
+        let iface_ref = node_get_field(&pending.include, 0).as_cons().unwrap();
+        let included = path_def_id(iface_ref);
+        self.expand_includes(&included);
+        let included_sig = match self.interface(&included) {
+            Some(included_sig) => included_sig,
+            None => {
+                report_error(Error::NotAnInterface(self.item_name(&included)));
+                return (0, 0);
+            }
+        };
+        let mut refinements = self.global_ctx.refinements_of(&included);
+        for with in node_get_fields(iface_ref).iter().skip(1) {
+            let fields = node_get_fields(with.as_cons().unwrap());
+            let name = fields[0].as_symbol().unwrap();
+            let decl = included_sig
+                .types
+                .iter()
+                .find(|(n, _)| n == name)
+                .map(|(_, def_id)| def_id.clone())
+                .filter(|def_id| {
+                    (self.opaque_tys.contains(def_id) || !def_id.is_local())
+                        && !refinements.contains_key(def_id)
+                });
+            match decl {
+                Some(decl) => {
+                    let t = self.global_ctx.interned_type(fields[1].as_cons().unwrap());
+                    refinements.insert(decl, t);
+                }
+                None => report_error(Error::NotAnOpaqueType(
+                    name.to_string(),
+                    included_sig.name.clone(),
+                )),
+            }
+        }
+        if !refinements.is_empty() {
+            let mut all = self.global_ctx.refinements_of(iface);
+            all.extend(refinements);
+            self.global_ctx.set_refinements(iface.clone(), all);
+        }
+        let added = (included_sig.types.len(), included_sig.defns.len());
+        if let Some(sig) = self.ifaces.get_mut(iface) {
+            sig.types
+                .splice(pending.types..pending.types, included_sig.types);
+            sig.defns
+                .splice(pending.defns..pending.defns, included_sig.defns);
+        }
+        added
+    }
+
+    fn collect_functor(&mut self, node: &Node) {
+        let def_id = DefLocalId::from_s_expr(node_get_attr(node, "def_id")).to_def_id();
+        let params = node_get_field(node, 0)
+            .as_slice()
+            .unwrap()
+            .iter()
+            .map(|x| {
+                let iface = node_get_field(x.as_cons().unwrap(), 1).as_cons().unwrap();
+                path_def_id(iface)
+            })
+            .collect();
+        let body = self.collect_members(node, node_get_field(node, 1).as_cons().unwrap());
+        self.functors.insert(def_id, (params, body));
+    }
+
+    fn collect_apply(&mut self, node: &Node) {
+        let mut refs = node_get_fields(node)
+            .iter()
+            .map(|x| path_def_id(x.as_cons().unwrap()))
+            .collect::<Vec<_>>();
+        let functor = refs.remove(0);
+        self.applications.push(PendingApply {
+            def_id: DefLocalId::from_s_expr(node_get_attr(node, "def_id")).to_def_id(),
+            functor,
+            args: refs,
+        });
+    }
+
+    // Checks that `module` provides the members of `iface`, recording the
+    // types it gives to the opaque types of `iface` in `subst`.
+    fn match_module(
+        &self,
+        iface_def_id: &DefId,
+        module: &ModuleSig,
+        subst: &mut HashMap<DefId, TypeRef<'gcx>>,
+    ) {
+        let iface = match self.interface(iface_def_id) {
+            Some(iface) => iface,
+            None => return,
+        };
+        // Refined types are transparent.
+        subst.extend(self.global_ctx.refinements_of(iface_def_id));
+        let mismatch = |name: &str, expected: TypeRef, found: TypeRef| {
+            report_error(Error::IncompatibleMember(
+                name.to_string(),
+                iface.name.clone(),
+                expected.to_string(),
+                found.to_string(),
+            ));
+        };
+        for (name, decl) in &iface.types {
+            let found = match module.types.iter().find(|(n, _)| n == name) {
+                Some((_, def_id)) => self.global_ctx.ty_alias(def_id).unwrap(),
+                None => {
+                    report_error(Error::MissingMember(name.clone(), iface.name.clone()));
+                    continue;
+                }
+            };
+            let expected = match self.global_ctx.ty_alias(decl) {
+                Some(t) => Some(self.global_ctx.subst_opaque(t, subst)),
+                None => subst.get(decl).cloned(),
+            };
+            match expected {
+                Some(expected) => {
+                    if !std::ptr::eq(expected, found) {
+                        mismatch(name, expected, found);
+                    }
+                }
+                None => {
+                    subst.insert(decl.clone(), found);
+                }
+            }
+        }
+        for (name, decl) in &iface.defns {
+            let found = match module.defns.iter().find(|(n, _)| n == name) {
+                Some((_, def_id)) => self.global_ctx.type_of(def_id).unwrap(),
+                None => {
+                    report_error(Error::MissingMember(name.clone(), iface.name.clone()));
+                    continue;
+                }
+            };
+            let expected = self
+                .global_ctx
+                .subst_opaque(self.global_ctx.type_of(decl).unwrap(), subst);
+            if !std::ptr::eq(expected, found) {
+                mismatch(name, expected, found);
+            }
+        }
+    }
+
+    // Checks the arguments of a functor application against the interfaces
+    // of the parameters and records the resulting module.
+    fn check_apply(&self, pending: &PendingApply) {
+        let (params, body) = match self.functors.get(&pending.functor) {
+            Some(functor) => functor,
+            None => {
+                report_error(Error::NotAFunctor(self.item_name(&pending.functor)));
+                return;
+            }
+        };
+        if params.len() != pending.args.len() {
+            report_error(Error::WrongArgumentCount(params.len(), pending.args.len()));
+            return;
+        }
+        let mut subst = HashMap::new();
+        for (iface, arg) in params.iter().zip(&pending.args) {
+            let module = match self.modules.get(arg) {
+                Some(module) => module,
+                None => {
+                    report_error(Error::NotAModule(self.item_name(arg)));
+                    return;
+                }
+            };
+            self.match_module(iface, module, &mut subst);
+        }
+        let error_type = self.global_ctx.error_type();
+        let instance = ModuleInstance {
+            types: body
+                .types
+                .iter()
+                .map(|(name, def_id)| {
+                    let t = self.global_ctx.ty_alias(def_id).unwrap_or(error_type);
+                    (name.clone(), self.global_ctx.subst_opaque(t, &subst))
+                })
+                .collect(),
+            defns: body
+                .defns
+                .iter()
+                .map(|(name, def_id)| {
+                    let t = self.global_ctx.type_of(def_id).unwrap();
+                    (name.clone(), self.global_ctx.subst_opaque(t, &subst))
+                })
+                .collect(),
+        };
+        self.global_ctx
+            .set_module_instance(pending.def_id.clone(), instance);
+    }
+
+    fn fn_sig_type(&self, fn_sig: &Node) -> TypeRef<'gcx> {
+        let decl = node_get_field(fn_sig, 0).as_cons().unwrap();
+        let fields = node_get_fields(decl);
+        let inputs = fields[0]
+            .as_slice()
+            .unwrap()
+            .iter()
+            .map(|x| {
+                let ty = node_get_field(x.as_cons().unwrap(), 1);
+                self.global_ctx.interned_type(ty.as_cons().unwrap())
+            })
+            .collect::<Vec<_>>();
+        let output = match fields.get(1) {
+            Some(fn_ret_ty) => self.global_ctx.interned_type(
+                node_get_field(fn_ret_ty.as_cons().unwrap(), 0)
+                    .as_cons()
+                    .unwrap(),
+            ),
+            None => self.global_ctx.unit_type(),
+        };
+        self.global_ctx.fn_type(&inputs, output)
+    }
+
+    fn collect_defn(&mut self, node: &Node) {
+        let def_id = DefLocalId::from_s_expr(node_get_attr(node, "def_id")).to_def_id();
+        let generics = node_get_fields(node_get_field(node, 0).as_cons().unwrap())
+            .iter()
+            .map(|x| {
+                let fields = node_get_fields(x.as_cons().unwrap());
+                GenericParamDef {
+                    name: fields[0].as_symbol().unwrap().to_string(),
+                    bounds: fields[1..]
+                        .iter()
+                        .map(|x| path_def_id(x.as_cons().unwrap()))
+                        .collect(),
+                }
+            })
+            .collect::<Vec<_>>();
+        if !generics.is_empty() {
+            self.global_ctx.set_generics_of(def_id.clone(), generics);
+        }
+        let fn_sig = node_get_field(node, 1).as_cons().unwrap();
+        self.fn_sigs.push((def_id, fn_sig.clone()));
+    }
+
+    // Bounds must be traits, which may be defined after the defn. The others
+    // are reported and dropped.
+    fn check_bounds(&self, def_id: &DefId) {
+        let mut generics = self.global_ctx.generics_of(def_id);
+        if generics.is_empty() {
+            return;
+        }
+        for param in &mut generics {
+            param.bounds.retain(|bound| {
+                let is_trait = self.global_ctx.trait_def(bound).is_some();
+                if !is_trait {
+                    report_error(Error::NotATrait(self.item_name(bound)));
+                }
+                is_trait
+            });
+        }
+        self.global_ctx.set_generics_of(def_id.clone(), generics);
+    }
+
+    fn collect_trait(&mut self, node: &Node) {
+        let def_id = DefLocalId::from_s_expr(node_get_attr(node, "def_id")).to_def_id();
+        let decls = node_get_fields(node_get_field(node, 0).as_cons().unwrap());
+        let methods = decls
+            .iter()
+            .map(|x| x.as_cons().unwrap())
+            .filter(|x| node_get_attr(x, "kind").as_keyword() == Some("defn"))
+            .map(|x| {
+                self.collect_defn(x);
+                let ident = node_get_attr(x, "ident").as_symbol().unwrap();
+                let def_id = DefLocalId::from_s_expr(node_get_attr(x, "def_id")).to_def_id();
+                (ident.to_string(), def_id)
+            })
+            .collect();
+        self.global_ctx.register_trait_def(TraitDef {
+            def_id,
+            name: node_get_attr(node, "ident").to_string(),
+            methods,
+        });
+    }
+
+    fn collect_impl(&mut self, node: &Node) {
+        let fields = node_get_fields(node);
+        let bindings = node_get_fields(fields[2].as_cons().unwrap());
+        let methods = bindings
+            .iter()
+            .map(|x| x.as_cons().unwrap())
+            .filter(|x| node_get_attr(x, "kind").as_keyword() == Some("defn"))
+            .map(|x| {
+                let ident = node_get_attr(x, "ident").as_symbol().unwrap();
+                let def_id = DefLocalId::from_s_expr(node_get_attr(x, "def_id")).to_def_id();
+                (ident.to_string(), def_id)
+            })
+            .collect();
+        self.impls.push(PendingImpl {
+            def_id: DefLocalId::from_s_expr(node_get_attr(node, "def_id")).to_def_id(),
+            trait_def_id: path_def_id(fields[0].as_cons().unwrap()),
+            self_ty: fields[1].as_cons().unwrap().clone(),
+            methods,
+        });
+    }
+
+    // Checks an impl against its trait before registering it.
+    fn check_impl(&self, pending: &PendingImpl) {
+        let trait_def = match self.global_ctx.trait_def(&pending.trait_def_id) {
+            Some(trait_def) => trait_def,
+            None => {
+                report_error(Error::NotATrait(self.item_name(&pending.trait_def_id)));
+                return;
+            }
+        };
+        let self_ty = self.global_ctx.interned_type(&pending.self_ty);
+        if !self
+            .global_ctx
+            .find_impls(&trait_def.def_id, self_ty)
+            .is_empty()
+        {
+            report_error(Error::ConflictingImpls(
+                trait_def.name.clone(),
+                self_ty.to_string(),
+            ));
+            return;
+        }
+        for (name, _) in &trait_def.methods {
+            if !pending.methods.iter().any(|(n, _)| n == name) {
+                report_error(Error::MissingTraitMethod(name.clone(), trait_def.name.clone()));
+            }
+        }
+        for (name, def_id) in &pending.methods {
+            let decl = match trait_def.method(name) {
+                Some(decl) => decl,
+                None => {
+                    report_error(Error::NotATraitMethod(name.clone(), trait_def.name.clone()));
+                    continue;
+                }
+            };
+            let expected = self
+                .global_ctx
+                .subst_params(self.global_ctx.type_of(decl).unwrap(), &[self_ty]);
+            let found = self.global_ctx.type_of(def_id).unwrap();
+            if !std::ptr::eq(expected, found) {
+                report_error(Error::IncompatibleMethod(
+                    name.clone(),
+                    trait_def.name.clone(),
+                    expected.to_string(),
+                    found.to_string(),
+                ));
+            }
+        }
+        self.global_ctx.register_impl_def(ImplDef {
+            def_id: pending.def_id.clone(),
+            trait_def_id: pending.trait_def_id.clone(),
+            self_ty,
+            methods: pending.methods.iter().cloned().collect(),
+        });
+    }
+
+    // Types may refer to classes defined later in the unit, so they are
+    // only interned once every class is registered. Other units see the
+    // interfaces with their includes expanded.
+    fn collect(mut self) {
+        for (def_id, ty) in &self.ty_aliases {
+            self.global_ctx
+                .set_ty_alias(def_id.clone(), self.global_ctx.interned_type(ty));
+        }
+        let ifaces = self
+            .includes
+            .iter()
+            .map(|(def_id, _)| def_id.clone())
+            .collect::<Vec<_>>();
+        for iface in ifaces {
+            self.expand_includes(&iface);
+        }
+        for (def_id, sig) in &self.ifaces {
+            self.global_ctx.register_interface(def_id.clone(), sig.clone());
+        }
+        for (def_id, ty) in &self.def_tys {
+            self.global_ctx
+                .set_type_of(def_id.clone(), self.global_ctx.interned_type(ty));
+        }
+        for (def_id, fn_sig) in &self.fn_sigs {
+            self.check_bounds(def_id);
+            self.global_ctx
+                .set_type_of(def_id.clone(), self.fn_sig_type(fn_sig));
+        }
+        for pending in &self.impls {
+            self.check_impl(pending);
+        }
+        for pending in &self.applications {
+            self.check_apply(pending);
+        }
+    }
+}
+
+impl<'gcx> HilVisitor for ItemCollector<'gcx> {
+    fn visit_binding(&mut self, node: &Node) {
+        if node_get_attr(node, "kind").as_keyword() == Some("defn") {
+            self.collect_defn(node);
+        }
+    }
+
+    fn visit_item(&mut self, node: &Node) {
+        let def_id = DefLocalId::from_s_expr(node_get_attr(node, "def_id")).to_def_id();
+        if let Some(ident) = node_find_attr(node, "ident") {
+            self.item_names.insert(def_id.clone(), ident.to_string());
+        }
+        match node_get_attr(node, "kind").as_keyword() {
+            Some("defn") => self.collect_defn(node),
+            Some("trait") => self.collect_trait(node),
+            Some("impl") => self.collect_impl(node),
+            Some("interface") => {
+                let sig = self.collect_members(node, node_get_field(node, 0).as_cons().unwrap());
+                self.ifaces.insert(def_id.clone(), sig);
+            }
+            Some("module") => {
+                let sig = self.collect_members(node, node_get_field(node, 0).as_cons().unwrap());
+                self.modules.insert(def_id.clone(), sig);
+            }
+            Some("functor") => self.collect_functor(node),
+            Some("apply") => self.collect_apply(node),
+            Some("const") | Some("static") => {
+                let ty = node_get_field(node, 0).as_cons().unwrap();
+                self.def_tys.push((def_id.clone(), ty.clone()));
+            }
+            _ => (),
+        }
+        if node_get_attr(node, "kind").as_keyword() != Some("class") {
+            return;
+        }
+        let name = node_get_attr(node, "ident").to_string();
+        let variant = node_get_field(node, 0).as_cons().unwrap();
+        let fields = node_get_fields(variant)
+            .iter()
+            .map(|x| {
+                let field_def = x.as_cons().unwrap();
+                let def_id =
+                    DefLocalId::from_s_expr(node_get_attr(field_def, "def_id")).to_def_id();
+                let ty = node_get_field(field_def, 1).as_cons().unwrap();
+                self.def_tys.push((def_id.clone(), ty.clone()));
+                FieldDef {
+                    def_id,
+                    name: node_get_field(field_def, 0).as_symbol().unwrap().to_string(),
+                    is_public: is_public(field_def),
+                }
+            })
+            .collect();
+        let variant = VariantDef {
+            name: name.clone(),
+            fields,
+        };
+        // Method signatures are collected by `visit_binding`.
+        let methods = node_get_fields(node)
+            .get(1)
+            .map(|x| node_get_fields(x.as_cons().unwrap()))
+            .unwrap_or_default()
+            .iter()
+            .map(|x| x.as_cons().unwrap())
+            .filter(|x| node_get_attr(x, "kind").as_keyword() == Some("defn"))
+            .map(|x| {
+                let ident = node_get_attr(x, "ident").as_symbol().unwrap();
+                let def_id = DefLocalId::from_s_expr(node_get_attr(x, "def_id")).to_def_id();
+                (ident.to_string(), def_id)
+            })
+            .collect();
+        self.global_ctx
+            .register_adt_def(AdtDef::new(def_id, name, vec![variant], methods));
+    }
+}
+
+struct BlockType<'gcx> {
+    expected: Option<TypeRef<'gcx>>,
+    ty: Option<TypeRef<'gcx>>,
+    diverges: bool,
+    warned: bool,
+}
+
+impl<'gcx> BlockType<'gcx> {
+    fn new(expected: Option<TypeRef<'gcx>>) -> Self {
+        BlockType {
+            expected,
+            ty: None,
+            diverges: false,
+            warned: false,
+        }
+    }
+}
+
+struct LoopType<'gcx> {
+    expected: Option<TypeRef<'gcx>>,
+    ty: Option<TypeRef<'gcx>>,
+    is_while: bool,
+}
+
 pub struct DefnTypeChecker<'gcx> {
     ty_ctx: TyCtx<'gcx>,
-    block_types: Vec<TypeRef<'gcx>>,
+    block_types: Vec<BlockType<'gcx>>,
+    block_result: Option<TypeRef<'gcx>>,
+    loop_types: Vec<LoopType<'gcx>>,
+    ret_type: Option<TypeRef<'gcx>>,
 }
 
 impl<'gcx> DefnTypeChecker<'gcx> {
@@ -144,22 +997,611 @@ impl<'gcx> DefnTypeChecker<'gcx> {
         DefnTypeChecker {
             ty_ctx,
             block_types: vec![],
+            block_result: None,
+            loop_types: vec![],
+            ret_type: None,
         }
     }
 
-    fn get_block_type(&self) -> TypeRef<'gcx> {
-        self.block_types.last().unwrap()
+    fn get_block_type(&mut self) -> &mut BlockType<'gcx> {
+        self.block_types.last_mut().unwrap()
     }
 
-    fn collect(self) -> TyCtx<'gcx> {
+    fn collect(mut self) -> TyCtx<'gcx> {
+        self.ty_ctx.finish();
         self.ty_ctx
     }
+
+    // Walks `block` with `expected` pushed onto the block type stack and
+    // returns the type of the block.
+    fn check_block(&mut self, expected: Option<TypeRef<'gcx>>, block: &Hil) -> TypeRef<'gcx> {
+        assert_eq!(block.as_cons().unwrap().car().as_symbol().unwrap(), "Block");
+        self.block_types.push(BlockType::new(expected));
+        self.visit(block);
+        self.block_result.take().unwrap()
+    }
+
+    fn check_expr(&mut self, dst: TypeRef<'gcx>, expr: &Node) {
+        let src = self.infer_expr_expected(expr, Some(dst));
+        self.ty_ctx.check_type(dst, src);
+    }
+
+    fn infer_expr(&mut self, expr: &Node) -> TypeRef<'gcx> {
+        self.infer_expr_expected(expr, None)
+    }
+
+    fn infer_expr_expected(
+        &mut self,
+        expr: &Node,
+        expected: Option<TypeRef<'gcx>>,
+    ) -> TypeRef<'gcx> {
+        let t = self.infer_expr_kind(expr, expected);
+        let hil_id = HilId::from_s_expr(node_get_attr(expr, "hil_id"));
+        self.ty_ctx.set_expr_type(hil_id, t);
+        t
+    }
+
+    fn infer_expr_kind(
+        &mut self,
+        expr: &Node,
+        expected: Option<TypeRef<'gcx>>,
+    ) -> TypeRef<'gcx> {
+        assert_eq!(expr.car().as_symbol().unwrap(), "Expr");
+        let kind = node_get_attr(expr, "kind").as_keyword().unwrap();
+        let fields = node_get_fields(expr);
+        if kind == "qpath" {
+            self.ty_ctx.infer_path(fields[0].as_cons().unwrap())
+        } else if kind == "lit" {
+            self.ty_ctx.infer_lit(fields[0])
+        } else if kind == "unary" {
+            let op = UnOp::from_s_expr(node_get_attr(expr, "op"));
+            let t = self.infer_expr(fields[0].as_cons().unwrap());
+            self.ty_ctx.infer_unary(op, t)
+        } else if kind == "binary" {
+            let op = BinOp::from_s_expr(node_get_attr(expr, "op"));
+            let t = self.infer_expr(fields[0].as_cons().unwrap());
+            let u = self.infer_expr(fields[1].as_cons().unwrap());
+            self.ty_ctx.infer_binary(op, t, u)
+        } else if kind == "if" {
+            self.infer_if(&fields, expected)
+        } else if kind == "assign" {
+            self.infer_assign(&fields)
+        } else if kind == "loop" || kind == "while" {
+            self.infer_loop(&fields, expected, kind == "while")
+        } else if kind == "break" {
+            self.infer_break(&fields)
+        } else if kind == "continue" {
+            self.ty_ctx.never_type()
+        } else if kind == "return" {
+            self.infer_return(&fields)
+        } else if kind == "tuple" {
+            self.infer_tuple(&fields, expected)
+        } else if kind == "closure" {
+            self.infer_closure(&fields, expected)
+        } else if kind == "call" {
+            self.infer_call(&fields)
+        } else if kind == "method_call" {
+            self.infer_method_call(expr, &fields)
+        } else if kind == "struct" {
+            self.infer_struct(&fields)
+        } else if kind == "field" {
+            self.infer_field(expr, &fields)
+        } else {
+            panic!("Cannot infer type for Expr kind {}", kind);
+        }
+    }
+
+    fn infer_if(&mut self, fields: &[&Hil], expected: Option<TypeRef<'gcx>>) -> TypeRef<'gcx> {
+        let bool_type = self.ty_ctx.bool_type();
+        self.check_expr(bool_type, fields[0].as_cons().unwrap());
+        if fields.len() == 2 {
+            let unit_type = self.ty_ctx.unit_type();
+            self.check_block(Some(unit_type), fields[1]);
+            return unit_type;
+        }
+        let t = self.check_block(expected, fields[1]);
+        let u = self.infer_else(fields[2], expected);
+        if expected.is_some() {
+            t
+        } else {
+            self.ty_ctx.join(t, u)
+        }
+    }
+
+    // Private fields can only be accessed by the unit of their class.
+    fn check_field_access(&self, adt_def: &AdtDef, field_def: &FieldDef) {
+        if !field_def.is_public && !adt_def.def_id.is_local() {
+            report_error(Error::PrivateMember(field_def.name.clone(), adt_def.name.clone()));
+        }
+    }
+
+    // Paths which are not classes are reported by name resolution, which
+    // leaves them unresolved.
+    fn infer_struct(&mut self, fields: &[&Hil]) -> TypeRef<'gcx> {
+        let adt_def = match fields[0].as_cons() {
+            Some(path) => self.ty_ctx.class_def(DefId::from_s_expr(node_get_field(path, 0))),
+            None => None,
+        };
+        let adt_def = match adt_def {
+            Some(adt_def) => adt_def,
+            None => {
+                for field in fields.iter().skip(1) {
+                    self.infer_expr(node_get_field(field.as_cons().unwrap(), 1).as_cons().unwrap());
+                }
+                return self.ty_ctx.error_type();
+            }
+        };
+        let mut initialized = HashSet::new();
+        for field in fields.iter().skip(1) {
+            let field = field.as_cons().unwrap();
+            let value = node_get_field(field, 1).as_cons().unwrap();
+            // Unknown fields are already reported by name resolution.
+            let def_id = match node_find_attr(field, "field") {
+                Some(def_id) => DefId::from_s_expr(def_id),
+                None => {
+                    self.infer_expr(value);
+                    continue;
+                }
+            };
+            if !initialized.insert(def_id.clone()) {
+                let name = node_get_field(field, 0).as_symbol().unwrap();
+                report_error(Error::DuplicateField(name.to_string()));
+            }
+            if let Some(field_def) = adt_def.fields().iter().find(|x| x.def_id == def_id) {
+                self.check_field_access(adt_def, field_def);
+            }
+            let t = self.ty_ctx.type_of(&def_id);
+            self.check_expr(t, value);
+        }
+        for field_def in adt_def.fields() {
+            if !initialized.contains(&field_def.def_id) {
+                report_error(Error::MissingField(
+                    field_def.name.clone(),
+                    adt_def.name.clone(),
+                ));
+            }
+        }
+        self.ty_ctx.adt_type(adt_def)
+    }
+
+    // Elements are checked against the expected tuple type when its arity
+    // matches, so that e.g. `loop` breaks inside elements see their type.
+    fn infer_tuple(
+        &mut self,
+        fields: &[&Hil],
+        expected: Option<TypeRef<'gcx>>,
+    ) -> TypeRef<'gcx> {
+        let elems = fields[0].as_slice().unwrap();
+        let expected = expected.map(|t| self.ty_ctx.shallow_resolve(t));
+        let expected = match expected.map(|t| t.kind()) {
+            Some(TypeKind::Tuple(ts)) if ts.len() == elems.len() => Some(*ts),
+            _ => None,
+        };
+        let ts = elems
+            .iter()
+            .enumerate()
+            .map(|(i, elem)| {
+                let elem = elem.as_cons().unwrap();
+                match expected {
+                    Some(ts) => {
+                        self.check_expr(ts[i], elem);
+                        ts[i]
+                    }
+                    None => self.infer_expr(elem),
+                }
+            })
+            .collect::<Vec<_>>();
+        self.ty_ctx.tuple_type(&ts)
+    }
+
+    // Fields are named for classes and numbered for tuples.
+    fn infer_field(&mut self, expr: &Node, fields: &[&Hil]) -> TypeRef<'gcx> {
+        let t = self.infer_expr(fields[0].as_cons().unwrap());
+        let t = self.ty_ctx.shallow_resolve(t);
+        if self.ty_ctx.is_error(t) {
+            return t;
+        }
+        let field = fields[1];
+        let resolved = match (t.kind(), field.as_symbol(), field.as_u64()) {
+            (TypeKind::Adt(adt_def, _), Some(name), _) => adt_def.field_index(name).map(|i| {
+                let field_def = &adt_def.fields()[i];
+                self.check_field_access(adt_def, field_def);
+                (i, self.ty_ctx.type_of(&field_def.def_id))
+            }),
+            (TypeKind::Tuple(ts), _, Some(i)) => usize::try_from(i)
+                .ok()
+                .and_then(|i| ts.get(i).map(|t| (i, *t))),
+            _ => None,
+        };
+        match resolved {
+            Some((index, field_type)) => {
+                let hil_id = HilId::from_s_expr(node_get_attr(expr, "hil_id"));
+                self.ty_ctx.set_field_index(hil_id, index);
+                field_type
+            }
+            None => {
+                let ty = self.ty_ctx.display(t);
+                report_error(Error::UnknownField(field.to_string(), ty));
+                self.ty_ctx.error_type()
+            }
+        }
+    }
+
+    // Locals and fields of places can be assigned to.
+    fn is_place(place: &Node) -> bool {
+        let fields = node_get_fields(place);
+        match node_get_attr(place, "kind").as_keyword() {
+            Some("qpath") => {
+                let path = fields[0].as_cons().unwrap();
+                node_get_attr(path, "kind").as_keyword() == Some("local")
+            }
+            Some("field") => Self::is_place(fields[0].as_cons().unwrap()),
+            _ => false,
+        }
+    }
+
+    fn infer_assign(&mut self, fields: &[&Hil]) -> TypeRef<'gcx> {
+        let place = fields[0].as_cons().unwrap();
+        if Self::is_place(place) {
+            let t = self.infer_expr(place);
+            self.check_expr(t, fields[1].as_cons().unwrap());
+        } else {
+            report_error(Error::InvalidAssignment);
+            self.infer_expr(fields[1].as_cons().unwrap());
+        }
+        self.ty_ctx.unit_type()
+    }
+
+    // A `while` loop has unit type, a `loop` has the type of the values it
+    // breaks with, or never if it does not break at all.
+    fn infer_loop(
+        &mut self,
+        fields: &[&Hil],
+        expected: Option<TypeRef<'gcx>>,
+        is_while: bool,
+    ) -> TypeRef<'gcx> {
+        let unit_type = self.ty_ctx.unit_type();
+        let body = if is_while {
+            let bool_type = self.ty_ctx.bool_type();
+            self.check_expr(bool_type, fields[0].as_cons().unwrap());
+            fields[1]
+        } else {
+            fields[0]
+        };
+        self.loop_types.push(LoopType {
+            expected,
+            ty: None,
+            is_while,
+        });
+        self.check_block(Some(unit_type), body);
+        let loop_type = self.loop_types.pop().unwrap();
+        if is_while {
+            unit_type
+        } else {
+            loop_type.ty.unwrap_or_else(|| self.ty_ctx.never_type())
+        }
+    }
+
+    fn infer_break(&mut self, fields: &[&Hil]) -> TypeRef<'gcx> {
+        let value = fields.first().map(|x| x.as_cons().unwrap());
+        let (is_while, expected) = match self.loop_types.last() {
+            Some(loop_type) => (loop_type.is_while, loop_type.expected.or(loop_type.ty)),
+            // Already reported by name resolution.
+            None => (true, None),
+        };
+        if is_while {
+            if let Some(value) = value {
+                if !self.loop_types.is_empty() {
+                    report_error(Error::BreakWithValue);
+                }
+                self.infer_expr(value);
+            }
+        } else {
+            let t = match (value, expected) {
+                (Some(value), Some(expected)) => {
+                    self.check_expr(expected, value);
+                    expected
+                }
+                (Some(value), None) => self.infer_expr(value),
+                (None, expected) => {
+                    let unit_type = self.ty_ctx.unit_type();
+                    if let Some(expected) = expected {
+                        self.ty_ctx.check_type(expected, unit_type);
+                    }
+                    unit_type
+                }
+            };
+            let loop_type = self.loop_types.last_mut().unwrap();
+            if loop_type.ty.is_none() {
+                loop_type.ty = Some(t);
+            }
+        }
+        self.ty_ctx.never_type()
+    }
+
+    // Closures without a return type take it from their first `return`.
+    fn infer_return(&mut self, fields: &[&Hil]) -> TypeRef<'gcx> {
+        let value = fields.first().map(|x| x.as_cons().unwrap());
+        match (self.ret_type, value) {
+            (Some(ret_type), Some(value)) => self.check_expr(ret_type, value),
+            (Some(ret_type), None) => {
+                let unit_type = self.ty_ctx.unit_type();
+                self.ty_ctx.check_type(ret_type, unit_type);
+            }
+            (None, Some(value)) => self.ret_type = Some(self.infer_expr(value)),
+            (None, None) => self.ret_type = Some(self.ty_ctx.unit_type()),
+        }
+        self.ty_ctx.never_type()
+    }
+
+    // Parameter and return types which are not annotated are taken from the
+    // expected function type.
+    fn infer_closure(
+        &mut self,
+        fields: &[&Hil],
+        expected: Option<TypeRef<'gcx>>,
+    ) -> TypeRef<'gcx> {
+        let decl = node_get_fields(fields[0].as_cons().unwrap());
+        let params = decl[0].as_slice().unwrap();
+        let expected = expected.map(|t| self.ty_ctx.shallow_resolve(t));
+        let expected = match expected.map(|t| t.kind()) {
+            Some(TypeKind::Fn(inputs, output)) if inputs.len() == params.len() => {
+                Some((*inputs, *output))
+            }
+            _ => None,
+        };
+        let inputs = params
+            .iter()
+            .enumerate()
+            .map(|(i, param)| {
+                let param = param.as_cons().unwrap();
+                let hil_id = HilId::from_s_expr(node_get_attr(param, "hil_id"));
+                let ty = node_get_fields(param)
+                    .get(1)
+                    .map(|x| self.ty_ctx.parse_ty(x.as_cons().unwrap()));
+                let t = match (ty, expected) {
+                    (Some(t), Some((inputs, _))) => {
+                        self.ty_ctx.check_type(inputs[i], t);
+                        t
+                    }
+                    (Some(t), None) => t,
+                    (None, Some((inputs, _))) => inputs[i],
+                    (None, None) => {
+                        let ident = node_get_field(param, 0).as_symbol().unwrap();
+                        self.ty_ctx.new_var(ident)
+                    }
+                };
+                self.ty_ctx.add_local(hil_id, t);
+                t
+            })
+            .collect::<Vec<_>>();
+        let ret_ty = match decl.get(1) {
+            Some(fn_ret_ty) => Some(self.ty_ctx.parse_ty(
+                node_get_field(fn_ret_ty.as_cons().unwrap(), 0)
+                    .as_cons()
+                    .unwrap(),
+            )),
+            None => expected.map(|(_, output)| output),
+        };
+        let outer_ret_type = std::mem::replace(&mut self.ret_type, ret_ty);
+        let outer_loop_types = std::mem::take(&mut self.loop_types);
+        let t = self.check_block(ret_ty, fields[1]);
+        let output = match (ret_ty, self.ret_type) {
+            (None, Some(ret_type)) => {
+                self.ty_ctx.check_type(ret_type, t);
+                ret_type
+            }
+            _ => ret_ty.unwrap_or(t),
+        };
+        self.ret_type = outer_ret_type;
+        self.loop_types = outer_loop_types;
+        self.ty_ctx.fn_type(&inputs, output)
+    }
+
+    fn infer_call(&mut self, fields: &[&Hil]) -> TypeRef<'gcx> {
+        let callee = self.infer_expr(fields[0].as_cons().unwrap());
+        let args = fields[1]
+            .as_slice()
+            .unwrap()
+            .iter()
+            .map(|x| x.as_cons().unwrap())
+            .collect::<Vec<_>>();
+        // A callee of unknown type is a function of the given arity.
+        if let Some(origin) = self.ty_ctx.var_origin(callee) {
+            let inputs = args
+                .iter()
+                .map(|_| self.ty_ctx.new_var(&origin))
+                .collect::<Vec<_>>();
+            let output = self.ty_ctx.new_var(&origin);
+            let fn_type = self.ty_ctx.fn_type(&inputs, output);
+            self.ty_ctx.unify(callee, fn_type);
+        }
+        let callee = self.ty_ctx.shallow_resolve(callee);
+        match callee.kind() {
+            TypeKind::Fn(inputs, output) if inputs.len() == args.len() => {
+                inputs
+                    .iter()
+                    .zip(args)
+                    .for_each(|(t, arg)| self.check_expr(t, arg));
+                output
+            }
+            TypeKind::Fn(inputs, output) => {
+                report_error(Error::WrongArgumentCount(inputs.len(), args.len()));
+                args.into_iter().for_each(|arg| {
+                    self.infer_expr(arg);
+                });
+                output
+            }
+            _ => {
+                if !self.ty_ctx.is_error(callee) {
+                    let ty = self.ty_ctx.display(callee);
+                    report_error(Error::NotCallable(ty));
+                }
+                args.into_iter().for_each(|arg| {
+                    self.infer_expr(arg);
+                });
+                self.ty_ctx.error_type()
+            }
+        }
+    }
+
+    // Candidate methods `name` for a receiver of type `recv`, as the trait
+    // method declarations with the type substituted for `Self`.
+    fn method_candidates(
+        &mut self,
+        recv: TypeRef<'gcx>,
+        name: &str,
+    ) -> Vec<(DefId, TypeRef<'gcx>)> {
+        let mut candidates = vec![];
+        if let TypeKind::Param(param) = recv.kind() {
+            let bounds = self
+                .ty_ctx
+                .generics()
+                .get(param.index as usize)
+                .map(|x| x.bounds.clone())
+                .unwrap_or_default();
+            // Bounds which are not traits were reported when collected.
+            for trait_def in bounds.iter().filter_map(|x| self.ty_ctx.trait_def(x)) {
+                if let Some(decl) = trait_def.method(name) {
+                    let t = self.ty_ctx.subst_params(self.ty_ctx.type_of(decl), &[recv]);
+                    candidates.push((decl.clone(), t));
+                }
+            }
+        } else {
+            // Methods of the class itself shadow the ones of trait impls.
+            if let TypeKind::Adt(adt_def, _) = recv.kind() {
+                if let Some(def_id) = adt_def.method(name) {
+                    return vec![(def_id.clone(), self.ty_ctx.type_of(def_id))];
+                }
+            }
+            for impl_def in self.ty_ctx.impls_for(recv) {
+                if let Some(def_id) = impl_def.methods.get(name) {
+                    candidates.push((def_id.clone(), self.ty_ctx.type_of(def_id)));
+                }
+            }
+        }
+        candidates
+    }
+
+    fn infer_method_call(&mut self, expr: &Node, fields: &[&Hil]) -> TypeRef<'gcx> {
+        let recv = self.infer_expr(fields[0].as_cons().unwrap());
+        let recv = self.ty_ctx.resolve_type(recv);
+        let name = fields[1].as_symbol().unwrap();
+        let args = fields[2]
+            .as_slice()
+            .unwrap()
+            .iter()
+            .map(|x| x.as_cons().unwrap())
+            .collect::<Vec<_>>();
+        let infer_args = |this: &mut Self| {
+            args.iter().for_each(|arg| {
+                this.infer_expr(arg);
+            });
+        };
+        // Methods are only looked up on known types.
+        if self.ty_ctx.is_error(recv) || self.ty_ctx.var_origin(recv).is_some() {
+            infer_args(self);
+            return self.ty_ctx.error_type();
+        }
+        let mut candidates = self.method_candidates(recv, name);
+        if candidates.len() != 1 {
+            let ty = self.ty_ctx.display(recv);
+            if candidates.is_empty() {
+                report_error(Error::UnknownMethod(name.to_string(), ty));
+            } else {
+                report_error(Error::AmbiguousMethod(name.to_string(), ty));
+            }
+            infer_args(self);
+            return self.ty_ctx.error_type();
+        }
+        let (def_id, t) = candidates.pop().unwrap();
+        let hil_id = HilId::from_s_expr(node_get_attr(expr, "hil_id"));
+        self.ty_ctx.set_method_resolution(hil_id, def_id);
+        match t.kind() {
+            // The receiver is the first parameter of the method.
+            TypeKind::Fn(inputs, output) if inputs.len() == args.len() + 1 => {
+                self.ty_ctx.check_type(inputs[0], recv);
+                inputs[1..]
+                    .iter()
+                    .zip(args)
+                    .for_each(|(t, arg)| self.check_expr(t, arg));
+                output
+            }
+            TypeKind::Fn(inputs, output) => {
+                report_error(Error::WrongArgumentCount(
+                    inputs.len().saturating_sub(1),
+                    args.len(),
+                ));
+                infer_args(self);
+                output
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    // Returns the type of the initializer, if any.
+    fn check_local(&mut self, local: &Node) -> Option<TypeRef<'gcx>> {
+        let hil_id = HilId::from_s_expr(node_get_attr(local, "hil_id"));
+        let fields = node_get_fields(local);
+        let find = |head| {
+            fields
+                .iter()
+                .skip(1)
+                .map(|x| x.as_cons().unwrap())
+                .find(|x| x.car().as_symbol() == Some(head))
+        };
+        let ty = find("Ty").map(|x| self.ty_ctx.parse_ty(x));
+        let init = find("Expr");
+        let env = self.ty_ctx.local_ids();
+        let mut init_type = None;
+        let t = match (ty, init) {
+            (Some(t), Some(init)) => {
+                let src = self.infer_expr_expected(init, Some(t));
+                self.ty_ctx.check_type(t, src);
+                init_type = Some(src);
+                t
+            }
+            (Some(t), None) => t,
+            (None, Some(init)) => {
+                let t = self.infer_expr(init);
+                init_type = Some(t);
+                t
+            }
+            (None, None) => self.ty_ctx.new_var(fields[0].as_symbol().unwrap()),
+        };
+        self.ty_ctx.add_local(hil_id, t);
+        // Only closures are generalized, like the value restriction of ML.
+        let is_closure = |init: &Node| node_get_attr(init, "kind").as_keyword() == Some("closure");
+        if ty.is_none() && init.is_some_and(is_closure) {
+            self.ty_ctx.generalize(hil_id, &env);
+        }
+        init_type
+    }
+
+    fn infer_else(&mut self, node: &Hil, expected: Option<TypeRef<'gcx>>) -> TypeRef<'gcx> {
+        let n = node.as_cons().unwrap();
+        if n.car().as_symbol().unwrap() == "Block" {
+            self.check_block(expected, node)
+        } else {
+            self.infer_expr_expected(n, expected)
+        }
+    }
 }
 
 impl<'gcx> HilVisitor for DefnTypeChecker<'gcx> {
+    // Expressions are not walked by the visitor, statements check them
+    // through `check_expr`/`infer_expr` which walk nested blocks themselves.
+    fn visit(&mut self, hil: &Hil) {
+        match hil.as_cons() {
+            Some(node) if node.car().as_symbol() == Some("Stmt") => self.visit_stmt(node),
+            _ => walk_hil(self, hil),
+        }
+    }
+
     fn visit_fn_sig(&mut self, node: &Node) {
         let decl = node_get_field(node, 0).as_cons().unwrap();
-        let params = node_get_field(decl, 0).as_slice().unwrap();
+        let fields = node_get_fields(decl);
+        let params = fields[0].as_slice().unwrap();
         params.iter().for_each(|x| {
             let n = x.as_cons().unwrap();
             let hil_id = HilId::from_s_expr(node_get_attr(n, "hil_id"));
@@ -168,33 +1610,76 @@ impl<'gcx> HilVisitor for DefnTypeChecker<'gcx> {
                 .parse_ty(node_get_field(n, 1).as_cons().unwrap());
             self.ty_ctx.add_local(hil_id, t);
         });
-        let fn_ret_ty = node_get_field(decl, 1).as_cons().unwrap();
-        self.block_types.push(
-            self.ty_ctx
-                .parse_ty(node_get_field(fn_ret_ty, 0).as_cons().unwrap()),
-        );
+        let ret_ty = match fields.get(1) {
+            Some(fn_ret_ty) => self.ty_ctx.parse_ty(
+                node_get_field(fn_ret_ty.as_cons().unwrap(), 0)
+                    .as_cons()
+                    .unwrap(),
+            ),
+            None => self.ty_ctx.unit_type(),
+        };
+        self.ret_type = Some(ret_ty);
+        self.block_types.push(BlockType::new(Some(ret_ty)));
     }
 
     fn visit_stmt(&mut self, node: &Node) {
+        let block_type = self.get_block_type();
+        if block_type.diverges && !block_type.warned {
+            block_type.warned = true;
+            report_warning(Warning::UnreachableCode);
+        }
         let kind = node_get_attr(node, "kind").as_keyword().unwrap();
-        if kind == "expr" {
-            self.ty_ctx.check_expr(
-                self.get_block_type(),
-                node_get_field(node, 0).as_cons().unwrap(),
-            );
+        let inner = node_get_field(node, 0).as_cons().unwrap();
+        let t = if kind == "expr" {
+            let expected = self.get_block_type().expected;
+            let t = self.infer_expr_expected(inner, expected);
+            if let Some(expected) = expected {
+                self.ty_ctx.check_type(expected, t);
+            }
+            self.get_block_type().ty = Some(expected.unwrap_or(t));
+            Some(t)
+        } else if kind == "semi" {
+            Some(self.infer_expr(inner))
+        } else if kind == "let" {
+            self.check_local(inner)
+        } else {
+            None
+        };
+        // Whatever the kind of the statement, the code after a diverging one
+        // is unreachable.
+        if t.is_some_and(|t| self.ty_ctx.is_never(t)) {
+            self.get_block_type().diverges = true;
         }
     }
 
     fn visit_post_block(&mut self, _: &Node) {
-        self.block_types.pop();
+        let block_type = self.block_types.pop().unwrap();
+        let t = match block_type.ty {
+            Some(t) => t,
+            None if block_type.diverges => self.ty_ctx.never_type(),
+            None => {
+                let unit_type = self.ty_ctx.unit_type();
+                if let Some(expected) = block_type.expected {
+                    self.ty_ctx.check_type(expected, unit_type);
+                }
+                unit_type
+            }
+        };
+        self.block_result = Some(t);
     }
 }
 
 pub fn ty_check<'gcx>(global_ctx: &'gcx GlobalContext<'gcx>, hil: &Hil) -> TypeCheckContext<'gcx> {
+    let mut item_collector = ItemCollector::new(global_ctx);
+    item_collector.visit(hil);
+    item_collector.collect();
     let mut type_checker = UnitTypeChecker::new(
         global_ctx,
         hil_get_unit_path(hil),
     );
     type_checker.visit(hil);
+    let mut const_evaluator = ConstEvaluator::new(global_ctx);
+    const_evaluator.visit(hil);
+    const_evaluator.eval_all();
     type_checker.collect()
 }
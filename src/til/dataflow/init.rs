@@ -0,0 +1,93 @@
+use crate::error::{report_error, Error};
+use crate::til::dataflow::{solve, Analysis, BitSet, Direction, Location};
+use crate::til::ssa::{rvalue_operands, terminator_operands};
+use crate::til::{BlockId, Body, Operand, Phi, Place, Statement, Terminator};
+
+// Locals which may not have been assigned yet, by index. A local is
+// definitely initialized where it is not in the set.
+pub struct MaybeUninit;
+
+impl Analysis for MaybeUninit {
+    type Domain = BitSet;
+
+    const DIRECTION: Direction = Direction::Forward;
+
+    fn bottom(&self, body: &Body) -> BitSet {
+        BitSet::new_empty(body.locals.len())
+    }
+
+    fn boundary(&self, body: &Body) -> BitSet {
+        let mut state = BitSet::new_filled(body.locals.len());
+        for local in body.args() {
+            state.remove(local.index());
+        }
+        state
+    }
+
+    fn phi_effect(&self, state: &mut BitSet, phi: &Phi, _block: BlockId) {
+        state.remove(phi.dest.index());
+    }
+
+    // Assigning a field needs the rest of the local to be initialized.
+    fn statement_effect(&self, state: &mut BitSet, statement: &Statement, _location: Location) {
+        let Statement::Assign(place, _) = statement;
+        if place.projection.is_empty() {
+            state.remove(place.local.index());
+        }
+    }
+
+    fn terminator_effect(&self, state: &mut BitSet, terminator: &Terminator, _location: Location) {
+        if let Terminator::Call { dest, .. } = terminator {
+            if dest.projection.is_empty() {
+                state.remove(dest.local.index());
+            }
+        }
+    }
+}
+
+// Places read by the statement or terminator at `location`, including the
+// locals whose field is assigned.
+fn reads<'b>(body: &'b Body, location: Location) -> Vec<&'b Place> {
+    let block = body.block(location.block);
+    let (operands, dest) = match block.statements.get(location.statement) {
+        Some(Statement::Assign(place, rvalue)) => (rvalue_operands(rvalue), Some(place)),
+        None => match &block.terminator {
+            Terminator::Call { dest, .. } => (terminator_operands(&block.terminator), Some(dest)),
+            terminator => (terminator_operands(terminator), None),
+        },
+    };
+    let mut places = operands
+        .into_iter()
+        .filter_map(|x| match x {
+            Operand::Copy(place) => Some(place),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    places.extend(dest.filter(|x| !x.projection.is_empty()));
+    places
+}
+
+// Reports the reads of `let` locals which may not have been assigned, once
+// per local.
+pub fn check_init(body: &Body) {
+    let results = solve(MaybeUninit, body);
+    let mut reported = vec![false; body.locals.len()];
+    for i in 0..body.blocks.len() {
+        let block = BlockId(u32::try_from(i).unwrap());
+        results.visit_block(body, block, |state, location| {
+            for place in reads(body, location) {
+                let local = place.local;
+                if local.index() <= body.arg_count
+                    || !state.contains(local.index())
+                    || reported[local.index()]
+                {
+                    continue;
+                }
+                if let Some(name) = &body.local_decl(local).name {
+                    reported[local.index()] = true;
+                    report_error(Error::PossiblyUninitialized(name.clone()));
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,61 @@
+use crate::til::dataflow::{Analysis, BitSet, Direction, Location};
+use crate::til::ssa::{rvalue_operands, terminator_operands};
+use crate::til::{BlockId, Body, Local, Operand, Phi, Statement, Terminator};
+
+// Locals whose current value may still be read, by index.
+pub struct Liveness;
+
+fn read(state: &mut BitSet, operand: &Operand) {
+    if let Operand::Copy(place) = operand {
+        state.insert(place.local.index());
+    }
+}
+
+impl Analysis for Liveness {
+    type Domain = BitSet;
+
+    const DIRECTION: Direction = Direction::Backward;
+
+    fn bottom(&self, body: &Body) -> BitSet {
+        BitSet::new_empty(body.locals.len())
+    }
+
+    fn boundary(&self, body: &Body) -> BitSet {
+        BitSet::new_empty(body.locals.len())
+    }
+
+    // Phi arguments are taken as read at the start of the block, which keeps
+    // them live on every incoming edge.
+    fn phi_effect(&self, state: &mut BitSet, phi: &Phi, _block: BlockId) {
+        state.remove(phi.dest.index());
+        for (_, arg) in &phi.args {
+            read(state, arg);
+        }
+    }
+
+    fn statement_effect(&self, state: &mut BitSet, statement: &Statement, _location: Location) {
+        let Statement::Assign(place, rvalue) = statement;
+        // Assigning a field keeps the other fields live.
+        if place.projection.is_empty() {
+            state.remove(place.local.index());
+        }
+        for operand in rvalue_operands(rvalue) {
+            read(state, operand);
+        }
+    }
+
+    fn terminator_effect(&self, state: &mut BitSet, terminator: &Terminator, _location: Location) {
+        match terminator {
+            Terminator::Return => {
+                state.insert(Local::RETURN.index());
+            }
+            Terminator::Call { dest, .. } if dest.projection.is_empty() => {
+                state.remove(dest.local.index());
+            }
+            _ => (),
+        }
+        for operand in terminator_operands(terminator) {
+            read(state, operand);
+        }
+    }
+}
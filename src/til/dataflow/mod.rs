@@ -0,0 +1,268 @@
+pub mod init;
+pub mod liveness;
+pub mod reaching;
+
+use std::collections::VecDeque;
+
+use crate::til::dom::Dominators;
+use crate::til::{BlockId, Body, Phi, Statement, Terminator};
+
+// Values of a dataflow analysis, which only grow when joined.
+pub trait Lattice: Clone + PartialEq {
+    // Joins `other` into `self` and returns whether `self` changed.
+    fn join(&mut self, other: &Self) -> bool;
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Forward,
+    Backward,
+}
+
+// Statement `statement` of `block`, the terminator coming after the last
+// statement.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Location {
+    pub block: BlockId,
+    pub statement: usize,
+}
+
+pub trait Analysis {
+    type Domain: Lattice;
+
+    const DIRECTION: Direction;
+
+    // Value of every block before the analysis reaches it.
+    fn bottom(&self, body: &Body) -> Self::Domain;
+
+    // Value at the start of the entry block for forward analyses, and at the
+    // end of the blocks without successors for backward ones.
+    fn boundary(&self, body: &Body) -> Self::Domain;
+
+    // Phis take effect together at the start of their block.
+    fn phi_effect(&self, _state: &mut Self::Domain, _phi: &Phi, _block: BlockId) {}
+
+    fn statement_effect(&self, state: &mut Self::Domain, statement: &Statement, location: Location);
+
+    fn terminator_effect(
+        &self,
+        state: &mut Self::Domain,
+        terminator: &Terminator,
+        location: Location,
+    );
+}
+
+// Fixpoint of an analysis, with the values at the start and at the end of
+// every block in program order.
+pub struct Results<A: Analysis> {
+    pub analysis: A,
+    entry: Vec<A::Domain>,
+    exit: Vec<A::Domain>,
+}
+
+impl<A: Analysis> Results<A> {
+    pub fn entry(&self, block: BlockId) -> &A::Domain {
+        &self.entry[block.index()]
+    }
+
+    pub fn exit(&self, block: BlockId) -> &A::Domain {
+        &self.exit[block.index()]
+    }
+
+    // Calls `f` with the value before every statement and the terminator of
+    // `block` in the direction of the analysis, which for backward analyses
+    // is the value after them in program order.
+    pub fn visit_block(
+        &self,
+        body: &Body,
+        block: BlockId,
+        mut f: impl FnMut(&A::Domain, Location),
+    ) {
+        let data = body.block(block);
+        let terminator = Location {
+            block,
+            statement: data.statements.len(),
+        };
+        match A::DIRECTION {
+            Direction::Forward => {
+                let mut state = self.entry(block).clone();
+                for phi in &data.phis {
+                    self.analysis.phi_effect(&mut state, phi, block);
+                }
+                for (i, statement) in data.statements.iter().enumerate() {
+                    let location = Location {
+                        block,
+                        statement: i,
+                    };
+                    f(&state, location);
+                    self.analysis
+                        .statement_effect(&mut state, statement, location);
+                }
+                f(&state, terminator);
+            }
+            Direction::Backward => {
+                let mut state = self.exit(block).clone();
+                f(&state, terminator);
+                self.analysis
+                    .terminator_effect(&mut state, &data.terminator, terminator);
+                for (i, statement) in data.statements.iter().enumerate().rev() {
+                    let location = Location {
+                        block,
+                        statement: i,
+                    };
+                    f(&state, location);
+                    self.analysis
+                        .statement_effect(&mut state, statement, location);
+                }
+            }
+        }
+    }
+}
+
+// Iterates `analysis` over the blocks of `body` with a worklist until
+// nothing changes. Forward analyses never reach unreachable blocks, which
+// keep the bottom value.
+pub fn solve<A: Analysis>(analysis: A, body: &Body) -> Results<A> {
+    let count = body.blocks.len();
+    let mut entry = vec![analysis.bottom(body); count];
+    let mut exit = entry.clone();
+    let dominators = Dominators::new(body);
+    let preds = body.predecessors();
+    let mut queued = vec![false; count];
+    let mut worklist = VecDeque::new();
+    match A::DIRECTION {
+        Direction::Forward => {
+            entry[BlockId::ENTRY.index()] = analysis.boundary(body);
+            worklist.extend(dominators.reverse_postorder().iter().copied());
+        }
+        Direction::Backward => {
+            for (i, block) in body.blocks.iter().enumerate() {
+                if block.terminator.successors().is_empty() {
+                    exit[i] = analysis.boundary(body);
+                }
+            }
+            // Postorder, followed by the unreachable blocks.
+            worklist.extend(dominators.reverse_postorder().iter().rev().copied());
+            let unreachable = (0..count).map(|x| BlockId(u32::try_from(x).unwrap()));
+            worklist.extend(unreachable.filter(|x| !dominators.is_reachable(*x)));
+        }
+    }
+    for block in &worklist {
+        queued[block.index()] = true;
+    }
+    while let Some(block) = worklist.pop_front() {
+        queued[block.index()] = false;
+        let data = body.block(block);
+        let terminator = Location {
+            block,
+            statement: data.statements.len(),
+        };
+        let (state, next) = match A::DIRECTION {
+            Direction::Forward => {
+                let mut state = entry[block.index()].clone();
+                for phi in &data.phis {
+                    analysis.phi_effect(&mut state, phi, block);
+                }
+                for (i, statement) in data.statements.iter().enumerate() {
+                    let location = Location {
+                        block,
+                        statement: i,
+                    };
+                    analysis.statement_effect(&mut state, statement, location);
+                }
+                analysis.terminator_effect(&mut state, &data.terminator, terminator);
+                exit[block.index()] = state.clone();
+                (state, data.terminator.successors())
+            }
+            Direction::Backward => {
+                let mut state = exit[block.index()].clone();
+                analysis.terminator_effect(&mut state, &data.terminator, terminator);
+                for (i, statement) in data.statements.iter().enumerate().rev() {
+                    let location = Location {
+                        block,
+                        statement: i,
+                    };
+                    analysis.statement_effect(&mut state, statement, location);
+                }
+                for phi in &data.phis {
+                    analysis.phi_effect(&mut state, phi, block);
+                }
+                entry[block.index()] = state.clone();
+                (state, preds[block.index()].clone())
+            }
+        };
+        for next in next {
+            let target = match A::DIRECTION {
+                Direction::Forward => &mut entry[next.index()],
+                Direction::Backward => &mut exit[next.index()],
+            };
+            if target.join(&state) && !queued[next.index()] {
+                queued[next.index()] = true;
+                worklist.push_back(next);
+            }
+        }
+    }
+    Results {
+        analysis,
+        entry,
+        exit,
+    }
+}
+
+// Set of small integers, such as locals or definitions, whose join is the
+// union.
+#[derive(Clone, PartialEq, Eq)]
+pub struct BitSet {
+    words: Vec<u64>,
+}
+
+impl BitSet {
+    pub fn new_empty(len: usize) -> Self {
+        BitSet {
+            words: vec![0; len.div_ceil(64)],
+        }
+    }
+
+    pub fn new_filled(len: usize) -> Self {
+        let mut set = BitSet::new_empty(len);
+        for i in 0..len {
+            set.insert(i);
+        }
+        set
+    }
+
+    // Returns whether `i` was not in the set.
+    pub fn insert(&mut self, i: usize) -> bool {
+        let old = self.words[i / 64];
+        self.words[i / 64] |= 1 << (i % 64);
+        old != self.words[i / 64]
+    }
+
+    // Returns whether `i` was in the set.
+    pub fn remove(&mut self, i: usize) -> bool {
+        let old = self.words[i / 64];
+        self.words[i / 64] &= !(1 << (i % 64));
+        old != self.words[i / 64]
+    }
+
+    pub fn contains(&self, i: usize) -> bool {
+        self.words[i / 64] & (1 << (i % 64)) != 0
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        let len = self.words.len() * 64;
+        (0..len).filter(|x| self.contains(*x))
+    }
+}
+
+impl Lattice for BitSet {
+    fn join(&mut self, other: &Self) -> bool {
+        let mut changed = false;
+        for (word, other) in self.words.iter_mut().zip(&other.words) {
+            let old = *word;
+            *word |= other;
+            changed |= old != *word;
+        }
+        changed
+    }
+}
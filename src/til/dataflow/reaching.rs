@@ -0,0 +1,125 @@
+use std::collections::HashMap;
+
+use crate::til::dataflow::{Analysis, BitSet, Direction, Location};
+use crate::til::{BlockId, Body, Local, Phi, Statement, Terminator};
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum DefSite {
+    // The value passed by the caller.
+    Arg,
+    Phi(BlockId),
+    // An assignment, or the destination of a call.
+    Location(Location),
+}
+
+pub struct Def {
+    pub local: Local,
+    pub site: DefSite,
+    // Assignments to a field leave the other definitions of the local in
+    // place.
+    pub partial: bool,
+}
+
+// Definitions which may have assigned the current value of their local, by
+// index in `defs`.
+pub struct ReachingDefs {
+    pub defs: Vec<Def>,
+    by_local: Vec<Vec<usize>>,
+    by_site: HashMap<(Local, DefSite), usize>,
+}
+
+impl ReachingDefs {
+    pub fn new(body: &Body) -> Self {
+        let mut reaching = ReachingDefs {
+            defs: vec![],
+            by_local: vec![vec![]; body.locals.len()],
+            by_site: HashMap::new(),
+        };
+        for local in body.args() {
+            reaching.add(local, DefSite::Arg, false);
+        }
+        for (i, block) in body.blocks.iter().enumerate() {
+            let block_id = BlockId(u32::try_from(i).unwrap());
+            for phi in &block.phis {
+                reaching.add(phi.dest, DefSite::Phi(block_id), false);
+            }
+            for (j, statement) in block.statements.iter().enumerate() {
+                let Statement::Assign(place, _) = statement;
+                let location = Location {
+                    block: block_id,
+                    statement: j,
+                };
+                let partial = !place.projection.is_empty();
+                reaching.add(place.local, DefSite::Location(location), partial);
+            }
+            if let Terminator::Call { dest, .. } = &block.terminator {
+                let location = Location {
+                    block: block_id,
+                    statement: block.statements.len(),
+                };
+                let partial = !dest.projection.is_empty();
+                reaching.add(dest.local, DefSite::Location(location), partial);
+            }
+        }
+        reaching
+    }
+
+    fn add(&mut self, local: Local, site: DefSite, partial: bool) {
+        let index = self.defs.len();
+        self.defs.push(Def {
+            local,
+            site,
+            partial,
+        });
+        self.by_local[local.index()].push(index);
+        self.by_site.insert((local, site), index);
+    }
+
+    // Definitions of `local`, which are the ones a set can hold for it.
+    pub fn defs_of(&self, local: Local) -> &[usize] {
+        &self.by_local[local.index()]
+    }
+
+    fn define(&self, state: &mut BitSet, local: Local, site: DefSite) {
+        let index = self.by_site[&(local, site)];
+        if !self.defs[index].partial {
+            for def in self.defs_of(local) {
+                state.remove(*def);
+            }
+        }
+        state.insert(index);
+    }
+}
+
+impl Analysis for ReachingDefs {
+    type Domain = BitSet;
+
+    const DIRECTION: Direction = Direction::Forward;
+
+    fn bottom(&self, _body: &Body) -> BitSet {
+        BitSet::new_empty(self.defs.len())
+    }
+
+    fn boundary(&self, body: &Body) -> BitSet {
+        let mut state = self.bottom(body);
+        for local in body.args() {
+            self.define(&mut state, local, DefSite::Arg);
+        }
+        state
+    }
+
+    fn phi_effect(&self, state: &mut BitSet, phi: &Phi, block: BlockId) {
+        self.define(state, phi.dest, DefSite::Phi(block));
+    }
+
+    fn statement_effect(&self, state: &mut BitSet, statement: &Statement, location: Location) {
+        let Statement::Assign(place, _) = statement;
+        self.define(state, place.local, DefSite::Location(location));
+    }
+
+    fn terminator_effect(&self, state: &mut BitSet, terminator: &Terminator, location: Location) {
+        if let Terminator::Call { dest, .. } = terminator {
+            self.define(state, dest.local, DefSite::Location(location));
+        }
+    }
+}
@@ -0,0 +1,154 @@
+use crate::til::{BlockId, Body};
+
+// Dominator tree and dominance frontiers of the blocks reachable from the
+// entry block, computed with the iterative algorithm of Cooper, Harvey and
+// Kennedy.
+pub struct Dominators {
+    // Reachable blocks in reverse postorder, so the entry block comes first
+    // and every block comes after its immediate dominator.
+    rpo: Vec<BlockId>,
+    rpo_index: Vec<Option<usize>>,
+    idom: Vec<Option<BlockId>>,
+    children: Vec<Vec<BlockId>>,
+    frontiers: Vec<Vec<BlockId>>,
+}
+
+impl Dominators {
+    pub fn new(body: &Body) -> Self {
+        let count = body.blocks.len();
+        let rpo = reverse_postorder(body);
+        let mut rpo_index = vec![None; count];
+        for (i, block) in rpo.iter().enumerate() {
+            rpo_index[block.index()] = Some(i);
+        }
+        let preds = body.predecessors();
+        let mut idom = vec![None; count];
+        idom[BlockId::ENTRY.index()] = Some(BlockId::ENTRY);
+        let intersect = |idom: &[Option<BlockId>], mut a: BlockId, mut b: BlockId| {
+            while a != b {
+                while rpo_index[a.index()] > rpo_index[b.index()] {
+                    a = idom[a.index()].unwrap();
+                }
+                while rpo_index[b.index()] > rpo_index[a.index()] {
+                    b = idom[b.index()].unwrap();
+                }
+            }
+            a
+        };
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for block in rpo.iter().skip(1) {
+                let mut new_idom = None;
+                for pred in &preds[block.index()] {
+                    if idom[pred.index()].is_none() {
+                        continue;
+                    }
+                    new_idom = Some(match new_idom {
+                        Some(new_idom) => intersect(&idom, *pred, new_idom),
+                        None => *pred,
+                    });
+                }
+                if new_idom != idom[block.index()] {
+                    idom[block.index()] = new_idom;
+                    changed = true;
+                }
+            }
+        }
+        let mut children = vec![vec![]; count];
+        for block in rpo.iter().skip(1) {
+            children[idom[block.index()].unwrap().index()].push(*block);
+        }
+        let mut frontiers: Vec<Vec<BlockId>> = vec![vec![]; count];
+        for block in &rpo {
+            let preds = preds[block.index()]
+                .iter()
+                .filter(|x| rpo_index[x.index()].is_some())
+                .collect::<Vec<_>>();
+            if preds.len() < 2 {
+                continue;
+            }
+            for pred in preds {
+                let mut runner = *pred;
+                while Some(runner) != idom[block.index()] {
+                    if !frontiers[runner.index()].contains(block) {
+                        frontiers[runner.index()].push(*block);
+                    }
+                    runner = idom[runner.index()].unwrap();
+                }
+            }
+        }
+        idom[BlockId::ENTRY.index()] = None;
+        Dominators {
+            rpo,
+            rpo_index,
+            idom,
+            children,
+            frontiers,
+        }
+    }
+
+    pub fn reverse_postorder(&self) -> &[BlockId] {
+        &self.rpo
+    }
+
+    pub fn is_reachable(&self, block: BlockId) -> bool {
+        self.rpo_index[block.index()].is_some()
+    }
+
+    // The entry block and unreachable blocks have none.
+    pub fn immediate_dominator(&self, block: BlockId) -> Option<BlockId> {
+        self.idom[block.index()]
+    }
+
+    pub fn dominates(&self, a: BlockId, mut b: BlockId) -> bool {
+        if !self.is_reachable(b) {
+            return false;
+        }
+        loop {
+            if a == b {
+                return true;
+            }
+            match self.idom[b.index()] {
+                Some(idom) => b = idom,
+                None => return false,
+            }
+        }
+    }
+
+    // Blocks immediately dominated by `block`.
+    pub fn children(&self, block: BlockId) -> &[BlockId] {
+        &self.children[block.index()]
+    }
+
+    pub fn frontier(&self, block: BlockId) -> &[BlockId] {
+        &self.frontiers[block.index()]
+    }
+}
+
+fn reverse_postorder(body: &Body) -> Vec<BlockId> {
+    let mut visited = vec![false; body.blocks.len()];
+    let mut postorder = vec![];
+    // Blocks with the successors which remain to be visited.
+    let mut stack = vec![(
+        BlockId::ENTRY,
+        body.block(BlockId::ENTRY).terminator.successors(),
+    )];
+    visited[BlockId::ENTRY.index()] = true;
+    while let Some((block, succs)) = stack.last_mut() {
+        match succs.pop() {
+            Some(succ) if !visited[succ.index()] => {
+                visited[succ.index()] = true;
+                let succs = body.block(succ).terminator.successors();
+                stack.push((succ, succs));
+            }
+            Some(_) => (),
+            None => {
+                postorder.push(*block);
+                stack.pop();
+            }
+        }
+    }
+    postorder.reverse();
+    postorder
+}
@@ -0,0 +1,352 @@
+use std::collections::HashMap;
+
+use crate::bui::Bui;
+use crate::bui_capnp;
+use crate::context::GlobalContext;
+use crate::def::DefId;
+use crate::op::{BinOp, UnOp};
+use crate::t::const_eval::ConstValue;
+use crate::t::TypeRef;
+use crate::til::opt::verify;
+use crate::til::{
+    BasicBlock, BlockId, Body, BodyTy, Local, LocalDecl, Operand, Place, Rvalue, Statement,
+    Terminator, Til,
+};
+
+// The TIL of the bodies which the BUI of an imported unit gives for inlining.
+// The unit numbers its own items 0 in its BUI, they are renumbered to
+// `unit_num`. Registering the result with
+// `GlobalContext::register_imported_til` lets the inline pass find the bodies.
+pub fn import_bodies<'gcx>(
+    ctx: &'gcx GlobalContext<'gcx>,
+    unit_num: u16,
+    unit_path: &[String],
+    bui: &Bui,
+) -> Til<'gcx> {
+    let reader = BodyReader { ctx, unit_num };
+    let mut item_paths = HashMap::new();
+    let mut bodies = vec![];
+    for item in bui.items() {
+        let Some(defn) = item.defn() else {
+            continue;
+        };
+        let Some(inline) = defn.inline_body() else {
+            continue;
+        };
+        let def_id = DefId::from_raw(unit_num, item.def());
+        // Bodies this compiler cannot read are called instead.
+        if let Some(body) = reader.body(&def_id, item.ident(), inline) {
+            let mut path = item
+                .namespace()
+                .iter()
+                .map(|x| x.to_string())
+                .collect::<Vec<_>>();
+            path.push(item.ident().to_string());
+            item_paths.insert(def_id, path);
+            bodies.push(body);
+        }
+    }
+    Til {
+        unit_path: unit_path.to_vec(),
+        item_paths,
+        bodies,
+    }
+}
+
+// `til` with the `DefId`s of its unit renumbered to `unit_num` and the
+// `numbers` of its BUI, which other units refer to its items by. Items the
+// BUI does not number are only referred to by `til`, and are numbered after
+// the ones it does.
+pub fn renumber_til<'gcx>(
+    til: &Til<'gcx>,
+    unit_num: u16,
+    numbers: &HashMap<DefId, u32>,
+) -> Til<'gcx> {
+    let mut renumberer = Renumberer {
+        unit_num,
+        numbers: numbers.clone(),
+    };
+    let item_paths = til
+        .item_paths
+        .iter()
+        .map(|(def_id, path)| (renumberer.def_id(def_id), path.clone()))
+        .collect();
+    let bodies = til
+        .bodies
+        .iter()
+        .map(|body| renumberer.body(body))
+        .collect();
+    Til {
+        unit_path: til.unit_path.clone(),
+        item_paths,
+        bodies,
+    }
+}
+
+struct Renumberer {
+    unit_num: u16,
+    numbers: HashMap<DefId, u32>,
+}
+
+impl Renumberer {
+    fn def_id(&mut self, def_id: &DefId) -> DefId {
+        if !def_id.is_local() {
+            return def_id.clone();
+        }
+        let next = u32::try_from(self.numbers.len()).unwrap();
+        let number = *self.numbers.entry(def_id.clone()).or_insert(next);
+        DefId::from_raw(self.unit_num, number)
+    }
+
+    fn body<'gcx>(&mut self, body: &Body<'gcx>) -> Body<'gcx> {
+        let mut body = body.clone();
+        body.ty = match &body.ty {
+            BodyTy::Fn(def_id) => BodyTy::Fn(self.def_id(def_id)),
+            BodyTy::Closure { owner, captures } => BodyTy::Closure {
+                owner: self.def_id(owner),
+                captures: *captures,
+            },
+        };
+        for block in &mut body.blocks {
+            for phi in &mut block.phis {
+                for (_, operand) in &mut phi.args {
+                    self.operand(operand);
+                }
+            }
+            for statement in &mut block.statements {
+                let Statement::Assign(_, rvalue) = statement;
+                match rvalue {
+                    Rvalue::Use(operand) | Rvalue::Unary(_, operand) => self.operand(operand),
+                    Rvalue::Binary(_, lhs, rhs) => {
+                        self.operand(lhs);
+                        self.operand(rhs);
+                    }
+                    Rvalue::Tuple(operands) | Rvalue::Closure(_, operands) => {
+                        operands.iter_mut().for_each(|x| self.operand(x));
+                    }
+                    Rvalue::Adt(def_id, operands) => {
+                        *def_id = self.def_id(def_id);
+                        operands.iter_mut().for_each(|x| self.operand(x));
+                    }
+                }
+            }
+            match &mut block.terminator {
+                Terminator::Branch { cond, .. } => self.operand(cond),
+                Terminator::Call { func, args, .. } => {
+                    self.operand(func);
+                    args.iter_mut().for_each(|x| self.operand(x));
+                }
+                Terminator::Goto(_) | Terminator::Return | Terminator::Unreachable => {}
+            }
+        }
+        body
+    }
+
+    fn operand(&mut self, operand: &mut Operand) {
+        match operand {
+            Operand::Copy(_) => {}
+            Operand::Const(value) => self.const_value(value),
+            Operand::Fn(def_id) | Operand::Static(def_id) => *def_id = self.def_id(def_id),
+        }
+    }
+
+    fn const_value(&mut self, value: &mut ConstValue) {
+        match value {
+            ConstValue::Bool(_) | ConstValue::Int(_) => {}
+            ConstValue::Tuple(values) => values.iter_mut().for_each(|x| self.const_value(x)),
+            ConstValue::Adt(def_id, values) => {
+                *def_id = self.def_id(def_id);
+                values.iter_mut().for_each(|x| self.const_value(x));
+            }
+        }
+    }
+}
+
+struct BodyReader<'gcx> {
+    ctx: &'gcx GlobalContext<'gcx>,
+    unit_num: u16,
+}
+
+impl<'gcx> BodyReader<'gcx> {
+    fn body(
+        &self,
+        def_id: &DefId,
+        name: &str,
+        reader: bui_capnp::til_body::Reader,
+    ) -> Option<Body<'gcx>> {
+        let mut locals = vec![];
+        for local in reader.get_locals().ok()? {
+            locals.push(LocalDecl {
+                ty: self.ty(local.get_ty().ok()?)?,
+                name: local
+                    .has_name()
+                    .then(|| local.get_name().unwrap().to_string()),
+            });
+        }
+        // The return place and the arguments are the first locals.
+        let arg_count = usize::try_from(reader.get_arg_count()).ok()?;
+        if arg_count >= locals.len() {
+            return None;
+        }
+        let mut blocks = vec![];
+        for block in reader.get_blocks().ok()? {
+            let mut statements = vec![];
+            for statement in block.get_statements().ok()? {
+                let place = self.place(statement.get_place().ok()?)?;
+                let rvalue = self.rvalue(statement.get_rvalue().ok()?)?;
+                statements.push(Statement::Assign(place, rvalue));
+            }
+            blocks.push(BasicBlock {
+                phis: vec![],
+                statements,
+                terminator: self.terminator(block.get_terminator().ok()?)?,
+            });
+        }
+        let body = Body {
+            ty: BodyTy::Fn(def_id.clone()),
+            name: name.to_string(),
+            public: true,
+            locals,
+            arg_count,
+            blocks,
+        };
+        verify(&body, false).ok()?;
+        Some(body)
+    }
+
+    // Items of other units are numbered by the imported unit, which only
+    // its own number can be translated from.
+    fn def_id(&self, reader: bui_capnp::item_ref::Reader) -> Option<DefId> {
+        if reader.get_unit() != 0 {
+            return None;
+        }
+        Some(DefId::from_raw(self.unit_num, reader.get_def()))
+    }
+
+    fn ty(&self, reader: bui_capnp::ty::Reader) -> Option<TypeRef<'gcx>> {
+        match reader.which().ok()? {
+            bui_capnp::ty::Bool(()) => Some(self.ctx.bool_type()),
+            bui_capnp::ty::Int(()) => Some(self.ctx.int_type()),
+            bui_capnp::ty::Tuple(elems) => {
+                let elems = elems
+                    .ok()?
+                    .iter()
+                    .map(|t| self.ty(t))
+                    .collect::<Option<Vec<_>>>()?;
+                Some(self.ctx.tuple_type(&elems))
+            }
+            bui_capnp::ty::Fn(decl) => {
+                let decl = decl.ok()?;
+                let inputs = decl
+                    .get_inputs()
+                    .ok()?
+                    .iter()
+                    .map(|t| self.ty(t))
+                    .collect::<Option<Vec<_>>>()?;
+                let output = self.ty(decl.get_output().ok()?)?;
+                Some(self.ctx.fn_type(&inputs, output))
+            }
+            bui_capnp::ty::Adt(_) | bui_capnp::ty::Param(_) => None,
+        }
+    }
+
+    fn const_value(&self, reader: bui_capnp::const_value::Reader) -> Option<ConstValue> {
+        match reader.which().ok()? {
+            bui_capnp::const_value::Bool(b) => Some(ConstValue::Bool(b)),
+            bui_capnp::const_value::Int(i) => Some(ConstValue::Int(i)),
+            bui_capnp::const_value::Tuple(elems) => {
+                let elems = elems
+                    .ok()?
+                    .iter()
+                    .map(|x| self.const_value(x))
+                    .collect::<Option<Vec<_>>>()?;
+                Some(ConstValue::Tuple(elems))
+            }
+            bui_capnp::const_value::Adt(adt) => {
+                let adt = adt.ok()?;
+                let fields = adt
+                    .get_fields()
+                    .ok()?
+                    .iter()
+                    .map(|x| self.const_value(x))
+                    .collect::<Option<Vec<_>>>()?;
+                Some(ConstValue::Adt(self.def_id(adt.get_adt().ok()?)?, fields))
+            }
+        }
+    }
+
+    fn place(&self, reader: bui_capnp::til_place::Reader) -> Option<Place> {
+        let projection = reader
+            .get_projection()
+            .ok()?
+            .iter()
+            .map(|x| usize::try_from(x).ok())
+            .collect::<Option<Vec<_>>>()?;
+        Some(Place {
+            local: Local(reader.get_local()),
+            projection,
+        })
+    }
+
+    fn operand(&self, reader: bui_capnp::til_operand::Reader) -> Option<Operand> {
+        match reader.which().ok()? {
+            bui_capnp::til_operand::Copy(place) => Some(Operand::Copy(self.place(place.ok()?)?)),
+            bui_capnp::til_operand::Const(value) => {
+                Some(Operand::Const(self.const_value(value.ok()?)?))
+            }
+            bui_capnp::til_operand::Fn(def) => Some(Operand::Fn(self.def_id(def.ok()?)?)),
+            bui_capnp::til_operand::Static(def) => Some(Operand::Static(self.def_id(def.ok()?)?)),
+        }
+    }
+
+    fn operands(
+        &self,
+        reader: capnp::struct_list::Reader<bui_capnp::til_operand::Owned>,
+    ) -> Option<Vec<Operand>> {
+        reader.iter().map(|x| self.operand(x)).collect()
+    }
+
+    fn rvalue(&self, reader: bui_capnp::til_rvalue::Reader) -> Option<Rvalue> {
+        match reader.which().ok()? {
+            bui_capnp::til_rvalue::Use(operand) => Some(Rvalue::Use(self.operand(operand.ok()?)?)),
+            bui_capnp::til_rvalue::Unary(unary) => {
+                let unary = unary.ok()?;
+                let op = UnOp::from_keyword(unary.get_op().ok()?)?;
+                Some(Rvalue::Unary(op, self.operand(unary.get_operand().ok()?)?))
+            }
+            bui_capnp::til_rvalue::Binary(binary) => {
+                let binary = binary.ok()?;
+                let op = BinOp::from_keyword(binary.get_op().ok()?)?;
+                let lhs = self.operand(binary.get_lhs().ok()?)?;
+                let rhs = self.operand(binary.get_rhs().ok()?)?;
+                Some(Rvalue::Binary(op, lhs, rhs))
+            }
+            bui_capnp::til_rvalue::Tuple(elems) => Some(Rvalue::Tuple(self.operands(elems.ok()?)?)),
+        }
+    }
+
+    fn terminator(&self, reader: bui_capnp::til_terminator::Reader) -> Option<Terminator> {
+        match reader.which().ok()? {
+            bui_capnp::til_terminator::Goto(target) => Some(Terminator::Goto(BlockId(target))),
+            bui_capnp::til_terminator::Branch(branch) => {
+                let branch = branch.ok()?;
+                Some(Terminator::Branch {
+                    cond: self.operand(branch.get_cond().ok()?)?,
+                    then_block: BlockId(branch.get_then_block()),
+                    else_block: BlockId(branch.get_else_block()),
+                })
+            }
+            bui_capnp::til_terminator::Return(()) => Some(Terminator::Return),
+            bui_capnp::til_terminator::Call(call) => {
+                let call = call.ok()?;
+                Some(Terminator::Call {
+                    func: self.operand(call.get_func().ok()?)?,
+                    args: self.operands(call.get_args().ok()?)?,
+                    dest: self.place(call.get_dest().ok()?)?,
+                    target: BlockId(call.get_target()),
+                })
+            }
+            bui_capnp::til_terminator::Unreachable(()) => Some(Terminator::Unreachable),
+        }
+    }
+}
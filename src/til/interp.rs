@@ -0,0 +1,484 @@
+use std::fmt;
+use std::rc::Rc;
+
+use crate::context::GlobalContext;
+use crate::def::DefId;
+use crate::op::{BinOp, UnOp};
+use crate::t::const_eval::ConstValue;
+use crate::t::TypeRef;
+use crate::til::{BlockId, BodyId, Local, Operand, Place, Rvalue, Statement, Terminator, Til};
+
+pub const DEFAULT_STEP_LIMIT: u64 = 1_000_000;
+pub const DEFAULT_FRAME_LIMIT: usize = 10_000;
+
+#[derive(Clone)]
+pub enum Value<'gcx> {
+    Bool(bool),
+    Int(i64),
+    Tuple(Vec<Value<'gcx>>),
+    // Field values in the order of the fields of the class.
+    Adt(DefId, Vec<Value<'gcx>>),
+    Fn(DefId),
+    Closure(Rc<Til<'gcx>>, BodyId, Vec<Value<'gcx>>),
+}
+
+impl<'gcx> Value<'gcx> {
+    pub fn unit() -> Self {
+        Value::Tuple(vec![])
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_int(&self) -> Option<i64> {
+        match self {
+            Value::Int(i) => Some(*i),
+            _ => None,
+        }
+    }
+
+    fn fields(&self) -> &[Value<'gcx>] {
+        match self {
+            Value::Tuple(fields) | Value::Adt(_, fields) => fields,
+            _ => panic!("Value has no fields"),
+        }
+    }
+
+    fn fields_mut(&mut self) -> &mut Vec<Value<'gcx>> {
+        match self {
+            Value::Tuple(fields) | Value::Adt(_, fields) => fields,
+            _ => panic!("Value has no fields"),
+        }
+    }
+}
+
+impl<'gcx> From<ConstValue> for Value<'gcx> {
+    fn from(value: ConstValue) -> Self {
+        match value {
+            ConstValue::Bool(b) => Value::Bool(b),
+            ConstValue::Int(i) => Value::Int(i),
+            ConstValue::Tuple(values) => {
+                Value::Tuple(values.into_iter().map(Value::from).collect())
+            }
+            ConstValue::Adt(def_id, values) => {
+                Value::Adt(def_id, values.into_iter().map(Value::from).collect())
+            }
+        }
+    }
+}
+
+// Functions are equal when they are the same defn, or closures of the same
+// body with equal captures.
+impl<'gcx> PartialEq for Value<'gcx> {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Int(a), Value::Int(b)) => a == b,
+            (Value::Tuple(a), Value::Tuple(b)) => a == b,
+            (Value::Adt(a, xs), Value::Adt(b, ys)) => a == b && xs == ys,
+            (Value::Fn(a), Value::Fn(b)) => a == b,
+            (Value::Closure(a, i, xs), Value::Closure(b, j, ys)) => {
+                Rc::ptr_eq(a, b) && i == j && xs == ys
+            }
+            _ => false,
+        }
+    }
+}
+
+impl<'gcx> fmt::Display for Value<'gcx> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let write_list = |f: &mut fmt::Formatter, values: &[Value]| {
+            for (i, value) in values.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{}", value)?;
+            }
+            Ok(())
+        };
+        match self {
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Int(i) => write!(f, "{}", i),
+            Value::Tuple(values) => {
+                write!(f, "(")?;
+                write_list(f, values)?;
+                if values.len() == 1 {
+                    write!(f, ",")?;
+                }
+                write!(f, ")")
+            }
+            Value::Adt(def_id, values) => {
+                write!(f, "{} {{ ", def_id)?;
+                write_list(f, values)?;
+                write!(f, " }}")
+            }
+            Value::Fn(def_id) => write!(f, "fn {}", def_id),
+            Value::Closure(til, body_id, _) => write!(f, "{}", til.body(*body_id).name),
+        }
+    }
+}
+
+// A runtime error with the bodies being executed, innermost first.
+pub struct RuntimeError {
+    pub message: String,
+    pub backtrace: Vec<String>,
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Runtime error: {}", self.message)?;
+        for frame in &self.backtrace {
+            write!(f, "\n  in {}", frame)?;
+        }
+        Ok(())
+    }
+}
+
+struct Frame<'gcx> {
+    til: Rc<Til<'gcx>>,
+    body_id: BodyId,
+    locals: Vec<Option<Value<'gcx>>>,
+    block: BlockId,
+    statement: usize,
+    // Where the caller stores the returned value.
+    dest: Option<Place>,
+}
+
+// Executes TIL bodies one statement at a time. Calls push frames instead of
+// recursing, so that deep recursion in the program only runs into the frame
+// limit.
+pub struct Interpreter<'gcx> {
+    global_ctx: &'gcx GlobalContext<'gcx>,
+    frames: Vec<Frame<'gcx>>,
+    steps: u64,
+    step_limit: u64,
+    frame_limit: usize,
+}
+
+impl<'gcx> Interpreter<'gcx> {
+    pub fn new(global_ctx: &'gcx GlobalContext<'gcx>) -> Self {
+        Interpreter {
+            global_ctx,
+            frames: vec![],
+            steps: 0,
+            step_limit: DEFAULT_STEP_LIMIT,
+            frame_limit: DEFAULT_FRAME_LIMIT,
+        }
+    }
+
+    // Number of statements and terminators one call may execute.
+    pub fn set_step_limit(&mut self, step_limit: u64) {
+        self.step_limit = step_limit;
+    }
+
+    pub fn set_frame_limit(&mut self, frame_limit: usize) {
+        self.frame_limit = frame_limit;
+    }
+
+    // Calls the defn `def_id`, whose unit must be registered in the global
+    // context.
+    pub fn call(
+        &mut self,
+        def_id: &DefId,
+        args: Vec<Value<'gcx>>,
+    ) -> Result<Value<'gcx>, RuntimeError> {
+        self.steps = 0;
+        self.frames.clear();
+        self.call_value(Value::Fn(def_id.clone()), args, None)?;
+        self.run()
+    }
+
+    fn error(&self, message: impl Into<String>) -> RuntimeError {
+        let backtrace = self
+            .frames
+            .iter()
+            .rev()
+            .map(|frame| frame.til.body(frame.body_id).name.clone())
+            .collect();
+        RuntimeError {
+            message: message.into(),
+            backtrace,
+        }
+    }
+
+    fn frame(&self) -> &Frame<'gcx> {
+        self.frames.last().unwrap()
+    }
+
+    fn frame_mut(&mut self) -> &mut Frame<'gcx> {
+        self.frames.last_mut().unwrap()
+    }
+
+    fn run(&mut self) -> Result<Value<'gcx>, RuntimeError> {
+        loop {
+            self.steps += 1;
+            if self.steps > self.step_limit {
+                return Err(self.error(format!("step limit of {} exceeded", self.step_limit)));
+            }
+            let frame = self.frame();
+            let til = frame.til.clone();
+            let block = til.body(frame.body_id).block(frame.block);
+            if let Some(statement) = block.statements.get(frame.statement) {
+                self.execute(statement)?;
+                self.frame_mut().statement += 1;
+                continue;
+            }
+            if let Some(value) = self.terminate(&block.terminator)? {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn execute(&mut self, statement: &Statement) -> Result<(), RuntimeError> {
+        match statement {
+            Statement::Assign(place, rvalue) => {
+                let value = self.eval_rvalue(rvalue)?;
+                self.store(place, value)
+            }
+        }
+    }
+
+    // Returns the result of the outermost call once it returns.
+    fn terminate(&mut self, terminator: &Terminator) -> Result<Option<Value<'gcx>>, RuntimeError> {
+        match terminator {
+            Terminator::Goto(target) => self.jump(*target),
+            Terminator::Branch {
+                cond,
+                then_block,
+                else_block,
+            } => {
+                let cond = self.eval_operand(cond)?;
+                let target = if cond.as_bool().unwrap() {
+                    then_block
+                } else {
+                    else_block
+                };
+                self.jump(*target);
+            }
+            Terminator::Return => {
+                let value = self.load(&Place::local(Local::RETURN))?;
+                let frame = self.frames.pop().unwrap();
+                match frame.dest {
+                    Some(dest) => self.store(&dest, value)?,
+                    None => return Ok(Some(value)),
+                }
+            }
+            Terminator::Call {
+                func,
+                args,
+                dest,
+                target,
+            } => {
+                let func = self.eval_operand(func)?;
+                let args = args
+                    .iter()
+                    .map(|arg| self.eval_operand(arg))
+                    .collect::<Result<Vec<_>, _>>()?;
+                // The caller resumes at `target` once the callee returns.
+                self.jump(*target);
+                self.call_value(func, args, Some(dest.clone()))?;
+            }
+            Terminator::Unreachable => return Err(self.error("entered unreachable code")),
+        }
+        Ok(None)
+    }
+
+    fn jump(&mut self, target: BlockId) {
+        let frame = self.frame_mut();
+        frame.block = target;
+        frame.statement = 0;
+    }
+
+    fn call_value(
+        &mut self,
+        func: Value<'gcx>,
+        args: Vec<Value<'gcx>>,
+        dest: Option<Place>,
+    ) -> Result<(), RuntimeError> {
+        let (til, body_id, args) = match func {
+            Value::Fn(def_id) => {
+                let def_id = self.dispatch(def_id, &args);
+                match self.global_ctx.fn_body(&def_id) {
+                    Some((til, body_id)) => (til, body_id, args),
+                    None => return Err(self.error(format!("{} has no body", def_id))),
+                }
+            }
+            Value::Closure(til, body_id, mut captures) => {
+                captures.extend(args);
+                (til, body_id, captures)
+            }
+            _ => unreachable!(),
+        };
+        if self.frames.len() >= self.frame_limit {
+            return Err(self.error(format!("frame limit of {} exceeded", self.frame_limit)));
+        }
+        let body = til.body(body_id);
+        let mut locals = vec![None; body.locals.len()];
+        for (local, arg) in body.args().zip(args) {
+            locals[local.index()] = Some(arg);
+        }
+        self.frames.push(Frame {
+            til,
+            body_id,
+            locals,
+            block: BlockId::ENTRY,
+            statement: 0,
+            dest,
+        });
+        Ok(())
+    }
+
+    // Methods of traits called on generic receivers are looked up in the
+    // impls for the type of the receiver.
+    fn dispatch(&self, def_id: DefId, args: &[Value<'gcx>]) -> DefId {
+        if self.global_ctx.fn_body(&def_id).is_some() {
+            return def_id;
+        }
+        let Some(t) = args.first().and_then(|recv| self.type_of_value(recv)) else {
+            return def_id;
+        };
+        for impl_def in self.global_ctx.impls_for(t) {
+            let trait_def = self.global_ctx.trait_def(&impl_def.trait_def_id).unwrap();
+            let method = trait_def.methods.iter().find(|(_, decl)| *decl == def_id);
+            if let Some(method) = method.and_then(|(name, _)| impl_def.methods.get(name)) {
+                return method.clone();
+            }
+        }
+        def_id
+    }
+
+    fn type_of_value(&self, value: &Value<'gcx>) -> Option<TypeRef<'gcx>> {
+        match value {
+            Value::Bool(_) => Some(self.global_ctx.bool_type()),
+            Value::Int(_) => Some(self.global_ctx.int_type()),
+            Value::Tuple(values) => {
+                let elems = values
+                    .iter()
+                    .map(|x| self.type_of_value(x))
+                    .collect::<Option<Vec<_>>>()?;
+                Some(self.global_ctx.tuple_type(&elems))
+            }
+            Value::Adt(def_id, _) => {
+                Some(self.global_ctx.adt_type(self.global_ctx.adt_def(def_id.clone())))
+            }
+            Value::Fn(_) | Value::Closure(..) => None,
+        }
+    }
+
+    fn load(&self, place: &Place) -> Result<Value<'gcx>, RuntimeError> {
+        let value = match &self.frame().locals[place.local.index()] {
+            Some(value) => value,
+            None => return Err(self.error(format!("use of uninitialized local {}", place.local))),
+        };
+        let value = place
+            .projection
+            .iter()
+            .fold(value, |value, index| &value.fields()[*index]);
+        Ok(value.clone())
+    }
+
+    fn store(&mut self, place: &Place, value: Value<'gcx>) -> Result<(), RuntimeError> {
+        if place.projection.is_empty() {
+            self.frame_mut().locals[place.local.index()] = Some(value);
+            return Ok(());
+        }
+        if self.frame().locals[place.local.index()].is_none() {
+            return Err(self.error(format!("use of uninitialized local {}", place.local)));
+        }
+        let local = self.frame_mut().locals[place.local.index()].as_mut().unwrap();
+        let slot = place
+            .projection
+            .iter()
+            .fold(local, |value, index| &mut value.fields_mut()[*index]);
+        *slot = value;
+        Ok(())
+    }
+
+    fn eval_operand(&self, operand: &Operand) -> Result<Value<'gcx>, RuntimeError> {
+        match operand {
+            Operand::Copy(place) => self.load(place),
+            Operand::Const(value) => Ok(Value::from(value.clone())),
+            Operand::Fn(def_id) => Ok(Value::Fn(def_id.clone())),
+            Operand::Static(def_id) => match self.global_ctx.const_value(def_id) {
+                Some(value) => Ok(Value::from(value)),
+                None => Err(self.error(format!("static {} has no value", def_id))),
+            },
+        }
+    }
+
+    fn eval_operands(&self, operands: &[Operand]) -> Result<Vec<Value<'gcx>>, RuntimeError> {
+        operands.iter().map(|x| self.eval_operand(x)).collect()
+    }
+
+    fn eval_rvalue(&self, rvalue: &Rvalue) -> Result<Value<'gcx>, RuntimeError> {
+        match rvalue {
+            Rvalue::Use(operand) => self.eval_operand(operand),
+            Rvalue::Unary(op, operand) => {
+                let value = self.eval_operand(operand)?;
+                match (op, value) {
+                    (UnOp::Neg, Value::Int(i)) => i
+                        .checked_neg()
+                        .map(Value::Int)
+                        .ok_or_else(|| self.error("attempt to negate with overflow")),
+                    (UnOp::Not, Value::Bool(b)) => Ok(Value::Bool(!b)),
+                    _ => unreachable!(),
+                }
+            }
+            Rvalue::Binary(op, lhs, rhs) => {
+                let lhs = self.eval_operand(lhs)?;
+                let rhs = self.eval_operand(rhs)?;
+                self.eval_binary(*op, lhs, rhs)
+            }
+            Rvalue::Tuple(elems) => Ok(Value::Tuple(self.eval_operands(elems)?)),
+            Rvalue::Adt(def_id, fields) => {
+                Ok(Value::Adt(def_id.clone(), self.eval_operands(fields)?))
+            }
+            Rvalue::Closure(body_id, captures) => Ok(Value::Closure(
+                self.frame().til.clone(),
+                *body_id,
+                self.eval_operands(captures)?,
+            )),
+        }
+    }
+
+    // Arithmetic is checked like in const evaluation.
+    fn eval_binary(
+        &self,
+        op: BinOp,
+        lhs: Value<'gcx>,
+        rhs: Value<'gcx>,
+    ) -> Result<Value<'gcx>, RuntimeError> {
+        if op == BinOp::Eq || op == BinOp::Ne {
+            return Ok(Value::Bool((lhs == rhs) == (op == BinOp::Eq)));
+        }
+        let (a, b) = match (lhs, rhs) {
+            (Value::Int(a), Value::Int(b)) => (a, b),
+            _ => unreachable!(),
+        };
+        let (value, reason) = match op {
+            BinOp::Add => (a.checked_add(b), "attempt to add with overflow"),
+            BinOp::Sub => (a.checked_sub(b), "attempt to subtract with overflow"),
+            BinOp::Mul => (a.checked_mul(b), "attempt to multiply with overflow"),
+            BinOp::Div if b == 0 => (None, "attempt to divide by zero"),
+            BinOp::Div => (a.checked_div(b), "attempt to divide with overflow"),
+            BinOp::Rem if b == 0 => (
+                None,
+                "attempt to calculate the remainder with a divisor of zero",
+            ),
+            BinOp::Rem => (
+                a.checked_rem(b),
+                "attempt to calculate the remainder with overflow",
+            ),
+            BinOp::Lt => return Ok(Value::Bool(a < b)),
+            BinOp::Le => return Ok(Value::Bool(a <= b)),
+            BinOp::Gt => return Ok(Value::Bool(a > b)),
+            BinOp::Ge => return Ok(Value::Bool(a >= b)),
+            BinOp::And | BinOp::Or | BinOp::Eq | BinOp::Ne => unreachable!(),
+        };
+        value.map(Value::Int).ok_or_else(|| self.error(reason))
+    }
+}
@@ -1,12 +1,450 @@
+pub mod dataflow;
+pub mod dom;
+pub mod import;
+pub mod interp;
+pub mod opt;
+pub mod ssa;
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::context::GlobalContext;
+use crate::def::DefId;
+use crate::op::{BinOp, UnOp};
+use crate::t::const_eval::ConstValue;
+use crate::t::{TypeKind, TypeRef};
+
+// Local 0 holds the return value, the arguments are locals 1 to
+// `arg_count`, followed by the user variables and temporaries.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Debug)]
+pub struct Local(pub u32);
+
+impl Local {
+    pub const RETURN: Local = Local(0);
+
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Debug)]
+pub struct BlockId(pub u32);
+
+impl BlockId {
+    pub const ENTRY: BlockId = BlockId(0);
+
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+// Index of a body in its `Til`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct BodyId(pub u32);
+
+impl BodyId {
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+#[derive(Clone, PartialEq, Eq)]
 pub enum BodyTy {
-    Fn()
+    Fn(DefId),
+    // Closures are lowered to bodies of their own, taking the captured
+    // locals of the enclosing body before their parameters.
+    Closure { owner: DefId, captures: usize },
+}
+
+#[derive(Clone)]
+pub struct LocalDecl<'gcx> {
+    pub ty: TypeRef<'gcx>,
+    // Name of the user variable, temporaries have none.
+    pub name: Option<String>,
+}
+
+// A local or a field of it, fields being numbered like in `ConstValue`.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct Place {
+    pub local: Local,
+    pub projection: Vec<usize>,
+}
+
+impl Place {
+    pub fn local(local: Local) -> Self {
+        Place {
+            local,
+            projection: vec![],
+        }
+    }
+
+    pub fn field(&self, index: usize) -> Self {
+        let mut projection = self.projection.clone();
+        projection.push(index);
+        Place {
+            local: self.local,
+            projection,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq)]
+pub enum Operand {
+    Copy(Place),
+    Const(ConstValue),
+    // A defn used as a value.
+    Fn(DefId),
+    Static(DefId),
+}
+
+impl Operand {
+    pub fn unit() -> Self {
+        Operand::Const(ConstValue::Tuple(vec![]))
+    }
+}
+
+#[derive(Clone, PartialEq, Eq)]
+pub enum Rvalue {
+    Use(Operand),
+    Unary(UnOp, Operand),
+    // `&&` and `||` are lowered to branches and never appear here.
+    Binary(BinOp, Operand, Operand),
+    Tuple(Vec<Operand>),
+    // Field values in the order of the fields of the class.
+    Adt(DefId, Vec<Operand>),
+    Closure(BodyId, Vec<Operand>),
+}
+
+#[derive(Clone, PartialEq, Eq)]
+pub enum Statement {
+    Assign(Place, Rvalue),
 }
 
-pub struct Block {
+#[derive(Clone, PartialEq, Eq)]
+pub enum Terminator {
+    Goto(BlockId),
+    Branch {
+        cond: Operand,
+        then_block: BlockId,
+        else_block: BlockId,
+    },
+    Return,
+    Call {
+        func: Operand,
+        args: Vec<Operand>,
+        dest: Place,
+        target: BlockId,
+    },
+    Unreachable,
+}
+
+impl Terminator {
+    pub fn successors(&self) -> Vec<BlockId> {
+        match self {
+            Terminator::Goto(target) => vec![*target],
+            Terminator::Branch {
+                then_block,
+                else_block,
+                ..
+            } => vec![*then_block, *else_block],
+            Terminator::Call { target, .. } => vec![*target],
+            Terminator::Return | Terminator::Unreachable => vec![],
+        }
+    }
+}
+
+// Takes the operand of the predecessor from which the block was entered.
+// Only bodies in SSA form have phis.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Phi {
+    pub dest: Local,
+    pub args: Vec<(BlockId, Operand)>,
+}
+
+#[derive(Clone)]
+pub struct BasicBlock {
+    pub phis: Vec<Phi>,
+    pub statements: Vec<Statement>,
+    pub terminator: Terminator,
+}
+
+#[derive(Clone)]
+pub struct Body<'gcx> {
+    pub ty: BodyTy,
+    // Name of the defn, for diagnostics.
+    pub name: String,
+    // Whether the defn is exported, closures never are.
+    pub public: bool,
+    pub locals: Vec<LocalDecl<'gcx>>,
+    pub arg_count: usize,
+    // The entry block comes first.
+    pub blocks: Vec<BasicBlock>,
+}
+
+impl<'gcx> Body<'gcx> {
+    pub fn args(&self) -> impl Iterator<Item = Local> {
+        (1..=self.arg_count).map(|i| Local(u32::try_from(i).unwrap()))
+    }
+
+    pub fn return_ty(&self) -> TypeRef<'gcx> {
+        self.locals[0].ty
+    }
+
+    pub fn local_decl(&self, local: Local) -> &LocalDecl<'gcx> {
+        &self.locals[local.index()]
+    }
+
+    // Distinct predecessors of each block, in the order of the blocks.
+    pub fn predecessors(&self) -> Vec<Vec<BlockId>> {
+        let mut preds = vec![vec![]; self.blocks.len()];
+        for (i, block) in self.blocks.iter().enumerate() {
+            let pred = BlockId(u32::try_from(i).unwrap());
+            for succ in block.terminator.successors() {
+                if !preds[succ.index()].contains(&pred) {
+                    preds[succ.index()].push(pred);
+                }
+            }
+        }
+        preds
+    }
+
+    pub fn block(&self, block: BlockId) -> &BasicBlock {
+        &self.blocks[block.index()]
+    }
+
+    pub fn place_ty(&self, ctx: &'gcx GlobalContext<'gcx>, place: &Place) -> TypeRef<'gcx> {
+        place
+            .projection
+            .iter()
+            .fold(self.local_decl(place.local).ty, |t, index| field_ty(ctx, t, *index))
+    }
+
+    pub fn operand_ty(&self, ctx: &'gcx GlobalContext<'gcx>, operand: &Operand) -> TypeRef<'gcx> {
+        match operand {
+            Operand::Copy(place) => self.place_ty(ctx, place),
+            Operand::Const(value) => const_ty(ctx, value),
+            Operand::Fn(def_id) | Operand::Static(def_id) => {
+                ctx.type_of(def_id).unwrap_or_else(|| ctx.error_type())
+            }
+        }
+    }
+}
+
+// Type of the field `index` of a tuple or class.
+pub fn field_ty<'gcx>(
+    ctx: &'gcx GlobalContext<'gcx>,
+    t: TypeRef<'gcx>,
+    index: usize,
+) -> TypeRef<'gcx> {
+    match t.kind() {
+        TypeKind::Tuple(elems) => elems[index],
+        TypeKind::Adt(adt_def, _) => ctx.type_of(&adt_def.fields()[index].def_id).unwrap(),
+        _ => ctx.error_type(),
+    }
+}
+
+pub fn const_ty<'gcx>(ctx: &'gcx GlobalContext<'gcx>, value: &ConstValue) -> TypeRef<'gcx> {
+    match value {
+        ConstValue::Bool(_) => ctx.bool_type(),
+        ConstValue::Int(_) => ctx.int_type(),
+        ConstValue::Tuple(values) => {
+            let elems = values.iter().map(|x| const_ty(ctx, x)).collect::<Vec<_>>();
+            ctx.tuple_type(&elems)
+        }
+        ConstValue::Adt(def_id, _) => ctx.adt_type(ctx.adt_def(def_id.clone())),
+    }
+}
+
+// Typed control-flow graphs of the defns of a unit and of their closures.
+pub struct Til<'gcx> {
+    pub unit_path: Vec<String>,
+    // Namespaces, modules and classes enclosing the named items of the unit,
+    // followed by the name of the item.
+    pub item_paths: HashMap<DefId, Vec<String>>,
+    pub bodies: Vec<Body<'gcx>>,
+}
+
+impl<'gcx> Til<'gcx> {
+    pub fn body(&self, body_id: BodyId) -> &Body<'gcx> {
+        &self.bodies[body_id.index()]
+    }
+
+    // Body of the defn `def_id`, if it is defined in this unit.
+    pub fn fn_body(&self, def_id: &DefId) -> Option<BodyId> {
+        self.bodies
+            .iter()
+            .position(|body| body.ty == BodyTy::Fn(def_id.clone()))
+            .map(|i| BodyId(u32::try_from(i).unwrap()))
+    }
+}
+
+impl fmt::Display for Local {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "_{}", self.0)
+    }
+}
+
+impl fmt::Display for BlockId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "bb{}", self.0)
+    }
+}
+
+impl fmt::Display for BodyId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "body{}", self.0)
+    }
+}
+
+impl fmt::Display for Place {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.local)?;
+        for index in &self.projection {
+            write!(f, ".{}", index)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operand::Copy(place) => write!(f, "{}", place),
+            Operand::Const(value) => write!(f, "const {}", value),
+            Operand::Fn(def_id) => write!(f, "fn {}", def_id),
+            Operand::Static(def_id) => write!(f, "static {}", def_id),
+        }
+    }
+}
+
+fn write_list<T: fmt::Display>(f: &mut fmt::Formatter, elems: &[T]) -> fmt::Result {
+    for (i, elem) in elems.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{}", elem)?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for Rvalue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Rvalue::Use(operand) => write!(f, "{}", operand),
+            Rvalue::Unary(op, operand) => write!(f, "{}({})", op.keyword(), operand),
+            Rvalue::Binary(op, lhs, rhs) => write!(f, "{}({}, {})", op.keyword(), lhs, rhs),
+            Rvalue::Tuple(elems) => {
+                write!(f, "(")?;
+                write_list(f, elems)?;
+                if elems.len() == 1 {
+                    write!(f, ",")?;
+                }
+                write!(f, ")")
+            }
+            Rvalue::Adt(def_id, fields) => {
+                write!(f, "{} {{ ", def_id)?;
+                write_list(f, fields)?;
+                write!(f, " }}")
+            }
+            Rvalue::Closure(body_id, captures) => {
+                write!(f, "closure {} [", body_id)?;
+                write_list(f, captures)?;
+                write!(f, "]")
+            }
+        }
+    }
+}
+
+impl fmt::Display for Phi {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} = phi [", self.dest)?;
+        for (i, (block, operand)) in self.args.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}: {}", block, operand)?;
+        }
+        write!(f, "]")
+    }
+}
+
+impl fmt::Display for Statement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Statement::Assign(place, rvalue) => write!(f, "{} = {}", place, rvalue),
+        }
+    }
+}
+
+impl fmt::Display for Terminator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Terminator::Goto(target) => write!(f, "goto {}", target),
+            Terminator::Branch {
+                cond,
+                then_block,
+                else_block,
+            } => write!(f, "branch {} [{}, {}]", cond, then_block, else_block),
+            Terminator::Return => write!(f, "return"),
+            Terminator::Call {
+                func,
+                args,
+                dest,
+                target,
+            } => {
+                write!(f, "{} = call {}(", dest, func)?;
+                write_list(f, args)?;
+                write!(f, ") -> {}", target)
+            }
+            Terminator::Unreachable => write!(f, "unreachable"),
+        }
+    }
+}
 
+impl<'gcx> fmt::Display for Body<'gcx> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.ty {
+            BodyTy::Fn(def_id) => write!(f, "fn {}(", def_id)?,
+            BodyTy::Closure { owner, .. } => write!(f, "closure in {}(", owner)?,
+        }
+        for (i, local) in self.args().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}: {}", local, self.local_decl(local).ty)?;
+        }
+        writeln!(f, ") -> {} {{", self.return_ty())?;
+        for (i, decl) in self.locals.iter().enumerate().skip(self.arg_count + 1) {
+            write!(f, "    let _{}: {};", i, decl.ty)?;
+            match &decl.name {
+                Some(name) => writeln!(f, " // {}", name)?,
+                None => writeln!(f)?,
+            }
+        }
+        for (i, block) in self.blocks.iter().enumerate() {
+            writeln!(f, "    bb{}: {{", i)?;
+            for phi in &block.phis {
+                writeln!(f, "        {};", phi)?;
+            }
+            for statement in &block.statements {
+                writeln!(f, "        {};", statement)?;
+            }
+            writeln!(f, "        {};", block.terminator)?;
+            writeln!(f, "    }}")?;
+        }
+        write!(f, "}}")
+    }
 }
 
-pub struct Til {
-    pub body_type: BodyTy,
-    pub blocks: Vec<Block>,
+impl<'gcx> fmt::Display for Til<'gcx> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, body) in self.bodies.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            writeln!(f, "// {}", BodyId(u32::try_from(i).unwrap()))?;
+            writeln!(f, "{}", body)?;
+        }
+        Ok(())
+    }
 }
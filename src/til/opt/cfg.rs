@@ -0,0 +1,99 @@
+use crate::til::dom::Dominators;
+use crate::til::{BasicBlock, BlockId, Body, Place, Rvalue, Statement, Terminator};
+
+pub fn retarget(terminator: &mut Terminator, map: impl Fn(BlockId) -> BlockId) {
+    match terminator {
+        Terminator::Goto(target) | Terminator::Call { target, .. } => *target = map(*target),
+        Terminator::Branch {
+            then_block,
+            else_block,
+            ..
+        } => {
+            *then_block = map(*then_block);
+            *else_block = map(*else_block);
+        }
+        Terminator::Return | Terminator::Unreachable => (),
+    }
+}
+
+// Removes the blocks which cannot be reached from the entry block, keeping
+// the order of the others.
+pub fn remove_dead_blocks(body: &mut Body) -> bool {
+    let dominators = Dominators::new(body);
+    let count = body.blocks.len();
+    let mut map = vec![None; count];
+    let mut next = 0;
+    for (i, new) in map.iter_mut().enumerate() {
+        if dominators.is_reachable(BlockId(u32::try_from(i).unwrap())) {
+            *new = Some(BlockId(next));
+            next += 1;
+        }
+    }
+    if next as usize == count {
+        return false;
+    }
+    let blocks = std::mem::take(&mut body.blocks);
+    for (block, new) in blocks.into_iter().zip(&map) {
+        if new.is_some() {
+            body.blocks.push(block);
+        }
+    }
+    for block in &mut body.blocks {
+        retarget(&mut block.terminator, |x| map[x.index()].unwrap());
+        for phi in &mut block.phis {
+            phi.args.retain(|(pred, _)| map[pred.index()].is_some());
+            for (pred, _) in &mut phi.args {
+                *pred = map[pred.index()].unwrap();
+            }
+        }
+    }
+    true
+}
+
+// Appends the blocks whose only predecessor jumps to them to that
+// predecessor. Their phis, which have a single argument, become copies, and
+// the merged blocks are left unreachable.
+pub fn merge_blocks(body: &mut Body) -> bool {
+    let mut changed = false;
+    loop {
+        let dominators = Dominators::new(body);
+        let preds = body.predecessors();
+        let merge = dominators.reverse_postorder().iter().find_map(|&block| {
+            let Terminator::Goto(target) = body.block(block).terminator else {
+                return None;
+            };
+            let mut preds = preds[target.index()].iter();
+            let single = preds.all(|x| *x == block || !dominators.is_reachable(*x));
+            (single && target != block && target != BlockId::ENTRY).then_some((block, target))
+        });
+        let Some((block, target)) = merge else {
+            return changed;
+        };
+        let merged = std::mem::replace(
+            &mut body.blocks[target.index()],
+            BasicBlock {
+                phis: vec![],
+                statements: vec![],
+                terminator: Terminator::Unreachable,
+            },
+        );
+        let data = &mut body.blocks[block.index()];
+        for phi in merged.phis {
+            let (_, arg) = phi.args.into_iter().next().unwrap();
+            let copy = Statement::Assign(Place::local(phi.dest), Rvalue::Use(arg));
+            data.statements.push(copy);
+        }
+        data.statements.extend(merged.statements);
+        data.terminator = merged.terminator;
+        for succ in data.terminator.successors() {
+            for phi in &mut body.blocks[succ.index()].phis {
+                for (pred, _) in &mut phi.args {
+                    if *pred == target {
+                        *pred = block;
+                    }
+                }
+            }
+        }
+        changed = true;
+    }
+}
@@ -0,0 +1,150 @@
+use crate::context::GlobalContext;
+use crate::op::{BinOp, UnOp};
+use crate::t::const_eval::ConstValue;
+use crate::til::dom::Dominators;
+use crate::til::opt::{prune_phi_args, single_assignment};
+use crate::til::ssa::{rvalue_operands_mut, terminator_operands_mut};
+use crate::til::{Body, Operand, Rvalue, Statement, Terminator};
+
+// Folds the operations whose operands are constants and replaces the uses of
+// the locals they assign with the constants. Operations which would fail at
+// run time are left for the program to fail, and branches on a constant
+// become jumps.
+pub fn run<'gcx>(ctx: &'gcx GlobalContext<'gcx>, body: &mut Body<'gcx>) -> bool {
+    let single = single_assignment(body);
+    let mut values: Vec<Option<ConstValue>> = vec![None; body.locals.len()];
+    let mut changed = false;
+    let mut folded_branch = false;
+    let substitute = |operand: &mut Operand, values: &[Option<ConstValue>]| {
+        let value = match operand {
+            Operand::Copy(place) => {
+                let mut value = values[place.local.index()].as_ref();
+                for i in &place.projection {
+                    value = match value {
+                        Some(ConstValue::Tuple(fields) | ConstValue::Adt(_, fields)) => {
+                            fields.get(*i)
+                        }
+                        _ => None,
+                    };
+                }
+                value.cloned()
+            }
+            Operand::Static(def_id) => ctx.const_value(def_id),
+            Operand::Const(_) | Operand::Fn(_) => None,
+        };
+        match value {
+            Some(value) => {
+                *operand = Operand::Const(value);
+                true
+            }
+            None => false,
+        }
+    };
+    // Definitions come before their uses in reverse postorder, except for
+    // phis on loops, which are not folded.
+    let dominators = Dominators::new(body);
+    for &block_id in dominators.reverse_postorder() {
+        let block = &mut body.blocks[block_id.index()];
+        for phi in &mut block.phis {
+            for (_, operand) in &mut phi.args {
+                changed |= substitute(operand, &values);
+            }
+            let mut args = phi.args.iter().map(|(_, x)| match x {
+                Operand::Const(value) => Some(value),
+                _ => None,
+            });
+            let first = args.next().flatten();
+            if single[phi.dest.index()] && args.all(|x| x.is_some() && x == first) {
+                values[phi.dest.index()] = first.cloned();
+            }
+        }
+        for statement in &mut block.statements {
+            let Statement::Assign(place, rvalue) = statement;
+            for operand in rvalue_operands_mut(rvalue) {
+                changed |= substitute(operand, &values);
+            }
+            let Some(value) = fold(rvalue) else {
+                continue;
+            };
+            let folded = Rvalue::Use(Operand::Const(value.clone()));
+            changed |= *rvalue != folded;
+            *rvalue = folded;
+            if place.projection.is_empty() && single[place.local.index()] {
+                values[place.local.index()] = Some(value);
+            }
+        }
+        for operand in terminator_operands_mut(&mut block.terminator) {
+            changed |= substitute(operand, &values);
+        }
+        if let Terminator::Branch {
+            cond: Operand::Const(ConstValue::Bool(cond)),
+            then_block,
+            else_block,
+        } = block.terminator
+        {
+            let target = if cond { then_block } else { else_block };
+            block.terminator = Terminator::Goto(target);
+            folded_branch = true;
+        }
+    }
+    if folded_branch {
+        prune_phi_args(body);
+    }
+    changed || folded_branch
+}
+
+fn fold(rvalue: &Rvalue) -> Option<ConstValue> {
+    let constant = |operand: &Operand| match operand {
+        Operand::Const(value) => Some(value.clone()),
+        _ => None,
+    };
+    match rvalue {
+        Rvalue::Use(operand) => constant(operand),
+        Rvalue::Unary(op, operand) => match (op, constant(operand)?) {
+            (UnOp::Neg, ConstValue::Int(i)) => i.checked_neg().map(ConstValue::Int),
+            (UnOp::Not, ConstValue::Bool(b)) => Some(ConstValue::Bool(!b)),
+            _ => None,
+        },
+        Rvalue::Binary(op, lhs, rhs) => fold_binary(*op, constant(lhs)?, constant(rhs)?),
+        Rvalue::Tuple(operands) => {
+            let values = operands.iter().map(constant).collect::<Option<Vec<_>>>()?;
+            Some(ConstValue::Tuple(values))
+        }
+        Rvalue::Adt(def_id, operands) => {
+            let values = operands.iter().map(constant).collect::<Option<Vec<_>>>()?;
+            Some(ConstValue::Adt(def_id.clone(), values))
+        }
+        Rvalue::Closure(..) => None,
+    }
+}
+
+// Like at run time, without the operations which fail.
+fn fold_binary(op: BinOp, lhs: ConstValue, rhs: ConstValue) -> Option<ConstValue> {
+    if op == BinOp::Eq || op == BinOp::Ne {
+        return Some(ConstValue::Bool((lhs == rhs) == (op == BinOp::Eq)));
+    }
+    let (a, b) = match (lhs, rhs) {
+        (ConstValue::Int(a), ConstValue::Int(b)) => (a, b),
+        (ConstValue::Bool(a), ConstValue::Bool(b)) => {
+            return match op {
+                BinOp::And => Some(ConstValue::Bool(a && b)),
+                BinOp::Or => Some(ConstValue::Bool(a || b)),
+                _ => None,
+            }
+        }
+        _ => return None,
+    };
+    let value = match op {
+        BinOp::Add => a.checked_add(b),
+        BinOp::Sub => a.checked_sub(b),
+        BinOp::Mul => a.checked_mul(b),
+        BinOp::Div => a.checked_div(b),
+        BinOp::Rem => a.checked_rem(b),
+        BinOp::Lt => return Some(ConstValue::Bool(a < b)),
+        BinOp::Le => return Some(ConstValue::Bool(a <= b)),
+        BinOp::Gt => return Some(ConstValue::Bool(a > b)),
+        BinOp::Ge => return Some(ConstValue::Bool(a >= b)),
+        BinOp::And | BinOp::Or | BinOp::Eq | BinOp::Ne => None,
+    };
+    value.map(ConstValue::Int)
+}
@@ -0,0 +1,90 @@
+use crate::til::opt::single_assignment;
+use crate::til::ssa::{rvalue_operands_mut, terminator_operands_mut};
+use crate::til::{Body, Operand, Place, Rvalue, Statement};
+
+// Replaces the uses of locals which copy another local, or a field of it, by
+// uses of the copied place. Both must hold one value for the whole body, so
+// that the copied place cannot change in between. Phis whose arguments are
+// all the same place, apart from the phi itself, are copies too.
+pub fn run(body: &mut Body) -> bool {
+    let single = single_assignment(body);
+    let mut copies: Vec<Option<Place>> = vec![None; body.locals.len()];
+    for block in &body.blocks {
+        for phi in &block.phis {
+            let mut places = phi.args.iter().filter_map(|(_, x)| match x {
+                Operand::Copy(place) if place.local != phi.dest => Some(Some(place)),
+                Operand::Copy(_) => None,
+                _ => Some(None),
+            });
+            let Some(Some(first)) = places.next() else {
+                continue;
+            };
+            if single[phi.dest.index()]
+                && single[first.local.index()]
+                && places.all(|x| x == Some(first))
+            {
+                copies[phi.dest.index()] = Some(first.clone());
+            }
+        }
+        for statement in &block.statements {
+            let Statement::Assign(dest, Rvalue::Use(Operand::Copy(place))) = statement else {
+                continue;
+            };
+            if dest.projection.is_empty()
+                && single[dest.local.index()]
+                && single[place.local.index()]
+                && dest.local != place.local
+            {
+                copies[dest.local.index()] = Some(place.clone());
+            }
+        }
+    }
+    // Copies of copies are resolved to the original place.
+    let resolve = |place: &Place| {
+        let mut place = place.clone();
+        let mut steps = 0;
+        while let Some(copied) = &copies[place.local.index()] {
+            let mut projection = copied.projection.clone();
+            projection.extend(place.projection);
+            place = Place {
+                local: copied.local,
+                projection,
+            };
+            // Phis can copy each other in a cycle.
+            steps += 1;
+            if steps > copies.len() {
+                return None;
+            }
+        }
+        Some(place)
+    };
+    let mut changed = false;
+    let mut replace = |operand: &mut Operand| {
+        if let Operand::Copy(place) = operand {
+            if copies[place.local.index()].is_none() {
+                return;
+            }
+            if let Some(copied) = resolve(place) {
+                *place = copied;
+                changed = true;
+            }
+        }
+    };
+    for block in &mut body.blocks {
+        for phi in &mut block.phis {
+            for (_, operand) in &mut phi.args {
+                replace(operand);
+            }
+        }
+        for statement in &mut block.statements {
+            let Statement::Assign(_, rvalue) = statement;
+            rvalue_operands_mut(rvalue)
+                .into_iter()
+                .for_each(&mut replace);
+        }
+        terminator_operands_mut(&mut block.terminator)
+            .into_iter()
+            .for_each(&mut replace);
+    }
+    changed
+}
@@ -0,0 +1,49 @@
+use crate::op::{BinOp, UnOp};
+use crate::til::dataflow::liveness::Liveness;
+use crate::til::dataflow::solve;
+use crate::til::ssa::remove_dead_phis;
+use crate::til::{BlockId, Body, Local, Rvalue, Statement};
+
+// Removes the assignments of values which are never read, and the phis which
+// only feed dead phis. Arithmetic which can overflow or divide by zero stays,
+// so that programs still fail where they did.
+pub fn run(body: &mut Body) -> bool {
+    let results = solve(Liveness, body);
+    let mut dead = vec![];
+    for i in 0..body.blocks.len() {
+        let block = BlockId(u32::try_from(i).unwrap());
+        results.visit_block(body, block, |live, location| {
+            let statements = &body.block(location.block).statements;
+            let Some(Statement::Assign(place, rvalue)) = statements.get(location.statement) else {
+                return;
+            };
+            if place.local != Local::RETURN
+                && !live.contains(place.local.index())
+                && !can_fail(rvalue)
+            {
+                dead.push(location);
+            }
+        });
+    }
+    let phis = body.blocks.iter().map(|x| x.phis.len()).sum::<usize>();
+    // Statements of a block are visited backwards, so removing them in order
+    // keeps the indices of the remaining ones.
+    for location in &dead {
+        body.blocks[location.block.index()]
+            .statements
+            .remove(location.statement);
+    }
+    remove_dead_phis(body);
+    !dead.is_empty() || body.blocks.iter().map(|x| x.phis.len()).sum::<usize>() != phis
+}
+
+fn can_fail(rvalue: &Rvalue) -> bool {
+    match rvalue {
+        Rvalue::Unary(UnOp::Neg, _) => true,
+        Rvalue::Binary(op, ..) => matches!(
+            op,
+            BinOp::Add | BinOp::Sub | BinOp::Mul | BinOp::Div | BinOp::Rem
+        ),
+        _ => false,
+    }
+}
@@ -0,0 +1,123 @@
+use crate::context::GlobalContext;
+use crate::def::DefId;
+use crate::t::const_eval::ConstValue;
+use crate::t::{TypeKind, TypeRef};
+use crate::til::opt::block_places_mut;
+use crate::til::opt::cfg::retarget;
+use crate::til::ssa::operands;
+use crate::til::{
+    BasicBlock, BlockId, Body, BodyTy, Local, Operand, Place, Rvalue, Statement, Terminator,
+};
+
+// Bodies of at most this many statements and terminators are written to the
+// BUI for other units to inline, larger ones only when marked `#:inline`.
+pub const INLINE_THRESHOLD: usize = 8;
+
+pub fn body_size(body: &Body) -> usize {
+    body.blocks.iter().map(|x| x.statements.len() + 1).sum()
+}
+
+// Whether another unit could inline `body`. It must not create closures,
+// whose bodies are not exported, nor use classes, whose layout is private to
+// the unit, nor refer to items other units have no symbol for.
+pub fn is_exportable(body: &Body, exported: impl Fn(&DefId) -> bool) -> bool {
+    if !matches!(body.ty, BodyTy::Fn(_)) || !body.locals.iter().all(|x| is_plain(x.ty)) {
+        return false;
+    }
+    body.blocks.iter().all(|block| {
+        let rvalues = block.statements.iter().all(|statement| {
+            let Statement::Assign(_, rvalue) = statement;
+            !matches!(rvalue, Rvalue::Adt(..) | Rvalue::Closure(..))
+        });
+        rvalues
+            && operands(block).into_iter().all(|operand| match operand {
+                Operand::Copy(_) => true,
+                Operand::Const(value) => !has_adt(value),
+                Operand::Fn(def_id) | Operand::Static(def_id) => {
+                    def_id.is_local() && exported(def_id)
+                }
+            })
+    })
+}
+
+fn is_plain(t: TypeRef) -> bool {
+    match t.kind() {
+        TypeKind::Bool | TypeKind::Int => true,
+        TypeKind::Tuple(elems) => elems.iter().all(|t| is_plain(t)),
+        TypeKind::Fn(inputs, output) => inputs.iter().all(|t| is_plain(t)) && is_plain(output),
+        _ => false,
+    }
+}
+
+fn has_adt(value: &ConstValue) -> bool {
+    match value {
+        ConstValue::Bool(_) | ConstValue::Int(_) => false,
+        ConstValue::Tuple(elems) => elems.iter().any(has_adt),
+        ConstValue::Adt(..) => true,
+    }
+}
+
+// Replaces the calls to defns of other units whose body was imported from
+// their BUI by a copy of that body. Calls of the copies are left alone, so
+// that recursive defns are inlined once.
+pub fn run<'gcx>(ctx: &'gcx GlobalContext<'gcx>, body: &mut Body<'gcx>) -> bool {
+    let mut changed = false;
+    for i in 0..body.blocks.len() {
+        let Terminator::Call {
+            func: Operand::Fn(def_id),
+            ..
+        } = &body.blocks[i].terminator
+        else {
+            continue;
+        };
+        if def_id.is_local() {
+            continue;
+        }
+        if let Some((til, body_id)) = ctx.imported_body(def_id) {
+            splice(body, BlockId(u32::try_from(i).unwrap()), til.body(body_id));
+            changed = true;
+        }
+    }
+    changed
+}
+
+// Appends the blocks of `callee`, with locals of their own, after the ones of
+// `body`. The call jumps to them after copying the arguments, and their
+// returns jump to a block copying the result to the destination of the call.
+fn splice<'gcx>(body: &mut Body<'gcx>, block: BlockId, callee: &Body<'gcx>) {
+    let base = u32::try_from(body.locals.len()).unwrap();
+    let first = u32::try_from(body.blocks.len()).unwrap();
+    let after = BlockId(first + u32::try_from(callee.blocks.len()).unwrap());
+    let local = |x: Local| Local(base + x.0);
+    body.locals.extend(callee.locals.iter().cloned());
+    let data = &mut body.blocks[block.index()];
+    let call = std::mem::replace(&mut data.terminator, Terminator::Goto(BlockId(first)));
+    let Terminator::Call {
+        args, dest, target, ..
+    } = call
+    else {
+        unreachable!();
+    };
+    for (arg, operand) in callee.args().zip(args) {
+        let copy = Statement::Assign(Place::local(local(arg)), Rvalue::Use(operand));
+        data.statements.push(copy);
+    }
+    for callee_block in &callee.blocks {
+        let mut data = callee_block.clone();
+        for place in block_places_mut(&mut data) {
+            place.local = local(place.local);
+        }
+        if data.terminator == Terminator::Return {
+            data.terminator = Terminator::Goto(after);
+        } else {
+            retarget(&mut data.terminator, |x| BlockId(first + x.0));
+        }
+        body.blocks.push(data);
+    }
+    let result = Operand::Copy(Place::local(local(Local::RETURN)));
+    body.blocks.push(BasicBlock {
+        phis: vec![],
+        statements: vec![Statement::Assign(dest, Rvalue::Use(result))],
+        terminator: Terminator::Goto(target),
+    });
+}
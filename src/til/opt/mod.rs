@@ -0,0 +1,307 @@
+pub mod cfg;
+pub mod const_prop;
+pub mod copy_prop;
+pub mod dce;
+pub mod inline;
+
+use crate::context::GlobalContext;
+use crate::til::dom::Dominators;
+use crate::til::ssa::{
+    from_ssa, rvalue_operands, rvalue_operands_mut, terminator_operands, to_ssa, verify_ssa,
+};
+use crate::til::{BasicBlock, Body, Local, Operand, Place, Statement, Terminator, Til};
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum OptLevel {
+    // Leaves the TIL as built.
+    O0,
+    // Runs every pass once.
+    O1,
+    // Runs the passes again while they find something to change.
+    O2,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Pass {
+    Inline,
+    ConstProp,
+    CopyProp,
+    DeadCode,
+    DeadBlocks,
+    MergeBlocks,
+}
+
+impl Pass {
+    pub const ALL: [Pass; 6] = [
+        Pass::Inline,
+        Pass::ConstProp,
+        Pass::CopyProp,
+        Pass::DeadCode,
+        Pass::MergeBlocks,
+        Pass::DeadBlocks,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Pass::Inline => "inline",
+            Pass::ConstProp => "const-prop",
+            Pass::CopyProp => "copy-prop",
+            Pass::DeadCode => "dead-code",
+            Pass::DeadBlocks => "dead-blocks",
+            Pass::MergeBlocks => "merge-blocks",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Pass> {
+        Pass::ALL.into_iter().find(|x| x.name() == name)
+    }
+
+    // Inlining runs once before SSA construction, the other passes on SSA
+    // form.
+    pub fn needs_ssa(self) -> bool {
+        self != Pass::Inline
+    }
+
+    // Runs the pass over a body, in SSA form if the pass needs it, and returns
+    // whether it changed anything.
+    pub fn run<'gcx>(self, ctx: &'gcx GlobalContext<'gcx>, body: &mut Body<'gcx>) -> bool {
+        match self {
+            Pass::Inline => inline::run(ctx, body),
+            Pass::ConstProp => const_prop::run(ctx, body),
+            Pass::CopyProp => copy_prop::run(body),
+            Pass::DeadCode => dce::run(body),
+            Pass::DeadBlocks => cfg::remove_dead_blocks(body),
+            Pass::MergeBlocks => cfg::merge_blocks(body),
+        }
+    }
+}
+
+// Upper bound on the rounds of `O2`, which usually settles much sooner.
+const MAX_ROUNDS: usize = 10;
+
+// Runs a list of passes over the bodies of a unit. Bodies are converted to
+// SSA form for the passes and back afterwards.
+pub struct PassManager {
+    passes: Vec<Pass>,
+    rounds: usize,
+    verify: bool,
+}
+
+impl PassManager {
+    pub fn new(level: OptLevel) -> Self {
+        let (passes, rounds) = match level {
+            OptLevel::O0 => (vec![], 0),
+            OptLevel::O1 => (Pass::ALL.to_vec(), 1),
+            OptLevel::O2 => (Pass::ALL.to_vec(), MAX_ROUNDS),
+        };
+        PassManager {
+            passes,
+            rounds,
+            verify: cfg!(debug_assertions),
+        }
+    }
+
+    pub fn passes(&self) -> &[Pass] {
+        &self.passes
+    }
+
+    // Replaces the passes of the level, which still decides the rounds.
+    pub fn set_passes(&mut self, passes: Vec<Pass>) {
+        self.passes = passes;
+        self.rounds = self.rounds.max(1);
+    }
+
+    // Checks the TIL after every pass, which debug builds do by default.
+    pub fn set_verify(&mut self, verify: bool) {
+        self.verify = verify;
+    }
+
+    pub fn run<'gcx>(&self, ctx: &'gcx GlobalContext<'gcx>, til: &mut Til<'gcx>) {
+        for body in &mut til.bodies {
+            self.run_body(ctx, body);
+        }
+    }
+
+    pub fn run_body<'gcx>(&self, ctx: &'gcx GlobalContext<'gcx>, body: &mut Body<'gcx>) {
+        if self.passes.is_empty() {
+            return;
+        }
+        for pass in self.passes.iter().filter(|x| !x.needs_ssa()) {
+            if pass.run(ctx, body) {
+                self.check(body, false, pass.name());
+            }
+        }
+        to_ssa(body);
+        self.check(body, true, "SSA construction");
+        for _ in 0..self.rounds {
+            let mut changed = false;
+            for pass in self.passes.iter().filter(|x| x.needs_ssa()) {
+                if pass.run(ctx, body) {
+                    changed = true;
+                    self.check(body, true, pass.name());
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+        from_ssa(body);
+        remove_unused_locals(body);
+        self.check(body, false, "SSA destruction");
+    }
+
+    fn check(&self, body: &Body, ssa: bool, after: &str) {
+        if !self.verify {
+            return;
+        }
+        if let Err(message) = verify(body, ssa) {
+            panic!("Invalid TIL after {}: {}\n{}", after, message, body);
+        }
+    }
+}
+
+// Checks that the blocks and locals `body` refers to exist, and that it is
+// in SSA form if `ssa` is set, or has no phis otherwise.
+pub fn verify(body: &Body, ssa: bool) -> Result<(), String> {
+    if body.blocks.is_empty() {
+        return Err(format!("`{}` has no blocks", body.name));
+    }
+    let check_local = |local: Local| {
+        if local.index() < body.locals.len() {
+            return Ok(());
+        }
+        Err(format!("{} is not a local of `{}`", local, body.name))
+    };
+    let check_operand = |operand: &Operand| match operand {
+        Operand::Copy(place) => check_local(place.local),
+        _ => Ok(()),
+    };
+    for (i, block) in body.blocks.iter().enumerate() {
+        if !ssa && !block.phis.is_empty() {
+            return Err(format!("bb{} of `{}` has phis", i, body.name));
+        }
+        for phi in &block.phis {
+            check_local(phi.dest)?;
+            for (_, operand) in &phi.args {
+                check_operand(operand)?;
+            }
+        }
+        for statement in &block.statements {
+            let Statement::Assign(place, rvalue) = statement;
+            check_local(place.local)?;
+            for operand in rvalue_operands(rvalue) {
+                check_operand(operand)?;
+            }
+        }
+        for operand in terminator_operands(&block.terminator) {
+            check_operand(operand)?;
+        }
+        if let Terminator::Call { dest, .. } = &block.terminator {
+            check_local(dest.local)?;
+        }
+        for succ in block.terminator.successors() {
+            if succ.index() >= body.blocks.len() {
+                return Err(format!("{} is not a block of `{}`", succ, body.name));
+            }
+        }
+    }
+    if ssa {
+        verify_ssa(body)?;
+    }
+    Ok(())
+}
+
+// Locals which hold one value for the whole body: arguments which are never
+// assigned, and other locals assigned once as a whole.
+fn single_assignment(body: &Body) -> Vec<bool> {
+    let mut defs = vec![0; body.locals.len()];
+    for arg in body.args() {
+        defs[arg.index()] += 1;
+    }
+    let mut partial = vec![false; body.locals.len()];
+    let mut define = |local: Local, whole: bool| {
+        defs[local.index()] += 1;
+        partial[local.index()] |= !whole;
+    };
+    for block in &body.blocks {
+        for phi in &block.phis {
+            define(phi.dest, true);
+        }
+        for statement in &block.statements {
+            let Statement::Assign(place, _) = statement;
+            define(place.local, place.projection.is_empty());
+        }
+        if let Terminator::Call { dest, .. } = &block.terminator {
+            define(dest.local, dest.projection.is_empty());
+        }
+    }
+    (0..body.locals.len())
+        .map(|i| i != Local::RETURN.index() && defs[i] == 1 && !partial[i])
+        .collect()
+}
+
+// Drops the phi arguments of edges which are gone or come from unreachable
+// blocks.
+fn prune_phi_args(body: &mut Body) {
+    let dominators = Dominators::new(body);
+    let preds = body.predecessors();
+    for (i, block) in body.blocks.iter_mut().enumerate() {
+        for phi in &mut block.phis {
+            phi.args
+                .retain(|(pred, _)| dominators.is_reachable(*pred) && preds[i].contains(pred));
+        }
+    }
+}
+
+// Places of the statements and terminator of a block without phis.
+fn block_places_mut<'b>(block: &'b mut BasicBlock) -> Vec<&'b mut Place> {
+    let mut places = vec![];
+    let copied = |operand: &'b mut Operand| match operand {
+        Operand::Copy(place) => Some(place),
+        _ => None,
+    };
+    for statement in &mut block.statements {
+        let Statement::Assign(place, rvalue) = statement;
+        places.push(place);
+        places.extend(rvalue_operands_mut(rvalue).into_iter().filter_map(copied));
+    }
+    match &mut block.terminator {
+        Terminator::Branch { cond, .. } => places.extend(copied(cond)),
+        Terminator::Call {
+            func, args, dest, ..
+        } => {
+            places.extend(copied(func));
+            places.extend(args.iter_mut().filter_map(copied));
+            places.push(dest);
+        }
+        Terminator::Goto(_) | Terminator::Return | Terminator::Unreachable => (),
+    }
+    places
+}
+
+fn places_mut<'b>(body: &'b mut Body) -> Vec<&'b mut Place> {
+    body.blocks.iter_mut().flat_map(block_places_mut).collect()
+}
+
+// Removes the locals which are not mentioned anymore, such as the ones SSA
+// form replaced, apart from the return place and the arguments.
+fn remove_unused_locals(body: &mut Body) {
+    let mut used = vec![false; body.locals.len()];
+    used[..=body.arg_count].fill(true);
+    for place in places_mut(body) {
+        used[place.local.index()] = true;
+    }
+    let mut map = vec![None; body.locals.len()];
+    let mut next = 0;
+    for (new, used) in map.iter_mut().zip(&used) {
+        if *used {
+            *new = Some(Local(next));
+            next += 1;
+        }
+    }
+    for place in places_mut(body) {
+        place.local = map[place.local.index()].unwrap();
+    }
+    let mut used = used.into_iter();
+    body.locals.retain(|_| used.next().unwrap());
+}
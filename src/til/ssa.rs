@@ -0,0 +1,463 @@
+use std::collections::HashMap;
+
+use crate::til::dom::Dominators;
+use crate::til::{
+    BasicBlock, BlockId, Body, Local, LocalDecl, Operand, Phi, Place, Rvalue, Statement, Terminator,
+};
+
+// Locals which SSA form renames, which are all but the return place and the
+// ones assigned through a projection.
+pub fn promotable_locals(body: &Body) -> Vec<bool> {
+    let mut promotable = vec![true; body.locals.len()];
+    promotable[Local::RETURN.index()] = false;
+    for block in &body.blocks {
+        for statement in &block.statements {
+            let Statement::Assign(place, _) = statement;
+            if !place.projection.is_empty() {
+                promotable[place.local.index()] = false;
+            }
+        }
+        if let Terminator::Call { dest, .. } = &block.terminator {
+            if !dest.projection.is_empty() {
+                promotable[dest.local.index()] = false;
+            }
+        }
+    }
+    promotable
+}
+
+// Operands of the statements and the terminator of `block`.
+pub fn operands(block: &BasicBlock) -> Vec<&Operand> {
+    let mut operands = vec![];
+    for statement in &block.statements {
+        let Statement::Assign(_, rvalue) = statement;
+        operands.extend(rvalue_operands(rvalue));
+    }
+    operands.extend(terminator_operands(&block.terminator));
+    operands
+}
+
+pub fn rvalue_operands(rvalue: &Rvalue) -> Vec<&Operand> {
+    match rvalue {
+        Rvalue::Use(operand) | Rvalue::Unary(_, operand) => vec![operand],
+        Rvalue::Binary(_, lhs, rhs) => vec![lhs, rhs],
+        Rvalue::Tuple(operands) | Rvalue::Adt(_, operands) | Rvalue::Closure(_, operands) => {
+            operands.iter().collect()
+        }
+    }
+}
+
+pub fn terminator_operands(terminator: &Terminator) -> Vec<&Operand> {
+    match terminator {
+        Terminator::Branch { cond, .. } => vec![cond],
+        Terminator::Call { func, args, .. } => {
+            let mut operands = vec![func];
+            operands.extend(args.iter());
+            operands
+        }
+        Terminator::Goto(_) | Terminator::Return | Terminator::Unreachable => vec![],
+    }
+}
+
+pub fn rvalue_operands_mut(rvalue: &mut Rvalue) -> Vec<&mut Operand> {
+    match rvalue {
+        Rvalue::Use(operand) | Rvalue::Unary(_, operand) => vec![operand],
+        Rvalue::Binary(_, lhs, rhs) => vec![lhs, rhs],
+        Rvalue::Tuple(operands) | Rvalue::Adt(_, operands) | Rvalue::Closure(_, operands) => {
+            operands.iter_mut().collect()
+        }
+    }
+}
+
+pub fn terminator_operands_mut(terminator: &mut Terminator) -> Vec<&mut Operand> {
+    match terminator {
+        Terminator::Branch { cond, .. } => vec![cond],
+        Terminator::Call { func, args, .. } => {
+            let mut operands = vec![func];
+            operands.extend(args.iter_mut());
+            operands
+        }
+        Terminator::Goto(_) | Terminator::Return | Terminator::Unreachable => vec![],
+    }
+}
+
+fn rename_operand(operand: &mut Operand, stacks: &[Vec<Local>]) {
+    if let Operand::Copy(place) = operand {
+        if let Some(current) = stacks[place.local.index()].last() {
+            place.local = *current;
+        }
+    }
+}
+
+// Gives the entry block no predecessors, so that it needs no phis.
+fn split_entry(body: &mut Body) {
+    if body.predecessors()[BlockId::ENTRY.index()].is_empty() {
+        return;
+    }
+    let shift = |block: &mut BlockId| block.0 += 1;
+    for block in &mut body.blocks {
+        match &mut block.terminator {
+            Terminator::Goto(target) | Terminator::Call { target, .. } => shift(target),
+            Terminator::Branch {
+                then_block,
+                else_block,
+                ..
+            } => {
+                shift(then_block);
+                shift(else_block);
+            }
+            Terminator::Return | Terminator::Unreachable => (),
+        }
+    }
+    let entry = BasicBlock {
+        phis: vec![],
+        statements: vec![],
+        terminator: Terminator::Goto(BlockId(1)),
+    };
+    body.blocks.insert(0, entry);
+}
+
+// Renames every assignment of a promotable local to a fresh local, and
+// inserts phis at the iterated dominance frontiers of the assignments, as
+// described by Cytron et al. The original locals keep the values of the
+// arguments, and read as uninitialized otherwise. Phis whose value is never
+// used are removed.
+pub fn to_ssa(body: &mut Body) {
+    split_entry(body);
+    let promotable = promotable_locals(body);
+    let dominators = Dominators::new(body);
+    let mut def_blocks = vec![vec![BlockId::ENTRY]; body.locals.len()];
+    for &block_id in dominators.reverse_postorder() {
+        let block = body.block(block_id);
+        let mut dests = vec![];
+        for statement in &block.statements {
+            let Statement::Assign(place, _) = statement;
+            dests.push(place.local);
+        }
+        if let Terminator::Call { dest, .. } = &block.terminator {
+            dests.push(dest.local);
+        }
+        for dest in dests {
+            if promotable[dest.index()] && !def_blocks[dest.index()].contains(&block_id) {
+                def_blocks[dest.index()].push(block_id);
+            }
+        }
+    }
+
+    // Original locals of the phis of each block.
+    let mut phi_locals: Vec<Vec<Local>> = vec![vec![]; body.blocks.len()];
+    for (i, mut worklist) in def_blocks.into_iter().enumerate() {
+        if !promotable[i] {
+            continue;
+        }
+        let local = Local(u32::try_from(i).unwrap());
+        let mut defined = worklist.clone();
+        while let Some(block) = worklist.pop() {
+            for &frontier in dominators.frontier(block) {
+                if phi_locals[frontier.index()].contains(&local) {
+                    continue;
+                }
+                phi_locals[frontier.index()].push(local);
+                body.blocks[frontier.index()].phis.push(Phi {
+                    dest: local,
+                    args: vec![],
+                });
+                if !defined.contains(&frontier) {
+                    defined.push(frontier);
+                    worklist.push(frontier);
+                }
+            }
+        }
+    }
+
+    // Current name of each promotable local while walking the dominator
+    // tree.
+    let mut stacks = promotable
+        .iter()
+        .enumerate()
+        .map(|(i, promotable)| match promotable {
+            true => vec![Local(u32::try_from(i).unwrap())],
+            false => vec![],
+        })
+        .collect::<Vec<_>>();
+    let rename = |body: &mut Body, stacks: &mut Vec<Vec<Local>>, local: Local| {
+        let decl = body.local_decl(local).clone();
+        body.locals.push(decl);
+        let new = Local(u32::try_from(body.locals.len() - 1).unwrap());
+        stacks[local.index()].push(new);
+        stacks.push(vec![]);
+        new
+    };
+    // Blocks to enter, or to leave with the locals to pop.
+    let mut walk: Vec<(BlockId, Option<Vec<Local>>)> = vec![(BlockId::ENTRY, None)];
+    while let Some((block_id, leave)) = walk.pop() {
+        if let Some(renamed) = leave {
+            for local in renamed {
+                stacks[local.index()].pop();
+            }
+            continue;
+        }
+        let mut renamed = vec![];
+        for (i, &local) in phi_locals[block_id.index()].iter().enumerate() {
+            let new = rename(body, &mut stacks, local);
+            body.blocks[block_id.index()].phis[i].dest = new;
+            renamed.push(local);
+        }
+        for i in 0..body.block(block_id).statements.len() {
+            let Statement::Assign(place, rvalue) = &mut body.blocks[block_id.index()].statements[i];
+            for operand in rvalue_operands_mut(rvalue) {
+                rename_operand(operand, &stacks);
+            }
+            let local = place.local;
+            if promotable[local.index()] {
+                let new = rename(body, &mut stacks, local);
+                let Statement::Assign(place, _) = &mut body.blocks[block_id.index()].statements[i];
+                place.local = new;
+                renamed.push(local);
+            }
+        }
+        let terminator = &mut body.blocks[block_id.index()].terminator;
+        for operand in terminator_operands_mut(terminator) {
+            rename_operand(operand, &stacks);
+        }
+        if let Terminator::Call { dest, .. } = terminator {
+            let local = dest.local;
+            if promotable[local.index()] {
+                let new = rename(body, &mut stacks, local);
+                if let Terminator::Call { dest, .. } = &mut body.blocks[block_id.index()].terminator
+                {
+                    dest.local = new;
+                }
+                renamed.push(local);
+            }
+        }
+        let mut succs = body.block(block_id).terminator.successors();
+        succs.dedup();
+        for succ in succs {
+            for (i, local) in phi_locals[succ.index()].iter().enumerate() {
+                let current = *stacks[local.index()].last().unwrap();
+                let operand = Operand::Copy(Place::local(current));
+                body.blocks[succ.index()].phis[i]
+                    .args
+                    .push((block_id, operand));
+            }
+        }
+        walk.push((block_id, Some(renamed)));
+        for child in dominators.children(block_id).iter().rev() {
+            walk.push((*child, None));
+        }
+    }
+    remove_dead_phis(body);
+}
+
+// Removes the phis whose value is only used by dead phis.
+pub fn remove_dead_phis(body: &mut Body) {
+    let mut live = vec![false; body.locals.len()];
+    let mut worklist = vec![];
+    let mark = |operand: &Operand, live: &mut Vec<bool>, worklist: &mut Vec<Local>| {
+        if let Operand::Copy(place) = operand {
+            if !live[place.local.index()] {
+                live[place.local.index()] = true;
+                worklist.push(place.local);
+            }
+        }
+    };
+    for block in &body.blocks {
+        for operand in operands(block) {
+            mark(operand, &mut live, &mut worklist);
+        }
+    }
+    let phis = body
+        .blocks
+        .iter()
+        .flat_map(|block| block.phis.iter())
+        .map(|phi| (phi.dest, phi))
+        .collect::<HashMap<_, _>>();
+    while let Some(local) = worklist.pop() {
+        if let Some(phi) = phis.get(&local) {
+            for (_, operand) in &phi.args {
+                mark(operand, &mut live, &mut worklist);
+            }
+        }
+    }
+    for block in &mut body.blocks {
+        block.phis.retain(|phi| live[phi.dest.index()]);
+    }
+}
+
+// Replaces the phis by copies at the end of the predecessors. Edges from
+// blocks with other successors, or from calls which assign their result
+// after the copies would run, are split. Copies for one edge happen in
+// parallel, going through temporaries when a copy reads the destination of
+// another one. Locals are not coalesced, and copies of locals which are
+// never assigned are dropped.
+pub fn from_ssa(body: &mut Body) {
+    let mut assigned = vec![false; body.locals.len()];
+    for arg in body.args() {
+        assigned[arg.index()] = true;
+    }
+    for block in &body.blocks {
+        for phi in &block.phis {
+            assigned[phi.dest.index()] = true;
+        }
+        for statement in &block.statements {
+            let Statement::Assign(place, _) = statement;
+            assigned[place.local.index()] = true;
+        }
+        if let Terminator::Call { dest, .. } = &block.terminator {
+            assigned[dest.local.index()] = true;
+        }
+    }
+    for i in 0..body.blocks.len() {
+        let succ = BlockId(u32::try_from(i).unwrap());
+        let phis = std::mem::take(&mut body.blocks[i].phis);
+        let mut copies: Vec<(BlockId, Vec<(Local, Operand)>)> = vec![];
+        for phi in phis {
+            for (pred, operand) in phi.args {
+                if let Operand::Copy(place) = &operand {
+                    if !assigned[place.local.index()] {
+                        continue;
+                    }
+                }
+                match copies.iter_mut().find(|(x, _)| *x == pred) {
+                    Some((_, copies)) => copies.push((phi.dest, operand)),
+                    None => copies.push((pred, vec![(phi.dest, operand)])),
+                }
+            }
+        }
+        for (pred, copies) in copies {
+            let statements = sequentialize(body, copies);
+            match &body.block(pred).terminator {
+                Terminator::Goto(_) => body.blocks[pred.index()].statements.extend(statements),
+                _ => {
+                    let block = BlockId(u32::try_from(body.blocks.len()).unwrap());
+                    body.blocks.push(BasicBlock {
+                        phis: vec![],
+                        statements,
+                        terminator: Terminator::Goto(succ),
+                    });
+                    retarget(&mut body.blocks[pred.index()].terminator, succ, block);
+                }
+            }
+        }
+    }
+}
+
+fn sequentialize(body: &mut Body, copies: Vec<(Local, Operand)>) -> Vec<Statement> {
+    let reads_dest = copies.iter().any(|(_, operand)| match operand {
+        Operand::Copy(place) => copies.iter().any(|(dest, _)| *dest == place.local),
+        _ => false,
+    });
+    if !reads_dest {
+        return copies
+            .into_iter()
+            .map(|(dest, operand)| Statement::Assign(Place::local(dest), Rvalue::Use(operand)))
+            .collect();
+    }
+    let mut statements = vec![];
+    let mut moves = vec![];
+    for (dest, operand) in copies {
+        body.locals.push(LocalDecl {
+            ty: body.local_decl(dest).ty,
+            name: None,
+        });
+        let temp = Local(u32::try_from(body.locals.len() - 1).unwrap());
+        statements.push(Statement::Assign(Place::local(temp), Rvalue::Use(operand)));
+        moves.push(Statement::Assign(
+            Place::local(dest),
+            Rvalue::Use(Operand::Copy(Place::local(temp))),
+        ));
+    }
+    statements.extend(moves);
+    statements
+}
+
+fn retarget(terminator: &mut Terminator, from: BlockId, to: BlockId) {
+    let retarget = |block: &mut BlockId| {
+        if *block == from {
+            *block = to;
+        }
+    };
+    match terminator {
+        Terminator::Goto(target) | Terminator::Call { target, .. } => retarget(target),
+        Terminator::Branch {
+            then_block,
+            else_block,
+            ..
+        } => {
+            retarget(then_block);
+            retarget(else_block);
+        }
+        Terminator::Return | Terminator::Unreachable => (),
+    }
+}
+
+// Checks that promotable locals are assigned at most once, in a block which
+// dominates their uses, and that phis have one operand per predecessor.
+pub fn verify_ssa(body: &Body) -> Result<(), String> {
+    let dominators = Dominators::new(body);
+    let preds = body.predecessors();
+    let promotable = promotable_locals(body);
+    let mut def_block: Vec<Option<BlockId>> = vec![None; body.locals.len()];
+    let mut define = |local: Local, block: BlockId| {
+        if !promotable[local.index()] {
+            return Ok(());
+        }
+        if def_block[local.index()].replace(block).is_some() {
+            return Err(format!(
+                "{} is assigned more than once in `{}`",
+                local, body.name
+            ));
+        }
+        Ok(())
+    };
+    for (i, block) in body.blocks.iter().enumerate() {
+        let block_id = BlockId(u32::try_from(i).unwrap());
+        for phi in &block.phis {
+            define(phi.dest, block_id)?;
+        }
+        for statement in &block.statements {
+            let Statement::Assign(place, _) = statement;
+            define(place.local, block_id)?;
+        }
+        if let Terminator::Call { dest, .. } = &block.terminator {
+            define(dest.local, block_id)?;
+        }
+    }
+    let check_use = |operand: &Operand, block: BlockId| match operand {
+        Operand::Copy(place) => match def_block[place.local.index()] {
+            Some(def) if !dominators.dominates(def, block) => Err(format!(
+                "Use of {} in {} is not dominated by its assignment in `{}`",
+                place.local, block, body.name
+            )),
+            _ => Ok(()),
+        },
+        _ => Ok(()),
+    };
+    for &block_id in dominators.reverse_postorder() {
+        let block = body.block(block_id);
+        for phi in &block.phis {
+            let mut args = phi.args.iter().map(|(pred, _)| *pred).collect::<Vec<_>>();
+            let mut expected = preds[block_id.index()]
+                .iter()
+                .copied()
+                .filter(|x| dominators.is_reachable(*x))
+                .collect::<Vec<_>>();
+            args.sort();
+            expected.sort();
+            if args != expected {
+                return Err(format!(
+                    "Phi of {} in {} does not match the predecessors in `{}`",
+                    phi.dest, block_id, body.name
+                ));
+            }
+            for (pred, operand) in &phi.args {
+                check_use(operand, *pred)?;
+            }
+        }
+        for operand in operands(block) {
+            check_use(operand, block_id)?;
+        }
+    }
+    Ok(())
+}